@@ -0,0 +1,60 @@
+//! Runs the app on the host over a scripted drive cycle, for fast iteration on the mapping.
+//!
+//! The simulation doubles stand in for the CSR8645 module, the OBD-II adapter and the flash, and
+//! the app runs on the std executor. Every behavior applied to the module is printed once the
+//! drive cycle is over. Run it with `cargo run-host-sim`.
+
+use dmz_sound_booster::config::app_config::AppConfig;
+use dmz_sound_booster::host_sim::run_drive_cycle;
+use dmz_sound_booster::obd::simulated_obd::DriveSample;
+use embassy_executor::Spawner;
+use embassy_time::Duration;
+
+/// Idles, pulls away through the gears, cruises on the highway and brakes to a stop.
+static DRIVE_CYCLE: [DriveSample; 6] = [
+    DriveSample {
+        at: Duration::from_millis(0),
+        speed: 0,
+        rpm: 800,
+    },
+    DriveSample {
+        at: Duration::from_millis(1000),
+        speed: 15,
+        rpm: 3000,
+    },
+    DriveSample {
+        at: Duration::from_millis(2000),
+        speed: 45,
+        rpm: 3500,
+    },
+    DriveSample {
+        at: Duration::from_millis(3000),
+        speed: 110,
+        rpm: 2800,
+    },
+    DriveSample {
+        at: Duration::from_millis(5000),
+        speed: 30,
+        rpm: 1500,
+    },
+    DriveSample {
+        at: Duration::from_millis(6000),
+        speed: 0,
+        rpm: 800,
+    },
+];
+
+/// Runs the drive cycle and prints the applied behaviors.
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let behaviors = run_drive_cycle(&DRIVE_CYCLE, &AppConfig::default()).await;
+
+    for (index, behavior) in behaviors.iter().enumerate() {
+        println!(
+            "{:>4}: volume {:>2}, bass {:>2}",
+            index, behavior.volume, behavior.bass
+        );
+    }
+    println!("{} behaviors applied", behaviors.len());
+    std::process::exit(0);
+}
@@ -0,0 +1,74 @@
+#![no_std]
+#![no_main]
+
+//! A UART echo peer for bench-testing the CSR8645 driver stack.
+//!
+//! Flash this on a second board and wire its USART1 (PA9 TX, PA10 RX) crossed over to the
+//! CSR8645 UART of the board under test, with a common ground. Every burst of bytes received is
+//! written back unchanged, so `send_data` followed by `receive_data` on the board under test
+//! must return the payload that was sent. Comparing the two checks the framing, the partial
+//! write handling and the flushes end to end without a module.
+
+use defmt::{error, info, warn};
+use defmt_rtt as _;
+use embassy_executor::Spawner;
+use embassy_stm32::peripherals::USART1;
+use embassy_stm32::usart::{self, Uart};
+use embassy_stm32::{bind_interrupts, init, Config};
+use panic_probe as _;
+
+bind_interrupts!(struct Irqs {
+    USART1 => usart::InterruptHandler<USART1>;
+});
+
+/// The baud rate of the echo, matching the default baud rate of the CSR8645 UART.
+const ECHO_BAUDRATE: u32 = 115_200;
+
+/// The largest burst of bytes echoed at once.
+const ECHO_BUFFER_LEN: usize = 512;
+
+/// Echoes every byte received on USART1 back to the sender.
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = init(Config::default());
+
+    let mut uart_config = usart::Config::default();
+    uart_config.baudrate = ECHO_BAUDRATE;
+    // Idle line detection needs the RX DMA, and the async echo write needs the TX DMA
+    let mut uart = match Uart::new(
+        p.USART1,
+        p.PA10,
+        p.PA9,
+        Irqs,
+        p.DMA2_CH7,
+        p.DMA2_CH2,
+        uart_config,
+    ) {
+        Ok(uart) => uart,
+        Err(e) => {
+            error!("Failed to initialize the echo UART: {:?}", e);
+            return;
+        }
+    };
+    info!("Echoing at {} baud", ECHO_BAUDRATE);
+
+    let mut buffer = [0u8; ECHO_BUFFER_LEN];
+    let mut echoed: u32 = 0;
+    loop {
+        // Each burst ends when the line goes idle, so a framed payload is echoed in one go
+        let len = match uart.read_until_idle(&mut buffer).await {
+            Ok(len) => len,
+            Err(e) => {
+                warn!("Echo read failed: {:?}", e);
+                continue;
+            }
+        };
+
+        if let Err(e) = embedded_io_async::Write::write_all(&mut uart, &buffer[..len]).await {
+            warn!("Echo write failed: {:?}", e);
+            continue;
+        }
+        echoed = echoed.wrapping_add(len as u32);
+        info!("Echoed {} bytes, {} in total", len, echoed);
+    }
+}
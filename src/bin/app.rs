@@ -0,0 +1,804 @@
+#![no_std]
+#![no_main]
+
+use crate::audio::audio_behavior::AudioBehavior;
+use crate::audio::audio_mapping::{
+    map_sensor_data_to_audio_behavior, neutral_behavior, MappingConfig,
+};
+use crate::audio::audio_preset::PresetManager;
+use crate::audio::behavior_sink::BehaviorSink;
+use crate::audio::behavior_smoother::AudioBehaviorSmoother;
+use crate::audio::dead_man_switch::DeadManSwitch;
+use crate::audio::thermal_guard::ThermalGuard;
+use crate::audio::volume_schedule::{VolumeSchedule, WallClock};
+use crate::bluetooth::bluetooth_controller::BluetoothController;
+use crate::bluetooth::bluetooth_service::BluetoothService;
+use crate::bluetooth::event_bus::BtEventBus;
+use crate::config::app_config::AppConfig;
+use crate::csr8645::csr8645::{BtEvent, ConnectionState};
+use crate::diagnostics::self_test::SelfTestReport;
+use crate::obd::engine_state::{EngineState, EngineStateTracker};
+use crate::obd::gear_estimator::GearEstimator;
+use crate::obd::obd_controller::ObdController;
+use crate::obd::obd_service::{ObdError, ObdService};
+use crate::obd::vehicle_profile::VehicleProfile;
+use crate::storage::config_store::SharedConfigStore;
+use crate::telemetry::telemetry::{Telemetry, TelemetrySample};
+use alloc::format;
+use defmt::{error, info};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration, Instant};
+use futures::future::{select, Either};
+
+/// The length of the sampling period of a calibration started from the dashboard button.
+const CALIBRATION_DURATION: Duration = Duration::from_secs(20);
+
+/// Signaled by the preset button task each time the dashboard button is pressed.
+pub static PRESET_BUTTON_PRESSED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Signaled by the preset button task when the dashboard button is held for `LONG_PRESS`.
+pub static CALIBRATION_REQUESTED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Carries the volume trim steps requested with the trim buttons and not yet applied by the app.
+pub static VOLUME_TRIM_STEPS: Signal<CriticalSectionRawMutex, i8> = Signal::new();
+
+/// Signaled by the ignition sense task when the ignition is switched off.
+pub static IGNITION_OFF: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Carries the link notifications of the CSR8645 module from the event task to the app.
+pub static BT_EVENTS: BtEventBus = BtEventBus::new();
+
+/// Carries the latest audio behavior and vehicle data from the app to the audio task.
+pub static AUDIO_UPDATES: Signal<CriticalSectionRawMutex, AudioUpdate> = Signal::new();
+
+/// Carries the link state of the phone from the app to the audio task.
+pub static AUDIO_LINK_STATE: Signal<CriticalSectionRawMutex, ConnectionState> = Signal::new();
+
+/// Carries the rev ceiling learned by a calibration from the app to the audio task.
+pub static AUDIO_REDLINE: Signal<CriticalSectionRawMutex, u16> = Signal::new();
+
+/// `AudioUpdate` holds what the audio pipeline follows from a vehicle reading.
+#[derive(Clone, Copy)]
+pub struct AudioUpdate {
+    /// The audio behavior mapped from the reading.
+    pub behavior: AudioBehavior,
+    /// The engine speed, in revolutions per minute, keying the engine tone.
+    pub rpm: u16,
+    /// The vehicle speed, in km/h, used to detect idling.
+    pub speed: u8,
+}
+
+/// The `App` struct represents the main application.
+///
+/// It contains all the components of the application, such as the Bluetooth module and the OBD-II Module.
+///
+/// The app is generic over the services talking to the hardware and over its clock, so it can
+/// also be driven by a `SimulatedObdTransport`, a `MockCsr8645Interface` and a `FixedClock` with
+/// the `simulation` feature. The audio behavior is applied to a `BehaviorSink`, so the same
+/// logic can drive another amplifier than the CSR8645 module, e.g. through a `DacSink`.
+pub struct App<'a, B: BluetoothService + 'a, O: ObdService, C: WallClock, S: BehaviorSink> {
+    bluetooth_module: &'a BluetoothController<'a, B>,
+    /// The target the audio behavior is applied to.
+    sink: S,
+    obd_module: ObdController<O>,
+    preset_manager: PresetManager,
+    gear_estimator: GearEstimator,
+    /// Tells whether the engine is running, cranking or off.
+    engine_state: EngineStateTracker,
+    mapping_config: MappingConfig,
+    smoother: AudioBehaviorSmoother,
+    volume_schedule: VolumeSchedule,
+    thermal_guard: ThermalGuard,
+    dead_man_switch: DeadManSwitch,
+    /// The time at which the last vehicle reading was taken.
+    last_reading: Option<Instant>,
+    clock: C,
+    config_store: &'a SharedConfigStore<'a>,
+    telemetry: Telemetry,
+    /// Whether the OBD-II device is polled, paused while no phone is connected.
+    polling_active: bool,
+}
+
+impl<'a, B: BluetoothService, O: ObdService, C: WallClock, S: BehaviorSink> App<'a, B, O, C, S> {
+    /// Creates a new `App` instance.
+    ///
+    /// Initializes the Bluetooth module and the OBD-II device.
+    ///
+    /// # Arguments
+    ///
+    /// * `bluetooth_module` - The Bluetooth controller, which is usually also the `sink`.
+    /// * `sink` - The target the audio behavior is applied to.
+    /// * `obd_module` - An instance of `ObdController`.
+    /// * `preset_manager` - An instance of `PresetManager`.
+    /// * `clock` - The clock driving the volume schedule, usually the RTC.
+    /// * `config_store` - The store holding the calibrated vehicle profile and the tuned boost
+    ///   profile.
+    /// * `config` - The tunable settings of the firmware.
+    pub fn new(
+        bluetooth_module: &'a BluetoothController<'a, B>,
+        sink: S,
+        obd_module: ObdController<O>,
+        preset_manager: PresetManager,
+        clock: C,
+        config_store: &'a SharedConfigStore<'a>,
+        config: &AppConfig,
+    ) -> Self {
+        let mut mapping_config = config.mapping;
+        match config_store.borrow_mut().import_boost_profile() {
+            Ok(Some(_)) => info!("Imported the staged boost profile"),
+            Ok(None) => {}
+            Err(e) => error!("Failed to import the staged boost profile: {:?}", e),
+        }
+        match config_store.borrow().boost_profile() {
+            Some(profile) => {
+                info!("Loaded the boost profile from flash");
+                profile.apply(&mut mapping_config);
+            }
+            None => info!("Using the built-in boost profile"),
+        }
+        if let Some(profile) = config_store.borrow().vehicle_profile() {
+            mapping_config.vehicle = profile;
+        }
+        let mut volume_schedule = VolumeSchedule::new();
+        for window in &config.quiet_windows {
+            volume_schedule.add_window(*window);
+        }
+
+        Self {
+            bluetooth_module,
+            sink,
+            obd_module,
+            preset_manager,
+            gear_estimator: GearEstimator::default(),
+            engine_state: EngineStateTracker::new(config.engine_state),
+            mapping_config,
+            smoother: AudioBehaviorSmoother::default(),
+            volume_schedule,
+            thermal_guard: ThermalGuard::new(config.thermal_guard),
+            dead_man_switch: DeadManSwitch::new(config.stale_after),
+            last_reading: None,
+            clock,
+            config_store,
+            telemetry: Telemetry::new(config.telemetry_interval),
+            polling_active: true,
+        }
+    }
+
+    /// Checks that the CSR8645 module and the OBD-II adapter respond, and logs a summary.
+    ///
+    /// # Returns
+    ///
+    /// * `SelfTestReport` - The outcome of each check.
+    pub async fn self_test(&mut self) -> SelfTestReport {
+        let mut report = SelfTestReport::default();
+
+        match self.bluetooth_module.ping().await {
+            Ok(()) => report.uart_ok = true,
+            Err(e) => report.details.push(format!("CSR8645 ping failed: {:?}", e)),
+        }
+
+        match self.bluetooth_module.firmware_version().await {
+            Ok(version) => {
+                report.bt_ok = true;
+                report.details.push(format!("CSR8645 firmware {}", version));
+            }
+            Err(e) => report
+                .details
+                .push(format!("CSR8645 version query failed: {:?}", e)),
+        }
+
+        match self.obd_module.adapter_version().await {
+            Ok(version) => {
+                report.obd_ok = true;
+                report.details.push(format!("OBD-II adapter {}", version));
+            }
+            Err(e) => report.details.push(format!("OBD-II probe failed: {:?}", e)),
+        }
+
+        report.log();
+        report
+    }
+
+    /// Learns the RPM range of the vehicle, applies it to the mapping and the redline warning,
+    /// and persists it.
+    ///
+    /// # Arguments
+    ///
+    /// * `duration` - The length of the sampling period.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the learned profile or an error.
+    async fn calibrate(&mut self, duration: Duration) -> Result<VehicleProfile, ObdError> {
+        let profile = self.obd_module.calibrate(duration).await?;
+        self.mapping_config.vehicle = profile;
+        AUDIO_REDLINE.signal(profile.max_rpm);
+
+        if let Err(e) = self.config_store.borrow_mut().set_vehicle_profile(profile) {
+            error!("Failed to persist the vehicle profile: {:?}", e);
+        }
+
+        Ok(profile)
+    }
+
+    /// Reacts to a link notification of the CSR8645 module.
+    ///
+    /// When the phone disconnects the amplifier is muted and the OBD-II polling is paused, and
+    /// both resume once a phone connects again.
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - The notification to react to.
+    async fn handle_bt_event(&mut self, event: BtEvent) {
+        let result = match event {
+            BtEvent::Disconnected => {
+                info!("Phone disconnected, pausing OBD-II polling");
+                self.polling_active = false;
+                AUDIO_LINK_STATE.signal(ConnectionState::Disconnected);
+                self.bluetooth_module.mute().await
+            }
+            BtEvent::Connected => {
+                info!("Phone connected, resuming OBD-II polling");
+                self.polling_active = true;
+                AUDIO_LINK_STATE.signal(ConnectionState::Connected);
+                self.bluetooth_module.unmute().await
+            }
+        };
+
+        if let Err(e) = result {
+            error!("Failed to update the amplifier on {:?}: {:?}", event, e);
+        }
+    }
+
+    /// Reverts the audio to neutral if no vehicle reading arrived within the staleness window.
+    async fn check_staleness(&mut self) {
+        let Some(timestamp) = self.last_reading else {
+            return;
+        };
+
+        if let Some(behavior) = self.dead_man_switch.check(timestamp, Instant::now()) {
+            self.sink.apply(&behavior).await;
+            // The vehicle data is unknown, so the engine tone and the idle detection fall quiet
+            AUDIO_UPDATES.signal(AudioUpdate {
+                behavior,
+                rpm: 0,
+                speed: 0,
+            });
+        }
+        self.sink.flush().await;
+    }
+
+    /// Runs the main logic of the application.
+    ///
+    /// For each speed and RPM reading polled from the OBD-II device according to its poll
+    /// schedule, it determines how to alter the audio behavior based on this data and the active
+    /// preset, and then alters the audio behavior.
+    ///
+    /// The link notifications published on `BT_EVENTS` are handled between readings, and while
+    /// no phone is connected the readings are not pulled at all until one connects. If the
+    /// readings stop or keep failing for longer than the staleness window, the audio reverts to
+    /// a neutral behavior until they resume. The neutral behavior is also applied while the
+    /// engine is off, as tracked from the debounced engine speed.
+    pub async fn run(&mut self) {
+        loop {
+            while let Ok(event) = BT_EVENTS.try_receive() {
+                self.handle_bt_event(event).await;
+            }
+
+            if !self.polling_active {
+                let event = core::pin::pin!(BT_EVENTS.receive());
+                let ignition_off = core::pin::pin!(IGNITION_OFF.wait());
+                match select(event, ignition_off).await {
+                    Either::Left((event, _)) => self.handle_bt_event(event).await,
+                    Either::Right(_) => {
+                        info!("Ignition off, shutting down");
+                        self.bluetooth_module.shutdown().await;
+                        return;
+                    }
+                }
+                continue;
+            }
+
+            let stale_after = self.dead_man_switch.stale_after();
+            // A query cut short by the timeout leaves its response behind on the line
+            let reading = match with_timeout(stale_after, self.obd_module.poll_next()).await {
+                Ok(reading) => Some(reading),
+                Err(_) => {
+                    self.obd_module.discard_pending().await;
+                    None
+                }
+            };
+
+            if IGNITION_OFF.try_take().is_some() {
+                info!("Ignition off, shutting down");
+                self.bluetooth_module.shutdown().await;
+                return;
+            }
+
+            if PRESET_BUTTON_PRESSED.try_take().is_some() {
+                let preset = self.preset_manager.on_button_press();
+                info!("Audio preset switched to {:?}", preset);
+            }
+
+            if let Some(steps) = VOLUME_TRIM_STEPS.try_take() {
+                let trim = self.bluetooth_module.volume_trim().saturating_add(steps);
+                self.bluetooth_module.set_volume_trim(trim);
+                info!("Volume trim set to {}", self.bluetooth_module.volume_trim());
+            }
+
+            if CALIBRATION_REQUESTED.try_take().is_some() {
+                info!("Calibrating, rev the engine up to its redline");
+                match self.calibrate(CALIBRATION_DURATION).await {
+                    Ok(profile) => info!("Calibrated vehicle profile {:?}", profile),
+                    Err(e) => error!("Calibration failed: {:?}", e),
+                }
+                continue;
+            }
+
+            let snapshot = match reading {
+                Some(Ok(snapshot)) => snapshot,
+                Some(Err(e)) => {
+                    error!("Failed to read vehicle data: {:?}", e);
+                    self.check_staleness().await;
+                    continue;
+                }
+                None => {
+                    self.check_staleness().await;
+                    continue;
+                }
+            };
+            self.last_reading = Some(snapshot.timestamp);
+            let (speed, rpm, maf, coolant_temp) = (
+                snapshot.speed,
+                snapshot.rpm,
+                snapshot.maf,
+                snapshot.coolant_temp,
+            );
+            if let Some(state) = self.engine_state.update(snapshot.timestamp, rpm) {
+                info!("Engine state changed to {:?}", state);
+            }
+            let gear = self.gear_estimator.estimate(speed, rpm);
+            // With the engine off there is neither road nor engine noise to compensate
+            let audio_behavior = if self.engine_state.state() == EngineState::Off {
+                neutral_behavior()
+            } else {
+                map_sensor_data_to_audio_behavior(
+                    speed,
+                    rpm,
+                    self.preset_manager.active(),
+                    gear,
+                    maf,
+                    snapshot.throttle,
+                    &self.mapping_config,
+                )
+            };
+            let audio_behavior = self
+                .smoother
+                .smooth(audio_behavior, self.mapping_config.response_mode);
+            let audio_behavior = match self.clock.time_of_day() {
+                Some(now) => self.volume_schedule.apply(audio_behavior, now),
+                None => audio_behavior,
+            };
+            // The coolant temperature stands in for the enclosure temperature
+            let audio_behavior = match coolant_temp {
+                Some(temperature) => self.thermal_guard.apply(audio_behavior, temperature),
+                None => audio_behavior,
+            };
+            let audio_behavior =
+                self.dead_man_switch
+                    .apply(audio_behavior, snapshot.timestamp, Instant::now());
+            self.sink.apply(&audio_behavior).await;
+            AUDIO_UPDATES.signal(AudioUpdate {
+                behavior: audio_behavior,
+                rpm,
+                speed,
+            });
+
+            let now = Instant::now();
+            if self.telemetry.is_due(now) {
+                let sample = TelemetrySample {
+                    speed,
+                    rpm,
+                    behavior: audio_behavior,
+                    connection_state: self.bluetooth_module.connection_state().await,
+                    rssi: self.bluetooth_module.rssi().await.ok(),
+                };
+                self.telemetry.record(now, &sample);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "simulation"))]
+pub(crate) mod tests {
+    use super::*;
+    use crate::audio::dac_sink::DacSink;
+    use crate::audio::volume_schedule::{FixedClock, TimeOfDay};
+    use crate::bluetooth::mock_csr8645::MockCsr8645Interface;
+    use crate::obd::obd_controller::{PID_RPM, PID_SPEED};
+    use crate::obd::poll_schedule::PollSchedule;
+    use crate::obd::simulated_obd::{DriveSample, SimulatedObdTransport};
+    use crate::storage::config_store::ConfigStore;
+    use crate::storage::ram_flash::RamFlash;
+    use core::cell::RefCell;
+    use embassy_futures::block_on;
+    use embassy_futures::join::join;
+    use embassy_time::Timer;
+    use std::sync::{Mutex, PoisonError};
+
+    /// Serializes the tests running the app, which share its signals and event bus.
+    pub(crate) static APP_LOCK: Mutex<()> = Mutex::new(());
+
+    /// The size of the flash region holding the configuration records.
+    const FLASH_REGION_SIZE: usize = 4096;
+
+    /// The time of day reported to the volume schedule, outside any quiet window.
+    const NOON: TimeOfDay = TimeOfDay::new(12, 0);
+
+    /// The time between two queries of the same PID, short so the tests run quickly.
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    /// A stop at idle, then a pull away to a steady cruise.
+    static DRIVE_CYCLE: [DriveSample; 2] = [
+        DriveSample {
+            at: Duration::from_millis(0),
+            speed: 0,
+            rpm: 800,
+        },
+        DriveSample {
+            at: Duration::from_millis(300),
+            speed: 60,
+            rpm: 2500,
+        },
+    ];
+
+    /// A steady cruise, held for as long as the test runs.
+    static CRUISE: [DriveSample; 1] = [DriveSample {
+        at: Duration::from_millis(0),
+        speed: 60,
+        rpm: 2500,
+    }];
+
+    /// Returns an OBD-II controller replaying the given drive cycle, polling RPM and speed only.
+    async fn simulated_obd(script: &'static [DriveSample]) -> ObdController<SimulatedObdTransport> {
+        let mut obd_module = ObdController::new(SimulatedObdTransport::new(script));
+        obd_module.init().await.unwrap();
+        let mut schedule = PollSchedule::new();
+        schedule.set_interval(PID_RPM, POLL_INTERVAL);
+        schedule.set_interval(PID_SPEED, POLL_INTERVAL);
+        obd_module.set_poll_schedule(schedule);
+        obd_module
+    }
+
+    /// Returns the behavior the mapping computes for a sample of the drive cycle.
+    fn expected_behavior(config: &AppConfig, sample: DriveSample) -> AudioBehavior {
+        let gear = GearEstimator::default().estimate(sample.speed, sample.rpm);
+        map_sensor_data_to_audio_behavior(
+            sample.speed,
+            sample.rpm,
+            config.default_preset,
+            gear,
+            None,
+            None,
+            &config.mapping,
+        )
+    }
+
+    #[test]
+    fn run_applies_the_mapping_of_the_drive_cycle() {
+        let _lock = APP_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let config = AppConfig::default();
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let mut bluetooth = BluetoothController::new(MockCsr8645Interface::new(), &config_store);
+        // Every behavior reaches the module, so the whole sequence is captured
+        bluetooth.set_behavior_interval(Duration::from_ticks(0));
+        let bluetooth = &bluetooth;
+
+        block_on(async {
+            let mut app = App::new(
+                bluetooth,
+                bluetooth,
+                simulated_obd(&DRIVE_CYCLE).await,
+                PresetManager::new(config.default_preset),
+                FixedClock(NOON),
+                &config_store,
+                &config,
+            );
+            join(app.run(), async {
+                Timer::after(Duration::from_millis(800)).await;
+                IGNITION_OFF.signal(());
+            })
+            .await;
+        });
+
+        let applied = bluetooth.service().applied_behaviors();
+        assert_eq!(
+            applied.first(),
+            Some(&expected_behavior(&config, DRIVE_CYCLE[0]))
+        );
+        assert_eq!(
+            applied.last(),
+            Some(&expected_behavior(&config, DRIVE_CYCLE[1]))
+        );
+        // Both levels only rise on the way from idle to cruise, the smoother never overshoots
+        for pair in applied.windows(2) {
+            assert!(pair[0].volume <= pair[1].volume, "{:?}", applied);
+            assert!(pair[0].bass <= pair[1].bass, "{:?}", applied);
+        }
+    }
+
+    #[test]
+    fn run_applies_the_behaviors_to_the_configured_sink() {
+        let _lock = APP_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let config = AppConfig::default();
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let bluetooth = BluetoothController::new(MockCsr8645Interface::new(), &config_store);
+
+        let sink = block_on(async {
+            let mut app = App::new(
+                &bluetooth,
+                DacSink::new(),
+                simulated_obd(&CRUISE).await,
+                PresetManager::new(config.default_preset),
+                FixedClock(NOON),
+                &config_store,
+                &config,
+            );
+            join(app.run(), async {
+                Timer::after(Duration::from_millis(300)).await;
+                IGNITION_OFF.signal(());
+            })
+            .await;
+            app.sink
+        });
+
+        let mut expected = DacSink::new();
+        block_on(expected.apply(&expected_behavior(&config, CRUISE[0])));
+        assert_ne!(sink.code(), 0);
+        assert_eq!(sink.code(), expected.code());
+        // The behaviors went to the DAC instead of the module
+        assert!(bluetooth.service().applied_behaviors().is_empty());
+    }
+
+    #[test]
+    fn run_pauses_polling_and_mutes_while_the_phone_is_disconnected() {
+        let _lock = APP_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let config = AppConfig::default();
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let mut bluetooth = BluetoothController::new(MockCsr8645Interface::new(), &config_store);
+        bluetooth.set_behavior_interval(Duration::from_ticks(0));
+        let bluetooth = &bluetooth;
+        let module = bluetooth.service();
+
+        block_on(async {
+            let mut app = App::new(
+                bluetooth,
+                bluetooth,
+                simulated_obd(&CRUISE).await,
+                PresetManager::new(config.default_preset),
+                FixedClock(NOON),
+                &config_store,
+                &config,
+            );
+            join(app.run(), async {
+                Timer::after(Duration::from_millis(100)).await;
+                BT_EVENTS.send(BtEvent::Disconnected).await;
+                Timer::after(Duration::from_millis(50)).await;
+                assert!(module.is_muted().await);
+                let applied = module.applied_behaviors().len();
+                assert!(applied > 0);
+
+                // No reading is polled while the phone is away, so no behavior is applied
+                Timer::after(Duration::from_millis(200)).await;
+                assert_eq!(module.applied_behaviors().len(), applied);
+
+                BT_EVENTS.send(BtEvent::Connected).await;
+                Timer::after(Duration::from_millis(100)).await;
+                assert!(!module.is_muted().await);
+                assert!(module.applied_behaviors().len() > applied);
+                IGNITION_OFF.signal(());
+            })
+            .await;
+            assert!(app.polling_active);
+        });
+    }
+
+    #[test]
+    fn calibrate_applies_the_learned_profile_and_keeps_it_across_boots() {
+        /// Idles, then revs to the ceiling of a diesel while stationary.
+        static REVS: [DriveSample; 2] = [
+            DriveSample {
+                at: Duration::from_millis(0),
+                speed: 0,
+                rpm: 750,
+            },
+            DriveSample {
+                at: Duration::from_millis(200),
+                speed: 0,
+                rpm: 4400,
+            },
+        ];
+        let _lock = APP_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let config = AppConfig::default();
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let learned = VehicleProfile {
+            idle_rpm: 750,
+            max_rpm: 4400,
+        };
+
+        {
+            let config_store = RefCell::new(ConfigStore::new(&mut flash));
+            let bluetooth = BluetoothController::new(MockCsr8645Interface::new(), &config_store);
+            block_on(async {
+                let mut app = App::new(
+                    &bluetooth,
+                    &bluetooth,
+                    simulated_obd(&REVS).await,
+                    PresetManager::new(config.default_preset),
+                    FixedClock(NOON),
+                    &config_store,
+                    &config,
+                );
+                assert_eq!(app.mapping_config.vehicle, config.mapping.vehicle);
+
+                let profile = app.calibrate(Duration::from_millis(500)).await.unwrap();
+
+                assert_eq!(profile, learned);
+                assert_eq!(app.mapping_config.vehicle, learned);
+            });
+        }
+
+        // The next boot loads the profile from flash instead of the defaults
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        assert_eq!(config_store.borrow().vehicle_profile(), Some(learned));
+        let bluetooth = BluetoothController::new(MockCsr8645Interface::new(), &config_store);
+        let app = block_on(async {
+            App::new(
+                &bluetooth,
+                &bluetooth,
+                simulated_obd(&REVS).await,
+                PresetManager::new(config.default_preset),
+                FixedClock(NOON),
+                &config_store,
+                &config,
+            )
+        });
+        assert_eq!(app.mapping_config.vehicle, learned);
+    }
+
+    /// An OBD-II adapter that never answers.
+    struct SilentAdapter;
+
+    impl ObdService for SilentAdapter {
+        async fn send_command(&mut self, _command: &str) -> Result<String, ObdError> {
+            Err(ObdError::NoData)
+        }
+    }
+
+    #[test]
+    fn self_test_passes_when_every_subsystem_answers() {
+        let config = AppConfig::default();
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let bluetooth = BluetoothController::new(MockCsr8645Interface::new(), &config_store);
+
+        let report = block_on(async {
+            let mut app = App::new(
+                &bluetooth,
+                &bluetooth,
+                simulated_obd(&CRUISE).await,
+                PresetManager::new(config.default_preset),
+                FixedClock(NOON),
+                &config_store,
+                &config,
+            );
+            app.self_test().await
+        });
+
+        assert!(report.all_ok(), "{:?}", report);
+        assert_eq!(
+            report.details,
+            ["CSR8645 firmware MOCK", "OBD-II adapter ELM327 v1.5"]
+        );
+    }
+
+    #[test]
+    fn self_test_reports_a_silent_adapter_without_failing_the_module() {
+        let config = AppConfig::default();
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let bluetooth = BluetoothController::new(MockCsr8645Interface::new(), &config_store);
+        let mut app = App::new(
+            &bluetooth,
+            &bluetooth,
+            ObdController::new(SilentAdapter),
+            PresetManager::new(config.default_preset),
+            FixedClock(NOON),
+            &config_store,
+            &config,
+        );
+
+        let report = block_on(app.self_test());
+
+        assert!(report.uart_ok && report.bt_ok);
+        assert!(!report.obd_ok);
+        assert!(!report.all_ok());
+        assert_eq!(report.details.len(), 2);
+        assert!(
+            report.details[1].starts_with("OBD-II probe failed"),
+            "{:?}",
+            report.details
+        );
+    }
+
+    /// An OBD-II adapter replaying a drive cycle that goes silent, as if unplugged, at a given
+    /// time.
+    struct FreezingAdapter {
+        /// The adapter answering until the freeze.
+        transport: SimulatedObdTransport,
+        /// The time from which no command is answered.
+        frozen_at: Instant,
+    }
+
+    impl ObdService for FreezingAdapter {
+        async fn send_command(&mut self, command: &str) -> Result<String, ObdError> {
+            if Instant::now() >= self.frozen_at {
+                return core::future::pending().await;
+            }
+            self.transport.send_command(command).await
+        }
+    }
+
+    #[test]
+    fn run_reverts_to_neutral_once_the_readings_freeze() {
+        let _lock = APP_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let stale_after = Duration::from_millis(200);
+        let config = AppConfig::builder().stale_after(stale_after).build();
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let mut bluetooth = BluetoothController::new(MockCsr8645Interface::new(), &config_store);
+        bluetooth.set_behavior_interval(Duration::from_ticks(0));
+        let bluetooth = &bluetooth;
+        let module = bluetooth.service();
+
+        block_on(async {
+            let start = Instant::now();
+            let freeze_after = Duration::from_millis(150);
+            let mut obd_module = ObdController::new(FreezingAdapter {
+                transport: SimulatedObdTransport::new(&CRUISE),
+                frozen_at: start + freeze_after,
+            });
+            obd_module.init().await.unwrap();
+            let mut schedule = PollSchedule::new();
+            schedule.set_interval(PID_RPM, POLL_INTERVAL);
+            schedule.set_interval(PID_SPEED, POLL_INTERVAL);
+            obd_module.set_poll_schedule(schedule);
+
+            let mut app = App::new(
+                bluetooth,
+                bluetooth,
+                obd_module,
+                PresetManager::new(config.default_preset),
+                FixedClock(NOON),
+                &config_store,
+                &config,
+            );
+            join(app.run(), async {
+                // Still within the staleness window of the last reading
+                Timer::at(start + freeze_after + stale_after / 2).await;
+                let applied = module.applied_behaviors();
+                assert!(!applied.is_empty());
+                assert!(!applied.contains(&neutral_behavior()), "{:?}", applied);
+
+                Timer::at(start + freeze_after + stale_after * 3).await;
+                assert_eq!(module.applied_behaviors().last(), Some(&neutral_behavior()));
+                IGNITION_OFF.signal(());
+            })
+            .await;
+        });
+    }
+}
@@ -0,0 +1,28 @@
+#![no_std]
+#![no_main]
+
+/// `AudioBehavior` is a struct that describes how the audio output should be altered.
+///
+/// It is computed from the vehicle sensor data and applied to the audio path.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct AudioBehavior {
+    /// The volume level to apply.
+    pub volume: u8,
+    /// The bass level to apply.
+    pub bass: u8,
+    /// Whether the RPM-keyed engine tone is mixed into the outgoing audio.
+    pub engine_tone: bool,
+    /// The digital gain applied to the outgoing audio, in dB.
+    pub target_gain_db: f32,
+}
+
+impl Default for AudioBehavior {
+    fn default() -> Self {
+        Self {
+            volume: 0,
+            bass: 0,
+            engine_tone: false,
+            target_gain_db: 0.0,
+        }
+    }
+}
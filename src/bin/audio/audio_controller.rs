@@ -1,8 +1,114 @@
 #![no_std]
 #![no_main]
 
+use crate::audio::audio_behavior::AudioBehavior;
 use crate::audio::audio_service::AudioService;
-use crate::csr8645::csr8645::Csr8645Error;
+use crate::audio::audio_source::AudioSource;
+use crate::audio::clip_detector::{ClipDetector, ClipDetectorConfig};
+use crate::audio::concealment::{PacketLossConcealer, PlcStrategy};
+use crate::audio::crossfade::Crossfade;
+use crate::audio::engine_tone::EngineTone;
+use crate::audio::idle_manager::{AmpCommand, IdleManager};
+use crate::audio::jitter_buffer::{JitterBuffer, UnderrunFill};
+use crate::audio::redline_warning::RedlineWarning;
+use crate::audio::sample_format::{self, AudioFormat, ConversionError};
+use crate::audio::soft_clip::SoftClip;
+use crate::audio::vu_meter::{VuLevels, VuMeter};
+use crate::csr8645::csr8645::{ConnectionState, Csr8645Error};
+use defmt::warn;
+use embassy_time::{with_timeout, Duration, Instant, Ticker};
+
+/// The most bytes of audio handled per transmission, which sizes the frame buffers.
+const MAX_FRAME_LEN: usize = 1024;
+
+/// The fewest bytes of audio handled per transmission, bounding the per-frame overhead.
+const MIN_FRAME_LEN: usize = 256;
+
+/// Returns the number of bytes of the stream played during the given period.
+///
+/// The length is rounded to the nearest byte, then down to whole stereo frames, and capped at
+/// `MAX_FRAME_LEN`.
+///
+/// # Arguments
+///
+/// * `period` - The time between two transmissions.
+///
+/// # Returns
+///
+/// * `usize` - The number of bytes handled per transmission.
+fn frame_len_for(period: Duration) -> usize {
+    let format = AudioFormat::A2DP_STEREO;
+    let len = (format.bytes_per_second() as u64 * period.as_micros() + 500_000) / 1_000_000;
+    let len = (len as usize).min(MAX_FRAME_LEN);
+    len - len % format.bytes_per_frame()
+}
+
+/// The CSR8645 PIO pin wired to the amplifier enable line.
+const AMP_ENABLE_PIO: u8 = 4;
+
+/// The number of frames each VU meter reading covers by default, about 46 ms.
+const DEFAULT_VU_WINDOW_FRAMES: u32 = 8;
+
+/// The default time the vehicle must stay idle before the amplifier is disabled.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The default time taken to fade from one source to the next.
+const DEFAULT_CROSSFADE_DURATION: Duration = Duration::from_millis(50);
+
+/// Scales a frame of 16-bit little-endian PCM samples by a gain, saturating at full scale.
+///
+/// # Arguments
+///
+/// * `buffer` - The frame to scale in place.
+/// * `gain_db` - The gain to apply, in dB.
+fn apply_gain(buffer: &mut [u8], gain_db: f32) {
+    if gain_db == 0.0 {
+        return;
+    }
+
+    let gain = libm::powf(10.0, gain_db / 20.0);
+    for sample in buffer.chunks_exact_mut(2) {
+        let value = i16::from_le_bytes([sample[0], sample[1]]) as f32 * gain;
+        let value = value.clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+        sample.copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Adds a frame of 16-bit little-endian PCM samples into another, saturating at full scale.
+///
+/// # Arguments
+///
+/// * `buffer` - The frame to add into.
+/// * `other` - The frame to add.
+fn add_samples(buffer: &mut [u8], other: &[u8]) {
+    for (sample, added) in buffer.chunks_exact_mut(2).zip(other.chunks_exact(2)) {
+        let value = i16::from_le_bytes([sample[0], sample[1]])
+            .saturating_add(i16::from_le_bytes([added[0], added[1]]));
+        sample.copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// `JitterConfig` holds the settings of the buffering between the Bluetooth stream and the amp.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct JitterConfig {
+    /// The number of frames buffered before playback starts, trading latency for robustness.
+    pub depth_frames: usize,
+    /// The time between two frames sent to the amp.
+    pub frame_period: Duration,
+    /// What is played when the stream stalls.
+    pub fill: UnderrunFill,
+}
+
+impl Default for JitterConfig {
+    fn default() -> Self {
+        Self {
+            depth_frames: 4,
+            // 256 stereo frames at 44.1 kHz
+            frame_period: Duration::from_micros(5_805),
+            fill: UnderrunFill::Silence,
+        }
+    }
+}
 
 /// `AudioController` is a struct that controls the audio services.
 ///
@@ -10,6 +116,44 @@ use crate::csr8645::csr8645::Csr8645Error;
 pub struct AudioController<'a, T: AudioService + 'a> {
     /// An instance of a type that implements the `AudioService` trait.
     audio_service: T,
+    /// The generator used to synthesize the engine-note overlay.
+    engine_tone: EngineTone,
+    /// The audio behavior currently applied.
+    behavior: AudioBehavior,
+    /// The latest engine speed, in revolutions per minute.
+    rpm: u16,
+    /// The latest vehicle speed, in km/h.
+    speed: u8,
+    /// The input feeding the amplifier.
+    source: AudioSource,
+    /// The input fed before the last source change, faded out while the crossfade runs.
+    previous_source: AudioSource,
+    /// Blends the previous source into the current one after a source change.
+    crossfade: Crossfade,
+    /// The state of the Bluetooth link the stream is received over.
+    connection_state: ConnectionState,
+    /// The settings of the jitter buffer.
+    jitter_config: JitterConfig,
+    /// The number of bytes of audio handled per transmission, following from the frame period.
+    frame_len: usize,
+    /// Smooths out the Bluetooth stream.
+    jitter_buffer: JitterBuffer,
+    /// Replaces the audio missing from frames dropped by the link.
+    concealer: PacketLossConcealer,
+    /// Paces the frames sent to the amp.
+    ticker: Ticker,
+    /// Powers the amp down while the vehicle is stopped and nothing is playing.
+    idle_manager: IdleManager,
+    /// Backs the gain off when the outgoing audio clips.
+    clip_detector: ClipDetector,
+    /// The format the module expects the outgoing audio in.
+    output_format: AudioFormat,
+    /// Measures the levels of the outgoing audio for a dashboard meter.
+    vu_meter: VuMeter,
+    /// Saturates the engine note overlay, or `None` to clip it at full scale.
+    soft_clip: Option<SoftClip>,
+    /// Chirps over the audio as the engine nears its redline, or `None` to stay silent.
+    redline_warning: Option<RedlineWarning>,
 }
 
 impl<'a, T: AudioService> AudioController<'a, T> {
@@ -18,34 +162,749 @@ impl<'a, T: AudioService> AudioController<'a, T> {
     /// # Arguments
     ///
     /// * `audio_service` - An instance of a type that implements the `AudioService` trait.
+    /// * `engine_tone` - The generator used to synthesize the engine-note overlay.
     ///
     /// # Returns
     ///
     /// * `Self` - The new `AudioController` instance.
-    pub fn new(audio_service: T) -> Self {
-        Self { audio_service }
+    pub fn new(audio_service: T, engine_tone: EngineTone) -> Self {
+        let jitter_config = JitterConfig::default();
+
+        Self {
+            audio_service,
+            engine_tone,
+            behavior: AudioBehavior::default(),
+            rpm: 0,
+            speed: 0,
+            source: AudioSource::default(),
+            previous_source: AudioSource::default(),
+            crossfade: Crossfade::new(DEFAULT_CROSSFADE_DURATION, AudioFormat::A2DP_STEREO),
+            connection_state: ConnectionState::Disconnected,
+            jitter_config,
+            frame_len: frame_len_for(jitter_config.frame_period),
+            jitter_buffer: JitterBuffer::new(
+                jitter_config.depth_frames * frame_len_for(jitter_config.frame_period),
+                jitter_config.fill,
+            ),
+            concealer: PacketLossConcealer::new(PlcStrategy::default()),
+            ticker: Ticker::every(jitter_config.frame_period),
+            idle_manager: IdleManager::new(DEFAULT_IDLE_TIMEOUT),
+            clip_detector: ClipDetector::new(ClipDetectorConfig::default()),
+            output_format: AudioFormat::A2DP_STEREO,
+            vu_meter: VuMeter::new(DEFAULT_VU_WINDOW_FRAMES),
+            soft_clip: Some(SoftClip::default()),
+            redline_warning: None,
+        }
     }
 
-    /// Handles the transmission of audio data.
+    /// Sets the time the vehicle must stay idle before the amp is disabled.
     ///
-    /// This method receives audio data from a mobile device and plays it on a speaker.
+    /// # Arguments
+    ///
+    /// * `timeout` - The new idle timeout.
+    pub fn set_idle_timeout(&mut self, timeout: Duration) {
+        self.idle_manager.set_timeout(timeout);
+    }
+
+    /// Updates the vehicle speed used to detect idling.
     ///
     /// # Arguments
     ///
-    /// * `data` - The audio data to be transmitted.
+    /// * `speed` - The vehicle speed, in km/h.
+    pub fn set_speed(&mut self, speed: u8) {
+        self.speed = speed;
+    }
+
+    /// Enables or disables the amp when the idle state changes.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), Csr8645Error>` - The result of the amp enable pin operation.
+    async fn update_idle(&mut self) -> Result<(), Csr8645Error> {
+        let audio_active = match self.source {
+            AudioSource::Bluetooth => self.jitter_buffer.is_primed(),
+            AudioSource::LineIn | AudioSource::SynthTone => true,
+        };
+
+        match self
+            .idle_manager
+            .update(Instant::now(), self.speed, audio_active)
+        {
+            Some(AmpCommand::Enable) => self.audio_service.set_pio(AMP_ENABLE_PIO, true).await,
+            Some(AmpCommand::Disable) => self.audio_service.set_pio(AMP_ENABLE_PIO, false).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Sets the settings of the jitter buffer, discarding the buffered audio.
+    ///
+    /// The frames carry the audio played during one frame period, up to `MAX_FRAME_LEN` bytes,
+    /// so the frame size follows from the frame period.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The new jitter buffer settings.
+    pub fn set_jitter_config(&mut self, config: JitterConfig) {
+        self.jitter_config = config;
+        self.frame_len = frame_len_for(config.frame_period);
+        self.jitter_buffer = JitterBuffer::new(config.depth_frames * self.frame_len, config.fill);
+        self.ticker = Ticker::every(config.frame_period);
+    }
+
+    /// Returns the number of bytes of audio handled per transmission.
+    pub fn frame_len(&self) -> usize {
+        self.frame_len
+    }
+
+    /// Sets how the audio missing from dropped frames is replaced.
+    ///
+    /// # Arguments
+    ///
+    /// * `strategy` - The new concealment strategy.
+    pub fn set_plc_strategy(&mut self, strategy: PlcStrategy) {
+        self.concealer = PacketLossConcealer::new(strategy);
+    }
+
+    /// Sizes the jitter buffer for a target latency between reception and playback.
+    ///
+    /// The target is split into as few frames of at most `MAX_FRAME_LEN` bytes as possible,
+    /// and the frames are sized to share it evenly, so both the depth of the buffer and the
+    /// frame size follow from the A2DP stream format and the target. The buffered audio is
+    /// discarded.
+    ///
+    /// # Arguments
+    ///
+    /// * `target` - The latency to aim for.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), Csr8645Error>` - `InvalidParameter` if the target is shorter than a frame
+    ///   of `MIN_FRAME_LEN` bytes.
+    pub fn set_latency_target(&mut self, target: Duration) -> Result<(), Csr8645Error> {
+        let bytes_per_second = AudioFormat::A2DP_STEREO.bytes_per_second() as u64;
+        let target_len = bytes_per_second * target.as_micros() / 1_000_000;
+        if target_len < MIN_FRAME_LEN as u64 {
+            return Err(Csr8645Error::InvalidParameter);
+        }
+
+        let depth_frames = target_len.div_ceil(MAX_FRAME_LEN as u64);
+        let frame_len = frame_len_for(Duration::from_micros(target.as_micros() / depth_frames));
+        self.set_jitter_config(JitterConfig {
+            depth_frames: depth_frames as usize,
+            frame_period: Duration::from_micros(frame_len as u64 * 1_000_000 / bytes_per_second),
+            fill: self.jitter_config.fill,
+        });
+        Ok(())
+    }
+
+    /// Updates the state of the Bluetooth link the stream is received over.
+    ///
+    /// The jitter buffer only pre-fills while connected, and is emptied on disconnection.
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - The new state of the link.
+    pub fn set_connection_state(&mut self, state: ConnectionState) {
+        if state == ConnectionState::Disconnected {
+            self.jitter_buffer.clear();
+        }
+        self.connection_state = state;
+    }
+
+    /// Sets the settings of the clipping backoff, discarding the current gain reduction.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The new clipping backoff settings.
+    pub fn set_clip_detector_config(&mut self, config: ClipDetectorConfig) {
+        self.clip_detector = ClipDetector::new(config);
+    }
+
+    /// Returns the gain reduction currently applied to avoid clipping, in dB.
+    pub fn clip_backoff_db(&self) -> f32 {
+        self.clip_detector.backoff_db()
+    }
+
+    /// Sets the number of frames each VU meter reading covers, resetting the meter.
+    ///
+    /// # Arguments
+    ///
+    /// * `window_frames` - The averaging window, in frames.
+    pub fn set_vu_window(&mut self, window_frames: u32) {
+        self.vu_meter = VuMeter::new(window_frames);
+    }
+
+    /// Returns the latest per-channel levels of the outgoing audio, e.g. for a display task.
+    pub fn vu_levels(&self) -> VuLevels {
+        self.vu_meter.levels()
+    }
+
+    /// Sets the format the module expects the outgoing audio in.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The output format.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), ConversionError>` - An error if the stream cannot be converted to the format.
+    pub fn set_output_format(&mut self, format: AudioFormat) -> Result<(), ConversionError> {
+        sample_format::check_conversion(AudioFormat::A2DP_STEREO, format)?;
+
+        self.output_format = format;
+        Ok(())
+    }
+
+    /// Sets the time taken to fade from one source to the next, cancelling any crossfade.
+    ///
+    /// # Arguments
+    ///
+    /// * `duration` - The crossfade duration, zero to switch sources instantly.
+    pub fn set_crossfade_duration(&mut self, duration: Duration) {
+        self.crossfade = Crossfade::new(duration, AudioFormat::A2DP_STEREO);
+    }
+
+    /// Sets the saturation applied when the engine note overlay pushes the audio past full
+    /// scale.
+    ///
+    /// The overlay saturates with the default drive unless configured otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `soft_clip` - The saturation stage, or `None` to clip the overlay at full scale.
+    pub fn set_soft_clip(&mut self, soft_clip: Option<SoftClip>) {
+        self.soft_clip = soft_clip;
+    }
+
+    /// Sets the warning chirped over the audio as the engine nears its redline.
+    ///
+    /// # Arguments
+    ///
+    /// * `warning` - The redline warning, rendering in `AudioFormat::A2DP_STEREO` like the rest
+    ///   of the pipeline, or `None` to disable it.
+    pub fn set_redline_warning(&mut self, warning: Option<RedlineWarning>) {
+        self.redline_warning = warning;
+    }
+
+    /// Sets the redline the warning is relative to, e.g. after a calibration.
+    ///
+    /// # Arguments
+    ///
+    /// * `redline` - The new redline, in revolutions per minute.
+    pub fn set_redline(&mut self, redline: u16) {
+        if let Some(warning) = &mut self.redline_warning {
+            warning.set_redline(redline);
+        }
+    }
+
+    /// Returns the number of times the Bluetooth stream stalled and the buffer ran dry.
+    pub fn underruns(&self) -> u32 {
+        self.jitter_buffer.underruns()
+    }
+
+    /// Selects the input feeding the amplifier.
+    ///
+    /// The line input is routed inside the CSR8645 module, while the Bluetooth stream and the
+    /// synthesized tone are pumped by `handle_audio_transmission`. Switching to a pumped source
+    /// crossfades from the previous one to avoid a click; the line input is routed by the
+    /// module as soon as it is selected, so switching to it cannot be faded.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The input to play.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), Csr8645Error>` - The result of the input selection.
+    pub async fn set_source(&mut self, source: AudioSource) -> Result<(), Csr8645Error> {
+        self.audio_service
+            .set_line_in(source == AudioSource::LineIn)
+            .await?;
+
+        if source != self.source && source != AudioSource::LineIn {
+            self.previous_source = self.source;
+            self.crossfade.start();
+        }
+        self.source = source;
+        Ok(())
+    }
+
+    /// Returns the input feeding the amplifier.
+    pub fn source(&self) -> AudioSource {
+        self.source
+    }
+
+    /// Applies a new audio behavior.
+    ///
+    /// # Arguments
+    ///
+    /// * `behavior` - The audio behavior to apply.
+    /// * `rpm` - The latest engine speed, in revolutions per minute.
+    pub fn apply_behavior(&mut self, behavior: AudioBehavior, rpm: u16) {
+        self.behavior = behavior;
+        self.rpm = rpm;
+    }
+
+    /// Renders one frame of a pumped source.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The source to render.
+    /// * `tone` - The engine tone of the frame, shared by both sources during a crossfade.
+    /// * `buffer` - The buffer the frame is rendered into, silent on entry.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), Csr8645Error>` - The result of the audio receiving operation.
+    async fn render_source(
+        &mut self,
+        source: AudioSource,
+        tone: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Csr8645Error> {
+        match source {
+            AudioSource::Bluetooth => {
+                // Receive audio data from the mobile device, giving up after one frame period
+                if self.connection_state == ConnectionState::Connected {
+                    let mut chunk = [0u8; MAX_FRAME_LEN];
+                    let chunk = &mut chunk[..buffer.len()];
+                    let audio_service = &self.audio_service;
+                    let received = with_timeout(self.jitter_config.frame_period, async {
+                        audio_service.receive_audio(chunk).await
+                    })
+                    .await;
+
+                    match received {
+                        Ok(result) => {
+                            let len = result?;
+                            self.jitter_buffer.push(&chunk[..len]);
+                        }
+                        Err(_) => warn!("Audio stream stalled"),
+                    }
+                }
+                let drained = self.jitter_buffer.pop_into(buffer);
+                self.concealer.process(buffer, drained);
+
+                // Overlay the engine note on the received audio
+                if self.behavior.engine_tone {
+                    match &self.soft_clip {
+                        Some(soft_clip) => soft_clip.mix(buffer, tone),
+                        None => add_samples(buffer, tone),
+                    }
+                }
+            }
+            AudioSource::SynthTone => buffer.copy_from_slice(tone),
+            // Routed inside the module, so nothing is pumped
+            AudioSource::LineIn => {}
+        }
+
+        Ok(())
+    }
+
+    /// Handles the transmission of audio data.
+    ///
+    /// With the Bluetooth source, this method receives audio data from a mobile device through
+    /// the jitter buffer and plays it on a speaker at a steady rate, mixing in the engine tone if
+    /// the current behavior enables it. A stalled stream is filled in rather than starving the
+    /// engine tone, and audio missing from dropped frames is concealed. With the synthesized
+    /// source only the engine tone is played, and with the line input nothing is pumped since the
+    /// module routes it directly. Right after a source change, both sources are rendered and
+    /// crossfaded. The frame is scanned for the clipping the target gain of the behavior would
+    /// cause, and the gain is then applied, reduced by the clipping backoff. The redline warning,
+    /// if set, is mixed over the result after the gain, so muting, a low target gain or the
+    /// clipping backoff never silence it, and the outgoing frame is metered.
     ///
     /// # Returns
     ///
     /// * `Result<(), Csr8645Error>` - The result of the audio transmission operation.
-    pub async fn handle_audio_transmission(&self, data: &[u8]) -> Result<(), Csr8645Error> {
-        let mut buffer = [0u8; 1024]; // Adjust the buffer size according to your needs
+    pub async fn handle_audio_transmission(&mut self) -> Result<(), Csr8645Error> {
+        if self.source == AudioSource::LineIn {
+            // Nothing is pumped, but the idle state still follows at the frame rate
+            self.ticker.next().await;
+            return self.update_idle().await;
+        }
 
-        // Receive audio data from the mobile device
-        self.audio_service.receive_audio(&mut buffer)?;
+        let fading_from = self.crossfade.is_active().then_some(self.previous_source);
+
+        // Synthesize the engine tone once, so a crossfade does not advance it twice
+        let needs_tone =
+            core::iter::once(self.source)
+                .chain(fading_from)
+                .any(|source| match source {
+                    AudioSource::Bluetooth => self.behavior.engine_tone,
+                    AudioSource::SynthTone => true,
+                    AudioSource::LineIn => false,
+                });
+        let frame_len = self.frame_len;
+        let mut tone = [0u8; MAX_FRAME_LEN];
+        let tone = &mut tone[..frame_len];
+        if needs_tone {
+            self.engine_tone.mix_into(tone, self.rpm);
+        }
+
+        let mut buffer = [0u8; MAX_FRAME_LEN];
+        let buffer = &mut buffer[..frame_len];
+        self.render_source(self.source, tone, buffer).await?;
+        if let Some(previous) = fading_from {
+            let mut outgoing = [0u8; MAX_FRAME_LEN];
+            let outgoing = &mut outgoing[..frame_len];
+            self.render_source(previous, tone, outgoing).await?;
+            self.crossfade.mix(outgoing, buffer);
+        }
+
+        // Apply the target gain, backing off before the saturation if it would clip the output
+        let backoff_db = self
+            .clip_detector
+            .process(buffer, self.behavior.target_gain_db);
+        apply_gain(buffer, self.behavior.target_gain_db - backoff_db);
+
+        // Chirp over whichever source plays when the engine nears the redline, at its own level
+        if let Some(warning) = &mut self.redline_warning {
+            let mut chirp = [0u8; MAX_FRAME_LEN];
+            let chirp = &mut chirp[..frame_len];
+            if warning.render(chirp, self.rpm) {
+                match &self.soft_clip {
+                    Some(soft_clip) => soft_clip.mix(buffer, chirp),
+                    None => add_samples(buffer, chirp),
+                }
+            }
+        }
+        self.vu_meter.process(buffer);
+
+        self.update_idle().await?;
+
+        // Convert to the format expected by the module
+        let mut samples = [0i16; MAX_FRAME_LEN / 2];
+        let samples = &mut samples[..frame_len / 2];
+        for (sample, bytes) in samples.iter_mut().zip(buffer.chunks_exact(2)) {
+            *sample = i16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        let mut output = [0u8; MAX_FRAME_LEN];
+        let len = sample_format::convert_frame(
+            samples,
+            AudioFormat::A2DP_STEREO,
+            self.output_format,
+            &mut output,
+        )
+        .map_err(|_| Csr8645Error::InvalidParameter)?;
 
         // Play the audio data on the speaker
-        self.audio_service.play_audio(&buffer)?;
+        self.ticker.next().await;
+        self.audio_service.play_audio(&output[..len]).await?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::engine_tone::FOUR_STROKE_FIRING_FACTOR;
+    use alloc::collections::VecDeque;
+    use alloc::vec::Vec;
+    use core::cell::{Cell, RefCell};
+    use embassy_futures::block_on;
+
+    /// `FakeAudio` is an `AudioService` serving canned stream chunks and capturing the output.
+    ///
+    /// Once the chunks run out, `receive_audio` blocks like a stalled phone stream.
+    #[derive(Default)]
+    struct FakeAudio {
+        /// The chunks served to the next calls to `receive_audio`, in order.
+        incoming: RefCell<VecDeque<Vec<u8>>>,
+        /// The number of calls to `receive_audio`, including the stalled ones.
+        receives: Cell<usize>,
+        /// The frames played, in order.
+        played: RefCell<Vec<Vec<u8>>>,
+        /// The line input selections, in order.
+        line_in: RefCell<Vec<bool>>,
+        /// The PIO levels driven, in order.
+        pio: RefCell<Vec<(u8, bool)>>,
+    }
+
+    impl AudioService for FakeAudio {
+        async fn play_audio(&self, data: &[u8]) -> Result<(), Csr8645Error> {
+            self.played.borrow_mut().push(data.to_vec());
+            Ok(())
+        }
+
+        async fn receive_audio(&self, buffer: &mut [u8]) -> Result<usize, Csr8645Error> {
+            self.receives.set(self.receives.get() + 1);
+            let Some(chunk) = self.incoming.borrow_mut().pop_front() else {
+                return core::future::pending().await;
+            };
+            let len = chunk.len().min(buffer.len());
+            buffer[..len].copy_from_slice(&chunk[..len]);
+            Ok(len)
+        }
+
+        async fn set_line_in(&self, enable: bool) -> Result<(), Csr8645Error> {
+            self.line_in.borrow_mut().push(enable);
+            Ok(())
+        }
+
+        async fn set_pio(&self, pin: u8, high: bool) -> Result<(), Csr8645Error> {
+            self.pio.borrow_mut().push((pin, high));
+            Ok(())
+        }
+    }
+
+    /// Returns a controller over a fake service, connected, with instant source switches.
+    fn controller() -> AudioController<'static, FakeAudio> {
+        let engine_tone =
+            EngineTone::new(AudioFormat::A2DP_STEREO, 4, FOUR_STROKE_FIRING_FACTOR, 0.5);
+        let mut controller = AudioController::new(FakeAudio::default(), engine_tone);
+        controller.set_connection_state(ConnectionState::Connected);
+        controller.set_crossfade_duration(Duration::from_ticks(0));
+        controller
+    }
+
+    /// Returns a frame of the stream holding the same sample everywhere.
+    fn constant_frame(len: usize, sample: i16) -> Vec<u8> {
+        sample.to_le_bytes().repeat(len / 2)
+    }
+
+    /// Queues `count` frames of a constant stream on the fake service.
+    fn feed(controller: &AudioController<'_, FakeAudio>, count: usize, sample: i16) {
+        let frame = constant_frame(controller.frame_len(), sample);
+        let mut incoming = controller.audio_service.incoming.borrow_mut();
+        incoming.extend(core::iter::repeat(frame).take(count));
+    }
+
+    #[test]
+    fn the_bluetooth_source_plays_the_received_stream() {
+        let mut controller = controller();
+        assert_eq!(controller.source(), AudioSource::Bluetooth);
+        feed(&controller, 8, 1000);
+
+        block_on(async {
+            for _ in 0..8 {
+                controller.handle_audio_transmission().await.unwrap();
+            }
+        });
+
+        let service = &controller.audio_service;
+        assert_eq!(service.receives.get(), 8);
+        assert_eq!(
+            service.played.borrow().last(),
+            Some(&constant_frame(controller.frame_len(), 1000))
+        );
+    }
+
+    #[test]
+    fn the_synth_tone_source_plays_the_engine_tone_without_receiving() {
+        let mut controller = controller();
+        feed(&controller, 4, 1000);
+        controller.apply_behavior(AudioBehavior::default(), 3000);
+
+        block_on(async {
+            controller.set_source(AudioSource::SynthTone).await.unwrap();
+            controller.handle_audio_transmission().await.unwrap();
+        });
+
+        let service = &controller.audio_service;
+        assert_eq!(service.receives.get(), 0);
+        assert_eq!(*service.line_in.borrow(), [false]);
+        let played = service.played.borrow();
+        assert_eq!(played.len(), 1);
+        assert!(played[0].iter().any(|&byte| byte != 0));
+        assert_ne!(played[0], constant_frame(controller.frame_len(), 1000));
+    }
+
+    #[test]
+    fn the_line_in_source_is_routed_by_the_module() {
+        let mut controller = controller();
+        feed(&controller, 4, 1000);
+
+        block_on(async {
+            controller.set_source(AudioSource::LineIn).await.unwrap();
+            controller.handle_audio_transmission().await.unwrap();
+            controller.set_source(AudioSource::Bluetooth).await.unwrap();
+            controller.handle_audio_transmission().await.unwrap();
+        });
+
+        let service = &controller.audio_service;
+        assert_eq!(*service.line_in.borrow(), [true, false]);
+        // Only the transmission back on the Bluetooth source received and played anything
+        assert_eq!(service.receives.get(), 1);
+        assert_eq!(service.played.borrow().len(), 1);
+    }
+
+    #[test]
+    fn a_stalled_source_is_filled_with_silence_and_counted() {
+        let mut controller = controller();
+        controller.set_plc_strategy(PlcStrategy::Silence);
+        let frame_len = controller.frame_len();
+        // One more frame than the default depth of four, then the phone stalls
+        feed(&controller, 5, 1000);
+
+        block_on(async {
+            for _ in 0..9 {
+                controller.handle_audio_transmission().await.unwrap();
+            }
+        });
+
+        let played = controller.audio_service.played.borrow();
+        let silence = constant_frame(frame_len, 0);
+        let audio = constant_frame(frame_len, 1000);
+        // Silent while pre-filling, then the five frames, then silence once the buffer runs dry
+        assert_eq!(played.len(), 9);
+        assert!(played[..3].iter().all(|frame| *frame == silence));
+        assert!(played[3..8].iter().all(|frame| *frame == audio));
+        assert_eq!(played[8], silence);
+        assert_eq!(controller.underruns(), 1);
+    }
+
+    #[test]
+    fn a_gap_is_concealed_with_the_chosen_strategy() {
+        let mut repeating = controller();
+        repeating.set_plc_strategy(PlcStrategy::Repeat);
+        let mut fading = controller();
+        assert_eq!(fading.concealer.strategy(), PlcStrategy::Fade);
+        feed(&repeating, 5, 1000);
+        feed(&fading, 5, 1000);
+
+        block_on(async {
+            for _ in 0..9 {
+                repeating.handle_audio_transmission().await.unwrap();
+                fading.handle_audio_transmission().await.unwrap();
+            }
+        });
+
+        let audio = constant_frame(repeating.frame_len(), 1000);
+        // The frame after the five received ones is lost, then replayed in place of silence
+        assert_eq!(repeating.audio_service.played.borrow()[8], audio);
+        let faded = fading.audio_service.played.borrow()[8].clone();
+        let faded: Vec<i16> = faded
+            .chunks_exact(2)
+            .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect();
+        assert_eq!(faded.first(), Some(&1000));
+        assert!(faded.windows(2).all(|pair| pair[1] <= pair[0]));
+        assert!(*faded.last().unwrap() > 0 && *faded.last().unwrap() < 600);
+    }
+
+    #[test]
+    fn the_buffer_only_fills_while_connected() {
+        let mut controller = controller();
+        controller.set_connection_state(ConnectionState::Disconnected);
+        feed(&controller, 4, 1000);
+
+        block_on(controller.handle_audio_transmission()).unwrap();
+
+        assert_eq!(controller.audio_service.receives.get(), 0);
+        assert_eq!(
+            controller.audio_service.played.borrow()[0],
+            constant_frame(controller.frame_len(), 0)
+        );
+    }
+
+    #[test]
+    fn a_latency_target_sizes_the_buffer_and_the_frames() {
+        let mut controller = controller();
+
+        // 20 ms at 44.1 kHz, 16-bit stereo is 3528 bytes, split into four frames
+        controller
+            .set_latency_target(Duration::from_millis(20))
+            .unwrap();
+        assert_eq!(controller.jitter_config.depth_frames, 4);
+        assert_eq!(controller.frame_len(), 880);
+        assert_eq!(
+            controller.jitter_config.frame_period,
+            Duration::from_micros(4_988)
+        );
+
+        // 100 ms is 17640 bytes, split into eighteen frames
+        controller
+            .set_latency_target(Duration::from_millis(100))
+            .unwrap();
+        assert_eq!(controller.jitter_config.depth_frames, 18);
+        assert_eq!(controller.frame_len(), 980);
+        assert_eq!(
+            controller.jitter_config.frame_period,
+            Duration::from_micros(5_555)
+        );
+    }
+
+    #[test]
+    fn a_latency_target_below_one_frame_is_rejected() {
+        let mut controller = controller();
+        let previous = controller.jitter_config;
+
+        let result = controller.set_latency_target(Duration::from_millis(1));
+
+        assert!(matches!(result, Err(Csr8645Error::InvalidParameter)));
+        assert_eq!(controller.jitter_config, previous);
+
+        controller
+            .set_latency_target(Duration::from_millis(2))
+            .unwrap();
+        assert_eq!(controller.jitter_config.depth_frames, 1);
+        assert_eq!(controller.frame_len(), 352);
+    }
+
+    #[test]
+    fn a_deeper_buffer_waits_longer_before_playing() {
+        let mut controller = controller();
+        let period = JitterConfig::default().frame_period;
+        controller.set_jitter_config(JitterConfig {
+            depth_frames: 6,
+            frame_period: period,
+            fill: UnderrunFill::Silence,
+        });
+        feed(&controller, 6, 1000);
+
+        block_on(async {
+            for _ in 0..6 {
+                controller.handle_audio_transmission().await.unwrap();
+            }
+        });
+
+        let played = controller.audio_service.played.borrow();
+        let silence = constant_frame(controller.frame_len(), 0);
+        assert!(played[..5].iter().all(|frame| *frame == silence));
+        assert_eq!(played[5], constant_frame(controller.frame_len(), 1000));
+    }
+
+    #[test]
+    fn the_amp_is_powered_down_at_a_silent_stop_and_back_up_on_moving_off() {
+        let mut controller = controller();
+        controller.set_connection_state(ConnectionState::Disconnected);
+        controller.set_idle_timeout(Duration::from_millis(20));
+        let start = Instant::now();
+
+        block_on(async {
+            while Instant::now() - start < Duration::from_millis(40) {
+                controller.handle_audio_transmission().await.unwrap();
+            }
+            assert_eq!(
+                *controller.audio_service.pio.borrow(),
+                [(AMP_ENABLE_PIO, false)]
+            );
+
+            controller.set_speed(30);
+            controller.handle_audio_transmission().await.unwrap();
+        });
+
+        assert_eq!(
+            *controller.audio_service.pio.borrow(),
+            [(AMP_ENABLE_PIO, false), (AMP_ENABLE_PIO, true)]
+        );
+    }
+
+    #[test]
+    fn the_synth_tone_keeps_the_amp_powered_at_a_stop() {
+        let mut controller = controller();
+        controller.set_idle_timeout(Duration::from_millis(20));
+        let start = Instant::now();
+
+        block_on(async {
+            controller.set_source(AudioSource::SynthTone).await.unwrap();
+            while Instant::now() - start < Duration::from_millis(40) {
+                controller.handle_audio_transmission().await.unwrap();
+            }
+        });
+
+        assert!(controller.audio_service.pio.borrow().is_empty());
+    }
+}
@@ -0,0 +1,494 @@
+#![no_std]
+#![no_main]
+
+use crate::audio::audio_behavior::AudioBehavior;
+use crate::audio::audio_preset::AudioPreset;
+use crate::audio::behavior_smoother::ResponseMode;
+use crate::audio::loudness_curve::LoudnessCurve;
+use crate::obd::gear_estimator::Gear;
+use crate::obd::vehicle_profile::{EngineType, VehicleProfile};
+
+/// The highest volume level accepted by the CSR8645 module.
+pub const MAX_VOLUME: u8 = 15;
+
+/// The highest bass level accepted by the CSR8645 module.
+pub const MAX_BASS: u8 = 12;
+
+/// The volume applied when the vehicle is stopped.
+const BASE_VOLUME: u8 = 6;
+
+/// The bass levels added across the engine effort range, from idle to the rev ceiling or full
+/// throttle.
+const REV_RANGE_BASS_STEPS: f32 = 10.0;
+
+/// The mass air flow increase, in g/s, that raises the expander gain by one volume level.
+const MAF_PER_EXPANDER_STEP: f32 = 25.0;
+
+/// The highest gain, in volume levels, added by the expander.
+const MAX_EXPANDER_GAIN: f32 = 4.0;
+
+/// `MappingConfig` holds the settings of the mapping from sensor data to audio behavior.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct MappingConfig {
+    /// The volume the limiter never exceeds.
+    pub max_volume: u8,
+    /// The bass level the limiter never exceeds.
+    pub max_bass: u8,
+    /// The width, in levels, of the soft knee below each ceiling, or `None` for a hard limit.
+    pub soft_knee: Option<f32>,
+    /// The curve translating speed into a volume offset.
+    pub loudness: LoudnessCurve,
+    /// Whether the expander raises the volume with the mass air flow.
+    pub expander: bool,
+    /// The RPM range of the vehicle, used to normalize the engine speed.
+    pub vehicle: VehicleProfile,
+    /// The factor applied to the RPM to bass slope, matching the engine type.
+    pub bass_slope: f32,
+    /// How fast the applied behavior follows the mapped one.
+    pub response_mode: ResponseMode,
+    /// The weight of the engine speed in the engine effort driving the bass.
+    pub rpm_weight: f32,
+    /// The weight of the throttle position in the engine effort, ignored when it is not read.
+    pub throttle_weight: f32,
+    /// The speed, in km/h, above which boosting is disabled, or `None` to always boost.
+    pub safety_speed_cap: Option<u8>,
+}
+
+impl Default for MappingConfig {
+    fn default() -> Self {
+        Self {
+            max_volume: MAX_VOLUME,
+            max_bass: MAX_BASS,
+            soft_knee: None,
+            loudness: LoudnessCurve::default(),
+            expander: false,
+            vehicle: VehicleProfile::default(),
+            bass_slope: EngineType::default().bass_slope(),
+            response_mode: ResponseMode::default(),
+            rpm_weight: 0.7,
+            throttle_weight: 0.3,
+            safety_speed_cap: None,
+        }
+    }
+}
+
+impl MappingConfig {
+    /// Creates the default mapping settings for an engine type.
+    ///
+    /// A calibrated vehicle profile still replaces the RPM range of the engine type.
+    ///
+    /// # Arguments
+    ///
+    /// * `engine_type` - The kind of engine fitted to the vehicle.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The mapping settings, with the RPM range and bass slope of the engine type.
+    pub fn for_engine(engine_type: EngineType) -> Self {
+        Self {
+            vehicle: engine_type.profile(),
+            bass_slope: engine_type.bass_slope(),
+            ..Self::default()
+        }
+    }
+}
+
+/// Returns the extra bass applied in the given gear.
+///
+/// Lower gears rev harder and get more punch, cruising gears stay neutral.
+fn gear_bass_offset(gear: Gear) -> f32 {
+    match gear {
+        Gear::Neutral => 0.0,
+        Gear::Forward(1 | 2) => 2.0,
+        Gear::Forward(3 | 4) => 1.0,
+        Gear::Forward(_) => 0.0,
+    }
+}
+
+/// Returns the gain added by the expander at the given mass air flow.
+///
+/// The mass air flow follows the engine effort, so the gain widens the dynamic range when the
+/// engine works hard. It grows linearly and saturates at `MAX_EXPANDER_GAIN`.
+///
+/// # Arguments
+///
+/// * `maf` - The mass air flow rate, in g/s.
+///
+/// # Returns
+///
+/// * `f32` - The gain, in volume levels, between 0.0 and `MAX_EXPANDER_GAIN`.
+pub fn expander_gain(maf: f32) -> f32 {
+    (maf.max(0.0) / MAF_PER_EXPANDER_STEP).min(MAX_EXPANDER_GAIN)
+}
+
+/// Returns the engine effort, blending the signals that are available.
+///
+/// The weights are renormalized over the signals present, so a vehicle lacking the throttle
+/// PID gets an effort driven by the RPM alone instead of one dragged down by a zero throttle.
+///
+/// # Arguments
+///
+/// * `rpm` - The engine speed, in revolutions per minute.
+/// * `throttle` - The throttle position, in percent, if it has been read.
+/// * `config` - The mapping settings.
+///
+/// # Returns
+///
+/// * `f32` - The engine effort, between 0.0 at idle and 1.0 at full effort.
+fn engine_effort(rpm: u16, throttle: Option<f32>, config: &MappingConfig) -> f32 {
+    let rpm_weight = config.rpm_weight.max(0.0);
+    let mut effort = rpm_weight * config.vehicle.normalize(rpm);
+    let mut total_weight = rpm_weight;
+
+    if let Some(throttle) = throttle {
+        let throttle_weight = config.throttle_weight.max(0.0);
+        effort += throttle_weight * (throttle / 100.0).clamp(0.0, 1.0);
+        total_weight += throttle_weight;
+    }
+
+    if total_weight <= 0.0 {
+        return 0.0;
+    }
+    effort / total_weight
+}
+
+/// Returns the safe behavior applied when the vehicle data cannot be trusted.
+///
+/// # Returns
+///
+/// * `AudioBehavior` - The base volume with neither bass boost nor extra gain.
+pub fn neutral_behavior() -> AudioBehavior {
+    AudioBehavior {
+        volume: BASE_VOLUME,
+        ..AudioBehavior::default()
+    }
+}
+
+/// Limits a level to a ceiling.
+///
+/// Without a knee the level is clamped. With a knee, levels within `knee` of the ceiling are
+/// compressed exponentially so the output stays continuous and monotonic while approaching the
+/// ceiling asymptotically.
+///
+/// # Arguments
+///
+/// * `level` - The level to limit.
+/// * `ceiling` - The level that must not be exceeded.
+/// * `knee` - The width of the soft knee below the ceiling, if any.
+///
+/// # Returns
+///
+/// * `f32` - The limited level, between 0.0 and `ceiling`.
+fn limit(level: f32, ceiling: f32, knee: Option<f32>) -> f32 {
+    let level = level.max(0.0);
+
+    let knee = match knee {
+        Some(knee) if knee > 0.0 => knee.min(ceiling),
+        _ => return level.min(ceiling),
+    };
+
+    let threshold = ceiling - knee;
+    if level <= threshold {
+        return level;
+    }
+
+    threshold + knee * (1.0 - libm::expf(-(level - threshold) / knee))
+}
+
+/// Maps the vehicle sensor data to the audio behavior to apply.
+///
+/// The volume grows with speed along the configured loudness curve to compensate road noise,
+/// and the bass grows with the engine effort, blending the RPM across the range of the vehicle
+/// profile with the throttle position when it is available. The engine type and the active
+/// preset scale the RPM to bass slope, the preset also offsets the volume, and lower gears add
+/// extra bass. When enabled, the expander raises the volume with the mass air flow. A final
+/// limiter stage keeps both below the configured ceilings.
+///
+/// Both levels are computed and limited as `f32`, and only rounded to whole levels at the end,
+/// so the soft knee can still reach the ceiling.
+///
+/// Above the safety speed cap, if one is set, the neutral behavior is returned regardless of
+/// the engine data.
+///
+/// # Arguments
+///
+/// * `speed` - The vehicle speed, in km/h.
+/// * `rpm` - The engine speed, in revolutions per minute.
+/// * `preset` - The active audio preset.
+/// * `gear` - The estimated gear.
+/// * `maf` - The mass air flow rate, in g/s, if it has been read.
+/// * `throttle` - The throttle position, in percent, if it has been read.
+/// * `config` - The mapping settings.
+///
+/// # Returns
+///
+/// * `AudioBehavior` - The audio behavior to apply.
+pub fn map_sensor_data_to_audio_behavior(
+    speed: u8,
+    rpm: u16,
+    preset: AudioPreset,
+    gear: Gear,
+    maf: Option<f32>,
+    throttle: Option<f32>,
+    config: &MappingConfig,
+) -> AudioBehavior {
+    if config.safety_speed_cap.is_some_and(|cap| speed > cap) {
+        return neutral_behavior();
+    }
+
+    let bias = preset.bias();
+
+    let volume =
+        BASE_VOLUME as f32 + config.loudness.volume_offset(speed) + bias.volume_offset as f32;
+    let volume = match maf {
+        Some(maf) if config.expander => volume + expander_gain(maf),
+        _ => volume,
+    };
+    let bass = engine_effort(rpm, throttle, config)
+        * REV_RANGE_BASS_STEPS
+        * bias.bass_slope
+        * config.bass_slope
+        + gear_bass_offset(gear);
+
+    let volume = limit(
+        volume,
+        config.max_volume.min(MAX_VOLUME) as f32,
+        config.soft_knee,
+    );
+    let bass = limit(bass, config.max_bass.min(MAX_BASS) as f32, config.soft_knee);
+
+    AudioBehavior {
+        volume: libm::roundf(volume) as u8,
+        bass: libm::roundf(bass) as u8,
+        ..AudioBehavior::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the behavior mapped in neutral with the normal preset and no optional signals.
+    fn mapped(speed: u8, rpm: u16, config: &MappingConfig) -> AudioBehavior {
+        map_sensor_data_to_audio_behavior(
+            speed,
+            rpm,
+            AudioPreset::Normal,
+            Gear::Neutral,
+            None,
+            None,
+            config,
+        )
+    }
+
+    #[test]
+    fn the_limiter_saturates_at_the_ceilings() {
+        let config = MappingConfig {
+            max_volume: 8,
+            max_bass: 5,
+            ..MappingConfig::default()
+        };
+
+        for rpm in [6000, 8000, u16::MAX] {
+            let behavior = mapped(u8::MAX, rpm, &config);
+            assert_eq!((behavior.volume, behavior.bass), (8, 5));
+        }
+    }
+
+    #[test]
+    fn the_ceilings_never_exceed_the_module_limits() {
+        let config = MappingConfig {
+            max_volume: u8::MAX,
+            max_bass: u8::MAX,
+            ..MappingConfig::default()
+        };
+
+        let behavior = mapped(u8::MAX, u16::MAX, &config);
+
+        assert_eq!((behavior.volume, behavior.bass), (MAX_VOLUME, MAX_BASS));
+    }
+
+    #[test]
+    fn the_soft_knee_is_monotonic_and_continuous() {
+        let ceiling = 10.0;
+        let knee = Some(3.0);
+        let step = 0.01;
+        let mut previous = limit(0.0, ceiling, knee);
+
+        for i in 1..=3000 {
+            let level = i as f32 * step;
+            let limited = limit(level, ceiling, knee);
+            assert!(limited >= previous, "{} dropped at {}", limited, level);
+            // The knee never amplifies, so the output moves no further than the input
+            assert!(
+                limited - previous <= step + f32::EPSILON,
+                "jump at {}",
+                level
+            );
+            assert!(limited <= ceiling);
+            previous = limited;
+        }
+        // Below the knee the level passes through untouched
+        assert_eq!(limit(7.0, ceiling, knee), 7.0);
+        assert_eq!(limit(5.0, ceiling, knee), 5.0);
+    }
+
+    #[test]
+    fn the_soft_knee_keeps_the_mapping_below_the_ceiling() {
+        let config = MappingConfig {
+            max_bass: 6,
+            soft_knee: Some(2.0),
+            ..MappingConfig::default()
+        };
+        let mut previous = 0;
+
+        for rpm in (800..=8000).step_by(100) {
+            let bass = mapped(0, rpm, &config).bass;
+            assert!(bass >= previous, "bass dropped at {} rpm", rpm);
+            assert!(bass <= 6);
+            previous = bass;
+        }
+        assert_eq!(previous, 6);
+    }
+
+    #[test]
+    fn the_expander_gain_rises_monotonically_with_the_maf() {
+        let mut previous = expander_gain(0.0);
+        assert_eq!(previous, 0.0);
+
+        for maf in (1..=1500).map(|step| step as f32 * 0.1) {
+            let gain = expander_gain(maf);
+            assert!(gain >= previous, "gain dropped at {} g/s", maf);
+            assert!(gain <= MAX_EXPANDER_GAIN);
+            previous = gain;
+        }
+        assert_eq!(expander_gain(25.0), 1.0);
+        assert_eq!(expander_gain(500.0), MAX_EXPANDER_GAIN);
+        assert_eq!(expander_gain(-3.0), 0.0);
+    }
+
+    #[test]
+    fn the_expander_only_applies_when_enabled() {
+        let expanded = |expander, maf| {
+            let config = MappingConfig {
+                expander,
+                ..MappingConfig::default()
+            };
+            map_sensor_data_to_audio_behavior(
+                30,
+                2000,
+                AudioPreset::Normal,
+                Gear::Neutral,
+                maf,
+                None,
+                &config,
+            )
+            .volume
+        };
+
+        let base = expanded(false, None);
+        assert_eq!(expanded(false, Some(100.0)), base);
+        assert_eq!(expanded(true, None), base);
+        assert!(expanded(true, Some(50.0)) > base);
+        assert!(expanded(true, Some(100.0)) > expanded(true, Some(50.0)));
+    }
+
+    /// Returns the bass mapped in neutral with the normal preset and the given throttle.
+    fn mapped_bass(rpm: u16, throttle: Option<f32>) -> u8 {
+        map_sensor_data_to_audio_behavior(
+            30,
+            rpm,
+            AudioPreset::Normal,
+            Gear::Neutral,
+            None,
+            throttle,
+            &MappingConfig::default(),
+        )
+        .bass
+    }
+
+    #[test]
+    fn a_missing_throttle_leaves_the_effort_to_the_rpm() {
+        let config = MappingConfig::default();
+        // Halfway between the default idle and rev ceiling
+        let rpm = 3900;
+
+        assert!((engine_effort(rpm, None, &config) - 0.5).abs() < 1e-6);
+        // A throttle matching the RPM changes nothing, so the weights add up either way
+        assert!((engine_effort(rpm, Some(50.0), &config) - 0.5).abs() < 1e-6);
+        assert!((engine_effort(rpm, Some(100.0), &config) - 0.65).abs() < 1e-6);
+        assert!((engine_effort(rpm, Some(0.0), &config) - 0.35).abs() < 1e-6);
+    }
+
+    #[test]
+    fn the_mapping_only_uses_the_throttle_when_it_is_present() {
+        let rpm = 3900;
+        let rpm_only = mapped_bass(rpm, None);
+
+        assert!(rpm_only > 0);
+        assert_eq!(mapped_bass(rpm, Some(50.0)), rpm_only);
+        // A closed throttle that was actually read pulls the bass down, a missing one does not
+        assert!(mapped_bass(rpm, Some(0.0)) < rpm_only);
+        assert!(mapped_bass(rpm, Some(100.0)) > rpm_only);
+    }
+
+    #[test]
+    fn zero_weights_give_no_effort() {
+        let config = MappingConfig {
+            rpm_weight: 0.0,
+            throttle_weight: 0.0,
+            ..MappingConfig::default()
+        };
+
+        assert_eq!(engine_effort(7000, Some(100.0), &config), 0.0);
+        assert_eq!(engine_effort(7000, None, &config), 0.0);
+    }
+
+    #[test]
+    fn a_diesel_gets_more_bass_than_a_petrol_at_the_same_rpm() {
+        let petrol = MappingConfig::for_engine(EngineType::Petrol);
+        let diesel = MappingConfig::for_engine(EngineType::Diesel);
+
+        // 4000 RPM is barely past half the petrol range but near the diesel redline
+        assert_eq!(mapped(0, 4000, &petrol).bass, 5);
+        assert_eq!(mapped(0, 4000, &diesel).bass, 7);
+    }
+
+    #[test]
+    fn the_diesel_bass_tops_out_at_its_redline_with_a_gentler_slope() {
+        let petrol = MappingConfig::for_engine(EngineType::Petrol);
+        let diesel = MappingConfig::for_engine(EngineType::Diesel);
+
+        assert_eq!(mapped(0, 4500, &diesel).bass, 8);
+        assert_eq!(mapped(0, 7000, &diesel).bass, 8);
+        assert_eq!(mapped(0, 7000, &petrol).bass, 10);
+        assert_eq!(mapped(0, 750, &diesel).bass, 0);
+    }
+
+    #[test]
+    fn the_safety_speed_cap_forces_the_neutral_behavior_above_it() {
+        let config = MappingConfig {
+            safety_speed_cap: Some(120),
+            ..MappingConfig::default()
+        };
+
+        let below = mapped(119, 6000, &config);
+        let at_cap = mapped(120, 6000, &config);
+        let above = mapped(121, 6000, &config);
+
+        assert_eq!(below, mapped(119, 6000, &MappingConfig::default()));
+        assert_eq!(at_cap, mapped(120, 6000, &MappingConfig::default()));
+        assert_ne!(at_cap, neutral_behavior());
+        assert_eq!(above, neutral_behavior());
+        // The forced behavior ignores the engine entirely
+        assert_eq!(mapped(u8::MAX, u16::MAX, &config), neutral_behavior());
+    }
+
+    #[test]
+    fn no_safety_speed_cap_always_boosts() {
+        let config = MappingConfig::default();
+        assert_eq!(config.safety_speed_cap, None);
+
+        assert_ne!(mapped(u8::MAX, 6000, &config), neutral_behavior());
+    }
+}
@@ -0,0 +1,142 @@
+#![no_std]
+#![no_main]
+
+/// Represents a user-selectable audio preset.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub enum AudioPreset {
+    Quiet,
+    Normal,
+    Sport,
+}
+
+/// `PresetBias` describes how a preset biases the mapping from sensor data to audio behavior.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct PresetBias {
+    /// The offset added to the mapped volume.
+    pub volume_offset: i8,
+    /// The factor applied to the RPM to bass slope.
+    pub bass_slope: f32,
+}
+
+impl AudioPreset {
+    /// Returns the preset that follows this one when cycling.
+    ///
+    /// # Returns
+    ///
+    /// * `AudioPreset` - The next preset, wrapping around from `Sport` to `Quiet`.
+    pub fn next(&self) -> AudioPreset {
+        match self {
+            AudioPreset::Quiet => AudioPreset::Normal,
+            AudioPreset::Normal => AudioPreset::Sport,
+            AudioPreset::Sport => AudioPreset::Quiet,
+        }
+    }
+
+    /// Returns the bias this preset applies to the mapping.
+    ///
+    /// # Returns
+    ///
+    /// * `PresetBias` - The volume offset and bass slope factor of the preset.
+    pub fn bias(&self) -> PresetBias {
+        match self {
+            AudioPreset::Quiet => PresetBias {
+                volume_offset: -3,
+                bass_slope: 0.5,
+            },
+            AudioPreset::Normal => PresetBias {
+                volume_offset: 0,
+                bass_slope: 1.0,
+            },
+            AudioPreset::Sport => PresetBias {
+                volume_offset: 2,
+                bass_slope: 1.5,
+            },
+        }
+    }
+}
+
+/// `PresetManager` is a struct that keeps track of the active audio preset.
+///
+/// The preset is cycled by a dashboard button and overrides the automatic mapping.
+pub struct PresetManager {
+    /// The preset currently applied.
+    active: AudioPreset,
+}
+
+impl PresetManager {
+    /// Creates a new instance of `PresetManager`.
+    ///
+    /// # Arguments
+    ///
+    /// * `initial` - The preset applied at startup.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `PresetManager` instance.
+    pub fn new(initial: AudioPreset) -> Self {
+        Self { active: initial }
+    }
+
+    /// Returns the preset currently applied.
+    pub fn active(&self) -> AudioPreset {
+        self.active
+    }
+
+    /// Handles a button press by switching to the next preset.
+    ///
+    /// # Returns
+    ///
+    /// * `AudioPreset` - The newly active preset.
+    pub fn on_button_press(&mut self) -> AudioPreset {
+        self.active = self.active.next();
+        self.active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::audio_mapping::{map_sensor_data_to_audio_behavior, MappingConfig};
+    use crate::obd::gear_estimator::Gear;
+
+    /// Returns the volume and bass the default mapping computes at 40 km/h and 3000 RPM.
+    fn mapped(preset: AudioPreset) -> (u8, u8) {
+        let behavior = map_sensor_data_to_audio_behavior(
+            40,
+            3000,
+            preset,
+            Gear::Neutral,
+            None,
+            None,
+            &MappingConfig::default(),
+        );
+        (behavior.volume, behavior.bass)
+    }
+
+    #[test]
+    fn each_preset_biases_the_volume_and_the_bass_slope() {
+        // The base volume of 6 plus 2 levels of road noise compensation, and 3.5 bass levels of
+        // engine effort, scaled by the preset
+        assert_eq!(mapped(AudioPreset::Normal), (8, 4));
+        assert_eq!(mapped(AudioPreset::Quiet), (5, 2));
+        assert_eq!(mapped(AudioPreset::Sport), (10, 5));
+    }
+
+    #[test]
+    fn repeated_button_presses_cycle_through_the_presets() {
+        let mut manager = PresetManager::new(AudioPreset::Normal);
+
+        let presses: Vec<AudioPreset> = (0..4).map(|_| manager.on_button_press()).collect();
+
+        assert_eq!(
+            presses,
+            [
+                AudioPreset::Sport,
+                AudioPreset::Quiet,
+                AudioPreset::Normal,
+                AudioPreset::Sport
+            ]
+        );
+        assert_eq!(manager.active(), AudioPreset::Sport);
+    }
+}
@@ -1,8 +1,8 @@
 #![no_std]
 #![no_main]
 
-use crate::csr8645::csr8645::Csr8645;
 use crate::csr8645::csr8645::Csr8645Error;
+use crate::csr8645::csr8645::{Csr8645Exchange, SharedCsr8645};
 
 /// `AudioService` is a trait that defines the necessary methods for audio services.
 pub trait AudioService {
@@ -15,7 +15,7 @@ pub trait AudioService {
     /// # Returns
     ///
     /// * `Result<(), Csr8645Error>` - The result of the audio playback operation.
-    fn play_audio(&self, data: &[u8]) -> Result<(), Csr8645Error>;
+    async fn play_audio(&self, data: &[u8]) -> Result<(), Csr8645Error>;
 
     /// Receives audio data into the provided buffer.
     ///
@@ -25,14 +25,38 @@ pub trait AudioService {
     ///
     /// # Returns
     ///
-    /// * `Result<(), Csr8645Error>` - The result of the audio receiving operation.
-    fn receive_audio(&self, buffer: &mut [u8]) -> Result<(), Csr8645Error>;
+    /// * `Result<usize, Csr8645Error>` - The number of bytes received, at the start of `buffer`.
+    ///   A read filling the whole buffer may have been truncated.
+    async fn receive_audio(&self, buffer: &mut [u8]) -> Result<usize, Csr8645Error>;
+
+    /// Routes the analog line input to the output, or back to the A2DP stream.
+    ///
+    /// # Arguments
+    ///
+    /// * `enable` - True to play the line input, false to play the A2DP stream.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), Csr8645Error>` - The result of the input selection.
+    async fn set_line_in(&self, enable: bool) -> Result<(), Csr8645Error>;
+
+    /// Drives one of the module PIO pins.
+    ///
+    /// # Arguments
+    ///
+    /// * `pin` - The PIO pin index.
+    /// * `high` - True to drive the pin high, false to drive it low.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), Csr8645Error>` - The result of the pin operation.
+    async fn set_pio(&self, pin: u8, high: bool) -> Result<(), Csr8645Error>;
 }
 
 /// `AudioServiceImpl` is a struct that implements the `AudioService` trait.
 pub struct AudioServiceImpl<'a> {
-    /// A reference to a `Csr8645` instance.
-    csr8645: &'a Csr8645<'a>,
+    /// A reference to the shared `Csr8645` instance.
+    csr8645: &'a SharedCsr8645<'a>,
 }
 
 impl<'a> AudioServiceImpl<'a> {
@@ -40,22 +64,42 @@ impl<'a> AudioServiceImpl<'a> {
     ///
     /// # Arguments
     ///
-    /// * `csr8645` - A reference to a `Csr8645` instance.
+    /// * `csr8645` - A reference to the shared `Csr8645` instance.
     ///
     /// # Returns
     ///
     /// * `Self` - The new `AudioServiceImpl` instance.
-    pub fn new(csr8645: &'a Csr8645<'a>) -> Self {
+    pub fn new(csr8645: &'a SharedCsr8645<'a>) -> Self {
         Self { csr8645 }
     }
 }
 
 impl<'a> AudioService for AudioServiceImpl<'a> {
-    fn play_audio(&self, data: &[u8]) -> Result<(), Csr8645Error> {
-        self.csr8645.play_audio(data)
+    async fn play_audio(&self, data: &[u8]) -> Result<(), Csr8645Error> {
+        Csr8645Exchange::lock(self.csr8645)
+            .await
+            .play_audio(data)
+            .await
+    }
+
+    async fn receive_audio(&self, buffer: &mut [u8]) -> Result<usize, Csr8645Error> {
+        Csr8645Exchange::lock(self.csr8645)
+            .await
+            .receive_audio(buffer)
+            .await
+    }
+
+    async fn set_line_in(&self, enable: bool) -> Result<(), Csr8645Error> {
+        Csr8645Exchange::lock(self.csr8645)
+            .await
+            .set_line_in(enable)
+            .await
     }
 
-    fn receive_audio(&self, buffer: &mut [u8]) -> Result<(), Csr8645Error> {
-        self.csr8645.receive_audio(buffer)
+    async fn set_pio(&self, pin: u8, high: bool) -> Result<(), Csr8645Error> {
+        Csr8645Exchange::lock(self.csr8645)
+            .await
+            .set_pio(pin, high)
+            .await
     }
 }
@@ -0,0 +1,14 @@
+#![no_std]
+#![no_main]
+
+/// Represents the input feeding the amplifier.
+#[derive(Clone, Copy, Debug, Default, PartialEq, defmt::Format)]
+pub enum AudioSource {
+    /// The A2DP stream received from the connected phone.
+    #[default]
+    Bluetooth,
+    /// The analog line input of the CSR8645 module.
+    LineIn,
+    /// The engine tone synthesized on the board.
+    SynthTone,
+}
@@ -0,0 +1,26 @@
+#![no_std]
+#![no_main]
+
+use crate::audio::audio_behavior::AudioBehavior;
+
+/// `BehaviorSink` is a trait that defines a target the audio behavior is applied to.
+///
+/// The mapping from the vehicle data to the audio behavior does not depend on the hardware, so
+/// the same logic can drive the CSR8645 module, a DAC controlled amplifier or any other target.
+pub trait BehaviorSink {
+    /// Applies an audio behavior to the target.
+    ///
+    /// Failures are reported by the sink itself, since a missed update is superseded by the
+    /// next one.
+    ///
+    /// # Arguments
+    ///
+    /// * `behavior` - The audio behavior to apply.
+    async fn apply(&mut self, behavior: &AudioBehavior);
+
+    /// Applies the last behavior the sink held back, if it coalesces updates.
+    ///
+    /// This is called when no new behavior is about to follow, e.g. when the vehicle data
+    /// stops, so a rate-limited sink does not keep a stale behavior on hold.
+    async fn flush(&mut self) {}
+}
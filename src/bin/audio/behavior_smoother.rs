@@ -0,0 +1,197 @@
+#![no_std]
+#![no_main]
+
+use crate::audio::audio_behavior::AudioBehavior;
+
+/// The default weight of a new target in the smoothed value, between 0.0 and 1.0.
+const DEFAULT_SMOOTHING_FACTOR: f32 = 0.2;
+
+/// Selects how fast the audio behavior follows the vehicle data.
+#[derive(Clone, Copy, Debug, Default, PartialEq, defmt::Format)]
+pub enum ResponseMode {
+    /// Both volume and bass are smoothed, hiding the jitter of the sensor data.
+    #[default]
+    Smooth,
+    /// The bass tracks the RPM instantly while the volume stays smoothed.
+    LowLatency,
+}
+
+/// `FieldSmoother` smooths a single level with an exponential moving average.
+pub struct FieldSmoother {
+    /// The weight of a new target in the smoothed value, between 0.0 and 1.0.
+    factor: f32,
+    /// The smoothed value, or `None` before the first target.
+    value: Option<f32>,
+}
+
+impl FieldSmoother {
+    /// Creates a new instance of `FieldSmoother`.
+    ///
+    /// # Arguments
+    ///
+    /// * `factor` - The weight of a new target in the smoothed value, between 0.0 and 1.0.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `FieldSmoother` instance.
+    pub fn new(factor: f32) -> Self {
+        Self {
+            factor: factor.clamp(0.0, 1.0),
+            value: None,
+        }
+    }
+
+    /// Moves the smoothed value towards a target.
+    ///
+    /// The first target is taken as is.
+    ///
+    /// # Arguments
+    ///
+    /// * `target` - The level to move towards.
+    ///
+    /// # Returns
+    ///
+    /// * `u8` - The smoothed level, rounded to the nearest integer.
+    pub fn update(&mut self, target: u8) -> u8 {
+        let target = target as f32;
+        let value = match self.value {
+            Some(value) => value + (target - value) * self.factor,
+            None => target,
+        };
+
+        self.value = Some(value);
+        libm::roundf(value) as u8
+    }
+
+    /// Jumps straight to a level, so smoothing later resumes from it.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The new level.
+    pub fn reset(&mut self, value: u8) {
+        self.value = Some(value as f32);
+    }
+}
+
+/// `AudioBehaviorSmoother` smooths the volume and bass of successive audio behaviors.
+///
+/// Each field has its own smoother, so the bass can bypass smoothing in low-latency mode while
+/// the volume keeps lagging gently behind the speed.
+pub struct AudioBehaviorSmoother {
+    /// Smooths the volume.
+    volume: FieldSmoother,
+    /// Smooths the bass.
+    bass: FieldSmoother,
+}
+
+impl Default for AudioBehaviorSmoother {
+    fn default() -> Self {
+        Self::new(DEFAULT_SMOOTHING_FACTOR)
+    }
+}
+
+impl AudioBehaviorSmoother {
+    /// Creates a new instance of `AudioBehaviorSmoother`.
+    ///
+    /// # Arguments
+    ///
+    /// * `factor` - The weight of a new target in the smoothed values, between 0.0 and 1.0.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `AudioBehaviorSmoother` instance.
+    pub fn new(factor: f32) -> Self {
+        Self {
+            volume: FieldSmoother::new(factor),
+            bass: FieldSmoother::new(factor),
+        }
+    }
+
+    /// Smooths a new target behavior.
+    ///
+    /// # Arguments
+    ///
+    /// * `target` - The behavior computed by the mapping.
+    /// * `mode` - Whether the bass is smoothed or tracks the target instantly.
+    ///
+    /// # Returns
+    ///
+    /// * `AudioBehavior` - The behavior to apply.
+    pub fn smooth(&mut self, target: AudioBehavior, mode: ResponseMode) -> AudioBehavior {
+        let bass = match mode {
+            ResponseMode::Smooth => self.bass.update(target.bass),
+            ResponseMode::LowLatency => {
+                self.bass.reset(target.bass);
+                target.bass
+            }
+        };
+
+        AudioBehavior {
+            volume: self.volume.update(target.volume),
+            bass,
+            ..target
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a behavior with the given volume and bass.
+    fn behavior(volume: u8, bass: u8) -> AudioBehavior {
+        AudioBehavior {
+            volume,
+            bass,
+            ..AudioBehavior::default()
+        }
+    }
+
+    #[test]
+    fn the_first_target_is_taken_as_is() {
+        let mut smoother = AudioBehaviorSmoother::new(0.5);
+
+        assert_eq!(
+            smoother.smooth(behavior(8, 4), ResponseMode::Smooth),
+            behavior(8, 4)
+        );
+    }
+
+    #[test]
+    fn smooth_mode_lags_both_fields() {
+        let mut smoother = AudioBehaviorSmoother::new(0.5);
+        smoother.smooth(behavior(4, 2), ResponseMode::Smooth);
+
+        let first = smoother.smooth(behavior(12, 10), ResponseMode::Smooth);
+        let second = smoother.smooth(behavior(12, 10), ResponseMode::Smooth);
+
+        assert_eq!(first, behavior(8, 6));
+        assert_eq!(second, behavior(10, 8));
+    }
+
+    #[test]
+    fn low_latency_bass_tracks_the_rpm_while_the_volume_lags() {
+        let mut smoother = AudioBehaviorSmoother::new(0.5);
+        smoother.smooth(behavior(4, 2), ResponseMode::LowLatency);
+
+        // A rev spike moves the bass at once, the volume only halfway
+        let spike = smoother.smooth(behavior(12, 14), ResponseMode::LowLatency);
+        assert_eq!(spike.bass, 14);
+        assert_eq!(spike.volume, 8);
+
+        let drop = smoother.smooth(behavior(12, 3), ResponseMode::LowLatency);
+        assert_eq!(drop.bass, 3);
+        assert_eq!(drop.volume, 10);
+    }
+
+    #[test]
+    fn smoothing_resumes_from_the_last_low_latency_bass() {
+        let mut smoother = AudioBehaviorSmoother::new(0.5);
+        smoother.smooth(behavior(4, 2), ResponseMode::Smooth);
+        smoother.smooth(behavior(4, 12), ResponseMode::LowLatency);
+
+        let resumed = smoother.smooth(behavior(4, 2), ResponseMode::Smooth);
+
+        assert_eq!(resumed.bass, 7);
+    }
+}
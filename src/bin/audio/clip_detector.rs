@@ -0,0 +1,230 @@
+#![no_std]
+#![no_main]
+
+use defmt::warn;
+
+/// The sample magnitude from which a sample counts as clipped, about -0.2 dBFS.
+const CLIP_LEVEL: f32 = 32_000.0;
+
+/// The number of frames between two clipping warnings, about one second of audio.
+const LOG_INTERVAL_FRAMES: u32 = 172;
+
+/// `ClipDetectorConfig` holds the settings of the clipping backoff.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct ClipDetectorConfig {
+    /// The share of clipped samples in a frame, between 0.0 and 1.0, above which the gain backs
+    /// off.
+    pub threshold_ratio: f32,
+    /// The gain removed, in dB, after each clipping frame.
+    pub backoff_step_db: f32,
+    /// The gain restored, in dB, after each clean frame.
+    pub recovery_step_db: f32,
+    /// The largest gain reduction, in dB.
+    pub max_backoff_db: f32,
+    /// The headroom, in dB, a frame must keep after a recovery step for the gain to be restored.
+    pub hysteresis_db: f32,
+}
+
+impl Default for ClipDetectorConfig {
+    fn default() -> Self {
+        Self {
+            threshold_ratio: 0.01,
+            backoff_step_db: 1.0,
+            recovery_step_db: 0.05,
+            max_backoff_db: 12.0,
+            hysteresis_db: 1.0,
+        }
+    }
+}
+
+/// `ClipDetector` scans outgoing PCM frames for samples near full scale and backs the gain off.
+///
+/// Frames are scanned before the gain stage saturates them, so the detector sees how far past
+/// full scale the gain would push them rather than a flattened output that looks clean once
+/// limited. The gain drops quickly while frames clip and is restored slowly, and only while the
+/// frame keeps `hysteresis_db` of headroom past the next recovery step, so the output neither
+/// pumps on loud passages nor hovers around the clipping point.
+pub struct ClipDetector {
+    /// The settings of the clipping backoff.
+    config: ClipDetectorConfig,
+    /// The gain reduction currently applied, in dB.
+    backoff_db: f32,
+    /// The number of clipping frames since the last warning.
+    clipping_frames: u32,
+    /// The number of frames left before the next warning may be logged.
+    log_holdoff: u32,
+}
+
+impl ClipDetector {
+    /// Creates a new instance of `ClipDetector`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The settings of the clipping backoff.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `ClipDetector` instance, without any gain reduction.
+    pub fn new(config: ClipDetectorConfig) -> Self {
+        Self {
+            config,
+            backoff_db: 0.0,
+            clipping_frames: 0,
+            log_holdoff: 0,
+        }
+    }
+
+    /// Returns the gain reduction currently applied, in dB.
+    pub fn backoff_db(&self) -> f32 {
+        self.backoff_db
+    }
+
+    /// Scans an outgoing frame and updates the gain reduction.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame` - The frame of 16-bit little-endian PCM samples, before the gain is applied.
+    /// * `target_gain_db` - The gain the frame is about to be scaled by, before any backoff.
+    ///
+    /// # Returns
+    ///
+    /// * `f32` - The gain reduction to apply to this frame and the next ones, in dB.
+    pub fn process(&mut self, frame: &[u8], target_gain_db: f32) -> f32 {
+        let gain = libm::powf(10.0, (target_gain_db - self.backoff_db) / 20.0);
+        let mut samples = 0usize;
+        let mut clipped = 0usize;
+        let mut peak = 0.0f32;
+        for sample in frame.chunks_exact(2) {
+            let level = i16::from_le_bytes([sample[0], sample[1]]).unsigned_abs() as f32 * gain;
+            samples += 1;
+            if level >= CLIP_LEVEL {
+                clipped += 1;
+            }
+            peak = peak.max(level);
+        }
+
+        self.log_holdoff = self.log_holdoff.saturating_sub(1);
+        if samples == 0 {
+            return self.backoff_db;
+        }
+
+        let ratio = clipped as f32 / samples as f32;
+        if ratio > self.config.threshold_ratio {
+            self.backoff_db =
+                (self.backoff_db + self.config.backoff_step_db).min(self.config.max_backoff_db);
+            self.clipping_frames = self.clipping_frames.saturating_add(1);
+            if self.log_holdoff == 0 {
+                warn!(
+                    "Clipping on {} frames, last {} of {} samples, gain backed off by {} dB",
+                    self.clipping_frames, clipped, samples, self.backoff_db
+                );
+                self.clipping_frames = 0;
+                self.log_holdoff = LOG_INTERVAL_FRAMES;
+            }
+        } else if self.backoff_db > 0.0 {
+            // Restore only if the frame would still clear full scale with some margin
+            let headroom = self.config.recovery_step_db + self.config.hysteresis_db;
+            if peak * libm::powf(10.0, headroom / 20.0) < CLIP_LEVEL {
+                self.backoff_db = (self.backoff_db - self.config.recovery_step_db).max(0.0);
+            }
+        }
+
+        self.backoff_db
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The number of samples in a synthetic frame.
+    const FRAME_SAMPLES: usize = 256;
+
+    /// Returns a frame whose samples alternate between the given level and its opposite.
+    fn frame(level: i16) -> Vec<u8> {
+        (0..FRAME_SAMPLES)
+            .flat_map(|i| {
+                let sample = if i % 2 == 0 { level } else { -level };
+                sample.to_le_bytes()
+            })
+            .collect()
+    }
+
+    /// Returns a detector with the default settings, already backed off by the given steps.
+    fn backed_off(steps: usize) -> ClipDetector {
+        let mut detector = ClipDetector::new(ClipDetectorConfig::default());
+        for _ in 0..steps {
+            detector.process(&frame(i16::MAX), 0.0);
+        }
+        detector
+    }
+
+    #[test]
+    fn a_clipping_frame_backs_the_gain_off() {
+        let mut detector = ClipDetector::new(ClipDetectorConfig::default());
+
+        assert_eq!(detector.process(&frame(i16::MAX), 0.0), 1.0);
+        assert_eq!(detector.process(&frame(i16::MAX), 0.0), 2.0);
+        assert_eq!(detector.backoff_db(), 2.0);
+    }
+
+    #[test]
+    fn the_backoff_is_capped() {
+        let detector = backed_off(50);
+
+        assert_eq!(
+            detector.backoff_db(),
+            ClipDetectorConfig::default().max_backoff_db
+        );
+    }
+
+    #[test]
+    fn the_frame_is_judged_at_the_target_gain() {
+        let mut detector = ClipDetector::new(ClipDetectorConfig::default());
+
+        // Half scale is clean as is, but clips once boosted by 7 dB
+        assert_eq!(detector.process(&frame(16_000), 0.0), 0.0);
+        assert_eq!(detector.process(&frame(16_000), 7.0), 1.0);
+    }
+
+    #[test]
+    fn a_few_clipped_samples_below_the_threshold_ratio_are_tolerated() {
+        let mut detector = ClipDetector::new(ClipDetectorConfig::default());
+        let mut quiet = frame(1_000);
+        quiet[..2].copy_from_slice(&i16::MAX.to_le_bytes());
+
+        assert_eq!(detector.process(&quiet, 0.0), 0.0);
+    }
+
+    #[test]
+    fn a_clean_frame_restores_the_gain_slowly() {
+        let mut detector = backed_off(2);
+        let clean = frame(3_000);
+
+        let after_one = detector.process(&clean, 0.0);
+        assert!((1.94..1.96).contains(&after_one), "{}", after_one);
+
+        for _ in 0..100 {
+            detector.process(&clean, 0.0);
+        }
+        assert_eq!(detector.backoff_db(), 0.0);
+    }
+
+    #[test]
+    fn a_frame_without_headroom_keeps_the_backoff() {
+        let mut detector = backed_off(1);
+
+        // Just under full scale once backed off, but it would clip after a recovery step and
+        // the hysteresis margin
+        for _ in 0..100 {
+            assert_eq!(detector.process(&frame(i16::MAX), 0.0), 1.0);
+        }
+    }
+
+    #[test]
+    fn an_empty_frame_leaves_the_backoff_alone() {
+        let mut detector = backed_off(3);
+
+        assert_eq!(detector.process(&[], 0.0), 3.0);
+    }
+}
@@ -0,0 +1,210 @@
+#![no_std]
+#![no_main]
+
+use alloc::vec::Vec;
+
+/// The number of consecutive frames concealed before giving up and playing the gap as is.
+const MAX_CONCEALED_FRAMES: u32 = 8;
+
+/// The gain applied to the repeated audio over each concealed frame of `PlcStrategy::Fade`.
+const FADE_PER_FRAME: f32 = 0.5;
+
+/// Represents how the audio missing from a dropped A2DP frame is replaced.
+#[derive(Clone, Copy, Debug, Default, PartialEq, defmt::Format)]
+pub enum PlcStrategy {
+    /// The gap is left as filled by the jitter buffer, silence by default.
+    Silence,
+    /// The last complete frame is repeated.
+    Repeat,
+    /// The last complete frame is repeated while fading out, so long gaps decay smoothly.
+    #[default]
+    Fade,
+}
+
+/// `PacketLossConcealer` replaces the audio missing from frames dropped over a weak link.
+///
+/// Inserting silence in the middle of a stream produces a click, so the last complete frame is
+/// kept and replayed over the gap according to the strategy. Concealment stops after
+/// `MAX_CONCEALED_FRAMES` consecutive lost frames, since replaying stale audio any longer
+/// sounds worse than a gap.
+pub struct PacketLossConcealer {
+    /// How the missing audio is replaced.
+    strategy: PlcStrategy,
+    /// The last frame received in full, empty until one has been received.
+    last_frame: Vec<u8>,
+    /// The number of consecutive frames concealed since the last complete frame.
+    lost_frames: u32,
+}
+
+impl PacketLossConcealer {
+    /// Creates a new instance of `PacketLossConcealer`.
+    ///
+    /// # Arguments
+    ///
+    /// * `strategy` - How the missing audio is replaced.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `PacketLossConcealer` instance.
+    pub fn new(strategy: PlcStrategy) -> Self {
+        Self {
+            strategy,
+            last_frame: Vec::new(),
+            lost_frames: 0,
+        }
+    }
+
+    /// Returns how the missing audio is replaced.
+    pub fn strategy(&self) -> PlcStrategy {
+        self.strategy
+    }
+
+    /// Forgets the last complete frame, so nothing is replayed until the next one.
+    pub fn reset(&mut self) {
+        self.last_frame.clear();
+        self.lost_frames = 0;
+    }
+
+    /// Conceals the missing end of a frame, or keeps the frame if it was received in full.
+    ///
+    /// The frame holds 16-bit little-endian PCM samples. The missing samples are replaced by
+    /// the samples at the same position in the last complete frame, scaled by the fade gain.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame` - The frame drained from the jitter buffer, concealed in place.
+    /// * `received` - The number of bytes at the start of the frame holding received audio.
+    pub fn process(&mut self, frame: &mut [u8], received: usize) {
+        if received >= frame.len() {
+            self.last_frame.clear();
+            self.last_frame.extend_from_slice(frame);
+            self.lost_frames = 0;
+            return;
+        }
+
+        let concealable = self.strategy != PlcStrategy::Silence
+            && self.last_frame.len() == frame.len()
+            && self.lost_frames < MAX_CONCEALED_FRAMES;
+        if concealable {
+            let (start_gain, end_gain) = match self.strategy {
+                PlcStrategy::Fade => (
+                    libm::powf(FADE_PER_FRAME, self.lost_frames as f32),
+                    libm::powf(FADE_PER_FRAME, (self.lost_frames + 1) as f32),
+                ),
+                _ => (1.0, 1.0),
+            };
+
+            let start = received + received % 2;
+            let gap = &mut frame[start..];
+            let samples = (gap.len() / 2).max(1) as f32;
+            for (i, (sample, source)) in gap
+                .chunks_exact_mut(2)
+                .zip(self.last_frame[start..].chunks_exact(2))
+                .enumerate()
+            {
+                let gain = start_gain + (end_gain - start_gain) * i as f32 / samples;
+                let value = i16::from_le_bytes([source[0], source[1]]) as f32 * gain;
+                sample.copy_from_slice(&(value as i16).to_le_bytes());
+            }
+        }
+
+        self.lost_frames = self.lost_frames.saturating_add(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The length of the test frames, in bytes.
+    const FRAME_LEN: usize = 16;
+
+    /// Returns a frame holding the same sample everywhere.
+    fn constant_frame(sample: i16) -> Vec<u8> {
+        sample.to_le_bytes().repeat(FRAME_LEN / 2)
+    }
+
+    /// Returns the samples of a frame.
+    fn samples(frame: &[u8]) -> Vec<i16> {
+        frame
+            .chunks_exact(2)
+            .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect()
+    }
+
+    /// Returns a concealer that has received one complete frame of the given sample.
+    fn concealer(strategy: PlcStrategy, sample: i16) -> PacketLossConcealer {
+        let mut concealer = PacketLossConcealer::new(strategy);
+        concealer.process(&mut constant_frame(sample), FRAME_LEN);
+        concealer
+    }
+
+    #[test]
+    fn repeat_replays_the_last_complete_frame() {
+        let mut concealer = concealer(PlcStrategy::Repeat, 1000);
+        let mut lost = constant_frame(0);
+        let mut partial = constant_frame(0);
+        partial[..4].copy_from_slice(&constant_frame(-500)[..4]);
+
+        concealer.process(&mut lost, 0);
+        concealer.process(&mut partial, 4);
+
+        assert_eq!(lost, constant_frame(1000));
+        assert_eq!(
+            samples(&partial),
+            [-500, -500, 1000, 1000, 1000, 1000, 1000, 1000]
+        );
+    }
+
+    #[test]
+    fn fade_decays_over_consecutive_lost_frames() {
+        let mut concealer = concealer(PlcStrategy::Fade, 16_000);
+        let mut first = constant_frame(0);
+        let mut second = constant_frame(0);
+
+        concealer.process(&mut first, 0);
+        concealer.process(&mut second, 0);
+
+        let first = samples(&first);
+        let second = samples(&second);
+        assert_eq!(first[0], 16_000);
+        // Each frame halves the level, continuing where the previous one left off
+        for pair in first.windows(2).chain(second.windows(2)) {
+            assert!(pair[1] < pair[0], "{:?} {:?}", first, second);
+        }
+        assert!(first[7] > 8000);
+        assert_eq!(second[0], 8000);
+        assert!(second[7] > 4000 && second[7] < 8000);
+    }
+
+    #[test]
+    fn silence_leaves_the_gap_as_filled() {
+        let mut concealer = concealer(PlcStrategy::Silence, 1000);
+        let mut lost = constant_frame(0);
+
+        concealer.process(&mut lost, 0);
+
+        assert_eq!(lost, constant_frame(0));
+    }
+
+    #[test]
+    fn nothing_is_replayed_without_a_recent_complete_frame() {
+        let mut fresh = PacketLossConcealer::new(PlcStrategy::Repeat);
+        let mut lost = constant_frame(0);
+        fresh.process(&mut lost, 0);
+        assert_eq!(lost, constant_frame(0));
+
+        let mut concealer = concealer(PlcStrategy::Repeat, 1000);
+        for _ in 0..MAX_CONCEALED_FRAMES {
+            concealer.process(&mut constant_frame(0), 0);
+        }
+        // A long gap is no longer concealed, until the next complete frame
+        let mut lost = constant_frame(0);
+        concealer.process(&mut lost, 0);
+        assert_eq!(lost, constant_frame(0));
+
+        concealer.reset();
+        concealer.process(&mut lost, 0);
+        assert_eq!(lost, constant_frame(0));
+    }
+}
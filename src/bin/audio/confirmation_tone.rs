@@ -0,0 +1,206 @@
+#![no_std]
+#![no_main]
+
+use crate::audio::sample_format::AudioFormat;
+use alloc::vec::Vec;
+use core::f32::consts::PI;
+use embassy_time::Duration;
+
+/// Represents an event the user is notified of with a short beep.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub enum ConfirmationTone {
+    /// A device paired and connected successfully.
+    PairSuccess,
+    /// The connected device went away.
+    Disconnect,
+    /// An operation failed.
+    Error,
+}
+
+impl ConfirmationTone {
+    /// Returns the index of the tone in the tone table.
+    fn index(&self) -> usize {
+        match self {
+            ConfirmationTone::PairSuccess => 0,
+            ConfirmationTone::Disconnect => 1,
+            ConfirmationTone::Error => 2,
+        }
+    }
+
+    /// Returns the tone played for this event unless it has been customized.
+    pub fn default_spec(&self) -> ToneSpec {
+        let (frequency, duration_ms) = match self {
+            ConfirmationTone::PairSuccess => (880.0, 150),
+            ConfirmationTone::Disconnect => (440.0, 250),
+            ConfirmationTone::Error => (220.0, 400),
+        };
+
+        ToneSpec {
+            frequency,
+            duration: Duration::from_millis(duration_ms),
+            gain: 0.5,
+        }
+    }
+}
+
+/// `ToneSpec` describes a single sine beep.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct ToneSpec {
+    /// The frequency of the beep, in Hz.
+    pub frequency: f32,
+    /// The length of the beep.
+    pub duration: Duration,
+    /// The gain of the beep, between 0.0 and 1.0.
+    pub gain: f32,
+}
+
+impl ToneSpec {
+    /// Renders the beep as 16-bit PCM samples.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The format of the output, the beep playing on every channel.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<u8>` - The rendered frames.
+    pub fn render(&self, format: AudioFormat) -> Vec<u8> {
+        let frames = (format.sample_rate as u64 * self.duration.as_millis() / 1000) as usize;
+        let amplitude = self.gain.clamp(0.0, 1.0) * i16::MAX as f32;
+        let step = 2.0 * PI * self.frequency / format.sample_rate as f32;
+        let frame_len = format.bytes_per_frame();
+
+        let mut pcm = Vec::with_capacity(frames * frame_len);
+        let mut phase = 0.0f32;
+        for _ in 0..frames {
+            let sample = (libm::sinf(phase) * amplitude) as i16;
+            let start = pcm.len();
+            pcm.resize(start + frame_len, 0);
+            format.write_frame(&mut pcm[start..], sample);
+
+            phase += step;
+            if phase >= 2.0 * PI {
+                phase -= 2.0 * PI;
+            }
+        }
+
+        pcm
+    }
+}
+
+/// `ConfirmationTones` holds the beep played for each confirmation event.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConfirmationTones {
+    /// The beeps, indexed by `ConfirmationTone::index`.
+    specs: [ToneSpec; 3],
+}
+
+impl Default for ConfirmationTones {
+    fn default() -> Self {
+        Self {
+            specs: [
+                ConfirmationTone::PairSuccess.default_spec(),
+                ConfirmationTone::Disconnect.default_spec(),
+                ConfirmationTone::Error.default_spec(),
+            ],
+        }
+    }
+}
+
+impl ConfirmationTones {
+    /// Returns the beep played for the given event.
+    pub fn spec(&self, kind: ConfirmationTone) -> ToneSpec {
+        self.specs[kind.index()]
+    }
+
+    /// Customizes the beep played for the given event.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The event to customize.
+    /// * `spec` - The beep to play for it.
+    pub fn set_spec(&mut self, kind: ConfirmationTone, spec: ToneSpec) {
+        self.specs[kind.index()] = spec;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every confirmation event.
+    const TONES: [ConfirmationTone; 3] = [
+        ConfirmationTone::PairSuccess,
+        ConfirmationTone::Disconnect,
+        ConfirmationTone::Error,
+    ];
+
+    /// Returns the left channel samples of a little-endian stereo buffer.
+    fn left_channel(pcm: &[u8]) -> Vec<i16> {
+        pcm.chunks_exact(4)
+            .map(|frame| i16::from_le_bytes([frame[0], frame[1]]))
+            .collect()
+    }
+
+    /// Estimates the dominant frequency of a beep from its rising zero crossings, in Hz.
+    fn dominant_frequency(samples: &[i16], sample_rate: u32) -> f32 {
+        let crossings = samples
+            .windows(2)
+            .filter(|pair| pair[0] < 0 && pair[1] >= 0)
+            .count();
+        crossings as f32 * sample_rate as f32 / samples.len() as f32
+    }
+
+    #[test]
+    fn each_tone_has_the_expected_length() {
+        let format = AudioFormat::A2DP_STEREO;
+        let tones = ConfirmationTones::default();
+
+        for (kind, duration_ms) in TONES.into_iter().zip([150, 250, 400]) {
+            let pcm = tones.spec(kind).render(format);
+            let frames = 44_100 * duration_ms / 1000;
+            assert_eq!(pcm.len(), frames * format.bytes_per_frame(), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn each_tone_has_the_expected_dominant_frequency() {
+        let format = AudioFormat::A2DP_STEREO;
+        let tones = ConfirmationTones::default();
+
+        for (kind, frequency) in TONES.into_iter().zip([880.0, 440.0, 220.0]) {
+            let pcm = tones.spec(kind).render(format);
+            let measured = dominant_frequency(&left_channel(&pcm), format.sample_rate);
+            assert!(
+                (measured - frequency).abs() < frequency * 0.02,
+                "{:?}: {} Hz",
+                kind,
+                measured
+            );
+        }
+    }
+
+    #[test]
+    fn a_customized_tone_replaces_only_its_event() {
+        let format = AudioFormat::A2DP_STEREO;
+        let mut tones = ConfirmationTones::default();
+        let spec = ToneSpec {
+            frequency: 1000.0,
+            duration: Duration::from_millis(100),
+            gain: 0.25,
+        };
+
+        tones.set_spec(ConfirmationTone::Error, spec);
+
+        let samples = left_channel(&tones.spec(ConfirmationTone::Error).render(format));
+        assert_eq!(samples.len(), 4410);
+        let measured = dominant_frequency(&samples, format.sample_rate);
+        assert!((measured - 1000.0).abs() < 20.0, "{} Hz", measured);
+        let peak = samples.iter().map(|s| s.unsigned_abs()).max().unwrap();
+        assert!(peak <= (0.25 * i16::MAX as f32) as u16);
+        assert_eq!(
+            tones.spec(ConfirmationTone::PairSuccess),
+            ConfirmationTone::PairSuccess.default_spec()
+        );
+    }
+}
@@ -0,0 +1,211 @@
+#![no_std]
+#![no_main]
+
+use crate::audio::sample_format::AudioFormat;
+use embassy_time::Duration;
+
+/// `Crossfade` blends an outgoing stream into an incoming one over a fixed duration.
+///
+/// The outgoing stream is ramped down while the incoming one is ramped up, with weights that
+/// always sum to 1.0, so switching between sources does not produce a click. The weights
+/// advance per audio frame rather than per transmitted buffer, keeping the ramp continuous
+/// across buffer boundaries and identical on every channel of a frame.
+pub struct Crossfade {
+    /// The number of audio frames the crossfade lasts.
+    total_frames: u32,
+    /// The number of audio frames mixed since the crossfade started.
+    position: u32,
+    /// The number of interleaved channels per audio frame.
+    channels: usize,
+    /// Whether a crossfade is in progress.
+    active: bool,
+}
+
+impl Crossfade {
+    /// Creates a new instance of `Crossfade`, idle until started.
+    ///
+    /// # Arguments
+    ///
+    /// * `duration` - How long the crossfade lasts, zero to switch instantly.
+    /// * `format` - The format of the mixed streams.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `Crossfade` instance.
+    pub fn new(duration: Duration, format: AudioFormat) -> Self {
+        let total_frames = duration.as_micros() * format.sample_rate as u64 / 1_000_000;
+
+        Self {
+            total_frames: total_frames.min(u32::MAX as u64) as u32,
+            position: 0,
+            channels: format.channels.max(1) as usize,
+            active: false,
+        }
+    }
+
+    /// Starts a new crossfade from the beginning.
+    pub fn start(&mut self) {
+        self.position = 0;
+        self.active = self.total_frames > 0;
+    }
+
+    /// Returns true while a crossfade is in progress.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Returns the weights of both streams at the given audio frame of the crossfade.
+    ///
+    /// # Arguments
+    ///
+    /// * `position` - The number of audio frames since the crossfade started.
+    ///
+    /// # Returns
+    ///
+    /// * `(f32, f32)` - The weights of the outgoing and incoming streams, summing to 1.0.
+    pub fn weights_at(&self, position: u32) -> (f32, f32) {
+        if self.total_frames == 0 {
+            return (0.0, 1.0);
+        }
+
+        let incoming = (position as f32 / self.total_frames as f32).min(1.0);
+        (1.0 - incoming, incoming)
+    }
+
+    /// Mixes the outgoing stream into the incoming one, advancing the crossfade.
+    ///
+    /// Both buffers hold interleaved 16-bit little-endian PCM samples. Once the crossfade
+    /// completes, the remainder of the buffer is left holding the incoming stream only.
+    ///
+    /// # Arguments
+    ///
+    /// * `outgoing` - The frame of the stream being faded out.
+    /// * `incoming` - The frame of the stream being faded in, overwritten with the mix.
+    pub fn mix(&mut self, outgoing: &[u8], incoming: &mut [u8]) {
+        if !self.active {
+            return;
+        }
+
+        let frame_len = self.channels * 2;
+        for (out_frame, in_frame) in outgoing
+            .chunks_exact(frame_len)
+            .zip(incoming.chunks_exact_mut(frame_len))
+        {
+            if self.position >= self.total_frames {
+                self.active = false;
+                return;
+            }
+
+            let (out_weight, in_weight) = self.weights_at(self.position);
+            for (out_sample, in_sample) in
+                out_frame.chunks_exact(2).zip(in_frame.chunks_exact_mut(2))
+            {
+                let out_value = i16::from_le_bytes([out_sample[0], out_sample[1]]) as f32;
+                let in_value = i16::from_le_bytes([in_sample[0], in_sample[1]]) as f32;
+                let mixed = (out_value * out_weight + in_value * in_weight)
+                    .clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+
+                in_sample.copy_from_slice(&mixed.to_le_bytes());
+            }
+            self.position += 1;
+        }
+
+        if self.position >= self.total_frames {
+            self.active = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    /// A mono format with one frame per millisecond, so durations read as frame counts.
+    const SLOW_MONO: AudioFormat = AudioFormat {
+        sample_rate: 1000,
+        channels: 1,
+        ..AudioFormat::A2DP_STEREO
+    };
+
+    /// Returns a buffer holding the same sample everywhere.
+    fn constant(samples: usize, sample: i16) -> Vec<u8> {
+        sample.to_le_bytes().repeat(samples)
+    }
+
+    /// Returns the samples of a buffer.
+    fn samples(buffer: &[u8]) -> Vec<i16> {
+        buffer
+            .chunks_exact(2)
+            .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn the_weights_sum_to_one_across_the_crossfade() {
+        let crossfade = Crossfade::new(Duration::from_millis(100), SLOW_MONO);
+        let mut previous_incoming = 0.0;
+
+        for position in 0..=120 {
+            let (outgoing, incoming) = crossfade.weights_at(position);
+            assert!((outgoing + incoming - 1.0).abs() < 1e-6, "at {}", position);
+            assert!(incoming >= previous_incoming, "at {}", position);
+            previous_incoming = incoming;
+        }
+        assert_eq!(crossfade.weights_at(0), (1.0, 0.0));
+        assert_eq!(crossfade.weights_at(50), (0.5, 0.5));
+        assert_eq!(crossfade.weights_at(100), (0.0, 1.0));
+    }
+
+    #[test]
+    fn the_crossfade_removes_the_click_of_a_source_switch() {
+        let mut crossfade = Crossfade::new(Duration::from_millis(100), SLOW_MONO);
+        crossfade.start();
+        let outgoing = constant(40, 16_000);
+        let mut mixed = Vec::new();
+
+        // The ramp carries on across buffer boundaries
+        for _ in 0..3 {
+            let mut incoming = constant(40, -16_000);
+            crossfade.mix(&outgoing, &mut incoming);
+            mixed.extend(samples(&incoming));
+        }
+
+        assert!(!crossfade.is_active());
+        assert_eq!(mixed.first(), Some(&16_000));
+        assert!(mixed[100..].iter().all(|&sample| sample == -16_000));
+        // An instant switch would jump by 32000 at once
+        let largest_step = mixed
+            .windows(2)
+            .map(|pair| (pair[1] as i32 - pair[0] as i32).abs())
+            .max()
+            .unwrap();
+        assert!(largest_step <= 321, "step of {}", largest_step);
+    }
+
+    #[test]
+    fn both_channels_of_a_frame_share_the_same_weights() {
+        let mut crossfade = Crossfade::new(Duration::from_millis(10), AudioFormat::A2DP_STEREO);
+        crossfade.start();
+        let outgoing = constant(64, 10_000);
+        let mut incoming = constant(64, 0);
+
+        crossfade.mix(&outgoing, &mut incoming);
+
+        for frame in samples(&incoming).chunks_exact(2) {
+            assert_eq!(frame[0], frame[1]);
+        }
+    }
+
+    #[test]
+    fn a_zero_duration_switches_instantly() {
+        let mut crossfade = Crossfade::new(Duration::from_ticks(0), SLOW_MONO);
+        crossfade.start();
+        let mut incoming = constant(8, -16_000);
+
+        crossfade.mix(&constant(8, 16_000), &mut incoming);
+
+        assert!(!crossfade.is_active());
+        assert_eq!(incoming, constant(8, -16_000));
+    }
+}
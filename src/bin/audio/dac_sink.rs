@@ -0,0 +1,71 @@
+#![no_std]
+#![no_main]
+
+use crate::audio::audio_behavior::AudioBehavior;
+use crate::audio::audio_mapping::MAX_VOLUME;
+use crate::audio::behavior_sink::BehaviorSink;
+
+/// The highest code of the 12-bit DAC.
+const MAX_DAC_CODE: u16 = 0x0FFF;
+
+/// `DacSink` is a `BehaviorSink` driving an amplifier through an analog volume input.
+///
+/// The volume of each behavior is scaled to a 12-bit DAC code, which the board glue writes to
+/// the DAC channel wired to the volume input. Amplifiers with a single analog input have no bass
+/// control, so the bass and the digital gain of the behavior are not applied.
+pub struct DacSink {
+    /// The DAC code of the last applied volume.
+    code: u16,
+}
+
+impl DacSink {
+    /// Creates a new instance of `DacSink`.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `DacSink` instance, at zero volume.
+    pub fn new() -> Self {
+        Self { code: 0 }
+    }
+
+    /// Returns the DAC code of the last applied volume.
+    pub fn code(&self) -> u16 {
+        self.code
+    }
+}
+
+impl BehaviorSink for DacSink {
+    async fn apply(&mut self, behavior: &AudioBehavior) {
+        let volume = behavior.volume.min(MAX_VOLUME) as u32;
+        self.code = (volume * MAX_DAC_CODE as u32 / MAX_VOLUME as u32) as u16;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_futures::block_on;
+
+    /// Returns the DAC code a sink holds after applying the given volume.
+    fn code_for(volume: u8) -> u16 {
+        let mut sink = DacSink::new();
+        block_on(sink.apply(&AudioBehavior {
+            volume,
+            ..AudioBehavior::default()
+        }));
+        sink.code()
+    }
+
+    #[test]
+    fn the_volume_is_scaled_to_the_dac_range() {
+        assert_eq!(DacSink::new().code(), 0);
+        assert_eq!(code_for(0), 0);
+        assert_eq!(code_for(MAX_VOLUME), MAX_DAC_CODE);
+        assert_eq!(code_for(5), 1365);
+    }
+
+    #[test]
+    fn volumes_above_the_maximum_are_clamped() {
+        assert_eq!(code_for(MAX_VOLUME + 10), MAX_DAC_CODE);
+    }
+}
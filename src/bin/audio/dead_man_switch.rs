@@ -0,0 +1,188 @@
+#![no_std]
+#![no_main]
+
+use crate::audio::audio_behavior::AudioBehavior;
+use crate::audio::audio_mapping::neutral_behavior;
+use defmt::{info, warn};
+use embassy_time::{Duration, Instant};
+
+/// The age past which a vehicle reading is considered stale by default.
+pub const DEFAULT_STALE_AFTER: Duration = Duration::from_secs(3);
+
+/// `DeadManSwitch` reverts the audio to a neutral behavior once the vehicle data stops updating.
+///
+/// Without it, unplugging the adapter at high RPM would keep the audio boosted until the next
+/// power cycle.
+pub struct DeadManSwitch {
+    /// The age past which a reading is considered stale.
+    stale_after: Duration,
+    /// Whether the neutral behavior is currently applied.
+    tripped: bool,
+}
+
+impl DeadManSwitch {
+    /// Creates a new instance of `DeadManSwitch`.
+    ///
+    /// # Arguments
+    ///
+    /// * `stale_after` - The age past which a reading is considered stale.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `DeadManSwitch` instance, not tripped.
+    pub fn new(stale_after: Duration) -> Self {
+        Self {
+            stale_after,
+            tripped: false,
+        }
+    }
+
+    /// Returns the age past which a reading is considered stale.
+    pub fn stale_after(&self) -> Duration {
+        self.stale_after
+    }
+
+    /// Returns whether the neutral behavior is currently applied.
+    pub fn is_tripped(&self) -> bool {
+        self.tripped
+    }
+
+    /// Checks whether a reading taken at the given time is stale.
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp` - The time at which the reading was taken.
+    /// * `now` - The current time.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the reading is older than the staleness threshold.
+    pub fn is_stale(&self, timestamp: Instant, now: Instant) -> bool {
+        now.saturating_duration_since(timestamp) > self.stale_after
+    }
+
+    /// Replaces the mapped behavior with the neutral one if the reading it was mapped from is
+    /// stale.
+    ///
+    /// # Arguments
+    ///
+    /// * `behavior` - The behavior mapped from the reading.
+    /// * `timestamp` - The time at which the reading was taken.
+    /// * `now` - The current time.
+    ///
+    /// # Returns
+    ///
+    /// * `AudioBehavior` - The mapped behavior, or the neutral one if the reading is stale.
+    pub fn apply(
+        &mut self,
+        behavior: AudioBehavior,
+        timestamp: Instant,
+        now: Instant,
+    ) -> AudioBehavior {
+        if self.is_stale(timestamp, now) {
+            self.trip();
+            return neutral_behavior();
+        }
+
+        if self.tripped {
+            info!("Vehicle data resumed");
+            self.tripped = false;
+        }
+        behavior
+    }
+
+    /// Checks the last reading while no new one arrives.
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp` - The time at which the last reading was taken.
+    /// * `now` - The current time.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<AudioBehavior>` - The neutral behavior to apply if the switch just tripped, or
+    ///   `None` if nothing needs to change.
+    pub fn check(&mut self, timestamp: Instant, now: Instant) -> Option<AudioBehavior> {
+        if self.tripped || !self.is_stale(timestamp, now) {
+            return None;
+        }
+
+        self.trip();
+        Some(neutral_behavior())
+    }
+
+    /// Marks the switch as tripped, warning on the transition.
+    fn trip(&mut self) {
+        if !self.tripped {
+            warn!(
+                "No vehicle data for {} ms, reverting to neutral audio",
+                self.stale_after.as_millis()
+            );
+            self.tripped = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a boosted behavior, as mapped at high RPM.
+    fn boosted() -> AudioBehavior {
+        AudioBehavior {
+            volume: 13,
+            bass: 9,
+            ..AudioBehavior::default()
+        }
+    }
+
+    #[test]
+    fn a_reading_becomes_stale_past_the_threshold() {
+        let switch = DeadManSwitch::new(Duration::from_millis(500));
+        let taken = Instant::from_millis(1000);
+
+        assert!(!switch.is_stale(taken, Instant::from_millis(1400)));
+        assert!(!switch.is_stale(taken, Instant::from_millis(1500)));
+        assert!(switch.is_stale(taken, Instant::from_millis(1501)));
+        // A reading from the future, e.g. taken just after now was sampled, is fresh
+        assert!(!switch.is_stale(taken, Instant::from_millis(900)));
+    }
+
+    #[test]
+    fn apply_reverts_to_neutral_until_fresh_readings_resume() {
+        let mut switch = DeadManSwitch::new(Duration::from_millis(500));
+        let taken = Instant::from_millis(1000);
+
+        assert_eq!(
+            switch.apply(boosted(), taken, Instant::from_millis(1200)),
+            boosted()
+        );
+        assert_eq!(
+            switch.apply(boosted(), taken, Instant::from_millis(2000)),
+            neutral_behavior()
+        );
+        assert!(switch.is_tripped());
+
+        let resumed = Instant::from_millis(2100);
+        assert_eq!(
+            switch.apply(boosted(), resumed, Instant::from_millis(2100)),
+            boosted()
+        );
+        assert!(!switch.is_tripped());
+    }
+
+    #[test]
+    fn check_trips_once_when_readings_stop() {
+        let mut switch = DeadManSwitch::new(Duration::from_millis(500));
+        let last = Instant::from_millis(1000);
+
+        assert_eq!(switch.check(last, Instant::from_millis(1300)), None);
+        assert_eq!(
+            switch.check(last, Instant::from_millis(1600)),
+            Some(neutral_behavior())
+        );
+        // The neutral behavior is already applied, so nothing changes until readings resume
+        assert_eq!(switch.check(last, Instant::from_millis(3000)), None);
+        assert!(switch.is_tripped());
+    }
+}
@@ -0,0 +1,255 @@
+#![no_std]
+#![no_main]
+
+use crate::audio::sample_format::{AudioFormat, Endianness};
+use crate::audio::soft_clip::SoftClip;
+use core::f32::consts::PI;
+
+/// Number of firings per crankshaft revolution for each cylinder of a four-stroke engine.
+pub const FOUR_STROKE_FIRING_FACTOR: f32 = 0.5;
+
+/// `EngineTone` is a struct that synthesizes an engine-note overlay keyed to RPM.
+///
+/// It produces 16-bit PCM samples at the engine's firing frequency and mixes them into an
+/// outgoing audio buffer, the same tone on every channel.
+pub struct EngineTone {
+    /// The format of the audio stream the tone is mixed into.
+    format: AudioFormat,
+    /// The number of cylinders of the engine.
+    cylinders: u8,
+    /// The number of firings per crankshaft revolution for each cylinder.
+    firing_factor: f32,
+    /// The gain applied to the tone, between 0.0 and `gain_ceiling`.
+    gain: f32,
+    /// The maximum gain the tone may be mixed at.
+    gain_ceiling: f32,
+    /// The current phase of the oscillator, in radians.
+    phase: f32,
+    /// The saturation applied to the mix, or `None` to clip it at full scale.
+    ///
+    /// The mix is soft clipped by default, as hard clipping the engine note sounds harsh.
+    soft_clip: Option<SoftClip>,
+}
+
+impl EngineTone {
+    /// Creates a new instance of `EngineTone`.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The format of the audio stream the tone is mixed into.
+    /// * `cylinders` - The number of cylinders of the engine.
+    /// * `firing_factor` - The number of firings per crankshaft revolution for each cylinder.
+    /// * `gain_ceiling` - The maximum gain the tone may be mixed at, between 0.0 and 1.0.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `EngineTone` instance.
+    pub fn new(format: AudioFormat, cylinders: u8, firing_factor: f32, gain_ceiling: f32) -> Self {
+        let gain_ceiling = gain_ceiling.clamp(0.0, 1.0);
+
+        Self {
+            format,
+            cylinders,
+            firing_factor,
+            gain: gain_ceiling,
+            gain_ceiling,
+            phase: 0.0,
+            soft_clip: Some(SoftClip::default()),
+        }
+    }
+
+    /// Sets the gain applied to the tone.
+    ///
+    /// The gain is clamped to the configured ceiling.
+    ///
+    /// # Arguments
+    ///
+    /// * `gain` - The new gain for the tone.
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain.clamp(0.0, self.gain_ceiling);
+    }
+
+    /// Sets the saturation applied when the tone pushes the mix past full scale.
+    ///
+    /// The default `SoftClip` bends the mix smoothly towards full scale.
+    ///
+    /// # Arguments
+    ///
+    /// * `soft_clip` - The saturation stage, or `None` to clip the mix at full scale.
+    pub fn set_soft_clip(&mut self, soft_clip: Option<SoftClip>) {
+        self.soft_clip = soft_clip;
+    }
+
+    /// Computes the frequency of the engine note for the given RPM.
+    ///
+    /// # Arguments
+    ///
+    /// * `rpm` - The engine speed, in revolutions per minute.
+    ///
+    /// # Returns
+    ///
+    /// * `f32` - The frequency of the engine note, in Hz.
+    pub fn frequency(&self, rpm: u16) -> f32 {
+        rpm as f32 / 60.0 * self.cylinders as f32 * self.firing_factor
+    }
+
+    /// Generates the next tone sample.
+    ///
+    /// # Arguments
+    ///
+    /// * `frequency` - The frequency of the tone, in Hz.
+    ///
+    /// # Returns
+    ///
+    /// * `f32` - The next sample, between -1.0 and 1.0.
+    fn next_sample(&mut self, frequency: f32) -> f32 {
+        let sample = libm::sinf(self.phase);
+
+        self.phase += 2.0 * PI * frequency / self.format.sample_rate.max(1) as f32;
+        if self.phase >= 2.0 * PI {
+            self.phase -= 2.0 * PI;
+        }
+
+        sample
+    }
+
+    /// Mixes the engine tone into the given audio buffer.
+    ///
+    /// The buffer holds frames in the format of the stream. The oscillator advances once per
+    /// frame and the tone is added to every channel, so its pitch does not depend on the number
+    /// of channels. The mixed output goes through the soft clip stage if one is set, and is
+    /// otherwise clipped at full scale instead of wrapping around.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - The audio buffer the tone will be mixed into.
+    /// * `rpm` - The engine speed, in revolutions per minute.
+    pub fn mix_into(&mut self, buffer: &mut [u8], rpm: u16) {
+        let frequency = self.frequency(rpm);
+        let amplitude = self.gain * i16::MAX as f32;
+
+        let frame_len = self.format.bytes_per_frame().max(2);
+
+        for frame in buffer.chunks_exact_mut(frame_len) {
+            let tone = self.next_sample(frequency) * amplitude;
+            for channel in frame.chunks_exact_mut(2) {
+                let bytes = [channel[0], channel[1]];
+                let sample = match self.format.endianness {
+                    Endianness::Little => i16::from_le_bytes(bytes),
+                    Endianness::Big => i16::from_be_bytes(bytes),
+                } as f32;
+                let mixed = match &self.soft_clip {
+                    Some(soft_clip) => soft_clip.saturate_pcm(sample + tone),
+                    None => (sample + tone).clamp(i16::MIN as f32, i16::MAX as f32) as i16,
+                };
+
+                channel.copy_from_slice(&match self.format.endianness {
+                    Endianness::Little => mixed.to_le_bytes(),
+                    Endianness::Big => mixed.to_be_bytes(),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A four-cylinder engine mixed into a 44.1 kHz stream at up to half scale.
+    fn tone() -> EngineTone {
+        EngineTone::new(AudioFormat::A2DP_STEREO, 4, FOUR_STROKE_FIRING_FACTOR, 0.5)
+    }
+
+    /// Returns the samples of a little-endian buffer.
+    fn samples(buffer: &[u8]) -> Vec<i16> {
+        buffer
+            .chunks_exact(2)
+            .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn the_frequency_scales_linearly_with_the_rpm() {
+        let tone = tone();
+
+        assert_eq!(tone.frequency(0), 0.0);
+        // Four cylinders firing every other revolution, twice per revolution
+        assert_eq!(tone.frequency(3000), 100.0);
+        assert_eq!(tone.frequency(6000), 2.0 * tone.frequency(3000));
+        assert_eq!(
+            tone.frequency(1500) + tone.frequency(4500),
+            2.0 * tone.frequency(3000)
+        );
+    }
+
+    #[test]
+    fn the_gain_is_clamped_to_the_ceiling() {
+        let mut tone = tone();
+        tone.set_soft_clip(None);
+        tone.set_gain(2.0);
+        let mut buffer = [0u8; 4 * 441];
+
+        tone.mix_into(&mut buffer, 3000);
+
+        let peak = samples(&buffer).iter().map(|s| s.unsigned_abs()).max();
+        let ceiling = (0.5 * i16::MAX as f32) as u16;
+        assert!(peak <= Some(ceiling), "{:?}", peak);
+        // A whole period of the 100 Hz note fits the buffer, so it reaches its peak
+        assert!(peak >= Some(ceiling - 100), "{:?}", peak);
+    }
+
+    #[test]
+    fn a_zero_gain_leaves_the_stream_untouched() {
+        let mut tone = tone();
+        tone.set_gain(0.0);
+        tone.set_soft_clip(None);
+        let mut buffer = [0x12u8; 256];
+
+        tone.mix_into(&mut buffer, 3000);
+
+        assert!(buffer.iter().all(|&byte| byte == 0x12));
+    }
+
+    #[test]
+    fn a_loud_stream_is_clipped_instead_of_wrapping() {
+        let mut tone = tone();
+        tone.set_soft_clip(None);
+        let mut buffer: Vec<u8> = core::iter::repeat(i16::MAX.to_le_bytes())
+            .take(2 * 441)
+            .flatten()
+            .collect();
+
+        tone.mix_into(&mut buffer, 3000);
+
+        // The positive half of the note would wrap a full-scale sample around to negative
+        assert!(samples(&buffer).iter().all(|&s| s > 0));
+        assert!(samples(&buffer).contains(&i16::MAX));
+    }
+
+    #[test]
+    fn the_soft_clip_keeps_the_mix_below_full_scale() {
+        let mut tone = tone();
+        let mut buffer: Vec<u8> = core::iter::repeat(i16::MAX.to_le_bytes())
+            .take(2 * 441)
+            .flatten()
+            .collect();
+
+        tone.mix_into(&mut buffer, 3000);
+
+        assert!(samples(&buffer).iter().all(|&s| s > 0 && s < i16::MAX));
+    }
+
+    #[test]
+    fn every_channel_of_a_frame_gets_the_same_tone() {
+        let mut tone = tone();
+        let mut buffer = [0u8; 4 * 100];
+
+        tone.mix_into(&mut buffer, 3000);
+
+        assert!(buffer.iter().any(|&byte| byte != 0));
+        for frame in buffer.chunks_exact(4) {
+            assert_eq!(frame[..2], frame[2..]);
+        }
+    }
+}
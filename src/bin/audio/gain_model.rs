@@ -0,0 +1,154 @@
+#![no_std]
+#![no_main]
+
+use crate::audio::audio_mapping::MAX_VOLUME;
+
+/// The number of volume levels of the CSR8645 module, including the muted level 0.
+pub const VOLUME_LEVELS: usize = MAX_VOLUME as usize + 1;
+
+/// The output level of each CSR8645 volume level relative to full scale, in dB.
+///
+/// The steps are not linear in dB: they are coarse at the bottom of the range and close to
+/// 3 dB apart near the top, where most of the listening happens. Level 0 mutes the output.
+pub const CSR8645_VOLUME_TABLE: [f32; VOLUME_LEVELS] = [
+    f32::NEG_INFINITY,
+    -45.0,
+    -40.0,
+    -36.0,
+    -32.0,
+    -28.5,
+    -25.0,
+    -22.0,
+    -19.0,
+    -16.0,
+    -13.0,
+    -10.5,
+    -8.0,
+    -5.5,
+    -3.0,
+    0.0,
+];
+
+/// `GainModel` converts a gain in dB into the discrete volume levels of the module.
+///
+/// A gain is applied relative to a reference level: the level whose output is closest to the
+/// output of the reference level plus the gain is chosen. A gain of 0 dB keeps the reference
+/// level, and a gain never mutes the output or unmutes a muted one.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct GainModel {
+    /// The output level of each volume level relative to full scale, in dB.
+    table: [f32; VOLUME_LEVELS],
+}
+
+impl Default for GainModel {
+    fn default() -> Self {
+        Self {
+            table: CSR8645_VOLUME_TABLE,
+        }
+    }
+}
+
+impl GainModel {
+    /// Creates a new instance of `GainModel` from a calibrated table.
+    ///
+    /// # Arguments
+    ///
+    /// * `table` - The output level of each volume level relative to full scale, in dB.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Self>` - The new `GainModel` instance, or `None` if the levels above 0 are not
+    ///   strictly increasing.
+    pub fn new(table: [f32; VOLUME_LEVELS]) -> Option<Self> {
+        if !table[1..].windows(2).all(|pair| pair[0] < pair[1]) {
+            return None;
+        }
+
+        Some(Self { table })
+    }
+
+    /// Returns the output level of a volume level relative to full scale, in dB.
+    ///
+    /// # Arguments
+    ///
+    /// * `level` - The volume level, clamped to `MAX_VOLUME`.
+    pub fn level_db(&self, level: u8) -> f32 {
+        self.table[level.min(MAX_VOLUME) as usize]
+    }
+
+    /// Returns the volume level producing a gain relative to a reference level.
+    ///
+    /// # Arguments
+    ///
+    /// * `reference` - The volume level the gain applies to.
+    /// * `gain_db` - The gain, in dB.
+    ///
+    /// # Returns
+    ///
+    /// * `u8` - The volume level closest to the reference shifted by the gain.
+    pub fn apply(&self, reference: u8, gain_db: f32) -> u8 {
+        let reference = reference.min(MAX_VOLUME);
+        if reference == 0 || gain_db == 0.0 {
+            return reference;
+        }
+
+        let target = self.level_db(reference) + gain_db;
+        (1..=MAX_VOLUME)
+            .min_by(|&a, &b| {
+                let distance_a = libm::fabsf(self.level_db(a) - target);
+                let distance_b = libm::fabsf(self.level_db(b) - target);
+                distance_a.total_cmp(&distance_b)
+            })
+            .unwrap_or(reference)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_db_keeps_the_reference_level() {
+        let model = GainModel::default();
+
+        for level in 0..=MAX_VOLUME {
+            assert_eq!(model.apply(level, 0.0), level);
+        }
+    }
+
+    #[test]
+    fn six_db_moves_by_the_documented_steps() {
+        let model = GainModel::default();
+
+        // Around the middle of the range the steps are 3 dB apart
+        assert_eq!(model.apply(8, 6.0), 10);
+        assert_eq!(model.apply(8, -6.0), 6);
+        // Near the top they are 2.5 dB apart, so 6 dB rounds to the nearest level
+        assert_eq!(model.apply(12, 6.0), 14);
+        assert_eq!(model.apply(12, -6.0), 10);
+    }
+
+    #[test]
+    fn gains_saturate_at_the_ends_without_muting() {
+        let model = GainModel::default();
+
+        assert_eq!(model.apply(14, 20.0), MAX_VOLUME);
+        assert_eq!(model.apply(5, -60.0), 1);
+        assert_eq!(model.apply(0, 6.0), 0);
+    }
+
+    #[test]
+    fn a_supplied_table_overrides_the_default_steps() {
+        let mut table = [0.0; VOLUME_LEVELS];
+        for (level, db) in table.iter_mut().enumerate() {
+            *db = (level as f32 - MAX_VOLUME as f32) * 1.5;
+        }
+        let model = GainModel::new(table).unwrap();
+
+        assert_eq!(model.apply(8, 6.0), 12);
+        assert_eq!(model.apply(8, -6.0), 4);
+
+        table[4] = table[3];
+        assert_eq!(GainModel::new(table), None);
+    }
+}
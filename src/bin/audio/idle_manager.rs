@@ -0,0 +1,150 @@
+#![no_std]
+#![no_main]
+
+use embassy_time::{Duration, Instant};
+
+/// Represents a change of the amplifier power requested by the `IdleManager`.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub enum AmpCommand {
+    /// Power the amplifier up.
+    Enable,
+    /// Power the amplifier down.
+    Disable,
+}
+
+/// `IdleManager` powers the amplifier down while the vehicle is stopped and nothing is playing.
+///
+/// At a stoplight with the phone paused the amplifier only produces hiss and draws power. Once
+/// both conditions have held for the timeout the amplifier is disabled, and it is re-enabled as
+/// soon as either audio or movement returns.
+pub struct IdleManager {
+    /// The time the vehicle must stay idle before the amplifier is disabled.
+    timeout: Duration,
+    /// The time at which the current idle period started, if any.
+    idle_since: Option<Instant>,
+    /// Whether the amplifier is currently enabled.
+    amp_enabled: bool,
+}
+
+impl IdleManager {
+    /// Creates a new instance of `IdleManager`, starting with the amplifier enabled.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The time the vehicle must stay idle before the amplifier is disabled.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `IdleManager` instance.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            idle_since: None,
+            amp_enabled: true,
+        }
+    }
+
+    /// Sets the time the vehicle must stay idle before the amplifier is disabled.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Returns whether the amplifier is currently enabled.
+    pub fn is_amp_enabled(&self) -> bool {
+        self.amp_enabled
+    }
+
+    /// Feeds the latest vehicle and audio state.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current time.
+    /// * `speed` - The vehicle speed, in km/h.
+    /// * `audio_active` - Whether audio is currently playing without underruns.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<AmpCommand>` - The change to apply to the amplifier, or `None` if it should not
+    ///   change.
+    pub fn update(&mut self, now: Instant, speed: u8, audio_active: bool) -> Option<AmpCommand> {
+        if speed > 0 || audio_active {
+            self.idle_since = None;
+            if !self.amp_enabled {
+                self.amp_enabled = true;
+                return Some(AmpCommand::Enable);
+            }
+            return None;
+        }
+
+        let idle_since = *self.idle_since.get_or_insert(now);
+        if self.amp_enabled && now - idle_since >= self.timeout {
+            self.amp_enabled = false;
+            return Some(AmpCommand::Disable);
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The idle timeout used by the tests.
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Returns the instant the given number of seconds into the test.
+    fn at(secs: u64) -> Instant {
+        Instant::from_secs(secs)
+    }
+
+    #[test]
+    fn the_amp_is_disabled_once_idle_past_the_timeout() {
+        let mut idle = IdleManager::new(TIMEOUT);
+
+        assert_eq!(idle.update(at(0), 0, false), None);
+        assert_eq!(idle.update(at(29), 0, false), None);
+        assert!(idle.is_amp_enabled());
+        assert_eq!(idle.update(at(30), 0, false), Some(AmpCommand::Disable));
+        assert!(!idle.is_amp_enabled());
+        // The command is only issued once
+        assert_eq!(idle.update(at(60), 0, false), None);
+    }
+
+    #[test]
+    fn the_amp_is_re_enabled_as_soon_as_audio_or_movement_returns() {
+        let mut idle = IdleManager::new(TIMEOUT);
+        idle.update(at(0), 0, false);
+        idle.update(at(30), 0, false);
+
+        assert_eq!(idle.update(at(31), 0, true), Some(AmpCommand::Enable));
+        assert!(idle.is_amp_enabled());
+
+        idle.update(at(32), 0, false);
+        idle.update(at(62), 0, false);
+        assert_eq!(idle.update(at(63), 5, false), Some(AmpCommand::Enable));
+        assert_eq!(idle.update(at(64), 5, false), None);
+    }
+
+    #[test]
+    fn audio_or_movement_restarts_the_idle_period() {
+        let mut idle = IdleManager::new(TIMEOUT);
+        idle.update(at(0), 0, false);
+
+        // A short burst of audio before the timeout resets the countdown
+        assert_eq!(idle.update(at(20), 0, true), None);
+        assert_eq!(idle.update(at(21), 0, false), None);
+        assert_eq!(idle.update(at(45), 0, false), None);
+        assert_eq!(idle.update(at(51), 0, false), Some(AmpCommand::Disable));
+    }
+
+    #[test]
+    fn the_timeout_is_configurable() {
+        let mut idle = IdleManager::new(TIMEOUT);
+        idle.set_timeout(Duration::from_secs(5));
+
+        idle.update(at(0), 0, false);
+
+        assert_eq!(idle.update(at(5), 0, false), Some(AmpCommand::Disable));
+    }
+}
@@ -0,0 +1,219 @@
+#![no_std]
+#![no_main]
+
+use alloc::collections::VecDeque;
+
+/// Represents what is played when the buffer runs dry.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub enum UnderrunFill {
+    /// Silence is played.
+    Silence,
+    /// The last sample received is held, avoiding a click when the stream resumes.
+    HoldLastSample,
+}
+
+/// `JitterBuffer` smooths out a bursty audio stream into a steady one.
+///
+/// Incoming 16-bit little-endian PCM is queued until `depth` bytes are available, then drained
+/// in fixed-size frames. When the source stalls and the queue runs dry, the missing samples are
+/// filled, an underrun is counted and the buffer pre-fills again before draining.
+pub struct JitterBuffer {
+    /// The queued audio bytes.
+    pending: VecDeque<u8>,
+    /// The number of bytes queued before draining starts.
+    depth: usize,
+    /// What is played when the buffer runs dry.
+    fill: UnderrunFill,
+    /// Whether the buffer has been pre-filled and is draining.
+    primed: bool,
+    /// The last sample drained, used by `UnderrunFill::HoldLastSample`.
+    last_sample: [u8; 2],
+    /// The number of underruns since the buffer was created.
+    underruns: u32,
+}
+
+impl JitterBuffer {
+    /// Creates a new instance of `JitterBuffer`.
+    ///
+    /// A deeper buffer rides out longer stalls at the cost of more latency.
+    ///
+    /// # Arguments
+    ///
+    /// * `depth` - The number of bytes queued before draining starts.
+    /// * `fill` - What is played when the buffer runs dry.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `JitterBuffer` instance.
+    pub fn new(depth: usize, fill: UnderrunFill) -> Self {
+        Self {
+            pending: VecDeque::with_capacity(depth * 2),
+            depth,
+            fill,
+            primed: false,
+            last_sample: [0; 2],
+            underruns: 0,
+        }
+    }
+
+    /// Queues received audio.
+    ///
+    /// The queue holds at most twice the depth, dropping the oldest bytes so latency stays
+    /// bounded if the source runs faster than the drain.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The received audio bytes.
+    pub fn push(&mut self, data: &[u8]) {
+        self.pending.extend(data.iter().copied());
+
+        let capacity = self.depth * 2;
+        if self.pending.len() > capacity {
+            let excess = self.pending.len() - capacity;
+            self.pending.drain(..excess);
+        }
+
+        if self.pending.len() >= self.depth {
+            self.primed = true;
+        }
+    }
+
+    /// Drains one frame of audio.
+    ///
+    /// While pre-filling the frame is silent. Once draining, missing samples are filled
+    /// according to the fill policy and count as one underrun.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame` - The buffer the drained audio is written to.
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The number of bytes at the start of the frame holding received audio, the
+    ///   rest being filled.
+    pub fn pop_into(&mut self, frame: &mut [u8]) -> usize {
+        if !self.primed {
+            frame.fill(0);
+            return 0;
+        }
+
+        let available = self.pending.len().min(frame.len());
+        for (byte, queued) in frame.iter_mut().zip(self.pending.drain(..available)) {
+            *byte = queued;
+        }
+        if available >= 2 {
+            let end = available - available % 2;
+            self.last_sample = [frame[end - 2], frame[end - 1]];
+        }
+
+        if available < frame.len() {
+            self.underruns += 1;
+            self.primed = false;
+
+            let sample = match self.fill {
+                UnderrunFill::Silence => [0; 2],
+                UnderrunFill::HoldLastSample => self.last_sample,
+            };
+            for (i, byte) in frame[available..].iter_mut().enumerate() {
+                *byte = sample[(available + i) % 2];
+            }
+        }
+
+        available
+    }
+
+    /// Discards the queued audio and waits for a new pre-fill.
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.primed = false;
+    }
+
+    /// Returns whether the buffer has been pre-filled and is draining received audio.
+    pub fn is_primed(&self) -> bool {
+        self.primed
+    }
+
+    /// Returns the number of underruns since the buffer was created.
+    pub fn underruns(&self) -> u32 {
+        self.underruns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns `count` bytes of 16-bit samples holding the same value.
+    fn samples(count: usize, sample: i16) -> Vec<u8> {
+        sample.to_le_bytes().repeat(count / 2)
+    }
+
+    #[test]
+    fn frames_are_silent_until_the_buffer_is_pre_filled() {
+        let mut buffer = JitterBuffer::new(8, UnderrunFill::Silence);
+        let mut frame = [0xAA; 4];
+
+        buffer.push(&samples(4, 100));
+        assert_eq!(buffer.pop_into(&mut frame), 0);
+        assert_eq!(frame, [0; 4]);
+        assert!(!buffer.is_primed());
+
+        buffer.push(&samples(4, 100));
+        assert!(buffer.is_primed());
+        assert_eq!(buffer.pop_into(&mut frame), 4);
+        assert_eq!(frame[..], samples(4, 100)[..]);
+        assert_eq!(buffer.underruns(), 0);
+    }
+
+    #[test]
+    fn a_stall_inserts_silence_and_counts_an_underrun() {
+        let mut buffer = JitterBuffer::new(4, UnderrunFill::Silence);
+        let mut frame = [0u8; 4];
+        buffer.push(&samples(6, 100));
+        buffer.pop_into(&mut frame);
+
+        // Only one sample is left for a two-sample frame
+        assert_eq!(buffer.pop_into(&mut frame), 2);
+        assert_eq!(frame, [100, 0, 0, 0]);
+        assert_eq!(buffer.underruns(), 1);
+        // Pre-filling again, so the stall does not count twice
+        assert_eq!(buffer.pop_into(&mut frame), 0);
+        assert_eq!(buffer.underruns(), 1);
+        assert!(!buffer.is_primed());
+    }
+
+    #[test]
+    fn a_stall_can_hold_the_last_sample() {
+        let mut buffer = JitterBuffer::new(4, UnderrunFill::HoldLastSample);
+        let mut frame = [0u8; 6];
+        buffer.push(&samples(4, -300));
+
+        assert_eq!(buffer.pop_into(&mut frame), 4);
+        assert_eq!(frame[..], samples(6, -300)[..]);
+        assert_eq!(buffer.underruns(), 1);
+    }
+
+    #[test]
+    fn a_source_running_fast_drops_the_oldest_audio() {
+        let mut buffer = JitterBuffer::new(4, UnderrunFill::Silence);
+        let mut frame = [0u8; 8];
+        buffer.push(&samples(8, 1));
+        buffer.push(&samples(4, 2));
+
+        assert_eq!(buffer.pop_into(&mut frame), 8);
+        assert_eq!(frame, [1, 0, 1, 0, 2, 0, 2, 0]);
+    }
+
+    #[test]
+    fn clear_waits_for_a_new_pre_fill() {
+        let mut buffer = JitterBuffer::new(4, UnderrunFill::Silence);
+        let mut frame = [0xAA; 4];
+        buffer.push(&samples(8, 100));
+
+        buffer.clear();
+
+        assert!(!buffer.is_primed());
+        assert_eq!(buffer.pop_into(&mut frame), 0);
+        assert_eq!(frame, [0; 4]);
+    }
+}
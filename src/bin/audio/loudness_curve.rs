@@ -0,0 +1,157 @@
+#![no_std]
+#![no_main]
+
+/// The speed increase, in km/h, that raises the volume by one level on the linear curve.
+const SPEED_PER_VOLUME_STEP: f32 = 20.0;
+
+/// The speed, in km/h, around which the logarithmic curve bends.
+const LOG_REFERENCE_SPEED: f32 = 30.0;
+
+/// The scale of the logarithmic curve, chosen so both curves meet near 200 km/h.
+const LOG_GAIN: f32 = 5.0;
+
+/// Represents the shape of the speed to volume compensation curve.
+///
+/// Road and wind noise rise quickly at low speed and flatten out on the highway, which a linear
+/// curve under-compensates in town and over-compensates at speed.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub enum LoudnessCurve {
+    /// One volume level per 20 km/h.
+    Linear,
+    /// A curve rising fast at low speed and flattening out at high speed.
+    Logarithmic,
+    /// Linear interpolation between `(speed, volume_delta)` points, sorted by speed.
+    ///
+    /// Speeds outside the table use the delta of the nearest point.
+    Table(&'static [(u8, f32)]),
+}
+
+impl Default for LoudnessCurve {
+    fn default() -> Self {
+        LoudnessCurve::Linear
+    }
+}
+
+impl LoudnessCurve {
+    /// Computes the volume offset compensating road noise at the given speed.
+    ///
+    /// # Arguments
+    ///
+    /// * `speed` - The vehicle speed, in km/h.
+    ///
+    /// # Returns
+    ///
+    /// * `f32` - The volume offset, in levels.
+    pub fn volume_offset(&self, speed: u8) -> f32 {
+        let speed = speed as f32;
+
+        match self {
+            LoudnessCurve::Linear => speed / SPEED_PER_VOLUME_STEP,
+            LoudnessCurve::Logarithmic => LOG_GAIN * libm::logf(1.0 + speed / LOG_REFERENCE_SPEED),
+            LoudnessCurve::Table(points) => interpolate(points, speed),
+        }
+    }
+}
+
+/// Interpolates linearly between the points of a lookup table.
+///
+/// # Arguments
+///
+/// * `points` - The `(speed, volume_delta)` points, sorted by speed.
+/// * `speed` - The vehicle speed, in km/h.
+///
+/// # Returns
+///
+/// * `f32` - The interpolated volume delta, or 0.0 if the table is empty.
+fn interpolate(points: &[(u8, f32)], speed: f32) -> f32 {
+    let (Some(&(first_speed, first_delta)), Some(&(_, last_delta))) =
+        (points.first(), points.last())
+    else {
+        return 0.0;
+    };
+
+    if speed <= first_speed as f32 {
+        return first_delta;
+    }
+
+    for window in points.windows(2) {
+        let (low_speed, low_delta) = (window[0].0 as f32, window[0].1);
+        let (high_speed, high_delta) = (window[1].0 as f32, window[1].1);
+
+        if speed <= high_speed {
+            if high_speed <= low_speed {
+                return high_delta;
+            }
+            let t = (speed - low_speed) / (high_speed - low_speed);
+            return low_delta + t * (high_delta - low_delta);
+        }
+    }
+
+    last_delta
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A table boosting quickly in town and gently on the highway.
+    static TABLE: [(u8, f32); 3] = [(0, 0.0), (50, 2.0), (100, 3.0)];
+
+    #[test]
+    fn both_curves_start_flat_at_standstill() {
+        assert_eq!(LoudnessCurve::Linear.volume_offset(0), 0.0);
+        assert_eq!(LoudnessCurve::Logarithmic.volume_offset(0), 0.0);
+    }
+
+    #[test]
+    fn the_logarithmic_curve_boosts_more_in_town_and_less_past_the_highway() {
+        let linear = |speed| LoudnessCurve::Linear.volume_offset(speed);
+        let log = |speed| LoudnessCurve::Logarithmic.volume_offset(speed);
+
+        // Low and mid speeds
+        assert!(log(10) > linear(10));
+        assert!(log(60) > linear(60));
+        // The curves meet near 200 km/h
+        assert!((log(200) - linear(200)).abs() < 0.5);
+        assert!(log(250) < linear(250));
+        assert_eq!(linear(60), 3.0);
+    }
+
+    #[test]
+    fn the_logarithmic_curve_flattens_out() {
+        let log = |speed| LoudnessCurve::Logarithmic.volume_offset(speed);
+
+        assert!(log(40) - log(20) > log(140) - log(120));
+        assert!(log(140) - log(120) > log(240) - log(220));
+    }
+
+    #[test]
+    fn the_table_is_interpolated_between_points() {
+        let table = LoudnessCurve::Table(&TABLE);
+
+        assert_eq!(table.volume_offset(0), 0.0);
+        assert_eq!(table.volume_offset(25), 1.0);
+        assert_eq!(table.volume_offset(50), 2.0);
+        assert_eq!(table.volume_offset(75), 2.5);
+        assert_eq!(table.volume_offset(100), 3.0);
+    }
+
+    #[test]
+    fn speeds_outside_the_table_use_the_nearest_point() {
+        static STARTS_MOVING: [(u8, f32); 2] = [(20, 1.0), (80, 4.0)];
+        let table = LoudnessCurve::Table(&STARTS_MOVING);
+
+        assert_eq!(table.volume_offset(0), 1.0);
+        assert_eq!(table.volume_offset(200), 4.0);
+        assert_eq!(LoudnessCurve::Table(&[]).volume_offset(50), 0.0);
+    }
+
+    #[test]
+    fn a_repeated_speed_steps_the_delta() {
+        static STEP: [(u8, f32); 3] = [(0, 0.0), (50, 1.0), (50, 3.0)];
+        let table = LoudnessCurve::Table(&STEP);
+
+        assert_eq!(table.volume_offset(50), 1.0);
+        assert_eq!(table.volume_offset(51), 3.0);
+    }
+}
@@ -1,2 +1,26 @@
+pub mod audio_behavior;
 pub mod audio_controller;
+pub mod audio_mapping;
+pub mod audio_preset;
 pub mod audio_service;
+pub mod audio_source;
+pub mod behavior_sink;
+pub mod behavior_smoother;
+pub mod clip_detector;
+pub mod concealment;
+pub mod confirmation_tone;
+pub mod crossfade;
+pub mod dac_sink;
+pub mod dead_man_switch;
+pub mod engine_tone;
+pub mod gain_model;
+pub mod idle_manager;
+pub mod jitter_buffer;
+pub mod loudness_curve;
+pub mod redline_warning;
+pub mod sample_format;
+pub mod soft_clip;
+pub mod sweep;
+pub mod thermal_guard;
+pub mod volume_schedule;
+pub mod vu_meter;
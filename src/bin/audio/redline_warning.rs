@@ -0,0 +1,300 @@
+#![no_std]
+#![no_main]
+
+use crate::audio::sample_format::AudioFormat;
+use core::f32::consts::PI;
+
+/// `RedlineWarningConfig` holds the settings of the warning chirp played near the redline.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct RedlineWarningConfig {
+    /// The fraction of the redline above which the warning sounds, between 0.0 and 1.0.
+    pub threshold: f32,
+    /// The frequency of the warning tone, in Hz.
+    pub frequency: f32,
+    /// The gain of the warning tone, between 0.0 and 1.0.
+    pub gain: f32,
+    /// The number of chirps per second right above the threshold.
+    pub min_chirp_rate: f32,
+    /// The number of chirps per second at the redline.
+    pub max_chirp_rate: f32,
+    /// The fraction of each chirp period the tone sounds for, between 0.0 and 1.0.
+    pub duty_cycle: f32,
+}
+
+impl Default for RedlineWarningConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 0.9,
+            frequency: 2_000.0,
+            gain: 0.3,
+            min_chirp_rate: 2.0,
+            max_chirp_rate: 10.0,
+            duty_cycle: 0.5,
+        }
+    }
+}
+
+/// `RedlineWarning` synthesizes an intermittent warning tone as the engine nears its redline.
+///
+/// Below the threshold the warning is silent. Above it, the tone is gated on and off into
+/// chirps whose rate grows linearly with the proximity to the redline, so the warning gets more
+/// urgent as the engine revs higher. It renders 16-bit PCM frames in the format of the outgoing
+/// audio, with the same sample on every channel, to be mixed over it.
+pub struct RedlineWarning {
+    /// The settings of the warning.
+    config: RedlineWarningConfig,
+    /// The format of the audio stream the warning is mixed into.
+    format: AudioFormat,
+    /// The engine speed the warning is relative to, in revolutions per minute.
+    redline: u16,
+    /// The current phase of the tone oscillator, in radians.
+    phase: f32,
+    /// The position within the current chirp period, between 0.0 and 1.0.
+    chirp_position: f32,
+}
+
+impl RedlineWarning {
+    /// Creates a new instance of `RedlineWarning`.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The format of the audio stream the warning is mixed into.
+    /// * `redline` - The engine speed the warning is relative to, usually the learned rev
+    ///   ceiling of the vehicle profile.
+    /// * `config` - The settings of the warning.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `RedlineWarning` instance.
+    pub fn new(format: AudioFormat, redline: u16, config: RedlineWarningConfig) -> Self {
+        Self {
+            config,
+            format,
+            redline,
+            phase: 0.0,
+            chirp_position: 0.0,
+        }
+    }
+
+    /// Sets the engine speed the warning is relative to, e.g. after a calibration.
+    ///
+    /// # Arguments
+    ///
+    /// * `redline` - The new redline, in revolutions per minute.
+    pub fn set_redline(&mut self, redline: u16) {
+        self.redline = redline;
+    }
+
+    /// Returns the engine speed the warning is relative to.
+    pub fn redline(&self) -> u16 {
+        self.redline
+    }
+
+    /// Computes the chirp rate of the warning at the given engine speed.
+    ///
+    /// # Arguments
+    ///
+    /// * `rpm` - The engine speed, in revolutions per minute.
+    ///
+    /// # Returns
+    ///
+    /// * `f32` - The number of chirps per second, 0.0 below the threshold and
+    ///   `max_chirp_rate` at or above the redline.
+    pub fn chirp_rate(&self, rpm: u16) -> f32 {
+        if self.redline == 0 {
+            return 0.0;
+        }
+
+        let threshold = self.config.threshold.clamp(0.0, 1.0);
+        let fraction = rpm as f32 / self.redline as f32;
+        if fraction < threshold {
+            return 0.0;
+        }
+
+        let proximity = if threshold < 1.0 {
+            ((fraction - threshold) / (1.0 - threshold)).min(1.0)
+        } else {
+            1.0
+        };
+        let min_rate = self.config.min_chirp_rate.max(0.0);
+        let max_rate = self.config.max_chirp_rate.max(min_rate);
+        min_rate + (max_rate - min_rate) * proximity
+    }
+
+    /// Renders the warning into the given buffer.
+    ///
+    /// The buffer is overwritten with frames in the format of the stream, silent between the
+    /// chirps. The tone and the chirps advance once per frame, so the pitch and the chirp rate do
+    /// not depend on the number of channels. Below the threshold nothing is written and the chirp
+    /// restarts from its beginning the next time the threshold is crossed.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - The buffer the warning is rendered into.
+    /// * `rpm` - The engine speed, in revolutions per minute.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the warning is sounding, false if it is silent and the buffer was
+    ///   left untouched.
+    pub fn render(&mut self, buffer: &mut [u8], rpm: u16) -> bool {
+        let rate = self.chirp_rate(rpm);
+        let sample_rate = self.format.sample_rate;
+        let frame_len = self.format.bytes_per_frame();
+        if rate <= 0.0 || sample_rate == 0 || frame_len == 0 {
+            self.phase = 0.0;
+            self.chirp_position = 0.0;
+            return false;
+        }
+
+        let amplitude = self.config.gain.clamp(0.0, 1.0) * i16::MAX as f32;
+        let duty_cycle = self.config.duty_cycle.clamp(0.0, 1.0);
+        let tone_step = 2.0 * PI * self.config.frequency / sample_rate as f32;
+        let chirp_step = rate / sample_rate as f32;
+
+        for frame in buffer.chunks_exact_mut(frame_len) {
+            let sample = if self.chirp_position < duty_cycle {
+                libm::sinf(self.phase) * amplitude
+            } else {
+                0.0
+            };
+            self.format.write_frame(frame, sample as i16);
+
+            self.phase += tone_step;
+            if self.phase >= 2.0 * PI {
+                self.phase -= 2.0 * PI;
+            }
+            self.chirp_position += chirp_step;
+            if self.chirp_position >= 1.0 {
+                self.chirp_position -= 1.0;
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::sample_format::Endianness;
+
+    /// The redline of the tested engine, in revolutions per minute.
+    const REDLINE: u16 = 6000;
+
+    /// Returns a warning with the default settings, mixed into the given format.
+    fn warning(format: AudioFormat) -> RedlineWarning {
+        RedlineWarning::new(format, REDLINE, RedlineWarningConfig::default())
+    }
+
+    /// Checks that a chirp rate is the expected one, give or take the rounding of the fractions.
+    fn assert_rate(rate: f32, expected: f32) {
+        assert!(
+            libm::fabsf(rate - expected) < 1e-3,
+            "{} != {}",
+            rate,
+            expected
+        );
+    }
+
+    /// Returns the left channel samples of a little-endian buffer.
+    fn left_samples(buffer: &[u8], format: AudioFormat) -> Vec<i16> {
+        buffer
+            .chunks_exact(format.bytes_per_frame())
+            .map(|frame| i16::from_le_bytes([frame[0], frame[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn the_warning_is_silent_below_the_threshold() {
+        let mut warning = warning(AudioFormat::A2DP_STEREO);
+        let mut buffer = [0xAAu8; 256];
+
+        assert_eq!(warning.chirp_rate(0), 0.0);
+        assert_eq!(warning.chirp_rate(5399), 0.0);
+        assert!(!warning.render(&mut buffer, 5000));
+        assert!(buffer.iter().all(|&byte| byte == 0xAA));
+    }
+
+    #[test]
+    fn the_chirp_rate_increases_as_the_rpm_nears_the_redline() {
+        let warning = warning(AudioFormat::A2DP_STEREO);
+
+        assert_rate(warning.chirp_rate(5400), 2.0);
+        assert_rate(warning.chirp_rate(5700), 6.0);
+        assert_rate(warning.chirp_rate(REDLINE), 10.0);
+        assert_rate(warning.chirp_rate(7500), 10.0);
+
+        let rates: Vec<f32> = (5400..=REDLINE)
+            .step_by(100)
+            .map(|rpm| warning.chirp_rate(rpm))
+            .collect();
+        assert!(
+            rates.windows(2).all(|pair| pair[0] < pair[1]),
+            "{:?}",
+            rates
+        );
+    }
+
+    #[test]
+    fn a_warning_without_a_redline_is_silent() {
+        let mut warning = warning(AudioFormat::A2DP_STEREO);
+        warning.set_redline(0);
+
+        assert_eq!(warning.chirp_rate(REDLINE), 0.0);
+        assert!(!warning.render(&mut [0u8; 64], REDLINE));
+    }
+
+    #[test]
+    fn every_channel_of_a_frame_gets_the_same_sample() {
+        let mut warning = warning(AudioFormat::A2DP_STEREO);
+        let mut buffer = [0u8; 1024];
+
+        assert!(warning.render(&mut buffer, REDLINE));
+
+        assert!(buffer.iter().any(|&byte| byte != 0));
+        for frame in buffer.chunks_exact(4) {
+            assert_eq!(frame[..2], frame[2..]);
+        }
+    }
+
+    #[test]
+    fn the_tone_advances_once_per_frame_whatever_the_channels() {
+        let mono = AudioFormat {
+            channels: 1,
+            ..AudioFormat::A2DP_STEREO
+        };
+        let mut mono_buffer = [0u8; 512];
+        let mut stereo_buffer = [0u8; 1024];
+
+        warning(mono).render(&mut mono_buffer, REDLINE);
+        warning(AudioFormat::A2DP_STEREO).render(&mut stereo_buffer, REDLINE);
+
+        assert_eq!(
+            left_samples(&mono_buffer, mono),
+            left_samples(&stereo_buffer, AudioFormat::A2DP_STEREO)
+        );
+    }
+
+    #[test]
+    fn the_tone_is_gated_into_chirps() {
+        let format = AudioFormat {
+            sample_rate: 8_000,
+            channels: 1,
+            endianness: Endianness::Little,
+        };
+        let config = RedlineWarningConfig {
+            frequency: 700.0,
+            ..RedlineWarningConfig::default()
+        };
+        let mut warning = RedlineWarning::new(format, REDLINE, config);
+        // Ten chirps per second at the redline, sounding for the first half of each 800 frames
+        let mut buffer = [0u8; 2 * 800];
+
+        assert!(warning.render(&mut buffer, REDLINE));
+
+        let samples = left_samples(&buffer, format);
+        assert!(samples[..400].iter().filter(|&&s| s != 0).count() > 350);
+        assert!(samples[401..].iter().all(|&s| s == 0));
+    }
+}
@@ -0,0 +1,268 @@
+#![no_std]
+#![no_main]
+
+/// Represents the byte order of 16-bit PCM samples.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+/// `AudioFormat` describes a stream of interleaved 16-bit PCM samples.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct AudioFormat {
+    /// The number of frames per second, in Hz.
+    pub sample_rate: u32,
+    /// The number of interleaved channels, 1 for mono and 2 for stereo.
+    pub channels: u8,
+    /// The byte order of the samples.
+    pub endianness: Endianness,
+}
+
+impl AudioFormat {
+    /// The 44.1 kHz stereo little-endian format delivered by phones over A2DP.
+    pub const A2DP_STEREO: AudioFormat = AudioFormat {
+        sample_rate: 44_100,
+        channels: 2,
+        endianness: Endianness::Little,
+    };
+
+    /// Returns the number of bytes of 16-bit samples streamed per second in this format.
+    pub fn bytes_per_second(&self) -> u32 {
+        self.sample_rate * self.channels as u32 * 2
+    }
+
+    /// Returns the number of bytes of a frame, holding one sample per channel.
+    pub fn bytes_per_frame(&self) -> usize {
+        self.channels as usize * 2
+    }
+
+    /// Writes the same sample to every channel of a frame.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame` - The frame to write, `bytes_per_frame` bytes long.
+    /// * `sample` - The sample to write.
+    pub fn write_frame(&self, frame: &mut [u8], sample: i16) {
+        let bytes = match self.endianness {
+            Endianness::Little => sample.to_le_bytes(),
+            Endianness::Big => sample.to_be_bytes(),
+        };
+        for channel in frame.chunks_exact_mut(2) {
+            channel.copy_from_slice(&bytes);
+        }
+    }
+}
+
+/// Represents an error that can occur while converting audio samples.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub enum ConversionError {
+    /// The conversion between the two formats is not supported.
+    UnsupportedConversion,
+    /// The output buffer cannot hold the converted samples.
+    BufferTooSmall,
+}
+
+/// Checks that samples can be converted from one format to another.
+///
+/// The sample rate is passed through, so both formats must share it. The channels are either
+/// kept or downmixed from stereo to mono.
+///
+/// # Arguments
+///
+/// * `fmt_in` - The format of the input samples.
+/// * `fmt_out` - The format of the output samples.
+///
+/// # Returns
+///
+/// A `Result` indicating whether the conversion is supported.
+pub fn check_conversion(fmt_in: AudioFormat, fmt_out: AudioFormat) -> Result<(), ConversionError> {
+    let channels_ok =
+        fmt_in.channels == fmt_out.channels || (fmt_in.channels == 2 && fmt_out.channels == 1);
+
+    if fmt_in.sample_rate != fmt_out.sample_rate || fmt_in.channels == 0 || !channels_ok {
+        return Err(ConversionError::UnsupportedConversion);
+    }
+
+    Ok(())
+}
+
+/// Converts samples to the format expected by the receiver.
+///
+/// The input samples are the received bytes read in the native, little-endian, order, so big-endian
+/// input is byte-swapped first. Stereo to mono downmixing averages both channels.
+///
+/// # Arguments
+///
+/// * `input` - The interleaved input samples.
+/// * `fmt_in` - The format of the input samples.
+/// * `fmt_out` - The format of the output samples.
+/// * `out` - The buffer where the output bytes will be stored.
+///
+/// # Returns
+///
+/// A `Result` containing the number of output bytes written, or an error if the conversion is
+/// not supported or the output buffer is too small.
+pub fn convert_frame(
+    input: &[i16],
+    fmt_in: AudioFormat,
+    fmt_out: AudioFormat,
+    out: &mut [u8],
+) -> Result<usize, ConversionError> {
+    check_conversion(fmt_in, fmt_out)?;
+
+    let decode = |sample: i16| match fmt_in.endianness {
+        Endianness::Little => sample,
+        Endianness::Big => sample.swap_bytes(),
+    };
+    let encode = |sample: i16| match fmt_out.endianness {
+        Endianness::Little => sample.to_le_bytes(),
+        Endianness::Big => sample.to_be_bytes(),
+    };
+
+    let downmix = fmt_in.channels != fmt_out.channels;
+    let samples = if downmix {
+        input.len() / 2
+    } else {
+        input.len()
+    };
+    if out.len() < samples * 2 {
+        return Err(ConversionError::BufferTooSmall);
+    }
+
+    if downmix {
+        for (frame, bytes) in input.chunks_exact(2).zip(out.chunks_exact_mut(2)) {
+            let mixed = (decode(frame[0]) as i32 + decode(frame[1]) as i32) / 2;
+            bytes.copy_from_slice(&encode(mixed as i16));
+        }
+    } else {
+        for (&sample, bytes) in input.iter().zip(out.chunks_exact_mut(2)) {
+            bytes.copy_from_slice(&encode(decode(sample)));
+        }
+    }
+
+    Ok(samples * 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The A2DP format with big-endian samples.
+    const STEREO_BIG: AudioFormat = AudioFormat {
+        endianness: Endianness::Big,
+        ..AudioFormat::A2DP_STEREO
+    };
+
+    /// The A2DP format downmixed to mono.
+    const MONO_LITTLE: AudioFormat = AudioFormat {
+        channels: 1,
+        ..AudioFormat::A2DP_STEREO
+    };
+
+    #[test]
+    fn little_endian_samples_are_swapped_to_big_endian() {
+        let mut out = [0u8; 4];
+
+        let len = convert_frame(
+            &[0x1234, -2],
+            AudioFormat::A2DP_STEREO,
+            STEREO_BIG,
+            &mut out,
+        )
+        .unwrap();
+
+        assert_eq!(len, 4);
+        assert_eq!(out, [0x12, 0x34, 0xFF, 0xFE]);
+    }
+
+    #[test]
+    fn big_endian_input_is_swapped_back() {
+        let mut out = [0u8; 4];
+        // Read natively, the big-endian bytes 12 34 and FF FE look swapped
+        let received = [0x3412, 0xFEFF_u16 as i16];
+
+        convert_frame(&received, STEREO_BIG, AudioFormat::A2DP_STEREO, &mut out).unwrap();
+
+        assert_eq!(out, [0x34, 0x12, 0xFE, 0xFF]);
+        assert_eq!(
+            [
+                i16::from_le_bytes([out[0], out[1]]),
+                i16::from_le_bytes([out[2], out[3]])
+            ],
+            [0x1234, -2]
+        );
+    }
+
+    #[test]
+    fn stereo_is_downmixed_to_the_average_of_both_channels() {
+        let mut out = [0u8; 8];
+
+        let len = convert_frame(
+            &[
+                1000,
+                3000,
+                -1000,
+                1000,
+                i16::MAX,
+                i16::MAX,
+                i16::MIN,
+                i16::MIN,
+            ],
+            AudioFormat::A2DP_STEREO,
+            MONO_LITTLE,
+            &mut out,
+        )
+        .unwrap();
+
+        assert_eq!(len, 8);
+        let mono: Vec<i16> = out
+            .chunks_exact(2)
+            .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect();
+        assert_eq!(mono, [2000, 0, i16::MAX, i16::MIN]);
+    }
+
+    #[test]
+    fn unsupported_conversions_are_rejected() {
+        let resampled = AudioFormat {
+            sample_rate: 48_000,
+            ..AudioFormat::A2DP_STEREO
+        };
+        let silent = AudioFormat {
+            channels: 0,
+            ..AudioFormat::A2DP_STEREO
+        };
+        let mut out = [0u8; 8];
+
+        for (fmt_in, fmt_out) in [
+            (AudioFormat::A2DP_STEREO, resampled),
+            (MONO_LITTLE, AudioFormat::A2DP_STEREO),
+            (silent, silent),
+        ] {
+            assert_eq!(
+                convert_frame(&[0; 4], fmt_in, fmt_out, &mut out),
+                Err(ConversionError::UnsupportedConversion)
+            );
+        }
+    }
+
+    #[test]
+    fn a_short_output_buffer_is_rejected() {
+        let mut out = [0u8; 6];
+
+        assert_eq!(
+            convert_frame(
+                &[0; 4],
+                AudioFormat::A2DP_STEREO,
+                AudioFormat::A2DP_STEREO,
+                &mut out
+            ),
+            Err(ConversionError::BufferTooSmall)
+        );
+        assert_eq!(
+            convert_frame(&[0; 4], AudioFormat::A2DP_STEREO, MONO_LITTLE, &mut out),
+            Ok(4)
+        );
+    }
+}
@@ -0,0 +1,150 @@
+#![no_std]
+#![no_main]
+
+/// The drive applied by default, leaving small signals at unity gain.
+pub const DEFAULT_DRIVE: f32 = 1.0;
+
+/// The lowest drive accepted, below which the stage only attenuates.
+const MIN_DRIVE: f32 = 0.1;
+
+/// The highest drive accepted, beyond which the stage behaves like a hard clipper.
+const MAX_DRIVE: f32 = 10.0;
+
+/// `SoftClip` saturates a signal smoothly instead of clipping it at full scale.
+///
+/// The stage follows a `tanh` curve: small signals pass through nearly linearly, with a gain
+/// equal to the drive, while large ones bend towards full scale without ever reaching past it.
+/// A higher drive saturates earlier and sounds warmer, a lower one stays cleaner.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct SoftClip {
+    /// The gain applied before the saturation curve.
+    drive: f32,
+}
+
+impl Default for SoftClip {
+    fn default() -> Self {
+        Self::new(DEFAULT_DRIVE)
+    }
+}
+
+impl SoftClip {
+    /// Creates a new instance of `SoftClip`.
+    ///
+    /// # Arguments
+    ///
+    /// * `drive` - The gain applied before the saturation curve, clamped between 0.1 and 10.0.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `SoftClip` instance.
+    pub fn new(drive: f32) -> Self {
+        Self {
+            drive: drive.clamp(MIN_DRIVE, MAX_DRIVE),
+        }
+    }
+
+    /// Returns the gain applied before the saturation curve.
+    pub fn drive(&self) -> f32 {
+        self.drive
+    }
+
+    /// Saturates a sample.
+    ///
+    /// # Arguments
+    ///
+    /// * `sample` - The sample, where 1.0 is full scale.
+    ///
+    /// # Returns
+    ///
+    /// * `f32` - The saturated sample, between -1.0 and 1.0.
+    pub fn saturate(&self, sample: f32) -> f32 {
+        libm::tanhf(self.drive * sample)
+    }
+
+    /// Saturates a 16-bit PCM sample.
+    ///
+    /// # Arguments
+    ///
+    /// * `sample` - The sample, in 16-bit full scale units, possibly beyond full scale.
+    ///
+    /// # Returns
+    ///
+    /// * `i16` - The saturated sample.
+    pub fn saturate_pcm(&self, sample: f32) -> i16 {
+        (self.saturate(sample / i16::MAX as f32) * i16::MAX as f32) as i16
+    }
+
+    /// Adds a frame of 16-bit little-endian PCM samples into another through the saturation
+    /// curve.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - The frame to add into.
+    /// * `other` - The frame to add.
+    pub fn mix(&self, buffer: &mut [u8], other: &[u8]) {
+        for (sample, added) in buffer.chunks_exact_mut(2).zip(other.chunks_exact(2)) {
+            let sum = i16::from_le_bytes([sample[0], sample[1]]) as f32
+                + i16::from_le_bytes([added[0], added[1]]) as f32;
+            sample.copy_from_slice(&self.saturate_pcm(sum).to_le_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_output_stays_within_full_scale_for_large_inputs() {
+        for drive in [MIN_DRIVE, DEFAULT_DRIVE, MAX_DRIVE] {
+            let soft_clip = SoftClip::new(drive);
+            for input in [1.0, 2.0, 10.0, 1000.0, f32::MAX] {
+                assert!(soft_clip.saturate(input) <= 1.0, "{} at {}", input, drive);
+                assert!(
+                    soft_clip.saturate(-input) >= -1.0,
+                    "{} at {}",
+                    -input,
+                    drive
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn small_signals_pass_through_near_linearly() {
+        let soft_clip = SoftClip::default();
+
+        for input in [0.001, 0.01, 0.05, -0.05] {
+            let output = soft_clip.saturate(input);
+            assert!((output - input).abs() <= input.abs() * 0.01, "{}", input);
+        }
+
+        // The drive sets the small-signal gain
+        let driven = SoftClip::new(4.0);
+        assert!((driven.saturate(0.001) - 0.004).abs() < 1e-6);
+    }
+
+    #[test]
+    fn the_drive_is_clamped() {
+        assert_eq!(SoftClip::new(0.0).drive(), MIN_DRIVE);
+        assert_eq!(SoftClip::new(100.0).drive(), MAX_DRIVE);
+        assert_eq!(SoftClip::default().drive(), DEFAULT_DRIVE);
+    }
+
+    #[test]
+    fn mixing_two_loud_frames_saturates_instead_of_wrapping() {
+        let soft_clip = SoftClip::default();
+        let mut buffer = 30_000i16.to_le_bytes().repeat(4);
+        let mut negative = (-30_000i16).to_le_bytes().repeat(4);
+
+        soft_clip.mix(&mut buffer, &30_000i16.to_le_bytes().repeat(4));
+        soft_clip.mix(&mut negative, &(-30_000i16).to_le_bytes().repeat(4));
+
+        for (positive, negative) in buffer.chunks_exact(2).zip(negative.chunks_exact(2)) {
+            let positive = i16::from_le_bytes([positive[0], positive[1]]);
+            let negative = i16::from_le_bytes([negative[0], negative[1]]);
+            assert!(positive > 30_000, "{}", positive);
+            assert!(negative < -30_000, "{}", negative);
+        }
+    }
+}
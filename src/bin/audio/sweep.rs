@@ -0,0 +1,248 @@
+#![no_std]
+#![no_main]
+
+use crate::audio::sample_format::AudioFormat;
+use core::f32::consts::PI;
+use embassy_time::Duration;
+
+/// `SineSweep` renders a logarithmic sine sweep, one buffer at a time.
+///
+/// The frequency rises (or falls) exponentially from the start to the end frequency, so each
+/// octave gets the same time. Installers play it to find rattles and check the speaker response.
+/// The sweep is rendered on the fly, so long sweeps do not need to fit in memory.
+pub struct SineSweep {
+    /// The frequency at the start of the sweep, in Hz.
+    start_hz: f32,
+    /// The frequency at the end of the sweep, in Hz.
+    end_hz: f32,
+    /// The peak amplitude of the samples.
+    amplitude: f32,
+    /// The format of the output.
+    format: AudioFormat,
+    /// The total number of frames of the sweep.
+    total_samples: u32,
+    /// The number of frames rendered so far.
+    rendered: u32,
+    /// The phase of the next sample, in radians.
+    phase: f32,
+}
+
+impl SineSweep {
+    /// Creates a new instance of `SineSweep`.
+    ///
+    /// # Arguments
+    ///
+    /// * `start_hz` - The frequency at the start of the sweep, in Hz.
+    /// * `end_hz` - The frequency at the end of the sweep, in Hz.
+    /// * `duration` - The length of the sweep.
+    /// * `gain` - The gain of the sweep, between 0.0 and 1.0.
+    /// * `format` - The format of the output, the sweep playing on every channel.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Self>` - The new `SineSweep` instance, or `None` if a frequency is not positive
+    ///   or above the Nyquist frequency.
+    pub fn new(
+        start_hz: f32,
+        end_hz: f32,
+        duration: Duration,
+        gain: f32,
+        format: AudioFormat,
+    ) -> Option<Self> {
+        let sample_rate = format.sample_rate;
+        let nyquist = sample_rate as f32 / 2.0;
+        let valid = |hz: f32| hz.is_finite() && hz > 0.0 && hz <= nyquist;
+        if !valid(start_hz) || !valid(end_hz) || format.channels == 0 {
+            return None;
+        }
+
+        Some(Self {
+            start_hz,
+            end_hz,
+            amplitude: gain.clamp(0.0, 1.0) * i16::MAX as f32,
+            format,
+            total_samples: (sample_rate as u64 * duration.as_millis() / 1000) as u32,
+            rendered: 0,
+            phase: 0.0,
+        })
+    }
+
+    /// Returns the instantaneous frequency of the sweep at the given frame.
+    ///
+    /// # Arguments
+    ///
+    /// * `sample` - The index of the frame.
+    ///
+    /// # Returns
+    ///
+    /// * `f32` - The frequency, in Hz, between the start and end frequencies.
+    pub fn frequency_at(&self, sample: u32) -> f32 {
+        if self.total_samples == 0 {
+            return self.start_hz;
+        }
+
+        let progress = sample.min(self.total_samples) as f32 / self.total_samples as f32;
+        self.start_hz * libm::powf(self.end_hz / self.start_hz, progress)
+    }
+
+    /// Returns whether the whole sweep has been rendered.
+    pub fn is_done(&self) -> bool {
+        self.rendered >= self.total_samples
+    }
+
+    /// Renders the next frames of the sweep as 16-bit PCM in the output format.
+    ///
+    /// # Arguments
+    ///
+    /// * `buf` - The buffer where the frames will be stored, only whole frames being written.
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The number of bytes written, 0 once the sweep is done.
+    pub fn fill(&mut self, buf: &mut [u8]) -> usize {
+        let frame_len = self.format.bytes_per_frame();
+        let mut len = 0;
+        for frame in buf.chunks_exact_mut(frame_len) {
+            if self.is_done() {
+                break;
+            }
+
+            let sample = (libm::sinf(self.phase) * self.amplitude) as i16;
+            self.format.write_frame(frame, sample);
+            len += frame_len;
+
+            self.phase +=
+                2.0 * PI * self.frequency_at(self.rendered) / self.format.sample_rate as f32;
+            if self.phase >= 2.0 * PI {
+                self.phase -= 2.0 * PI;
+            }
+            self.rendered += 1;
+        }
+
+        len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The A2DP sample rate with a single channel, so each sample is a frame.
+    const MONO: AudioFormat = AudioFormat {
+        channels: 1,
+        ..AudioFormat::A2DP_STEREO
+    };
+
+    /// Renders a whole sweep and returns its samples.
+    fn render(mut sweep: SineSweep) -> Vec<i16> {
+        let mut samples = Vec::new();
+        let mut buf = [0u8; 512];
+        loop {
+            let len = sweep.fill(&mut buf);
+            if len == 0 {
+                return samples;
+            }
+            samples.extend(
+                buf[..len]
+                    .chunks_exact(2)
+                    .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]])),
+            );
+        }
+    }
+
+    /// Returns the frequency of a window of samples, in Hz, from its rising zero crossings.
+    fn measured_frequency(window: &[i16]) -> f32 {
+        let cycles = window
+            .windows(2)
+            .filter(|pair| pair[0] < 0 && pair[1] >= 0)
+            .count();
+        cycles as f32 * MONO.sample_rate as f32 / window.len() as f32
+    }
+
+    #[test]
+    fn the_frequency_moves_logarithmically_from_start_to_end() {
+        let sweep = SineSweep::new(100.0, 1000.0, Duration::from_secs(1), 0.5, MONO).unwrap();
+        let total = MONO.sample_rate;
+
+        assert_eq!(sweep.frequency_at(0), 100.0);
+        assert!((sweep.frequency_at(total) - 1000.0).abs() < 0.01);
+        // Halfway through in time is halfway through in octaves
+        assert!((sweep.frequency_at(total / 2) - 316.23).abs() < 0.1);
+        assert!((sweep.frequency_at(total * 2) - 1000.0).abs() < 0.01);
+        for sample in (0..total).step_by(100) {
+            assert!(sweep.frequency_at(sample + 100) > sweep.frequency_at(sample));
+        }
+    }
+
+    #[test]
+    fn the_rendered_sweep_rises_over_its_duration() {
+        let sweep = SineSweep::new(100.0, 1000.0, Duration::from_secs(1), 0.5, MONO).unwrap();
+
+        let samples = render(sweep);
+
+        assert_eq!(samples.len(), MONO.sample_rate as usize);
+        let tenth = samples.len() / 10;
+        // Over the first tenth the sweep runs from 100 to 126 Hz, over the last from 794 to 1000
+        let early = measured_frequency(&samples[..tenth]);
+        let late = measured_frequency(&samples[samples.len() - tenth..]);
+        assert!((95.0..135.0).contains(&early), "{}", early);
+        assert!((780.0..1010.0).contains(&late), "{}", late);
+    }
+
+    #[test]
+    fn a_falling_sweep_is_rendered_too() {
+        let sweep = SineSweep::new(2000.0, 200.0, Duration::from_millis(500), 0.5, MONO).unwrap();
+
+        let samples = render(sweep);
+
+        let tenth = samples.len() / 10;
+        let early = measured_frequency(&samples[..tenth]);
+        let late = measured_frequency(&samples[samples.len() - tenth..]);
+        assert!(early > late * 5.0, "{} {}", early, late);
+    }
+
+    #[test]
+    fn the_samples_stay_within_the_amplitude() {
+        let gain = 0.25;
+        let sweep = SineSweep::new(50.0, 5000.0, Duration::from_millis(200), gain, MONO).unwrap();
+
+        let samples = render(sweep);
+
+        let bound = gain * i16::MAX as f32;
+        let peak = samples.iter().map(|sample| sample.unsigned_abs()).max();
+        assert!(samples.iter().all(|&s| (s as f32).abs() <= bound));
+        assert!(peak.is_some_and(|peak| peak as f32 > bound * 0.99));
+    }
+
+    #[test]
+    fn every_channel_carries_the_sweep() {
+        let mut sweep = SineSweep::new(
+            440.0,
+            880.0,
+            Duration::from_millis(10),
+            1.0,
+            AudioFormat::A2DP_STEREO,
+        )
+        .unwrap();
+        // Not a whole number of stereo frames, so the dangling bytes are left alone
+        let mut buf = [0xAAu8; 402];
+
+        let len = sweep.fill(&mut buf);
+
+        assert_eq!(len, 400);
+        assert!(buf[..len]
+            .chunks_exact(4)
+            .all(|frame| frame[..2] == frame[2..]));
+        assert_eq!(buf[len..], [0xAA, 0xAA]);
+    }
+
+    #[test]
+    fn frequencies_outside_the_audio_band_are_rejected() {
+        let duration = Duration::from_secs(1);
+
+        assert!(SineSweep::new(0.0, 1000.0, duration, 0.5, MONO).is_none());
+        assert!(SineSweep::new(-20.0, 1000.0, duration, 0.5, MONO).is_none());
+        assert!(SineSweep::new(20.0, 30_000.0, duration, 0.5, MONO).is_none());
+        assert!(SineSweep::new(f32::NAN, 1000.0, duration, 0.5, MONO).is_none());
+    }
+}
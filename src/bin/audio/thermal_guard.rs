@@ -0,0 +1,195 @@
+#![no_std]
+#![no_main]
+
+use crate::audio::audio_behavior::AudioBehavior;
+use defmt::warn;
+
+/// `ThermalGuardConfig` holds the temperatures at which the gain is capped.
+///
+/// The defaults suit the engine coolant used as a proxy, which normally runs between 90 and
+/// 105 degrees Celsius once warm, so the cap only engages once the engine runs hot.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct ThermalGuardConfig {
+    /// The temperature, in degrees Celsius, from which the gain is capped.
+    pub threshold: i16,
+    /// The temperature, in degrees Celsius, at which the cap reaches `max_cut_db`.
+    pub full_cut: i16,
+    /// The gain ceiling at `full_cut` and above, in dB below unity.
+    pub max_cut_db: f32,
+    /// How far, in degrees Celsius, the temperature must fall below the threshold to lift the cap.
+    pub hysteresis: i16,
+}
+
+impl Default for ThermalGuardConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 112,
+            full_cut: 125,
+            max_cut_db: 12.0,
+            hysteresis: 5,
+        }
+    }
+}
+
+/// `ThermalGuard` caps the gain while the amplifier is hot to protect the hardware.
+///
+/// It takes a temperature reading, either from a sensor near the amplifier or the engine coolant
+/// as a proxy. Above the threshold, the gain ceiling drops linearly from unity down to
+/// `max_cut_db` below it at `full_cut`. The cap is only lifted once the temperature falls back
+/// below the threshold by the hysteresis margin.
+pub struct ThermalGuard {
+    /// The temperatures at which the gain is capped.
+    config: ThermalGuardConfig,
+    /// Whether the gain is currently capped.
+    active: bool,
+}
+
+impl ThermalGuard {
+    /// Creates a new instance of `ThermalGuard`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The temperatures at which the gain is capped.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `ThermalGuard` instance, inactive.
+    pub fn new(config: ThermalGuardConfig) -> Self {
+        Self {
+            config,
+            active: false,
+        }
+    }
+
+    /// Returns whether the gain is currently capped.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Feeds a temperature reading and returns the gain ceiling.
+    ///
+    /// # Arguments
+    ///
+    /// * `temperature` - The temperature, in degrees Celsius.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<f32>` - The gain ceiling in dB, or `None` if the gain is not capped.
+    pub fn update(&mut self, temperature: i16) -> Option<f32> {
+        let ThermalGuardConfig {
+            threshold,
+            full_cut,
+            max_cut_db,
+            hysteresis,
+        } = self.config;
+
+        if !self.active && temperature >= threshold {
+            warn!("Amplifier hot at {} degC, capping the gain", temperature);
+            self.active = true;
+        } else if self.active && temperature < threshold - hysteresis {
+            warn!(
+                "Amplifier cooled down to {} degC, restoring the gain",
+                temperature
+            );
+            self.active = false;
+        }
+
+        if !self.active {
+            return None;
+        }
+
+        let span = (full_cut - threshold).max(1) as f32;
+        let progress = ((temperature - threshold) as f32 / span).clamp(0.0, 1.0);
+        Some(-max_cut_db * progress)
+    }
+
+    /// Caps the gain of a behavior according to the temperature.
+    ///
+    /// # Arguments
+    ///
+    /// * `behavior` - The behavior to cap.
+    /// * `temperature` - The temperature, in degrees Celsius.
+    ///
+    /// # Returns
+    ///
+    /// * `AudioBehavior` - The behavior with its target gain capped if the guard is active.
+    pub fn apply(&mut self, behavior: AudioBehavior, temperature: i16) -> AudioBehavior {
+        match self.update(temperature) {
+            Some(ceiling) => AudioBehavior {
+                target_gain_db: behavior.target_gain_db.min(ceiling),
+                ..behavior
+            },
+            None => behavior,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_cap_engages_at_the_threshold() {
+        let mut guard = ThermalGuard::new(ThermalGuardConfig::default());
+
+        assert_eq!(guard.update(95), None);
+        assert_eq!(guard.update(111), None);
+        assert!(!guard.is_active());
+
+        assert_eq!(guard.update(112), Some(0.0));
+        assert!(guard.is_active());
+    }
+
+    #[test]
+    fn the_cap_is_lifted_below_the_hysteresis_margin() {
+        let mut guard = ThermalGuard::new(ThermalGuardConfig::default());
+        guard.update(118);
+
+        // Cooling below the threshold but within the margin keeps the gain capped
+        assert_eq!(guard.update(110), Some(0.0));
+        assert_eq!(guard.update(107), Some(0.0));
+        assert!(guard.is_active());
+
+        assert_eq!(guard.update(106), None);
+        assert!(!guard.is_active());
+        // Warming back up within the margin does not engage it again
+        assert_eq!(guard.update(110), None);
+    }
+
+    #[test]
+    fn the_ceiling_drops_linearly_to_the_full_cut() {
+        let mut guard = ThermalGuard::new(ThermalGuardConfig {
+            threshold: 100,
+            full_cut: 120,
+            max_cut_db: 10.0,
+            hysteresis: 5,
+        });
+
+        let ceilings: Vec<_> = [100, 105, 110, 120, 140]
+            .into_iter()
+            .map(|temperature| guard.update(temperature).unwrap())
+            .collect();
+
+        assert_eq!(ceilings, [0.0, -2.5, -5.0, -10.0, -10.0]);
+    }
+
+    #[test]
+    fn apply_only_lowers_the_target_gain() {
+        let mut guard = ThermalGuard::new(ThermalGuardConfig::default());
+        let loud = AudioBehavior {
+            target_gain_db: 3.0,
+            ..AudioBehavior::default()
+        };
+        let quiet = AudioBehavior {
+            target_gain_db: -20.0,
+            ..AudioBehavior::default()
+        };
+
+        assert_eq!(guard.apply(loud, 90), loud);
+        assert_eq!(guard.apply(loud, 125).target_gain_db, -12.0);
+        assert_eq!(guard.apply(quiet, 125), quiet);
+        // Partway to the full cut, the ceiling sits partway down
+        let capped = guard.apply(loud, 118).target_gain_db;
+        assert!((capped - -12.0 * 6.0 / 13.0).abs() < 1e-4, "{}", capped);
+    }
+}
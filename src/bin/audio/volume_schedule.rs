@@ -0,0 +1,263 @@
+#![no_std]
+#![no_main]
+
+use crate::audio::audio_behavior::AudioBehavior;
+use alloc::vec::Vec;
+use defmt::error;
+use embassy_stm32::rtc::{DateTime, Rtc};
+
+/// `TimeOfDay` is a wall-clock time with minute resolution.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub struct TimeOfDay {
+    /// The hour, from 0 to 23.
+    pub hour: u8,
+    /// The minute, from 0 to 59.
+    pub minute: u8,
+}
+
+impl TimeOfDay {
+    /// Creates a new instance of `TimeOfDay`.
+    pub const fn new(hour: u8, minute: u8) -> Self {
+        Self { hour, minute }
+    }
+
+    /// Returns the number of minutes since midnight.
+    fn minutes(&self) -> u16 {
+        self.hour as u16 * 60 + self.minute as u16
+    }
+}
+
+impl From<&DateTime> for TimeOfDay {
+    fn from(datetime: &DateTime) -> Self {
+        Self::new(datetime.hour(), datetime.minute())
+    }
+}
+
+/// `WallClock` is a trait that defines the source of the time of day driving the volume schedule.
+///
+/// The app only depends on this trait, so it can be driven by a simulated clock instead of the
+/// RTC peripheral.
+pub trait WallClock {
+    /// Returns the current time of day.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<TimeOfDay>` - The current time, or `None` if the clock could not be read.
+    fn time_of_day(&self) -> Option<TimeOfDay>;
+}
+
+impl WallClock for Rtc {
+    fn time_of_day(&self) -> Option<TimeOfDay> {
+        match self.now() {
+            Ok(now) => Some(TimeOfDay::from(&now)),
+            Err(e) => {
+                error!("Failed to read the RTC: {:?}", defmt::Debug2Format(&e));
+                None
+            }
+        }
+    }
+}
+
+/// `FixedClock` is a `WallClock` always reporting the same time of day.
+#[cfg(feature = "simulation")]
+pub struct FixedClock(pub TimeOfDay);
+
+#[cfg(feature = "simulation")]
+impl WallClock for FixedClock {
+    fn time_of_day(&self) -> Option<TimeOfDay> {
+        Some(self.0)
+    }
+}
+
+/// `QuietWindow` caps the volume between two times of the day.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct QuietWindow {
+    /// The start of the window, inclusive.
+    pub start: TimeOfDay,
+    /// The end of the window, exclusive. An end before the start wraps past midnight.
+    pub end: TimeOfDay,
+    /// The highest volume allowed within the window.
+    pub max_volume: u8,
+}
+
+impl QuietWindow {
+    /// Checks whether the window covers the given time.
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - The time to check.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the time falls within the window, false otherwise.
+    pub fn contains(&self, time: TimeOfDay) -> bool {
+        let (start, end, time) = (self.start.minutes(), self.end.minutes(), time.minutes());
+
+        if start <= end {
+            time >= start && time < end
+        } else {
+            time >= start || time < end
+        }
+    }
+}
+
+/// `VolumeSchedule` caps the volume during configured times of the day, e.g. late at night.
+///
+/// It runs as the last stage after the mapping and the limiter, so the cap holds regardless of
+/// the vehicle data.
+pub struct VolumeSchedule {
+    /// The windows during which the volume is capped.
+    windows: Vec<QuietWindow>,
+}
+
+impl VolumeSchedule {
+    /// Creates a new instance of `VolumeSchedule` without any window.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `VolumeSchedule` instance.
+    pub fn new() -> Self {
+        Self {
+            windows: Vec::new(),
+        }
+    }
+
+    /// Adds a window during which the volume is capped.
+    ///
+    /// # Arguments
+    ///
+    /// * `window` - The window to add.
+    pub fn add_window(&mut self, window: QuietWindow) {
+        self.windows.push(window);
+    }
+
+    /// Returns the volume ceiling in effect at the given time.
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - The current time.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<u8>` - The lowest ceiling of the windows covering the time, or `None` if no
+    ///   window covers it.
+    pub fn ceiling(&self, time: TimeOfDay) -> Option<u8> {
+        self.windows
+            .iter()
+            .filter(|window| window.contains(time))
+            .map(|window| window.max_volume)
+            .min()
+    }
+
+    /// Caps the volume of a behavior according to the schedule.
+    ///
+    /// # Arguments
+    ///
+    /// * `behavior` - The behavior computed by the mapping.
+    /// * `time` - The current time.
+    ///
+    /// # Returns
+    ///
+    /// * `AudioBehavior` - The behavior with its volume capped if a window is in effect.
+    pub fn apply(&self, behavior: AudioBehavior, time: TimeOfDay) -> AudioBehavior {
+        match self.ceiling(time) {
+            Some(ceiling) => AudioBehavior {
+                volume: behavior.volume.min(ceiling),
+                ..behavior
+            },
+            None => behavior,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a behavior at the given volume.
+    fn at_volume(volume: u8) -> AudioBehavior {
+        AudioBehavior {
+            volume,
+            bass: 7,
+            ..AudioBehavior::default()
+        }
+    }
+
+    /// Returns a schedule with a single window.
+    fn schedule(start: TimeOfDay, end: TimeOfDay, max_volume: u8) -> VolumeSchedule {
+        let mut schedule = VolumeSchedule::new();
+        schedule.add_window(QuietWindow {
+            start,
+            end,
+            max_volume,
+        });
+        schedule
+    }
+
+    #[test]
+    fn the_volume_is_capped_inside_a_window() {
+        let schedule = schedule(TimeOfDay::new(13, 0), TimeOfDay::new(15, 30), 4);
+
+        for time in [
+            TimeOfDay::new(13, 0),
+            TimeOfDay::new(14, 0),
+            TimeOfDay::new(15, 29),
+        ] {
+            assert_eq!(schedule.apply(at_volume(12), time), at_volume(4));
+        }
+        // A volume already below the ceiling passes through
+        assert_eq!(
+            schedule.apply(at_volume(3), TimeOfDay::new(14, 0)),
+            at_volume(3)
+        );
+    }
+
+    #[test]
+    fn the_volume_passes_through_outside_a_window() {
+        let schedule = schedule(TimeOfDay::new(13, 0), TimeOfDay::new(15, 30), 4);
+
+        for time in [
+            TimeOfDay::new(12, 59),
+            TimeOfDay::new(15, 30),
+            TimeOfDay::new(0, 0),
+        ] {
+            assert_eq!(schedule.apply(at_volume(12), time), at_volume(12));
+        }
+        assert_eq!(VolumeSchedule::new().ceiling(TimeOfDay::new(14, 0)), None);
+    }
+
+    #[test]
+    fn a_window_can_wrap_past_midnight() {
+        let schedule = schedule(TimeOfDay::new(22, 0), TimeOfDay::new(6, 0), 5);
+
+        for time in [
+            TimeOfDay::new(22, 0),
+            TimeOfDay::new(23, 59),
+            TimeOfDay::new(0, 0),
+            TimeOfDay::new(5, 59),
+        ] {
+            assert_eq!(schedule.ceiling(time), Some(5), "{:?}", time);
+        }
+        for time in [
+            TimeOfDay::new(6, 0),
+            TimeOfDay::new(12, 0),
+            TimeOfDay::new(21, 59),
+        ] {
+            assert_eq!(schedule.ceiling(time), None, "{:?}", time);
+        }
+    }
+
+    #[test]
+    fn overlapping_windows_apply_the_lowest_ceiling() {
+        let mut schedule = schedule(TimeOfDay::new(21, 0), TimeOfDay::new(7, 0), 8);
+        schedule.add_window(QuietWindow {
+            start: TimeOfDay::new(0, 0),
+            end: TimeOfDay::new(5, 0),
+            max_volume: 3,
+        });
+
+        assert_eq!(schedule.ceiling(TimeOfDay::new(22, 0)), Some(8));
+        assert_eq!(schedule.ceiling(TimeOfDay::new(2, 0)), Some(3));
+        assert_eq!(schedule.ceiling(TimeOfDay::new(6, 0)), Some(8));
+    }
+}
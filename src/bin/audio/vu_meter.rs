@@ -0,0 +1,202 @@
+#![no_std]
+#![no_main]
+
+/// `ChannelLevel` holds the level of one channel, relative to full scale.
+#[derive(Clone, Copy, Debug, Default, PartialEq, defmt::Format)]
+pub struct ChannelLevel {
+    /// The largest sample magnitude, between 0.0 and 1.0.
+    pub peak: f32,
+    /// The root mean square of the samples, between 0.0 and 1.0.
+    pub rms: f32,
+}
+
+/// `VuLevels` holds the levels of both channels of a stereo stream.
+#[derive(Clone, Copy, Debug, Default, PartialEq, defmt::Format)]
+pub struct VuLevels {
+    /// The level of the left channel.
+    pub left: ChannelLevel,
+    /// The level of the right channel.
+    pub right: ChannelLevel,
+}
+
+/// Accumulates the samples of one channel over a window.
+#[derive(Clone, Copy, Default)]
+struct ChannelAccumulator {
+    /// The largest sample magnitude seen, relative to full scale.
+    peak: f32,
+    /// The sum of the squared samples, relative to full scale.
+    sum_squares: f32,
+    /// The number of samples accumulated.
+    samples: u32,
+}
+
+impl ChannelAccumulator {
+    /// Adds a sample.
+    fn push(&mut self, sample: i16) {
+        let value = sample as f32 / -(i16::MIN as f32);
+        self.peak = self.peak.max(libm::fabsf(value));
+        self.sum_squares += value * value;
+        self.samples += 1;
+    }
+
+    /// Returns the level of the accumulated samples.
+    fn level(&self) -> ChannelLevel {
+        if self.samples == 0 {
+            return ChannelLevel::default();
+        }
+
+        ChannelLevel {
+            peak: self.peak,
+            rms: libm::sqrtf(self.sum_squares / self.samples as f32),
+        }
+    }
+}
+
+/// `VuMeter` measures the per-channel peak and RMS levels of an interleaved stereo stream.
+///
+/// Levels are accumulated over a window of frames and published once the window is complete,
+/// so a display task reading them sees a steady meter rather than per-frame flicker.
+pub struct VuMeter {
+    /// The number of frames each published level covers.
+    window_frames: u32,
+    /// The number of frames accumulated in the current window.
+    frames: u32,
+    /// The samples of the left channel in the current window.
+    left: ChannelAccumulator,
+    /// The samples of the right channel in the current window.
+    right: ChannelAccumulator,
+    /// The levels of the last complete window.
+    levels: VuLevels,
+}
+
+impl VuMeter {
+    /// Creates a new instance of `VuMeter`.
+    ///
+    /// # Arguments
+    ///
+    /// * `window_frames` - The number of frames each published level covers, at least 1.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `VuMeter` instance, reading silence.
+    pub fn new(window_frames: u32) -> Self {
+        Self {
+            window_frames: window_frames.max(1),
+            frames: 0,
+            left: ChannelAccumulator::default(),
+            right: ChannelAccumulator::default(),
+            levels: VuLevels::default(),
+        }
+    }
+
+    /// Returns the levels of the last complete window.
+    pub fn levels(&self) -> VuLevels {
+        self.levels
+    }
+
+    /// Accumulates a frame, publishing the levels once the window is complete.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame` - The frame of interleaved stereo 16-bit little-endian PCM samples.
+    pub fn process(&mut self, frame: &[u8]) {
+        for pair in frame.chunks_exact(4) {
+            self.left.push(i16::from_le_bytes([pair[0], pair[1]]));
+            self.right.push(i16::from_le_bytes([pair[2], pair[3]]));
+        }
+
+        self.frames += 1;
+        if self.frames < self.window_frames {
+            return;
+        }
+
+        self.levels = VuLevels {
+            left: self.left.level(),
+            right: self.right.level(),
+        };
+        self.frames = 0;
+        self.left = ChannelAccumulator::default();
+        self.right = ChannelAccumulator::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use core::f32::consts::{FRAC_1_SQRT_2, PI};
+
+    /// The samples per cycle of the test sine, so a frame holds whole cycles.
+    const PERIOD: usize = 48;
+
+    /// Returns a frame of `cycles` sine cycles, with the given amplitude on each channel.
+    fn sine_frame(cycles: usize, left: f32, right: f32) -> Vec<u8> {
+        let mut frame = Vec::new();
+        for index in 0..cycles * PERIOD {
+            let phase = libm::sinf(2.0 * PI * index as f32 / PERIOD as f32);
+            for amplitude in [left, right] {
+                let sample = (phase * amplitude * i16::MAX as f32) as i16;
+                frame.extend_from_slice(&sample.to_le_bytes());
+            }
+        }
+        frame
+    }
+
+    /// Asserts that a measured level is within a small tolerance of the expected one.
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "{} is not close to {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn the_rms_of_a_sine_is_its_peak_over_root_two() {
+        let mut meter = VuMeter::new(1);
+
+        meter.process(&sine_frame(10, 0.5, 0.25));
+
+        let levels = meter.levels();
+        assert_close(levels.left.peak, 0.5);
+        assert_close(levels.left.rms, levels.left.peak * FRAC_1_SQRT_2);
+        assert_close(levels.right.peak, 0.25);
+        assert_close(levels.right.rms, levels.right.peak * FRAC_1_SQRT_2);
+    }
+
+    #[test]
+    fn levels_are_published_once_the_window_is_complete() {
+        let mut meter = VuMeter::new(3);
+        let frame = sine_frame(2, 0.5, 0.5);
+
+        meter.process(&frame);
+        meter.process(&frame);
+        assert_eq!(meter.levels(), VuLevels::default());
+
+        meter.process(&frame);
+        assert_close(meter.levels().left.peak, 0.5);
+
+        // The next window starts over, so a quieter stream replaces the levels
+        for _ in 0..3 {
+            meter.process(&sine_frame(2, 0.1, 0.1));
+        }
+        assert_close(meter.levels().left.peak, 0.1);
+        assert_close(meter.levels().right.rms, 0.1 * FRAC_1_SQRT_2);
+    }
+
+    #[test]
+    fn a_single_spike_is_captured_as_the_peak() {
+        let mut meter = VuMeter::new(2);
+        let mut spike = sine_frame(1, 0.0, 0.0);
+        spike[8..10].copy_from_slice(&i16::MIN.to_le_bytes());
+
+        meter.process(&sine_frame(1, 0.0, 0.0));
+        meter.process(&spike);
+
+        let levels = meter.levels();
+        assert_eq!(levels.left.peak, 1.0);
+        assert!(levels.left.rms > 0.0 && levels.left.rms < 0.2);
+        assert_eq!(levels.right, ChannelLevel::default());
+    }
+}
@@ -1,16 +1,66 @@
 #![no_std]
 #![no_main]
 
+use crate::audio::audio_behavior::AudioBehavior;
+use crate::audio::audio_mapping::MAX_VOLUME;
+use crate::audio::behavior_sink::BehaviorSink;
+use crate::audio::gain_model::GainModel;
 use crate::bluetooth::bluetooth_service::BluetoothService;
-use crate::csr8645::csr8645::Csr8645Error;
-use alloc::string::String;
+use crate::bluetooth::codec_fallback::{CodecFallback, CodecFallbackConfig};
+use crate::bluetooth::link_stats::LinkStats;
+use crate::bluetooth::status_summary::{RssiSample, StatusSummary};
+use crate::csr8645::bt_addr::BtAddr;
+use crate::csr8645::csr8645::{
+    AvrcpCommand, ConnectionState, Csr8645Error, EventMode, ScannedDevice,
+};
+use crate::storage::config_store::SharedConfigStore;
+use alloc::string::{String, ToString};
 use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use defmt::{error, info, warn};
+use embassy_time::{Duration, Instant, Timer};
+
+/// The number of connection attempts made when reconnecting on boot.
+const RECONNECT_ATTEMPTS: u8 = 3;
+
+/// The delay between two connection attempts.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// The length of the window the data channel throughput is measured over.
+const LINK_STATS_WINDOW: Duration = Duration::from_secs(1);
+
+/// The shortest time between two behaviors sent to the module by default.
+const DEFAULT_BEHAVIOR_INTERVAL: Duration = Duration::from_millis(100);
 
 /// `BluetoothController` is a struct that controls the Bluetooth services.
 ///
 /// It uses an instance of a type that implements the `BluetoothService` trait to handle Bluetooth operations.
 pub struct BluetoothController<'a, T: BluetoothService + 'a> {
     bluetooth_service: T,
+    /// Decides when to fall back to a more robust codec on a weak link.
+    codec_fallback: RefCell<CodecFallback>,
+    /// Persists the address of the last connected device.
+    config_store: &'a SharedConfigStore<'a>,
+    /// Measures the traffic on the data channel since the last connection.
+    link_stats: RefCell<LinkStats>,
+    /// The lowest volume ever applied, overriding the mapping and the presets.
+    min_volume: u8,
+    /// The highest volume ever applied, overriding the mapping and the presets.
+    max_volume: u8,
+    /// Converts the target gain of a behavior into volume levels.
+    gain_model: GainModel,
+    /// The shortest time between two behaviors sent to the module.
+    behavior_interval: Duration,
+    /// The time the last behavior was sent to the module.
+    last_behavior_at: Cell<Option<Instant>>,
+    /// The latest behavior held back by the rate limit, superseding any earlier one.
+    pending_behavior: Cell<Option<AudioBehavior>>,
+    /// The offset added to every mapped volume, e.g. for rear speakers.
+    volume_trim: Cell<i8>,
+    /// The latest signal strength read, reported by `status_summary`.
+    last_rssi: Cell<Option<RssiSample>>,
+    /// The volume last applied, reported by `status_summary`.
+    last_volume: Cell<Option<u8>>,
 }
 
 impl<'a, T: BluetoothService> BluetoothController<'a, T> {
@@ -19,12 +69,130 @@ impl<'a, T: BluetoothService> BluetoothController<'a, T> {
     /// # Arguments
     ///
     /// * `bluetooth_service` - An instance of a type that implements the `BluetoothService` trait.
+    /// * `config_store` - The store persisting the address of the last connected device and
+    ///   the volume trim.
     ///
     /// # Returns
     ///
     /// * `Self` - The new `BluetoothController` instance.
-    pub fn new(bluetooth_service: T) -> Self {
-        Self { bluetooth_service }
+    pub fn new(bluetooth_service: T, config_store: &'a SharedConfigStore<'a>) -> Self {
+        let volume_trim = config_store.borrow().volume_trim();
+
+        Self {
+            bluetooth_service,
+            codec_fallback: RefCell::new(CodecFallback::new(CodecFallbackConfig::default())),
+            config_store,
+            link_stats: RefCell::new(LinkStats::new(LINK_STATS_WINDOW)),
+            min_volume: 0,
+            max_volume: MAX_VOLUME,
+            gain_model: GainModel::default(),
+            behavior_interval: DEFAULT_BEHAVIOR_INTERVAL,
+            last_behavior_at: Cell::new(None),
+            pending_behavior: Cell::new(None),
+            volume_trim: Cell::new(volume_trim),
+            last_rssi: Cell::new(None),
+            last_volume: Cell::new(None),
+        }
+    }
+
+    /// Returns the service the controller talks through.
+    pub fn service(&self) -> &T {
+        &self.bluetooth_service
+    }
+
+    /// Sets the model converting the target gain of a behavior into volume levels, e.g. with a
+    /// table calibrated for the amplifier in use.
+    ///
+    /// # Arguments
+    ///
+    /// * `gain_model` - The new gain model.
+    pub fn set_gain_model(&mut self, gain_model: GainModel) {
+        self.gain_model = gain_model;
+    }
+
+    /// Sets the shortest time between two behaviors sent to the module.
+    ///
+    /// # Arguments
+    ///
+    /// * `interval` - The new interval, zero to send every behavior.
+    pub fn set_behavior_interval(&mut self, interval: Duration) {
+        self.behavior_interval = interval;
+    }
+
+    /// Sets the thresholds used to fall back to SBC on a weak link.
+    ///
+    /// The fallback state is reset and the preferred codec is assumed to be active.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The new codec fallback thresholds.
+    pub fn set_codec_fallback_config(&mut self, config: CodecFallbackConfig) {
+        *self.codec_fallback.get_mut() = CodecFallback::new(config);
+    }
+
+    /// Sets the hard floor and ceiling of the volume.
+    ///
+    /// Every volume applied through `set_volume` or `alter_behavior` is clamped into the range,
+    /// whatever the mapping and the active preset ask for.
+    ///
+    /// # Arguments
+    ///
+    /// * `min` - The lowest volume applied.
+    /// * `max` - The highest volume applied.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or `InvalidParameter` if `min` exceeds `max` or `max`
+    /// exceeds `MAX_VOLUME`.
+    pub fn set_volume_limits(&mut self, min: u8, max: u8) -> Result<(), Csr8645Error> {
+        if min > max || max > MAX_VOLUME {
+            error!("Rejected volume limits {}..={}", min, max);
+            return Err(Csr8645Error::InvalidParameter);
+        }
+
+        self.min_volume = min;
+        self.max_volume = max;
+        Ok(())
+    }
+
+    /// Sets the offset added to every mapped volume, and persists it.
+    ///
+    /// The trim shifts the volume of each behavior, e.g. to balance rear speakers or a second
+    /// phone connected in multipoint, before it is clamped into the valid range and the volume
+    /// limits. It takes effect with the next behavior.
+    ///
+    /// # Arguments
+    ///
+    /// * `delta` - The volume offset, in levels, clamped to plus or minus `MAX_VOLUME`.
+    pub fn set_volume_trim(&self, delta: i8) {
+        let max = MAX_VOLUME as i8;
+        let trim = delta.clamp(-max, max);
+        self.volume_trim.set(trim);
+
+        if let Err(e) = self.config_store.borrow_mut().set_volume_trim(trim) {
+            error!("Failed to persist the volume trim: {:?}", e);
+        }
+    }
+
+    /// Returns the offset added to every mapped volume.
+    pub fn volume_trim(&self) -> i8 {
+        self.volume_trim.get()
+    }
+
+    /// Sets the volume, clamped into the volume limits.
+    ///
+    /// # Arguments
+    ///
+    /// * `volume` - The volume to apply.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    pub async fn set_volume(&self, volume: u8) -> Result<(), Csr8645Error> {
+        let volume = volume.clamp(self.min_volume, self.max_volume);
+        self.bluetooth_service.set_volume(volume).await?;
+        self.last_volume.set(Some(volume));
+        Ok(())
     }
 
     /// Initializes the CSR8645 module with the given settings.
@@ -36,17 +204,35 @@ impl<'a, T: BluetoothService> BluetoothController<'a, T> {
     /// # Returns
     ///
     /// A `Result` indicating the success or failure of the operation.
-    pub fn initialize(&self, pin: &str) -> Result<(), Csr8645Error> {
-        self.bluetooth_service.initialize(pin)
+    pub async fn initialize(&self, pin: &str) -> Result<(), Csr8645Error> {
+        self.bluetooth_service.initialize(pin).await
     }
 
     /// Scans for nearby devices.
     ///
     /// # Returns
     ///
-    /// A `Result` containing a list of the addresses of the nearby devices or an error.
-    pub fn scan_devices(&self) -> Result<Vec<String>, Csr8645Error> {
-        self.bluetooth_service.scan_devices()
+    /// A `Result` containing a list of the nearby devices or an error.
+    pub async fn scan_devices(&self) -> Result<Vec<ScannedDevice>, Csr8645Error> {
+        self.bluetooth_service.scan_devices().await
+    }
+
+    /// Scans for nearby devices whose name starts with the given prefix, ignoring case.
+    ///
+    /// # Arguments
+    ///
+    /// * `name_prefix` - The prefix the device names must start with.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a list of the matching nearby devices or an error.
+    pub async fn scan_devices_filtered(
+        &self,
+        name_prefix: &str,
+    ) -> Result<Vec<ScannedDevice>, Csr8645Error> {
+        self.bluetooth_service
+            .scan_devices_filtered(name_prefix)
+            .await
     }
 
     /// Connects to a device with the given address.
@@ -58,8 +244,105 @@ impl<'a, T: BluetoothService> BluetoothController<'a, T> {
     /// # Returns
     ///
     /// A `Result` indicating the success or failure of the operation.
-    pub fn connect_to_device(&self, address: &str) -> Result<(), Csr8645Error> {
-        self.bluetooth_service.connect_to_device(address)
+    pub async fn connect_to_device(&self, address: BtAddr) -> Result<(), Csr8645Error> {
+        self.bluetooth_service.connect_to_device(&address).await?;
+        self.link_stats.borrow_mut().reset();
+
+        match self.bluetooth_service.connected_device().await {
+            Ok(Some(device)) => info!(
+                "Connected to {=str} ({})",
+                device.name.as_deref().unwrap_or("unnamed"),
+                device.address
+            ),
+            Ok(None) => warn!("Connected, but the module reports no peer"),
+            Err(e) => warn!("Failed to query the connected device: {:?}", e),
+        }
+
+        if let Err(e) = self
+            .config_store
+            .borrow_mut()
+            .set_last_address(&address.to_string())
+        {
+            error!("Failed to persist the last address: {:?}", e);
+        }
+
+        Ok(())
+    }
+
+    /// Connects to a device with the given address text.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address of the device, as `AABBCCDDEEFF` or `AA:BB:CC:DD:EE:FF`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation, with
+    /// `Csr8645Error::InvalidParameter` if the address is malformed.
+    pub async fn connect_to_device_str(&self, address: &str) -> Result<(), Csr8645Error> {
+        let address = address
+            .parse::<BtAddr>()
+            .map_err(|_| Csr8645Error::InvalidParameter)?;
+
+        self.connect_to_device(address).await
+    }
+
+    /// Connects to a device, retrying on failure.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address of the device to connect to.
+    /// * `attempts` - The maximum number of connection attempts.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success of the operation or the error of the last attempt.
+    pub async fn connect_with_retry(
+        &self,
+        address: BtAddr,
+        attempts: u8,
+    ) -> Result<(), Csr8645Error> {
+        let mut attempt = 1;
+        loop {
+            match self.connect_to_device(address).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt >= attempts => return Err(e),
+                Err(e) => {
+                    warn!("Connection attempt {} failed: {:?}", attempt, e);
+                    attempt += 1;
+                    Timer::after(RECONNECT_DELAY).await;
+                }
+            }
+        }
+    }
+
+    /// Reconnects to the last connected device, if one was stored.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing whether a device was reconnected, or the error of the last attempt.
+    /// If no address was stored, `Ok(false)` is returned.
+    pub async fn auto_reconnect(&self) -> Result<bool, Csr8645Error> {
+        let stored = self
+            .config_store
+            .borrow()
+            .last_address()
+            .map(|a| a.parse::<BtAddr>());
+        let address = match stored {
+            Some(Ok(address)) => address,
+            Some(Err(_)) => {
+                warn!("Ignoring malformed stored address");
+                return Ok(false);
+            }
+            None => {
+                info!("No stored device to reconnect to");
+                return Ok(false);
+            }
+        };
+
+        info!("Reconnecting to {}", address);
+        self.connect_with_retry(address, RECONNECT_ATTEMPTS).await?;
+        Ok(true)
     }
 
     /// Sends data to the connected device.
@@ -71,8 +354,52 @@ impl<'a, T: BluetoothService> BluetoothController<'a, T> {
     /// # Returns
     ///
     /// A `Result` indicating the success or failure of the operation.
-    pub fn send_data(&self, data: &[u8]) -> Result<(), Csr8645Error> {
-        self.bluetooth_service.send_data(data)
+    pub async fn send_data(&self, data: &[u8]) -> Result<(), Csr8645Error> {
+        self.bluetooth_service.send_data(data).await
+    }
+
+    /// Sends data to the connected device, accounting it in the link statistics.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The data to send.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    pub async fn send_framed(&self, data: &[u8]) -> Result<(), Csr8645Error> {
+        self.bluetooth_service.send_data(data).await?;
+        self.link_stats
+            .borrow_mut()
+            .record_sent(Instant::now(), data);
+
+        Ok(())
+    }
+
+    /// Receives data from the connected device, accounting it in the link statistics.
+    ///
+    /// A read filling the whole buffer may have been truncated, so the caller must treat
+    /// `n == buffer.len()` as possibly incomplete.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - The buffer where the received data will be stored.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` holding the number of bytes received, at the start of `buffer`.
+    pub async fn receive_framed(&self, buffer: &mut [u8]) -> Result<usize, Csr8645Error> {
+        let len = self.bluetooth_service.receive_data(buffer).await?;
+        self.link_stats
+            .borrow_mut()
+            .record_received(Instant::now(), &buffer[..len]);
+
+        Ok(len)
+    }
+
+    /// Returns the traffic measured on the data channel since the last connection.
+    pub fn link_stats(&self) -> LinkStats {
+        *self.link_stats.borrow()
     }
 
     /// Transmits audio data to the CSR8645 module.
@@ -84,8 +411,8 @@ impl<'a, T: BluetoothService> BluetoothController<'a, T> {
     /// # Returns
     ///
     /// A `Result` indicating the success or failure of the operation.
-    pub fn transmit_audio(&self, audio_data: &[u8]) -> Result<(), Csr8645Error> {
-        self.bluetooth_service.transmit_audio(audio_data)
+    pub async fn transmit_audio(&self, audio_data: &[u8]) -> Result<(), Csr8645Error> {
+        self.bluetooth_service.transmit_audio(audio_data).await
     }
 
     /// Receives audio data from the CSR8645 module.
@@ -96,8 +423,796 @@ impl<'a, T: BluetoothService> BluetoothController<'a, T> {
     ///
     /// # Returns
     ///
+    /// A `Result` holding the number of bytes received, at the start of `buffer`.
+    pub async fn receive_audio(&self, buffer: &mut [u8]) -> Result<usize, Csr8645Error> {
+        self.bluetooth_service.receive_audio(buffer).await
+    }
+
+    /// Applies an audio behavior to the CSR8645 module, at most once per behavior interval.
+    ///
+    /// The mapping may produce a new behavior on every reading, which would flood the module
+    /// UART during fast RPM changes. A behavior arriving within the interval is held back,
+    /// replacing any behavior already held, and is sent by the next call after the interval
+    /// or by `flush_behavior`.
+    ///
+    /// # Arguments
+    ///
+    /// * `behavior` - The audio behavior to apply.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    pub async fn alter_behavior(&self, behavior: AudioBehavior) -> Result<(), Csr8645Error> {
+        if let Some(last) = self.last_behavior_at.get() {
+            if Instant::now() < last + self.behavior_interval {
+                self.pending_behavior.set(Some(behavior));
+                return Ok(());
+            }
+        }
+
+        self.send_behavior(behavior).await
+    }
+
+    /// Sends the behavior held back by the rate limit, waiting for the interval to elapse.
+    ///
+    /// # Returns
+    ///
     /// A `Result` indicating the success or failure of the operation.
-    pub fn receive_audio(&self, buffer: &mut [u8]) -> Result<(), Csr8645Error> {
-        self.bluetooth_service.receive_audio(buffer)
+    pub async fn flush_behavior(&self) -> Result<(), Csr8645Error> {
+        let Some(behavior) = self.pending_behavior.get() else {
+            return Ok(());
+        };
+
+        if let Some(last) = self.last_behavior_at.get() {
+            Timer::at(last + self.behavior_interval).await;
+        }
+        self.send_behavior(behavior).await
+    }
+
+    /// Sends an audio behavior to the CSR8645 module.
+    ///
+    /// The target gain shifts the volume along the gain model and the volume trim is added,
+    /// then the volume is clamped into the valid range and the volume limits. While muted the
+    /// volume is left untouched, so only the bass follows the behavior.
+    ///
+    /// # Arguments
+    ///
+    /// * `behavior` - The audio behavior to apply.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    async fn send_behavior(&self, behavior: AudioBehavior) -> Result<(), Csr8645Error> {
+        self.pending_behavior.set(None);
+        self.last_behavior_at.set(Some(Instant::now()));
+
+        if !self.bluetooth_service.is_muted().await {
+            let volume = self
+                .gain_model
+                .apply(behavior.volume, behavior.target_gain_db);
+            let volume =
+                (volume as i16 + self.volume_trim.get() as i16).clamp(0, MAX_VOLUME as i16);
+            self.set_volume(volume as u8).await?;
+        }
+        // Firmwares without an equalizer still get the volume changes
+        match self.bluetooth_service.set_bass(behavior.bass).await {
+            Err(Csr8645Error::Unsupported) => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Returns the state of the link with the remote device.
+    pub async fn connection_state(&self) -> ConnectionState {
+        self.bluetooth_service.connection_state().await
+    }
+
+    /// Returns how link changes are detected, by notification or by polling.
+    pub async fn event_mode(&self) -> EventMode {
+        self.bluetooth_service.event_mode().await
+    }
+
+    /// Gathers the state of the link and the module into one snapshot.
+    ///
+    /// Only the connected device and the module state are queried; the other fields are the
+    /// latest values already known, as documented on `StatusSummary`. A failed query leaves its
+    /// field empty instead of failing the whole summary.
+    ///
+    /// # Returns
+    ///
+    /// * `StatusSummary` - The snapshot of the link and the module.
+    pub async fn status_summary(&self) -> StatusSummary {
+        let peer = match self.bluetooth_service.connected_device().await {
+            Ok(peer) => peer,
+            Err(e) => {
+                warn!("Failed to query the connected device: {:?}", e);
+                None
+            }
+        };
+        let module_state = match self.bluetooth_service.get_status().await {
+            Ok(state) => Some(state),
+            Err(e) => {
+                warn!("Failed to query the module state: {:?}", e);
+                None
+            }
+        };
+
+        StatusSummary {
+            connection_state: self.bluetooth_service.connection_state().await,
+            peer,
+            rssi: self.last_rssi.get(),
+            volume: self.last_volume.get(),
+            muted: self.bluetooth_service.is_muted().await,
+            codec: self.codec_fallback.borrow().active_codec(),
+            module_state,
+        }
+    }
+
+    /// Enables or disables multipoint connections to two devices.
+    ///
+    /// # Arguments
+    ///
+    /// * `on` - True to enable multipoint, false to disable it.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    pub async fn enable_multipoint(&self, on: bool) -> Result<(), Csr8645Error> {
+        self.bluetooth_service.enable_multipoint(on).await
+    }
+
+    /// Returns the addresses of the connected devices, oldest first.
+    pub async fn connected_devices(&self) -> Vec<BtAddr> {
+        self.bluetooth_service.connected_devices().await
+    }
+
+    /// Gets the address and name of the device currently connected.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the connected device, `None` if no device is connected, or an error.
+    pub async fn connected_device(&self) -> Result<Option<ScannedDevice>, Csr8645Error> {
+        self.bluetooth_service.connected_device().await
+    }
+
+    /// Gets the signal strength of the current connection.
+    ///
+    /// The reading is kept as the RSSI reported by `status_summary`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the RSSI of the connection in dBm or an error.
+    pub async fn rssi(&self) -> Result<i8, Csr8645Error> {
+        let rssi = self.bluetooth_service.get_rssi().await?;
+        self.record_rssi(rssi);
+        Ok(rssi)
+    }
+
+    /// Remembers a signal strength reading for `status_summary`.
+    fn record_rssi(&self, dbm: i8) {
+        self.last_rssi.set(Some(RssiSample {
+            dbm,
+            at: Instant::now(),
+        }));
+    }
+
+    /// Checks that the module responds to commands.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    pub async fn ping(&self) -> Result<(), Csr8645Error> {
+        self.bluetooth_service.ping().await
+    }
+
+    /// Gets the firmware version of the module.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the version string or an error.
+    pub async fn firmware_version(&self) -> Result<String, Csr8645Error> {
+        self.bluetooth_service.get_version().await
+    }
+
+    /// Brings the Bluetooth link down cleanly before power is lost, e.g. on ignition-off.
+    ///
+    /// The connected device is disconnected, pending audio is flushed, its address is persisted
+    /// so it can be reconnected on the next boot, and the module is put to sleep. Each step is
+    /// attempted even if a previous one failed.
+    pub async fn shutdown(&self) {
+        let peer = match self.bluetooth_service.connected_device().await {
+            Ok(peer) => peer,
+            Err(e) => {
+                warn!("Failed to query the connected device: {:?}", e);
+                None
+            }
+        };
+
+        if let Err(e) = self.bluetooth_service.disconnect().await {
+            error!("Failed to disconnect: {:?}", e);
+        }
+
+        if let Err(e) = self.bluetooth_service.flush_audio().await {
+            error!("Failed to flush audio: {:?}", e);
+        }
+
+        if let Some(device) = peer {
+            if let Err(e) = self
+                .config_store
+                .borrow_mut()
+                .set_last_address(&device.address.to_string())
+            {
+                error!("Failed to persist the last address: {:?}", e);
+            }
+        }
+
+        if let Err(e) = self.bluetooth_service.sleep().await {
+            error!("Failed to put the module to sleep: {:?}", e);
+        }
+
+        info!("Bluetooth shut down");
+    }
+
+    /// Answers the incoming call, e.g. from a steering-wheel button.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    pub async fn answer_call(&self) -> Result<(), Csr8645Error> {
+        self.bluetooth_service.answer_call().await
+    }
+
+    /// Rejects the incoming call.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    pub async fn reject_call(&self) -> Result<(), Csr8645Error> {
+        self.bluetooth_service.reject_call().await
+    }
+
+    /// Ends the call in progress.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    pub async fn end_call(&self) -> Result<(), Csr8645Error> {
+        self.bluetooth_service.end_call().await
+    }
+
+    /// Forwards a track control command from the head unit to the connected phone.
+    ///
+    /// # Arguments
+    ///
+    /// * `cmd` - The passthrough command to forward.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    pub async fn avrcp(&self, cmd: AvrcpCommand) -> Result<(), Csr8645Error> {
+        self.bluetooth_service.avrcp(cmd).await
+    }
+
+    /// Plays a logarithmic sine sweep, letting installers find rattles and check the speakers.
+    ///
+    /// # Arguments
+    ///
+    /// * `start_hz` - The frequency at the start of the sweep, in Hz.
+    /// * `end_hz` - The frequency at the end of the sweep, in Hz.
+    /// * `duration` - The length of the sweep.
+    /// * `gain` - The gain of the sweep, between 0.0 and 1.0.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    pub async fn play_sweep(
+        &self,
+        start_hz: f32,
+        end_hz: f32,
+        duration: Duration,
+        gain: f32,
+    ) -> Result<(), Csr8645Error> {
+        self.bluetooth_service
+            .play_sweep(start_hz, end_hz, duration, gain)
+            .await
+    }
+
+    /// Mutes the audio output, for example during a phone call.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    pub async fn mute(&self) -> Result<(), Csr8645Error> {
+        self.bluetooth_service.mute().await
+    }
+
+    /// Unmutes the audio output, restoring the volume it had before muting.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    pub async fn unmute(&self) -> Result<(), Csr8645Error> {
+        self.bluetooth_service.unmute().await
+    }
+
+    /// Enables or disables dry-run mode.
+    ///
+    /// In dry-run mode the AT commands are logged and recorded instead of being written to the
+    /// module, which allows bench development without attached hardware.
+    ///
+    /// # Arguments
+    ///
+    /// * `enable` - True to enable dry-run mode, false to disable it.
+    pub async fn set_dry_run(&self, enable: bool) {
+        self.bluetooth_service.set_dry_run(enable).await
+    }
+
+    /// Returns the commands recorded while in dry-run mode.
+    ///
+    /// # Returns
+    ///
+    /// A list of the recorded commands, in the order they were issued.
+    pub async fn recorded_commands(&self) -> Vec<Vec<u8>> {
+        self.bluetooth_service.recorded_commands().await
+    }
+
+    /// Samples the link quality and switches codec if needed.
+    ///
+    /// When the RSSI stays below the fallback threshold for enough consecutive samples the codec
+    /// is switched to SBC, and it is switched back once the signal stays above the recovery
+    /// threshold. The codec is only considered switched once the module acknowledged it, and a
+    /// module that does not support codec selection disables the fallback.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    pub async fn monitor_link_quality(&self) -> Result<(), Csr8645Error> {
+        let rssi = self.rssi().await?;
+
+        // The borrow ends before the codec is sent, so the fallback is never held across an await
+        let proposed = self.codec_fallback.borrow_mut().update(rssi);
+        if let Some(codec) = proposed {
+            info!("Switching codec to {:?} at RSSI {} dBm", codec, rssi);
+            match self.bluetooth_service.set_codec(codec).await {
+                Ok(()) => self.codec_fallback.borrow_mut().commit(codec),
+                Err(Csr8645Error::Unsupported) => {
+                    warn!("Codec selection is not supported, disabling the codec fallback");
+                    self.codec_fallback.borrow_mut().disable();
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The sink is implemented on a shared reference, so the controller the app talks to can also be
+/// the sink its behaviors are applied to.
+impl<'a, T: BluetoothService> BehaviorSink for &BluetoothController<'a, T> {
+    async fn apply(&mut self, behavior: &AudioBehavior) {
+        if let Err(e) = self.alter_behavior(*behavior).await {
+            error!("Failed to apply the audio behavior: {:?}", e);
+        }
+    }
+
+    async fn flush(&mut self) {
+        if let Err(e) = self.flush_behavior().await {
+            error!("Failed to apply the audio behavior: {:?}", e);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "simulation"))]
+mod tests {
+    use super::*;
+    use crate::bluetooth::mock_csr8645::MockCsr8645Interface;
+    use crate::csr8645::csr8645::{BtEvent, ModuleState};
+    use crate::storage::config_store::ConfigStore;
+    use crate::storage::ram_flash::RamFlash;
+    use embassy_futures::block_on;
+
+    /// The size of the simulated flash region holding the configuration records.
+    const FLASH_REGION_SIZE: usize = 4096;
+
+    /// Returns a controller over a mock module, sending every behavior it is given.
+    fn mock_controller<'a>(
+        config_store: &'a SharedConfigStore<'a>,
+    ) -> BluetoothController<'a, MockCsr8645Interface> {
+        let mut controller = BluetoothController::new(MockCsr8645Interface::new(), config_store);
+        controller.set_behavior_interval(Duration::from_ticks(0));
+        controller
+    }
+
+    /// Applies a behavior with the given mapped volume and returns the volume sent to the module.
+    fn applied_volume(
+        controller: &BluetoothController<'_, MockCsr8645Interface>,
+        volume: u8,
+    ) -> u8 {
+        let behavior = AudioBehavior {
+            volume,
+            bass: 6,
+            ..AudioBehavior::default()
+        };
+        block_on(controller.alter_behavior(behavior)).unwrap();
+        controller
+            .service()
+            .applied_behaviors()
+            .last()
+            .unwrap()
+            .volume
+    }
+
+    #[test]
+    fn the_target_gain_shifts_the_volume_along_the_gain_model() {
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let controller = mock_controller(&config_store);
+        let applied = |target_gain_db| {
+            let behavior = AudioBehavior {
+                volume: 8,
+                target_gain_db,
+                ..AudioBehavior::default()
+            };
+            block_on(controller.alter_behavior(behavior)).unwrap();
+            controller
+                .service()
+                .applied_behaviors()
+                .last()
+                .unwrap()
+                .volume
+        };
+
+        assert_eq!(applied(0.0), 8);
+        assert_eq!(applied(6.0), 10);
+        assert_eq!(applied(-6.0), 6);
+    }
+
+    #[test]
+    fn rapid_behaviors_are_coalesced_into_the_latest_one() {
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let mut controller = mock_controller(&config_store);
+        let interval = Duration::from_millis(200);
+        controller.set_behavior_interval(interval);
+        let behavior = |volume| AudioBehavior {
+            volume,
+            bass: volume / 2,
+            ..AudioBehavior::default()
+        };
+        let start = Instant::now();
+
+        block_on(async {
+            controller.alter_behavior(behavior(1)).await.unwrap();
+            for volume in 2..12 {
+                controller.alter_behavior(behavior(volume)).await.unwrap();
+            }
+            // The ten updates arrived within the interval, so only the first one went out
+            assert_eq!(controller.service().applied_behaviors().len(), 1);
+
+            controller.flush_behavior().await.unwrap();
+        });
+
+        assert!(Instant::now() - start >= interval);
+        assert_eq!(
+            controller.service().applied_behaviors(),
+            [behavior(1), behavior(11)]
+        );
+    }
+
+    #[test]
+    fn a_behavior_after_the_interval_is_sent_at_once() {
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let mut controller = mock_controller(&config_store);
+        let interval = Duration::from_millis(20);
+        controller.set_behavior_interval(interval);
+
+        assert_eq!(applied_volume(&controller, 5), 5);
+        assert_eq!(applied_volume(&controller, 7), 5);
+        block_on(Timer::after(interval));
+        assert_eq!(applied_volume(&controller, 9), 9);
+        // The held behavior was superseded, so nothing is left to flush
+        block_on(controller.flush_behavior()).unwrap();
+        assert_eq!(controller.service().applied_behaviors().len(), 2);
+    }
+
+    #[test]
+    fn the_volume_trim_shifts_the_mapped_volume() {
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let controller = mock_controller(&config_store);
+        assert_eq!(applied_volume(&controller, 8), 8);
+
+        controller.set_volume_trim(3);
+        assert_eq!(applied_volume(&controller, 8), 11);
+
+        controller.set_volume_trim(-2);
+        assert_eq!(applied_volume(&controller, 8), 6);
+    }
+
+    #[test]
+    fn the_trimmed_volume_is_clamped_at_both_ends() {
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let mut controller = mock_controller(&config_store);
+
+        controller.set_volume_trim(5);
+        assert_eq!(applied_volume(&controller, 13), MAX_VOLUME);
+        controller.set_volume_trim(-5);
+        assert_eq!(applied_volume(&controller, 3), 0);
+
+        // The volume limits still apply on top of the trim
+        controller.set_volume_limits(2, 12).unwrap();
+        assert_eq!(applied_volume(&controller, 3), 2);
+        controller.set_volume_trim(5);
+        assert_eq!(applied_volume(&controller, 10), 12);
+    }
+
+    #[test]
+    fn the_volume_trim_is_clamped_to_the_volume_range() {
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let controller = mock_controller(&config_store);
+
+        controller.set_volume_trim(i8::MAX);
+        assert_eq!(controller.volume_trim(), MAX_VOLUME as i8);
+        controller.set_volume_trim(i8::MIN);
+        assert_eq!(controller.volume_trim(), -(MAX_VOLUME as i8));
+    }
+
+    #[test]
+    fn the_volume_trim_persists_across_reboots() {
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        {
+            let config_store = RefCell::new(ConfigStore::new(&mut flash));
+            mock_controller(&config_store).set_volume_trim(-4);
+        }
+
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let controller = mock_controller(&config_store);
+
+        assert_eq!(controller.volume_trim(), -4);
+        assert_eq!(applied_volume(&controller, 9), 5);
+    }
+
+    #[test]
+    fn a_fresh_status_summary_has_no_cached_values() {
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let controller = mock_controller(&config_store);
+
+        let summary = block_on(controller.status_summary());
+
+        assert_eq!(summary.connection_state, ConnectionState::Connected);
+        assert_eq!(summary.module_state, Some(ModuleState::Connected));
+        assert_eq!(summary.rssi, None);
+        assert_eq!(summary.volume, None);
+        assert!(!summary.muted);
+        let preferred = CodecFallback::new(CodecFallbackConfig::default()).active_codec();
+        assert_eq!(summary.codec, preferred);
+    }
+
+    #[test]
+    fn the_status_summary_reflects_prior_state_changes() {
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let controller = mock_controller(&config_store);
+        let before = Instant::now();
+
+        let rssi = block_on(controller.rssi()).unwrap();
+        applied_volume(&controller, 10);
+        let summary = block_on(async {
+            controller.mute().await.unwrap();
+            controller.service().push_event(BtEvent::Disconnected);
+            controller.service().poll_event().await.unwrap();
+            controller.status_summary().await
+        });
+
+        // The live queries follow the link, the cached values stay as last known
+        assert_eq!(summary.connection_state, ConnectionState::Disconnected);
+        assert_eq!(summary.module_state, Some(ModuleState::Disconnected));
+        assert_eq!(summary.peer, None);
+        assert_eq!(summary.rssi.map(|sample| sample.dbm), Some(rssi));
+        assert!(summary.rssi.is_some_and(|sample| sample.at >= before));
+        assert_eq!(summary.volume, Some(10));
+        assert!(summary.muted);
+    }
+
+    #[test]
+    fn a_muted_behavior_keeps_the_cached_volume() {
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let controller = mock_controller(&config_store);
+        applied_volume(&controller, 7);
+
+        block_on(controller.mute()).unwrap();
+        applied_volume(&controller, 12);
+
+        assert_eq!(block_on(controller.status_summary()).volume, Some(7));
+    }
+
+    #[test]
+    fn behaviors_applied_while_muted_leave_the_volume_alone() {
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let controller = mock_controller(&config_store);
+        applied_volume(&controller, 7);
+
+        block_on(controller.mute()).unwrap();
+        // The OBD readings keep changing the mapped volume during the mute
+        assert_eq!(applied_volume(&controller, 12), 7);
+        assert_eq!(applied_volume(&controller, 3), 7);
+        block_on(controller.unmute()).unwrap();
+
+        assert_eq!(applied_volume(&controller, 12), 12);
+    }
+
+    #[test]
+    fn auto_reconnect_without_a_stored_address_does_nothing() {
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let controller = mock_controller(&config_store);
+
+        assert!(!block_on(controller.auto_reconnect()).unwrap());
+        assert!(controller.service().connections().is_empty());
+    }
+
+    #[test]
+    fn auto_reconnect_connects_to_the_stored_address() {
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        {
+            let config_store = RefCell::new(ConfigStore::new(&mut flash));
+            let controller = mock_controller(&config_store);
+            block_on(controller.connect_to_device_str("A1:B2:C3:D4:E5:F6")).unwrap();
+        }
+
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let controller = mock_controller(&config_store);
+
+        assert!(block_on(controller.auto_reconnect()).unwrap());
+        assert_eq!(
+            controller.service().connections(),
+            ["A1:B2:C3:D4:E5:F6".parse::<BtAddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn auto_reconnect_ignores_a_malformed_stored_address() {
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        config_store
+            .borrow_mut()
+            .set_last_address("not an address")
+            .unwrap();
+        let controller = mock_controller(&config_store);
+
+        assert!(!block_on(controller.auto_reconnect()).unwrap());
+        assert!(controller.service().connections().is_empty());
+    }
+
+    #[test]
+    fn shutdown_disconnects_flushes_persists_and_sleeps_in_order() {
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let controller = mock_controller(&config_store);
+        let address = "A1:B2:C3:D4:E5:F6".parse::<BtAddr>().unwrap();
+        // Connected behind the controller's back, so only the shutdown can persist the address
+        block_on(controller.service().connect_to_device(&address)).unwrap();
+        assert_eq!(config_store.borrow().last_address(), None);
+
+        block_on(controller.shutdown());
+
+        assert_eq!(
+            controller.service().commands(),
+            ["connect", "disconnect", "flush_audio", "sleep"]
+        );
+        assert_eq!(
+            block_on(controller.connection_state()),
+            ConnectionState::Disconnected
+        );
+        assert_eq!(
+            config_store.borrow().last_address(),
+            Some(address.to_string().as_str())
+        );
+    }
+
+    #[test]
+    fn shutdown_without_a_peer_keeps_the_stored_address() {
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        config_store
+            .borrow_mut()
+            .set_last_address("A1:B2:C3:D4:E5:F6")
+            .unwrap();
+        let controller = mock_controller(&config_store);
+
+        block_on(controller.shutdown());
+
+        assert_eq!(
+            controller.service().commands(),
+            ["disconnect", "flush_audio", "sleep"]
+        );
+        assert_eq!(
+            config_store.borrow().last_address(),
+            Some("A1:B2:C3:D4:E5:F6")
+        );
+    }
+
+    #[test]
+    fn a_malformed_address_string_never_reaches_the_module() {
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let controller = mock_controller(&config_store);
+
+        let result = block_on(controller.connect_to_device_str("A1:B2:C3:D4:E5"));
+
+        assert!(matches!(result, Err(Csr8645Error::InvalidParameter)));
+        assert!(controller.service().connections().is_empty());
+        assert_eq!(config_store.borrow().last_address(), None);
+    }
+
+    #[test]
+    fn framed_transfers_are_accounted_until_the_next_connection() {
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let controller = mock_controller(&config_store);
+        let mut buffer = [0xFFu8; 4];
+
+        block_on(async {
+            controller.send_framed(b"123456789").await.unwrap();
+            // The mock module fills the whole buffer with zeros
+            controller.receive_framed(&mut buffer).await.unwrap();
+        });
+
+        let stats = controller.link_stats();
+        assert_eq!((stats.sent.bytes, stats.sent.crc), (9, 0xCBF4_3926));
+        assert_eq!((stats.received.bytes, stats.received.crc), (4, 0x2144_DF1C));
+
+        let address = "A1B2C3D4E5F6".parse::<BtAddr>().unwrap();
+        block_on(controller.connect_to_device(address)).unwrap();
+
+        assert_eq!(controller.link_stats(), LinkStats::new(LINK_STATS_WINDOW));
+    }
+
+    #[test]
+    fn mapped_volumes_are_clamped_into_the_volume_limits() {
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let mut controller = mock_controller(&config_store);
+        controller.set_volume_limits(4, 10).unwrap();
+
+        assert_eq!(applied_volume(&controller, MAX_VOLUME), 10);
+        assert_eq!(applied_volume(&controller, 1), 4);
+        assert_eq!(applied_volume(&controller, 7), 7);
+
+        // A volume set directly is clamped too
+        let summary = block_on(async {
+            controller.set_volume(0).await.unwrap();
+            controller.status_summary().await
+        });
+        assert_eq!(summary.volume, Some(4));
+    }
+
+    #[test]
+    fn invalid_volume_limits_are_rejected_and_the_previous_ones_kept() {
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let mut controller = mock_controller(&config_store);
+        controller.set_volume_limits(3, 9).unwrap();
+
+        assert!(matches!(
+            controller.set_volume_limits(9, 3),
+            Err(Csr8645Error::InvalidParameter)
+        ));
+        assert!(matches!(
+            controller.set_volume_limits(0, MAX_VOLUME + 1),
+            Err(Csr8645Error::InvalidParameter)
+        ));
+
+        assert_eq!(applied_volume(&controller, MAX_VOLUME), 9);
+        assert_eq!(applied_volume(&controller, 0), 3);
+        // A single-level range pins the volume
+        controller.set_volume_limits(6, 6).unwrap();
+        assert_eq!(applied_volume(&controller, 12), 6);
     }
 }
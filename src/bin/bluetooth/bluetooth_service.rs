@@ -1,9 +1,18 @@
 #![no_std]
 #![no_main]
 
-use crate::csr8645::csr8645::{Csr8645, Csr8645Error};
+use crate::csr8645::bt_addr::BtAddr;
+use crate::csr8645::byte_channel::ByteChannel;
+use crate::csr8645::csr8645::{
+    AudioCodec, AvrcpCommand, BtEvent, ConnectionState, Csr8645Error, Csr8645Exchange, Csr8645Uart,
+    EventMode, ModuleState, ScannedDevice, SharedCsr8645,
+};
 use alloc::string::String;
 use alloc::vec::Vec;
+use embassy_time::Duration;
+
+/// The longest time the shared CSR8645 is held while waiting for a link notification.
+const EVENT_POLL_WINDOW: Duration = Duration::from_millis(20);
 
 /// `BluetoothService` is a trait that defines the methods necessary to handle Bluetooth operations.
 ///
@@ -19,7 +28,7 @@ pub trait BluetoothService {
     /// # Returns
     ///
     /// A `Result` indicating the success or failure of the operation.
-    fn initialize(&self, pin: &str) -> Result<(), Csr8645Error>;
+    async fn initialize(&self, pin: &str) -> Result<(), Csr8645Error>;
 
     /// Scans for nearby devices.
     ///
@@ -27,8 +36,22 @@ pub trait BluetoothService {
     ///
     /// # Returns
     ///
-    /// A `Result` containing a list of the addresses of the nearby devices or an error.
-    fn scan_devices(&self) -> Result<Vec<String>, Csr8645Error>;
+    /// A `Result` containing a list of the nearby devices or an error.
+    async fn scan_devices(&self) -> Result<Vec<ScannedDevice>, Csr8645Error>;
+
+    /// Scans for nearby devices whose name starts with the given prefix, ignoring case.
+    ///
+    /// # Arguments
+    ///
+    /// * `name_prefix` - The prefix the device names must start with.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a list of the matching nearby devices or an error.
+    async fn scan_devices_filtered(
+        &self,
+        name_prefix: &str,
+    ) -> Result<Vec<ScannedDevice>, Csr8645Error>;
 
     /// Connects to a device with the given address.
     ///
@@ -39,7 +62,7 @@ pub trait BluetoothService {
     /// # Returns
     ///
     /// A `Result` indicating the success or failure of the operation.
-    fn connect_to_device(&self, address: &str) -> Result<(), Csr8645Error>;
+    async fn connect_to_device(&self, address: &BtAddr) -> Result<(), Csr8645Error>;
 
     /// Sends data to the connected device.
     ///
@@ -50,7 +73,7 @@ pub trait BluetoothService {
     /// # Returns
     ///
     /// A `Result` indicating the success or failure of the operation.
-    fn send_data(&self, data: &[u8]) -> Result<(), Csr8645Error>;
+    async fn send_data(&self, data: &[u8]) -> Result<(), Csr8645Error>;
 
     /// Transmits audio data to the CSR8645 module.
     ///
@@ -61,66 +84,482 @@ pub trait BluetoothService {
     /// # Returns
     ///
     /// A `Result` indicating the success or failure of the operation.
-    fn transmit_audio(&self, audio_data: &[u8]) -> Result<(), Csr8645Error>;
+    async fn transmit_audio(&self, audio_data: &[u8]) -> Result<(), Csr8645Error>;
+
+    /// Receives data from the connected device.
+    ///
+    /// Fewer bytes than `buffer.len()` may be received. A read filling the whole buffer may have
+    /// been truncated, so the caller must treat `n == buffer.len()` as possibly incomplete.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - The buffer where the received data will be stored.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` holding the number of bytes received, at the start of `buffer`.
+    async fn receive_data(&self, buffer: &mut [u8]) -> Result<usize, Csr8645Error>;
+
+    /// Receives data from the connected device until the buffer is full.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - The buffer to fill with the received data.
+    /// * `timeout` - How long to wait for the whole buffer to be filled.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation, `Csr8645Error::Timeout`
+    /// if the buffer was not filled in time.
+    async fn receive_exact(&self, buffer: &mut [u8], timeout: Duration)
+        -> Result<(), Csr8645Error>;
 
     /// Receives audio data from the CSR8645 module.
     ///
+    /// As with `receive_data`, a read filling the whole buffer may have been truncated.
+    ///
     /// # Arguments
     ///
     /// * `buffer` - The buffer where the received audio data will be stored.
     ///
     /// # Returns
     ///
+    /// A `Result` holding the number of bytes received, at the start of `buffer`.
+    async fn receive_audio(&self, buffer: &mut [u8]) -> Result<usize, Csr8645Error>;
+
+    /// Gets the signal strength of the current connection.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the RSSI of the connection in dBm or an error.
+    async fn get_rssi(&self) -> Result<i8, Csr8645Error>;
+
+    /// Checks that the module responds to commands.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    async fn ping(&self) -> Result<(), Csr8645Error>;
+
+    /// Gets the firmware version of the module.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the version string or an error.
+    async fn get_version(&self) -> Result<String, Csr8645Error>;
+
+    /// Gets the state reported by the module.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the module state or an error.
+    async fn get_status(&self) -> Result<ModuleState, Csr8645Error>;
+
+    /// Sets the audio codec used for A2DP streaming.
+    ///
+    /// # Arguments
+    ///
+    /// * `codec` - The codec to use.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    async fn set_codec(&self, codec: AudioCodec) -> Result<(), Csr8645Error>;
+
+    /// Sets the output volume.
+    ///
+    /// # Arguments
+    ///
+    /// * `volume` - The new volume level.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    async fn set_volume(&self, volume: u8) -> Result<(), Csr8645Error>;
+
+    /// Sets the bass level.
+    ///
+    /// # Arguments
+    ///
+    /// * `bass` - The new bass level.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    async fn set_bass(&self, bass: u8) -> Result<(), Csr8645Error>;
+
+    /// Mutes the audio output, remembering the current volume.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    async fn mute(&self) -> Result<(), Csr8645Error>;
+
+    /// Unmutes the audio output, restoring the volume it had before muting.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    async fn unmute(&self) -> Result<(), Csr8645Error>;
+
+    /// Returns whether the audio output is muted.
+    async fn is_muted(&self) -> bool;
+
+    /// Returns the state of the link with the remote device.
+    async fn connection_state(&self) -> ConnectionState;
+
+    /// Enables or disables multipoint connections to two devices.
+    ///
+    /// # Arguments
+    ///
+    /// * `on` - True to enable multipoint, false to disable it.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    async fn enable_multipoint(&self, on: bool) -> Result<(), Csr8645Error>;
+
+    /// Returns the addresses of the connected devices, oldest first.
+    async fn connected_devices(&self) -> Vec<BtAddr>;
+
+    /// Disconnects from the current device.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    async fn disconnect(&self) -> Result<(), Csr8645Error>;
+
+    /// Waits until all the audio data written so far has been sent to the module.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    async fn flush_audio(&self) -> Result<(), Csr8645Error>;
+
+    /// Puts the CSR8645 module into its low-power sleep mode.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    async fn sleep(&self) -> Result<(), Csr8645Error>;
+
+    /// Gets the address and name of the device currently connected.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the connected device, `None` if no device is connected, or an error.
+    async fn connected_device(&self) -> Result<Option<ScannedDevice>, Csr8645Error>;
+
+    /// Answers the incoming call.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    async fn answer_call(&self) -> Result<(), Csr8645Error>;
+
+    /// Rejects the incoming call.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    async fn reject_call(&self) -> Result<(), Csr8645Error>;
+
+    /// Ends the call in progress.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    async fn end_call(&self) -> Result<(), Csr8645Error>;
+
+    /// Forwards a track control command to the connected phone.
+    ///
+    /// # Arguments
+    ///
+    /// * `cmd` - The passthrough command to forward.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    async fn avrcp(&self, cmd: AvrcpCommand) -> Result<(), Csr8645Error>;
+
+    /// Plays a logarithmic sine sweep for speaker diagnostics.
+    ///
+    /// # Arguments
+    ///
+    /// * `start_hz` - The frequency at the start of the sweep, in Hz.
+    /// * `end_hz` - The frequency at the end of the sweep, in Hz.
+    /// * `duration` - The length of the sweep.
+    /// * `gain` - The gain of the sweep, between 0.0 and 1.0.
+    ///
+    /// # Returns
+    ///
     /// A `Result` indicating the success or failure of the operation.
-    fn receive_audio(&self, buffer: &mut [u8]) -> Result<(), Csr8645Error>;
+    async fn play_sweep(
+        &self,
+        start_hz: f32,
+        end_hz: f32,
+        duration: Duration,
+        gain: f32,
+    ) -> Result<(), Csr8645Error>;
+
+    /// Waits briefly for a link change notification from the module.
+    ///
+    /// When the module cannot notify link changes, the link state is queried instead and the
+    /// event is synthesized from its changes.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the notified event, `None` if none arrived, or an error.
+    async fn poll_event(&self) -> Result<Option<BtEvent>, Csr8645Error>;
+
+    /// Returns how link changes are detected, by notification or by polling.
+    async fn event_mode(&self) -> EventMode;
+
+    /// Enables or disables dry-run mode, recording commands instead of sending them.
+    ///
+    /// # Arguments
+    ///
+    /// * `enable` - True to enable dry-run mode, false to disable it.
+    async fn set_dry_run(&self, enable: bool);
+
+    /// Returns the commands recorded while in dry-run mode.
+    ///
+    /// # Returns
+    ///
+    /// A list of the recorded commands, in the order they were issued.
+    async fn recorded_commands(&self) -> Vec<Vec<u8>>;
 }
 
 /// `BluetoothServiceImpl` is a struct that implements the `BluetoothService` trait.
 ///
 /// This struct provides the actual implementation of the Bluetooth operations defined in the `BluetoothService` trait.
-/// It uses a reference to a `Csr8645` instance to perform these operations.
-pub struct BluetoothServiceImpl<'a> {
-    /// A reference to a `Csr8645` instance.
-    csr8645: &'a Csr8645<'a>,
+/// It locks the shared `Csr8645` instance to perform these operations, along with its byte
+/// channel for the operations that talk to the module. The module is on the board UART unless
+/// another byte channel is given.
+pub struct BluetoothServiceImpl<'a, C: ByteChannel = Csr8645Uart<'a>> {
+    /// A reference to the shared `Csr8645` instance.
+    csr8645: &'a SharedCsr8645<'a, C>,
 }
 
-impl<'a> BluetoothServiceImpl<'a> {
+impl<'a, C: ByteChannel> BluetoothServiceImpl<'a, C> {
     /// Creates a new instance of `BluetoothServiceImpl`.
     ///
     /// # Arguments
     ///
-    /// * `csr8645` - A reference to a `Csr8645` instance.
+    /// * `csr8645` - A reference to the shared `Csr8645` instance.
     ///
     /// # Returns
     ///
     /// * `Self` - The new `BluetoothServiceImpl` instance.
-    pub fn new(csr8645: &'a Csr8645<'a>) -> Self {
+    pub fn new(csr8645: &'a SharedCsr8645<'a, C>) -> Self {
         Self { csr8645 }
     }
+
+    /// Locks the shared `Csr8645` instance and its byte channel for a command/response cycle.
+    ///
+    /// # Returns
+    ///
+    /// * `Csr8645Exchange` - The locked driver, released along with the channel when dropped.
+    async fn exchange(&self) -> Csr8645Exchange<'_, C> {
+        Csr8645Exchange::lock(self.csr8645).await
+    }
 }
 
-impl<'a> BluetoothService for BluetoothServiceImpl<'a> {
-    fn initialize(&self, pin: &str) -> Result<(), Csr8645Error> {
-        self.csr8645.set_pin(pin)
+impl<'a, C: ByteChannel> BluetoothService for BluetoothServiceImpl<'a, C> {
+    async fn initialize(&self, pin: &str) -> Result<(), Csr8645Error> {
+        self.exchange().await.set_pin(pin).await
+    }
+
+    async fn scan_devices(&self) -> Result<Vec<ScannedDevice>, Csr8645Error> {
+        self.exchange().await.scan().await
+    }
+
+    async fn scan_devices_filtered(
+        &self,
+        name_prefix: &str,
+    ) -> Result<Vec<ScannedDevice>, Csr8645Error> {
+        self.exchange().await.scan_filtered(name_prefix).await
+    }
+
+    async fn connect_to_device(&self, address: &BtAddr) -> Result<(), Csr8645Error> {
+        self.exchange().await.connect(address).await
+    }
+
+    async fn send_data(&self, data: &[u8]) -> Result<(), Csr8645Error> {
+        self.exchange().await.send_data(data).await
+    }
+
+    async fn transmit_audio(&self, audio_data: &[u8]) -> Result<(), Csr8645Error> {
+        self.exchange().await.play_audio(audio_data).await
+    }
+
+    async fn receive_data(&self, buffer: &mut [u8]) -> Result<usize, Csr8645Error> {
+        self.exchange().await.receive_data(buffer).await
+    }
+
+    async fn receive_exact(
+        &self,
+        buffer: &mut [u8],
+        timeout: Duration,
+    ) -> Result<(), Csr8645Error> {
+        self.exchange().await.receive_exact(buffer, timeout).await
+    }
+
+    async fn receive_audio(&self, buffer: &mut [u8]) -> Result<usize, Csr8645Error> {
+        self.exchange().await.receive_audio(buffer).await
+    }
+
+    async fn get_rssi(&self) -> Result<i8, Csr8645Error> {
+        self.exchange().await.get_rssi().await
+    }
+
+    async fn ping(&self) -> Result<(), Csr8645Error> {
+        self.exchange().await.ping().await
+    }
+
+    async fn get_version(&self) -> Result<String, Csr8645Error> {
+        self.exchange().await.get_version().await
+    }
+
+    async fn get_status(&self) -> Result<ModuleState, Csr8645Error> {
+        self.exchange().await.get_status().await
+    }
+
+    async fn set_codec(&self, codec: AudioCodec) -> Result<(), Csr8645Error> {
+        self.exchange().await.set_codec(codec).await
+    }
+
+    async fn set_volume(&self, volume: u8) -> Result<(), Csr8645Error> {
+        self.exchange().await.set_volume(volume).await
+    }
+
+    async fn set_bass(&self, bass: u8) -> Result<(), Csr8645Error> {
+        self.exchange().await.set_bass(bass).await
+    }
+
+    async fn mute(&self) -> Result<(), Csr8645Error> {
+        self.exchange().await.mute().await
     }
 
-    fn scan_devices(&self) -> Result<Vec<String>, Csr8645Error> {
-        self.csr8645.scan()
+    async fn unmute(&self) -> Result<(), Csr8645Error> {
+        self.exchange().await.unmute().await
     }
 
-    fn connect_to_device(&self, address: &str) -> Result<(), Csr8645Error> {
-        self.csr8645.connect(address)
+    async fn is_muted(&self) -> bool {
+        self.csr8645.lock().await.is_muted()
     }
 
-    fn send_data(&self, data: &[u8]) -> Result<(), Csr8645Error> {
-        self.csr8645.send_data(data)
+    async fn connection_state(&self) -> ConnectionState {
+        self.csr8645.lock().await.connection_state()
     }
 
-    fn transmit_audio(&self, audio_data: &[u8]) -> Result<(), Csr8645Error> {
-        self.csr8645.send_audio(audio_data)
+    async fn enable_multipoint(&self, on: bool) -> Result<(), Csr8645Error> {
+        self.exchange().await.enable_multipoint(on).await
     }
 
-    fn receive_audio(&self, buffer: &mut [u8]) -> Result<(), Csr8645Error> {
-        self.csr8645.receive_audio(buffer)
+    async fn connected_devices(&self) -> Vec<BtAddr> {
+        self.csr8645.lock().await.connected_devices()
+    }
+
+    async fn disconnect(&self) -> Result<(), Csr8645Error> {
+        self.exchange().await.disconnect().await
+    }
+
+    async fn flush_audio(&self) -> Result<(), Csr8645Error> {
+        self.exchange().await.flush_audio().await
+    }
+
+    /// Closes the driver, which puts the module to sleep once it is disconnected.
+    ///
+    /// Sleeping is the last step of the shutdown, and the driver is never dropped, so this is
+    /// where it is closed.
+    async fn sleep(&self) -> Result<(), Csr8645Error> {
+        self.exchange().await.close().await
+    }
+
+    async fn connected_device(&self) -> Result<Option<ScannedDevice>, Csr8645Error> {
+        self.exchange().await.connected_device().await
+    }
+
+    async fn answer_call(&self) -> Result<(), Csr8645Error> {
+        self.exchange().await.answer_call().await
+    }
+
+    async fn reject_call(&self) -> Result<(), Csr8645Error> {
+        self.exchange().await.reject_call().await
+    }
+
+    async fn end_call(&self) -> Result<(), Csr8645Error> {
+        self.exchange().await.end_call().await
+    }
+
+    async fn avrcp(&self, cmd: AvrcpCommand) -> Result<(), Csr8645Error> {
+        self.exchange().await.avrcp(cmd).await
+    }
+
+    async fn play_sweep(
+        &self,
+        start_hz: f32,
+        end_hz: f32,
+        duration: Duration,
+        gain: f32,
+    ) -> Result<(), Csr8645Error> {
+        self.exchange()
+            .await
+            .play_sweep(start_hz, end_hz, duration, gain)
+            .await
+    }
+
+    async fn poll_event(&self) -> Result<Option<BtEvent>, Csr8645Error> {
+        self.exchange().await.poll_event(EVENT_POLL_WINDOW).await
+    }
+
+    async fn event_mode(&self) -> EventMode {
+        self.csr8645.lock().await.event_mode()
+    }
+
+    async fn set_dry_run(&self, enable: bool) {
+        self.csr8645.lock().await.set_dry_run(enable)
+    }
+
+    async fn recorded_commands(&self) -> Vec<Vec<u8>> {
+        self.csr8645.lock().await.recorded_commands().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::bluetooth_controller::BluetoothController;
+    use crate::csr8645::csr8645::Csr8645Driver;
+    use crate::csr8645::loopback::LoopbackChannel;
+    use crate::storage::config_store::ConfigStore;
+    use crate::storage::ram_flash::RamFlash;
+    use crate::uart::shared_uart::{SharedChannel, SharedUart};
+    use core::cell::RefCell;
+    use embassy_futures::block_on;
+    use embassy_sync::mutex::Mutex;
+
+    /// The size of the simulated flash region holding the configuration records.
+    const FLASH_REGION_SIZE: usize = 4096;
+
+    #[test]
+    fn a_controller_command_reaches_the_module_through_the_shared_driver() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_reply(b"AT+VOL=7\r\n", b"OK\r\n");
+        // The driver talks through a shared UART, so the written bytes can be read back
+        let uart = SharedUart::new(channel);
+        let csr8645: SharedCsr8645<'_, SharedChannel<'_, LoopbackChannel>> =
+            Mutex::new(Csr8645Driver::new(SharedChannel::new(&uart), 115_200).unwrap());
+        let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+        let config_store = RefCell::new(ConfigStore::new(&mut flash));
+        let controller =
+            BluetoothController::new(BluetoothServiceImpl::new(&csr8645), &config_store);
+
+        // BluetoothController -> BluetoothServiceImpl -> SharedCsr8645 -> Csr8645Driver -> UART
+        block_on(controller.set_volume(7)).unwrap();
+
+        // The exchange released the bus once the OK was read
+        assert_eq!(uart.try_lock().unwrap().written(), b"AT+VOL=7\r\n");
     }
 }
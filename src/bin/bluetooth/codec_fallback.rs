@@ -0,0 +1,226 @@
+#![no_std]
+#![no_main]
+
+use crate::csr8645::csr8645::AudioCodec;
+use core::ops::RangeInclusive;
+use defmt::warn;
+
+/// The RSSI values, in dBm, a Bluetooth controller can report; anything else is a bogus reading
+/// such as the `127` sent when no measurement is available.
+const VALID_RSSI: RangeInclusive<i8> = -127..=20;
+
+/// `CodecFallbackConfig` holds the thresholds used to switch between codecs.
+///
+/// The gap between `fallback_rssi` and `recovery_rssi` provides hysteresis so the codec does not
+/// flap when the signal hovers around a single threshold.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct CodecFallbackConfig {
+    /// The codec used while the signal is good.
+    pub preferred_codec: AudioCodec,
+    /// The RSSI, in dBm, below which a sample counts as weak.
+    pub fallback_rssi: i8,
+    /// The RSSI, in dBm, above which a sample counts as recovered.
+    pub recovery_rssi: i8,
+    /// The number of consecutive samples required before switching.
+    pub consecutive_samples: u8,
+}
+
+impl Default for CodecFallbackConfig {
+    fn default() -> Self {
+        Self {
+            preferred_codec: AudioCodec::AptX,
+            fallback_rssi: -80,
+            recovery_rssi: -70,
+            consecutive_samples: 3,
+        }
+    }
+}
+
+/// `CodecFallback` tracks the RSSI history and decides when the codec should change.
+pub struct CodecFallback {
+    /// The thresholds used to switch between codecs.
+    config: CodecFallbackConfig,
+    /// The codec currently in use.
+    active_codec: AudioCodec,
+    /// The number of consecutive samples crossing the threshold towards the other codec.
+    streak: u8,
+    /// Whether the module accepts codec changes at all.
+    enabled: bool,
+}
+
+impl CodecFallback {
+    /// Creates a new instance of `CodecFallback`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The thresholds used to switch between codecs.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `CodecFallback` instance, starting on the preferred codec.
+    pub fn new(config: CodecFallbackConfig) -> Self {
+        Self {
+            config,
+            active_codec: config.preferred_codec,
+            streak: 0,
+            enabled: true,
+        }
+    }
+
+    /// Returns the codec currently in use.
+    pub fn active_codec(&self) -> AudioCodec {
+        self.active_codec
+    }
+
+    /// Feeds a new RSSI sample.
+    ///
+    /// The active codec is left unchanged until the switch is confirmed with `commit`, so a
+    /// codec the module refused is not taken as active. Readings outside the range a Bluetooth
+    /// controller can report are ignored, as is every sample once the fallback is disabled.
+    ///
+    /// # Arguments
+    ///
+    /// * `rssi` - The latest RSSI of the connection, in dBm.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<AudioCodec>` - The codec to switch to, or `None` if the codec should not change.
+    pub fn update(&mut self, rssi: i8) -> Option<AudioCodec> {
+        if !self.enabled {
+            return None;
+        }
+
+        if !VALID_RSSI.contains(&rssi) {
+            warn!("Ignoring out of range RSSI {} dBm", rssi);
+            return None;
+        }
+
+        let on_fallback = self.active_codec == AudioCodec::Sbc;
+        let crossing = if on_fallback {
+            rssi > self.config.recovery_rssi
+        } else {
+            rssi < self.config.fallback_rssi
+        };
+
+        if !crossing {
+            self.streak = 0;
+            return None;
+        }
+
+        self.streak += 1;
+        if self.streak < self.config.consecutive_samples {
+            return None;
+        }
+
+        self.streak = 0;
+        Some(if on_fallback {
+            self.config.preferred_codec
+        } else {
+            AudioCodec::Sbc
+        })
+    }
+
+    /// Records that the module switched to the codec returned by `update`.
+    ///
+    /// # Arguments
+    ///
+    /// * `codec` - The codec the module acknowledged.
+    pub fn commit(&mut self, codec: AudioCodec) {
+        self.active_codec = codec;
+    }
+
+    /// Stops proposing codec changes, for a module whose firmware lacks codec selection.
+    ///
+    /// The active codec is left as it was, since the module never switched away from it.
+    pub fn disable(&mut self) {
+        self.enabled = false;
+        self.streak = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds an RSSI time series and returns the codec proposed after each sample, committing
+    /// every proposal as the module would.
+    fn feed(fallback: &mut CodecFallback, series: &[i8]) -> Vec<Option<AudioCodec>> {
+        series
+            .iter()
+            .map(|&rssi| {
+                let proposal = fallback.update(rssi);
+                if let Some(codec) = proposal {
+                    fallback.commit(codec);
+                }
+                proposal
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_weakening_signal_falls_back_to_sbc() {
+        let mut fallback = CodecFallback::new(CodecFallbackConfig::default());
+
+        let proposals = feed(&mut fallback, &[-60, -75, -81, -85, -90]);
+
+        assert_eq!(proposals, [None, None, None, None, Some(AudioCodec::Sbc)]);
+        assert_eq!(fallback.active_codec(), AudioCodec::Sbc);
+    }
+
+    #[test]
+    fn a_recovering_signal_returns_to_the_preferred_codec() {
+        let mut fallback = CodecFallback::new(CodecFallbackConfig::default());
+        feed(&mut fallback, &[-90, -90, -90]);
+
+        // Within the hysteresis band the fallback codec is kept
+        let proposals = feed(&mut fallback, &[-75, -72, -70, -65, -60, -55]);
+
+        assert_eq!(
+            proposals,
+            [None, None, None, None, None, Some(AudioCodec::AptX)]
+        );
+        assert_eq!(fallback.active_codec(), AudioCodec::AptX);
+    }
+
+    #[test]
+    fn a_dip_shorter_than_the_streak_keeps_the_codec() {
+        let mut fallback = CodecFallback::new(CodecFallbackConfig::default());
+
+        let proposals = feed(&mut fallback, &[-85, -85, -60, -85, -85, -60]);
+
+        assert!(proposals.iter().all(Option::is_none), "{:?}", proposals);
+        assert_eq!(fallback.active_codec(), AudioCodec::AptX);
+    }
+
+    #[test]
+    fn an_uncommitted_switch_keeps_the_active_codec() {
+        let mut fallback = CodecFallback::new(CodecFallbackConfig::default());
+
+        for _ in 0..2 {
+            fallback.update(-90);
+        }
+        assert_eq!(fallback.update(-90), Some(AudioCodec::Sbc));
+
+        assert_eq!(fallback.active_codec(), AudioCodec::AptX);
+    }
+
+    #[test]
+    fn bogus_readings_are_ignored() {
+        let mut fallback = CodecFallback::new(CodecFallbackConfig::default());
+
+        let proposals = feed(&mut fallback, &[-90, -90, 127, -90]);
+
+        // The bogus reading neither counts towards the streak nor resets it
+        assert_eq!(proposals, [None, None, None, Some(AudioCodec::Sbc)]);
+    }
+
+    #[test]
+    fn a_disabled_fallback_proposes_nothing() {
+        let mut fallback = CodecFallback::new(CodecFallbackConfig::default());
+        fallback.disable();
+
+        let proposals = feed(&mut fallback, &[-90; 6]);
+
+        assert!(proposals.iter().all(Option::is_none), "{:?}", proposals);
+    }
+}
@@ -0,0 +1,43 @@
+#![no_std]
+#![no_main]
+
+use crate::bluetooth::bluetooth_service::BluetoothService;
+use crate::csr8645::csr8645::BtEvent;
+use defmt::{error, info};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Timer};
+
+/// The number of link notifications queued before the publisher waits for the app.
+pub const BT_EVENT_QUEUE_LEN: usize = 4;
+
+/// The delay between two notification polls, leaving the shared CSR8645 to the other services.
+const EVENT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Carries the link notifications of the CSR8645 module to the app.
+pub type BtEventBus = Channel<CriticalSectionRawMutex, BtEvent, BT_EVENT_QUEUE_LEN>;
+
+/// Polls the module for link notifications and publishes them on the bus.
+///
+/// This never returns and is meant to run in its own task, so the app reacts to the phone
+/// connecting or disconnecting without polling the module itself. When the firmware lacks
+/// notifications, the events are synthesized from periodic link state queries instead.
+///
+/// # Arguments
+///
+/// * `service` - The service the notifications are polled through.
+/// * `bus` - The bus the notifications are published on.
+pub async fn publish_events<T: BluetoothService>(service: &T, bus: &BtEventBus) -> ! {
+    loop {
+        match service.poll_event().await {
+            Ok(Some(event)) => {
+                info!("Bluetooth event: {:?}", event);
+                bus.send(event).await;
+            }
+            Ok(None) => {}
+            Err(e) => error!("Failed to poll Bluetooth events: {:?}", e),
+        }
+
+        Timer::after(EVENT_POLL_INTERVAL).await;
+    }
+}
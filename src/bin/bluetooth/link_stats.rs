@@ -0,0 +1,197 @@
+#![no_std]
+#![no_main]
+
+use embassy_time::{Duration, Instant};
+
+/// The reflected polynomial of the IEEE 802.3 CRC-32.
+const CRC32_POLYNOMIAL: u32 = 0xEDB8_8320;
+
+/// Feeds bytes into a running IEEE 802.3 CRC-32.
+///
+/// # Arguments
+///
+/// * `crc` - The CRC of the bytes fed so far, e.g. `DirectionStats::crc`, or 0 to start.
+/// * `data` - The bytes to feed.
+///
+/// # Returns
+///
+/// * `u32` - The CRC of all the bytes fed so far.
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (CRC32_POLYNOMIAL & mask);
+        }
+    }
+    !crc
+}
+
+/// `DirectionStats` accumulates the traffic in one direction of the data channel.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct DirectionStats {
+    /// The total number of bytes transferred.
+    pub bytes: u64,
+    /// The CRC-32 of all the bytes transferred.
+    pub crc: u32,
+    /// The throughput over the last complete window, in bytes per second.
+    pub throughput: u32,
+    /// The time at which the current window started.
+    window_start: Option<Instant>,
+    /// The number of bytes transferred in the current window.
+    window_bytes: u32,
+}
+
+impl DirectionStats {
+    /// Creates an empty instance of `DirectionStats`.
+    const fn new() -> Self {
+        Self {
+            bytes: 0,
+            crc: 0,
+            throughput: 0,
+            window_start: None,
+            window_bytes: 0,
+        }
+    }
+
+    /// Records transferred bytes.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The time of the transfer.
+    /// * `window` - The length of a throughput window.
+    /// * `data` - The transferred bytes.
+    fn record(&mut self, now: Instant, window: Duration, data: &[u8]) {
+        self.bytes += data.len() as u64;
+        self.crc = crc32_update(self.crc, data);
+
+        let window_start = *self.window_start.get_or_insert(now);
+        let elapsed = now - window_start;
+        if elapsed >= window {
+            self.throughput =
+                (self.window_bytes as u64 * 1_000_000 / elapsed.as_micros().max(1)) as u32;
+            self.window_start = Some(now);
+            self.window_bytes = 0;
+        }
+        self.window_bytes = self.window_bytes.saturating_add(data.len() as u32);
+    }
+}
+
+/// `LinkStats` measures the effective throughput and integrity of the SPP data channel.
+///
+/// Comparing the CRCs on both ends of the link after a transfer detects corrupted bytes.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct LinkStats {
+    /// The length of a throughput window.
+    window: Duration,
+    /// The traffic sent to the remote device.
+    pub sent: DirectionStats,
+    /// The traffic received from the remote device.
+    pub received: DirectionStats,
+}
+
+impl LinkStats {
+    /// Creates a new instance of `LinkStats`.
+    ///
+    /// # Arguments
+    ///
+    /// * `window` - The length of a throughput window.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `LinkStats` instance.
+    pub const fn new(window: Duration) -> Self {
+        Self {
+            window,
+            sent: DirectionStats::new(),
+            received: DirectionStats::new(),
+        }
+    }
+
+    /// Records bytes sent to the remote device.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The time of the transfer.
+    /// * `data` - The bytes sent.
+    pub fn record_sent(&mut self, now: Instant, data: &[u8]) {
+        self.sent.record(now, self.window, data);
+    }
+
+    /// Records bytes received from the remote device.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The time of the transfer.
+    /// * `data` - The bytes received.
+    pub fn record_received(&mut self, now: Instant, data: &[u8]) {
+        self.received.record(now, self.window, data);
+    }
+
+    /// Clears all the counters, e.g. after a reconnection.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.window);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The standard check input of CRC-32.
+    const CHECK_INPUT: &[u8] = b"123456789";
+
+    /// The CRC-32 of the check input.
+    const CHECK_CRC: u32 = 0xCBF4_3926;
+
+    /// The throughput window used by the tests.
+    const WINDOW: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn the_crc_matches_the_standard_check_value() {
+        assert_eq!(crc32_update(0, CHECK_INPUT), CHECK_CRC);
+        assert_eq!(crc32_update(0, &[]), 0);
+    }
+
+    #[test]
+    fn the_crc_runs_across_transfers() {
+        let mut stats = LinkStats::new(WINDOW);
+
+        for chunk in CHECK_INPUT.chunks(2) {
+            stats.record_sent(Instant::from_millis(0), chunk);
+        }
+
+        assert_eq!(stats.sent.bytes, 9);
+        assert_eq!(stats.sent.crc, CHECK_CRC);
+        assert_eq!(stats.received, DirectionStats::new());
+    }
+
+    #[test]
+    fn the_throughput_covers_the_last_complete_window() {
+        let mut stats = LinkStats::new(WINDOW);
+
+        stats.record_received(Instant::from_millis(0), &[0; 100]);
+        stats.record_received(Instant::from_millis(500), &[0; 100]);
+        assert_eq!(stats.received.throughput, 0);
+
+        stats.record_received(Instant::from_millis(1000), &[0; 50]);
+        assert_eq!(stats.received.throughput, 200);
+        assert_eq!(stats.received.bytes, 250);
+
+        // The next window only holds the 50 bytes, spread over two seconds
+        stats.record_received(Instant::from_millis(3000), &[0; 10]);
+        assert_eq!(stats.received.throughput, 25);
+    }
+
+    #[test]
+    fn reset_clears_both_directions() {
+        let mut stats = LinkStats::new(WINDOW);
+        stats.record_sent(Instant::from_millis(0), CHECK_INPUT);
+        stats.record_received(Instant::from_millis(0), CHECK_INPUT);
+
+        stats.reset();
+
+        assert_eq!(stats, LinkStats::new(WINDOW));
+    }
+}
@@ -0,0 +1,273 @@
+#![no_std]
+#![no_main]
+
+use crate::audio::audio_behavior::AudioBehavior;
+use crate::bluetooth::bluetooth_service::BluetoothService;
+use crate::csr8645::bt_addr::BtAddr;
+use crate::csr8645::csr8645::{
+    AudioCodec, AvrcpCommand, BtEvent, ConnectionState, Csr8645Error, EventMode, ModuleState,
+    ScannedDevice,
+};
+use alloc::collections::VecDeque;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use embassy_time::Duration;
+
+/// `MockCsr8645Interface` is a `BluetoothService` standing in for the CSR8645 module.
+///
+/// Every command succeeds without touching the hardware, and the volume and bass levels applied
+/// by `BluetoothController::alter_behavior` are captured as a sequence of `AudioBehavior`s. The
+/// addresses connected to and the link and power commands are captured as well. While connected,
+/// the last address connected to is reported as the peer.
+pub struct MockCsr8645Interface {
+    /// The volume set since the last captured behavior.
+    volume: Cell<u8>,
+    /// Whether the audio output is muted.
+    muted: Cell<bool>,
+    /// The state of the simulated link.
+    connection_state: Cell<ConnectionState>,
+    /// The behaviors applied so far, in order.
+    applied: RefCell<Vec<AudioBehavior>>,
+    /// The link notifications waiting to be polled.
+    events: RefCell<VecDeque<BtEvent>>,
+    /// The addresses connected to so far, in order.
+    connections: RefCell<Vec<BtAddr>>,
+    /// The link and power commands received so far, in order.
+    commands: RefCell<Vec<&'static str>>,
+}
+
+impl MockCsr8645Interface {
+    /// Creates a new instance of `MockCsr8645Interface`.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `MockCsr8645Interface` instance, connected and unmuted.
+    pub fn new() -> Self {
+        Self {
+            volume: Cell::new(0),
+            muted: Cell::new(false),
+            connection_state: Cell::new(ConnectionState::Connected),
+            applied: RefCell::new(Vec::new()),
+            events: RefCell::new(VecDeque::new()),
+            connections: RefCell::new(Vec::new()),
+            commands: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Queues a link notification, returned by the next `poll_event`.
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - The notification to queue.
+    pub fn push_event(&self, event: BtEvent) {
+        self.events.borrow_mut().push_back(event);
+    }
+
+    /// Returns the behaviors applied so far, in order.
+    pub fn applied_behaviors(&self) -> Vec<AudioBehavior> {
+        self.applied.borrow().clone()
+    }
+
+    /// Returns the addresses connected to so far, in order.
+    pub fn connections(&self) -> Vec<BtAddr> {
+        self.connections.borrow().clone()
+    }
+
+    /// Returns the link and power commands received so far, in order, e.g. `disconnect`.
+    pub fn commands(&self) -> Vec<&'static str> {
+        self.commands.borrow().clone()
+    }
+}
+
+impl BluetoothService for MockCsr8645Interface {
+    async fn initialize(&self, _pin: &str) -> Result<(), Csr8645Error> {
+        Ok(())
+    }
+
+    async fn scan_devices(&self) -> Result<Vec<ScannedDevice>, Csr8645Error> {
+        Ok(Vec::new())
+    }
+
+    async fn scan_devices_filtered(
+        &self,
+        _name_prefix: &str,
+    ) -> Result<Vec<ScannedDevice>, Csr8645Error> {
+        Ok(Vec::new())
+    }
+
+    async fn connect_to_device(&self, address: &BtAddr) -> Result<(), Csr8645Error> {
+        self.connections.borrow_mut().push(*address);
+        self.commands.borrow_mut().push("connect");
+        self.connection_state.set(ConnectionState::Connected);
+        Ok(())
+    }
+
+    async fn send_data(&self, _data: &[u8]) -> Result<(), Csr8645Error> {
+        Ok(())
+    }
+
+    async fn transmit_audio(&self, _audio_data: &[u8]) -> Result<(), Csr8645Error> {
+        Ok(())
+    }
+
+    async fn receive_data(&self, buffer: &mut [u8]) -> Result<usize, Csr8645Error> {
+        buffer.fill(0);
+        Ok(buffer.len())
+    }
+
+    async fn receive_exact(
+        &self,
+        buffer: &mut [u8],
+        _timeout: Duration,
+    ) -> Result<(), Csr8645Error> {
+        buffer.fill(0);
+        Ok(())
+    }
+
+    async fn receive_audio(&self, buffer: &mut [u8]) -> Result<usize, Csr8645Error> {
+        buffer.fill(0);
+        Ok(buffer.len())
+    }
+
+    async fn get_rssi(&self) -> Result<i8, Csr8645Error> {
+        Ok(-60)
+    }
+
+    async fn ping(&self) -> Result<(), Csr8645Error> {
+        Ok(())
+    }
+
+    async fn get_version(&self) -> Result<String, Csr8645Error> {
+        Ok("MOCK".to_string())
+    }
+
+    async fn get_status(&self) -> Result<ModuleState, Csr8645Error> {
+        Ok(match self.connection_state.get() {
+            ConnectionState::Connected => ModuleState::Connected,
+            ConnectionState::Disconnected => ModuleState::Disconnected,
+        })
+    }
+
+    async fn set_codec(&self, _codec: AudioCodec) -> Result<(), Csr8645Error> {
+        Ok(())
+    }
+
+    async fn set_volume(&self, volume: u8) -> Result<(), Csr8645Error> {
+        self.volume.set(volume);
+        Ok(())
+    }
+
+    async fn set_bass(&self, bass: u8) -> Result<(), Csr8645Error> {
+        // The controller sets the volume first, so the bass completes a behavior
+        self.applied.borrow_mut().push(AudioBehavior {
+            volume: self.volume.get(),
+            bass,
+            ..AudioBehavior::default()
+        });
+        Ok(())
+    }
+
+    async fn mute(&self) -> Result<(), Csr8645Error> {
+        self.muted.set(true);
+        Ok(())
+    }
+
+    async fn unmute(&self) -> Result<(), Csr8645Error> {
+        self.muted.set(false);
+        Ok(())
+    }
+
+    async fn is_muted(&self) -> bool {
+        self.muted.get()
+    }
+
+    async fn connection_state(&self) -> ConnectionState {
+        self.connection_state.get()
+    }
+
+    async fn enable_multipoint(&self, _on: bool) -> Result<(), Csr8645Error> {
+        Ok(())
+    }
+
+    async fn connected_devices(&self) -> Vec<BtAddr> {
+        Vec::new()
+    }
+
+    async fn disconnect(&self) -> Result<(), Csr8645Error> {
+        self.connection_state.set(ConnectionState::Disconnected);
+        self.commands.borrow_mut().push("disconnect");
+        Ok(())
+    }
+
+    async fn flush_audio(&self) -> Result<(), Csr8645Error> {
+        self.commands.borrow_mut().push("flush_audio");
+        Ok(())
+    }
+
+    async fn sleep(&self) -> Result<(), Csr8645Error> {
+        self.commands.borrow_mut().push("sleep");
+        Ok(())
+    }
+
+    async fn connected_device(&self) -> Result<Option<ScannedDevice>, Csr8645Error> {
+        if self.connection_state.get() != ConnectionState::Connected {
+            return Ok(None);
+        }
+
+        Ok(self
+            .connections
+            .borrow()
+            .last()
+            .map(|&address| ScannedDevice {
+                address,
+                name: None,
+            }))
+    }
+
+    async fn answer_call(&self) -> Result<(), Csr8645Error> {
+        Ok(())
+    }
+
+    async fn reject_call(&self) -> Result<(), Csr8645Error> {
+        Ok(())
+    }
+
+    async fn end_call(&self) -> Result<(), Csr8645Error> {
+        Ok(())
+    }
+
+    async fn avrcp(&self, _cmd: AvrcpCommand) -> Result<(), Csr8645Error> {
+        Ok(())
+    }
+
+    async fn play_sweep(
+        &self,
+        _start_hz: f32,
+        _end_hz: f32,
+        _duration: Duration,
+        _gain: f32,
+    ) -> Result<(), Csr8645Error> {
+        Ok(())
+    }
+
+    async fn poll_event(&self) -> Result<Option<BtEvent>, Csr8645Error> {
+        let event = self.events.borrow_mut().pop_front();
+        match event {
+            Some(BtEvent::Connected) => self.connection_state.set(ConnectionState::Connected),
+            Some(BtEvent::Disconnected) => self.connection_state.set(ConnectionState::Disconnected),
+            None => {}
+        }
+        Ok(event)
+    }
+
+    async fn event_mode(&self) -> EventMode {
+        EventMode::Notifications
+    }
+
+    async fn set_dry_run(&self, _enable: bool) {}
+
+    async fn recorded_commands(&self) -> Vec<Vec<u8>> {
+        Vec::new()
+    }
+}
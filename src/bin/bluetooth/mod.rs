@@ -1,2 +1,8 @@
 pub mod bluetooth_controller;
 pub mod bluetooth_service;
+pub mod codec_fallback;
+pub mod event_bus;
+pub mod link_stats;
+#[cfg(feature = "simulation")]
+pub mod mock_csr8645;
+pub mod status_summary;
@@ -0,0 +1,38 @@
+#![no_std]
+#![no_main]
+
+use crate::csr8645::csr8645::{AudioCodec, ConnectionState, ModuleState, ScannedDevice};
+use embassy_time::Instant;
+
+/// `RssiSample` holds a signal strength reading and the time it was taken.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct RssiSample {
+    /// The signal strength, in dBm.
+    pub dbm: i8,
+    /// The time the signal strength was read.
+    pub at: Instant,
+}
+
+/// `StatusSummary` is a snapshot of the Bluetooth link and the module, e.g. for a display.
+///
+/// Cheap fields are queried from the module when the summary is built. The others are the
+/// latest values the controller already knows of, so building a summary does not slow down
+/// the audio path; each field states which it is.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatusSummary {
+    /// The state of the link, cached by the driver from the last notification or query.
+    pub connection_state: ConnectionState,
+    /// The connected device, queried live, or `None` if disconnected or the query failed.
+    pub peer: Option<ScannedDevice>,
+    /// The latest signal strength read through the controller, or `None` if none was read since
+    /// the controller was created. Check `at` for its age.
+    pub rssi: Option<RssiSample>,
+    /// The volume last applied through the controller, or `None` if none was applied yet.
+    pub volume: Option<u8>,
+    /// Whether the output is muted, cached by the driver.
+    pub muted: bool,
+    /// The codec last selected by the codec fallback.
+    pub codec: AudioCodec,
+    /// The state of the module, queried live, or `None` if the query failed.
+    pub module_state: Option<ModuleState>,
+}
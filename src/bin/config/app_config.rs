@@ -0,0 +1,411 @@
+#![no_std]
+#![no_main]
+
+use crate::audio::audio_controller::DEFAULT_IDLE_TIMEOUT;
+use crate::audio::audio_mapping::MappingConfig;
+use crate::audio::audio_preset::AudioPreset;
+use crate::audio::dead_man_switch::DEFAULT_STALE_AFTER;
+use crate::audio::redline_warning::RedlineWarningConfig;
+use crate::audio::thermal_guard::ThermalGuardConfig;
+use crate::audio::volume_schedule::QuietWindow;
+use crate::csr8645::csr8645::{CommandPacing, InitConfig, RetryPolicy, DEFAULT_CONNECT_TIMEOUT};
+use crate::obd::engine_state::EngineStateConfig;
+use crate::obd::poll_schedule::PollSchedule;
+use crate::obd::vehicle_profile::EngineType;
+use alloc::vec::Vec;
+use embassy_time::Duration;
+
+/// The time between two telemetry reports by default.
+const DEFAULT_TELEMETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The baud rate of the ELM327 adapter UART by default.
+const DEFAULT_OBD_BAUDRATE: u32 = 38400;
+
+/// The number of cylinders the engine tone is synthesized for by default.
+const DEFAULT_ENGINE_CYLINDERS: u8 = 4;
+
+/// The largest ring the DMA may fill with the bytes received from the CSR8645 module, which is
+/// also the default.
+pub const MAX_CSR8645_RX_RING_LEN: usize = 4096;
+
+/// `AppConfig` gathers the tunable settings of the firmware in one place.
+///
+/// It is built once in `run_app` and handed to each component, so the firmware can be tuned
+/// without editing the modules themselves.
+#[derive(Clone)]
+pub struct AppConfig {
+    /// The settings applied when bringing up the CSR8645 module.
+    pub csr8645_init: InitConfig,
+    /// How CSR8645 queries are re-issued after a transient failure.
+    pub csr8645_retry_policy: RetryPolicy,
+    /// How fast commands are sent to the CSR8645 module.
+    pub csr8645_pacing: CommandPacing,
+    /// The length of the ring the DMA fills with the bytes received from the CSR8645 module, at
+    /// most `MAX_CSR8645_RX_RING_LEN`.
+    pub csr8645_rx_ring_len: usize,
+    /// The baud rate the UART wired to the ELM327 adapter is opened at.
+    pub obd_baudrate: u32,
+    /// The time `connect` waits for the CSR8645 module to confirm the link.
+    pub connect_timeout: Duration,
+    /// The per-PID intervals used to poll the OBD-II adapter.
+    pub poll_schedule: PollSchedule,
+    /// The settings of the mapping from sensor data to audio behavior.
+    pub mapping: MappingConfig,
+    /// The preset active on boot.
+    pub default_preset: AudioPreset,
+    /// The time between two telemetry reports.
+    pub telemetry_interval: Duration,
+    /// The age past which the vehicle data is considered frozen and the audio reverts to neutral.
+    pub stale_after: Duration,
+    /// The thresholds used to tell whether the engine is running, cranking or off.
+    pub engine_state: EngineStateConfig,
+    /// The times of the day during which the volume is capped, none by default.
+    pub quiet_windows: Vec<QuietWindow>,
+    /// The temperatures at which the gain is capped to protect the amplifier.
+    pub thermal_guard: ThermalGuardConfig,
+    /// The time the vehicle must stay stopped with no audio playing before the amp is disabled.
+    pub idle_timeout: Duration,
+    /// The number of cylinders of the four-stroke engine the engine tone follows.
+    pub engine_cylinders: u8,
+    /// The chirp played as the engine nears its redline, or `None` to never play it.
+    pub redline_warning: Option<RedlineWarningConfig>,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            csr8645_init: InitConfig::default(),
+            csr8645_retry_policy: RetryPolicy::default(),
+            csr8645_pacing: CommandPacing::default(),
+            csr8645_rx_ring_len: MAX_CSR8645_RX_RING_LEN,
+            obd_baudrate: DEFAULT_OBD_BAUDRATE,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            poll_schedule: PollSchedule::default(),
+            mapping: MappingConfig::for_engine(EngineType::default()),
+            default_preset: AudioPreset::Normal,
+            telemetry_interval: DEFAULT_TELEMETRY_INTERVAL,
+            stale_after: DEFAULT_STALE_AFTER,
+            engine_state: EngineStateConfig::default(),
+            quiet_windows: Vec::new(),
+            thermal_guard: ThermalGuardConfig::default(),
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            engine_cylinders: DEFAULT_ENGINE_CYLINDERS,
+            redline_warning: Some(RedlineWarningConfig::default()),
+        }
+    }
+}
+
+impl AppConfig {
+    /// Returns a builder starting from the default settings.
+    ///
+    /// # Returns
+    ///
+    /// * `AppConfigBuilder` - The new builder.
+    pub fn builder() -> AppConfigBuilder {
+        AppConfigBuilder {
+            config: Self::default(),
+        }
+    }
+}
+
+/// `AppConfigBuilder` builds an `AppConfig`, overriding only the settings that differ from the
+/// defaults.
+pub struct AppConfigBuilder {
+    /// The configuration built so far.
+    config: AppConfig,
+}
+
+impl AppConfigBuilder {
+    /// Sets the settings applied when bringing up the CSR8645 module.
+    ///
+    /// # Arguments
+    ///
+    /// * `init` - The module settings.
+    pub fn csr8645_init(mut self, init: InitConfig) -> Self {
+        self.config.csr8645_init = init;
+        self
+    }
+
+    /// Sets how CSR8645 queries are re-issued after a transient failure.
+    ///
+    /// # Arguments
+    ///
+    /// * `retry_policy` - The retry policy.
+    pub fn csr8645_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.config.csr8645_retry_policy = retry_policy;
+        self
+    }
+
+    /// Sets how fast commands are sent to the CSR8645 module.
+    ///
+    /// # Arguments
+    ///
+    /// * `pacing` - The command pacing.
+    pub fn csr8645_pacing(mut self, pacing: CommandPacing) -> Self {
+        self.config.csr8645_pacing = pacing;
+        self
+    }
+
+    /// Sets the length of the ring the DMA fills with the bytes received from the CSR8645 module.
+    ///
+    /// # Arguments
+    ///
+    /// * `len` - The ring length, clamped to `MAX_CSR8645_RX_RING_LEN`.
+    pub fn csr8645_rx_ring_len(mut self, len: usize) -> Self {
+        self.config.csr8645_rx_ring_len = len.min(MAX_CSR8645_RX_RING_LEN);
+        self
+    }
+
+    /// Sets the baud rate the UART wired to the ELM327 adapter is opened at.
+    ///
+    /// # Arguments
+    ///
+    /// * `baudrate` - The baud rate.
+    pub fn obd_baudrate(mut self, baudrate: u32) -> Self {
+        self.config.obd_baudrate = baudrate;
+        self
+    }
+
+    /// Sets the time `connect` waits for the CSR8645 module to confirm the link.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The connection timeout.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.config.connect_timeout = timeout;
+        self
+    }
+
+    /// Sets the per-PID intervals used to poll the OBD-II adapter.
+    ///
+    /// # Arguments
+    ///
+    /// * `poll_schedule` - The poll schedule.
+    pub fn poll_schedule(mut self, poll_schedule: PollSchedule) -> Self {
+        self.config.poll_schedule = poll_schedule;
+        self
+    }
+
+    /// Sets the settings of the mapping from sensor data to audio behavior.
+    ///
+    /// # Arguments
+    ///
+    /// * `mapping` - The mapping settings.
+    pub fn mapping(mut self, mapping: MappingConfig) -> Self {
+        self.config.mapping = mapping;
+        self
+    }
+
+    /// Sets the kind of engine fitted to the vehicle.
+    ///
+    /// The RPM range and the bass slope of the mapping are replaced with the presets of the
+    /// engine type, so call this before `mapping` to override them. A calibrated vehicle profile
+    /// still replaces the RPM range at runtime.
+    ///
+    /// # Arguments
+    ///
+    /// * `engine_type` - The kind of engine.
+    pub fn engine_type(mut self, engine_type: EngineType) -> Self {
+        let preset = MappingConfig::for_engine(engine_type);
+        self.config.mapping.vehicle = preset.vehicle;
+        self.config.mapping.bass_slope = preset.bass_slope;
+        self
+    }
+
+    /// Sets the preset active on boot.
+    ///
+    /// # Arguments
+    ///
+    /// * `preset` - The default preset.
+    pub fn default_preset(mut self, preset: AudioPreset) -> Self {
+        self.config.default_preset = preset;
+        self
+    }
+
+    /// Sets the time between two telemetry reports.
+    ///
+    /// # Arguments
+    ///
+    /// * `interval` - The telemetry interval.
+    pub fn telemetry_interval(mut self, interval: Duration) -> Self {
+        self.config.telemetry_interval = interval;
+        self
+    }
+
+    /// Sets the age past which the vehicle data is considered frozen.
+    ///
+    /// # Arguments
+    ///
+    /// * `stale_after` - The staleness threshold.
+    pub fn stale_after(mut self, stale_after: Duration) -> Self {
+        self.config.stale_after = stale_after;
+        self
+    }
+
+    /// Sets the thresholds used to tell whether the engine is running, cranking or off.
+    ///
+    /// # Arguments
+    ///
+    /// * `engine_state` - The engine state thresholds.
+    pub fn engine_state(mut self, engine_state: EngineStateConfig) -> Self {
+        self.config.engine_state = engine_state;
+        self
+    }
+
+    /// Adds a time of the day during which the volume is capped.
+    ///
+    /// # Arguments
+    ///
+    /// * `window` - The quiet window.
+    pub fn quiet_window(mut self, window: QuietWindow) -> Self {
+        self.config.quiet_windows.push(window);
+        self
+    }
+
+    /// Sets the temperatures at which the gain is capped to protect the amplifier.
+    ///
+    /// # Arguments
+    ///
+    /// * `thermal_guard` - The thermal guard settings.
+    pub fn thermal_guard(mut self, thermal_guard: ThermalGuardConfig) -> Self {
+        self.config.thermal_guard = thermal_guard;
+        self
+    }
+
+    /// Sets the time the vehicle must stay stopped with no audio playing before the amp is
+    /// disabled.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The idle timeout.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.config.idle_timeout = timeout;
+        self
+    }
+
+    /// Sets the number of cylinders of the four-stroke engine the engine tone follows.
+    ///
+    /// # Arguments
+    ///
+    /// * `cylinders` - The number of cylinders.
+    pub fn engine_cylinders(mut self, cylinders: u8) -> Self {
+        self.config.engine_cylinders = cylinders;
+        self
+    }
+
+    /// Sets the chirp played as the engine nears its redline.
+    ///
+    /// # Arguments
+    ///
+    /// * `warning` - The warning settings, or `None` to never play it.
+    pub fn redline_warning(mut self, warning: Option<RedlineWarningConfig>) -> Self {
+        self.config.redline_warning = warning;
+        self
+    }
+
+    /// Builds the configuration.
+    ///
+    /// # Returns
+    ///
+    /// * `AppConfig` - The configuration holding the settings set on the builder.
+    pub fn build(self) -> AppConfig {
+        self.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::volume_schedule::TimeOfDay;
+
+    #[test]
+    fn the_builder_only_overrides_the_settings_it_is_given() {
+        let retry_policy = RetryPolicy {
+            attempts: 4,
+            delay: Duration::from_millis(20),
+        };
+        let pacing = CommandPacing {
+            inter_command_delay: Duration::from_millis(50),
+            inter_byte_delay: Duration::from_millis(1),
+        };
+
+        let config = AppConfig::builder()
+            .csr8645_retry_policy(retry_policy)
+            .csr8645_pacing(pacing)
+            .obd_baudrate(9600)
+            .default_preset(AudioPreset::Sport)
+            .engine_cylinders(6)
+            .redline_warning(None)
+            .build();
+
+        let defaults = AppConfig::default();
+        assert_eq!(config.csr8645_retry_policy, retry_policy);
+        assert_eq!(config.csr8645_pacing, pacing);
+        assert_eq!(config.obd_baudrate, 9600);
+        assert_eq!(config.default_preset, AudioPreset::Sport);
+        assert_eq!(config.engine_cylinders, 6);
+        assert_eq!(config.redline_warning, None);
+        assert_eq!(config.csr8645_init, defaults.csr8645_init);
+        assert_eq!(config.csr8645_rx_ring_len, defaults.csr8645_rx_ring_len);
+        assert_eq!(config.connect_timeout, defaults.connect_timeout);
+        assert_eq!(config.mapping, defaults.mapping);
+        assert_eq!(config.stale_after, defaults.stale_after);
+        assert_eq!(config.idle_timeout, defaults.idle_timeout);
+        assert!(config.quiet_windows.is_empty());
+    }
+
+    #[test]
+    fn the_rx_ring_length_is_clamped() {
+        let config = AppConfig::builder().csr8645_rx_ring_len(256).build();
+        assert_eq!(config.csr8645_rx_ring_len, 256);
+
+        let config = AppConfig::builder()
+            .csr8645_rx_ring_len(MAX_CSR8645_RX_RING_LEN + 1)
+            .build();
+        assert_eq!(config.csr8645_rx_ring_len, MAX_CSR8645_RX_RING_LEN);
+    }
+
+    #[test]
+    fn the_engine_type_presets_can_be_overridden_by_a_later_mapping() {
+        let diesel = MappingConfig::for_engine(EngineType::Diesel);
+
+        let config = AppConfig::builder()
+            .mapping(MappingConfig {
+                max_volume: 10,
+                ..MappingConfig::default()
+            })
+            .engine_type(EngineType::Diesel)
+            .build();
+        assert_eq!(config.mapping.vehicle, diesel.vehicle);
+        assert_eq!(config.mapping.bass_slope, diesel.bass_slope);
+        // Only the RPM range and the bass slope come from the engine type
+        assert_eq!(config.mapping.max_volume, 10);
+
+        let config = AppConfig::builder()
+            .engine_type(EngineType::Diesel)
+            .mapping(MappingConfig::default())
+            .build();
+        assert_eq!(config.mapping, MappingConfig::default());
+    }
+
+    #[test]
+    fn quiet_windows_accumulate() {
+        let evening = QuietWindow {
+            start: TimeOfDay::new(22, 0),
+            end: TimeOfDay::new(6, 0),
+            max_volume: 4,
+        };
+        let school_run = QuietWindow {
+            start: TimeOfDay::new(8, 0),
+            end: TimeOfDay::new(8, 30),
+            max_volume: 8,
+        };
+
+        let config = AppConfig::builder()
+            .quiet_window(evening)
+            .quiet_window(school_run)
+            .build();
+
+        assert_eq!(config.quiet_windows, [evening, school_run]);
+    }
+}
@@ -0,0 +1,109 @@
+#![no_std]
+#![no_main]
+
+use core::fmt;
+use core::str::FromStr;
+
+use crate::csr8645::parser::ParseError;
+
+/// `BtAddr` is a 48-bit Bluetooth device address.
+///
+/// It parses from the `AABBCCDDEEFF` form used by the CSR8645 module as well as the
+/// colon-separated `AA:BB:CC:DD:EE:FF` form, and displays in the module form.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct BtAddr(pub [u8; 6]);
+
+impl FromStr for BtAddr {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let mut digits = s.chars().filter(|&c| c != ':');
+
+        // Colons are only accepted between every pair of digits, as in `AA:BB:CC:DD:EE:FF`
+        if s.contains(':') {
+            let colon_layout = s.len() == 17
+                && s.bytes()
+                    .enumerate()
+                    .all(|(i, b)| (b == b':') == (i % 3 == 2));
+            if !colon_layout {
+                return Err(ParseError::InvalidAddress);
+            }
+        }
+
+        let mut addr = [0u8; 6];
+        for byte in addr.iter_mut() {
+            let high = digits.next().and_then(|c| c.to_digit(16));
+            let low = digits.next().and_then(|c| c.to_digit(16));
+            match (high, low) {
+                (Some(high), Some(low)) => *byte = (high << 4 | low) as u8,
+                _ => return Err(ParseError::InvalidAddress),
+            }
+        }
+
+        if digits.next().is_some() {
+            return Err(ParseError::InvalidAddress);
+        }
+
+        Ok(BtAddr(addr))
+    }
+}
+
+impl fmt::Display for BtAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    /// The address used by the tests.
+    const ADDR: BtAddr = BtAddr([0xA1, 0xB2, 0xC3, 0xD4, 0xE5, 0xF6]);
+
+    #[test]
+    fn the_module_form_parses() {
+        assert_eq!("A1B2C3D4E5F6".parse(), Ok(ADDR));
+        assert_eq!("a1b2c3d4e5f6".parse(), Ok(ADDR));
+        assert_eq!(" A1B2C3D4E5F6\r\n".parse(), Ok(ADDR));
+    }
+
+    #[test]
+    fn the_colon_form_parses() {
+        assert_eq!("A1:B2:C3:D4:E5:F6".parse(), Ok(ADDR));
+        assert_eq!("a1:b2:c3:d4:e5:f6".parse(), Ok(ADDR));
+    }
+
+    #[test]
+    fn malformed_addresses_are_rejected() {
+        for malformed in [
+            "",
+            "A1B2C3D4E5",
+            "A1B2C3D4E5F6A7",
+            "A1B2C3D4E5G6",
+            "A1:B2:C3:D4:E5",
+            "A1B2:C3D4:E5F6",
+            "A1:B2:C3:D4:E5:F6:",
+            ":A1B2C3D4E5F6",
+            "A1-B2-C3-D4-E5-F6",
+        ] {
+            assert_eq!(
+                malformed.parse::<BtAddr>(),
+                Err(ParseError::InvalidAddress),
+                "{:?}",
+                malformed
+            );
+        }
+    }
+
+    #[test]
+    fn the_address_displays_in_the_module_form() {
+        assert_eq!(ADDR.to_string(), "A1B2C3D4E5F6");
+        assert_eq!(ADDR.to_string().parse(), Ok(ADDR));
+    }
+}
@@ -0,0 +1,163 @@
+#![no_std]
+#![no_main]
+
+use embassy_embedded_hal::SetConfig;
+use embassy_stm32::usart::{BasicInstance, Config, ConfigError, Error, RxDma, TxDma, Uart};
+
+/// Represents an error that can occur while re-opening a byte channel.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub enum ReconfigureError {
+    /// The UART rejected the configuration.
+    Config(ConfigError),
+    /// The channel is shared, and another user is in the middle of an exchange.
+    Busy,
+}
+
+impl From<ConfigError> for ReconfigureError {
+    fn from(err: ConfigError) -> ReconfigureError {
+        ReconfigureError::Config(err)
+    }
+}
+
+/// `ByteChannel` is a trait that defines the byte transport the CSR8645 driver talks through.
+pub trait ByteChannel {
+    /// Writes some of the given bytes.
+    ///
+    /// Depending on the transport, fewer bytes than given may be accepted, so callers must
+    /// write the remainder themselves.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The bytes to write.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the number of bytes written or an error.
+    async fn write_some(&mut self, data: &[u8]) -> Result<usize, Error>;
+
+    /// Reads bytes until the given buffer is full.
+    ///
+    /// # Arguments
+    ///
+    /// * `buf` - The buffer where the received bytes will be stored.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    async fn read(&mut self, buf: &mut [u8]) -> Result<(), Error>;
+
+    /// Reads bytes until the line goes idle or the given buffer is full.
+    ///
+    /// # Arguments
+    ///
+    /// * `buf` - The buffer where the received bytes will be stored.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the number of bytes received or an error.
+    async fn read_until_idle(&mut self, buf: &mut [u8]) -> Result<usize, Error>;
+
+    /// Discards the bytes received but not read yet.
+    fn flush_rx(&mut self);
+
+    /// Waits until all the written bytes have left the transmitter.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    async fn flush_tx(&mut self) -> Result<(), Error>;
+
+    /// Re-opens the channel at another baud rate.
+    ///
+    /// # Arguments
+    ///
+    /// * `baudrate` - The new baud rate.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    fn set_baudrate(&mut self, baudrate: u32) -> Result<(), ReconfigureError>;
+
+    /// Takes exclusive use of the transport for a command/response exchange.
+    ///
+    /// A channel over a dedicated UART has nothing to do. A channel sharing its bus with other
+    /// users holds the bus until `end_exchange`, so their bytes never interleave with the
+    /// exchange.
+    async fn begin_exchange(&mut self) {}
+
+    /// Releases the transport taken by `begin_exchange`.
+    fn end_exchange(&mut self) {}
+
+    /// Returns the number of times received bytes were lost because they were not read in time.
+    ///
+    /// Only channels buffering in the background can count these; the others report zero.
+    fn overruns(&self) -> u32 {
+        0
+    }
+
+    /// Notes that the driver owning the channel was dropped without being closed, leaving the
+    /// module connected and awake.
+    ///
+    /// Nothing is done by default; the loopback channel counts these for the tests.
+    fn dropped_unclosed(&mut self) {}
+}
+
+/// `UartChannel` is a byte channel over a UART, remembering the configuration it was opened with.
+///
+/// The UART cannot report its configuration back, so re-opening it at another baud rate would
+/// otherwise reset the framing and the other settings to their defaults.
+pub struct UartChannel<'d, T: BasicInstance, TxD, RxD> {
+    /// The UART the bytes go through.
+    uart: Uart<'d, T, TxD, RxD>,
+    /// The configuration the UART currently runs with.
+    config: Config,
+}
+
+impl<'d, T: BasicInstance, TxD, RxD> UartChannel<'d, T, TxD, RxD> {
+    /// Creates a new instance of `UartChannel`.
+    ///
+    /// # Arguments
+    ///
+    /// * `uart` - The UART the bytes go through.
+    /// * `config` - The configuration the UART was opened with.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `UartChannel` instance.
+    pub fn new(uart: Uart<'d, T, TxD, RxD>, config: Config) -> Self {
+        Self { uart, config }
+    }
+}
+
+impl<'d, T: BasicInstance, TxD: TxDma<T>, RxD: RxDma<T>> ByteChannel
+    for UartChannel<'d, T, TxD, RxD>
+{
+    async fn write_some(&mut self, data: &[u8]) -> Result<usize, Error> {
+        embedded_io_async::Write::write(&mut self.uart, data).await
+    }
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        self.uart.read(buf).await
+    }
+
+    async fn read_until_idle(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        self.uart.read_until_idle(buf).await
+    }
+
+    fn flush_rx(&mut self) {
+        while self.uart.nb_read().is_ok() {}
+    }
+
+    async fn flush_tx(&mut self) -> Result<(), Error> {
+        embedded_io_async::Write::flush(&mut self.uart).await
+    }
+
+    /// Re-opens the UART at another baud rate, keeping the rest of its configuration.
+    fn set_baudrate(&mut self, baudrate: u32) -> Result<(), ReconfigureError> {
+        let mut config = self.config;
+        config.baudrate = baudrate;
+        SetConfig::set_config(&mut self.uart, &config)?;
+        self.config = config;
+        Ok(())
+    }
+}
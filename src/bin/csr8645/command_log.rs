@@ -0,0 +1,167 @@
+#![no_std]
+#![no_main]
+
+use defmt::info;
+use heapless::{Deque, Vec};
+
+use crate::csr8645::csr8645::Csr8645Error;
+
+/// The number of exchanges kept in the log.
+pub const COMMAND_LOG_CAPACITY: usize = 16;
+
+/// The number of command bytes kept per exchange. Longer commands are truncated.
+const MAX_COMMAND_LEN: usize = 32;
+
+/// The number of response bytes kept per exchange. Longer responses are truncated.
+const MAX_RESPONSE_LEN: usize = 64;
+
+/// `CommandRecord` is a single AT exchange with the CSR8645 module.
+#[derive(Clone, Debug)]
+pub struct CommandRecord {
+    /// The bytes of the command sent.
+    pub command: Vec<u8, MAX_COMMAND_LEN>,
+    /// The response lines received, each followed by `\n`.
+    pub response: Vec<u8, MAX_RESPONSE_LEN>,
+    /// The outcome of the exchange.
+    pub result: Result<(), Csr8645Error>,
+}
+
+/// `CommandLog` keeps the most recent AT exchanges for post-mortem debugging.
+///
+/// The records live in a fixed-capacity ring, so logging never allocates and the oldest
+/// exchange is dropped once the log is full.
+pub struct CommandLog {
+    /// The recorded exchanges, oldest first.
+    records: Deque<CommandRecord, COMMAND_LOG_CAPACITY>,
+}
+
+impl CommandLog {
+    /// Creates a new instance of `CommandLog`.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new, empty `CommandLog` instance.
+    pub const fn new() -> Self {
+        Self {
+            records: Deque::new(),
+        }
+    }
+
+    /// Starts recording a new exchange, dropping the oldest one if the log is full.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command sent to the module.
+    pub fn begin(&mut self, command: &[u8]) {
+        if self.records.is_full() {
+            self.records.pop_front();
+        }
+
+        let len = command.len().min(MAX_COMMAND_LEN);
+        let record = CommandRecord {
+            command: Vec::from_slice(&command[..len]).unwrap_or_default(),
+            response: Vec::new(),
+            result: Ok(()),
+        };
+        let _ = self.records.push_back(record);
+    }
+
+    /// Appends a response line to the current exchange.
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The response line, without its terminator.
+    pub fn append_response(&mut self, line: &[u8]) {
+        if let Some(record) = self.records.back_mut() {
+            for &byte in line.iter().chain(b"\n") {
+                if record.response.push(byte).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Marks the current exchange as failed.
+    ///
+    /// # Arguments
+    ///
+    /// * `err` - The error the exchange failed with.
+    pub fn fail(&mut self, err: Csr8645Error) {
+        if let Some(record) = self.records.back_mut() {
+            record.result = Err(err);
+        }
+    }
+
+    /// Returns the recorded exchanges, oldest first.
+    pub fn records(&self) -> impl Iterator<Item = &CommandRecord> {
+        self.records.iter()
+    }
+
+    /// Logs every recorded exchange.
+    pub fn dump(&self) {
+        info!("Last {} AT exchanges:", self.records.len());
+        for record in self.records.iter() {
+            info!(
+                "{=[u8]:a} -> {=[u8]:a} ({:?})",
+                record.command.as_slice(),
+                record.response.as_slice(),
+                record.result
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_full_log_keeps_only_the_most_recent_exchanges() {
+        let mut log = CommandLog::new();
+
+        for index in 0..COMMAND_LOG_CAPACITY + 3 {
+            log.begin(format!("AT+PIO={}?\r\n", index).as_bytes());
+            log.append_response(b"OK");
+        }
+
+        assert_eq!(log.records().count(), COMMAND_LOG_CAPACITY);
+        let oldest = log.records().next().unwrap();
+        assert_eq!(oldest.command.as_slice(), b"AT+PIO=3?\r\n");
+        let latest = log.records().last().unwrap();
+        assert_eq!(
+            latest.command.as_slice(),
+            format!("AT+PIO={}?\r\n", COMMAND_LOG_CAPACITY + 2).as_bytes()
+        );
+    }
+
+    #[test]
+    fn a_failure_is_recorded_on_the_current_exchange() {
+        let mut log = CommandLog::new();
+        log.begin(b"AT+PIN?\r\n");
+        log.append_response(b"OK+PIN:0000");
+        log.begin(b"AT+PIN=1234\r\n");
+        log.append_response(b"ERROR");
+        log.fail(Csr8645Error::InvalidResponse);
+
+        let mut records = log.records();
+        let succeeded = records.next().unwrap();
+        assert!(succeeded.result.is_ok());
+        assert_eq!(succeeded.response.as_slice(), b"OK+PIN:0000\n");
+        let failed = records.next().unwrap();
+        assert!(matches!(failed.result, Err(Csr8645Error::InvalidResponse)));
+        assert_eq!(failed.response.as_slice(), b"ERROR\n");
+    }
+
+    #[test]
+    fn long_exchanges_are_truncated() {
+        let mut log = CommandLog::new();
+        log.begin(&[b'A'; MAX_COMMAND_LEN + 8]);
+        for _ in 0..10 {
+            log.append_response(b"OK+NAME:DMZ Sound Booster");
+        }
+
+        let record = log.records().next().unwrap();
+        assert_eq!(record.command.len(), MAX_COMMAND_LEN);
+        assert_eq!(record.response.len(), MAX_RESPONSE_LEN);
+    }
+}
@@ -1,331 +1,4100 @@
 #![no_std]
 #![no_main]
 
-use alloc::boxed::Box;
 use alloc::format;
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
-use core::pin::Pin;
-use defmt::{error, info};
-use embassy_stm32::dma::NoDma;
-use embassy_stm32::interrupt;
-use embassy_stm32::usart::{Config, Uart};
+use core::convert::Infallible;
+use core::ops::{Deref, DerefMut};
+use defmt::{error, info, warn};
+use embassy_stm32::gpio::Output;
+use embassy_stm32::peripherals;
+use embassy_stm32::usart::{ConfigError, Error};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::{Mutex, MutexGuard};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use embedded_hal::digital::OutputPin;
+
+use crate::audio::confirmation_tone::{ConfirmationTone, ConfirmationTones, ToneSpec};
+use crate::audio::sample_format::AudioFormat;
+use crate::audio::sweep::SineSweep;
+use crate::csr8645::bt_addr::BtAddr;
+use crate::csr8645::byte_channel::{ByteChannel, ReconfigureError};
+#[cfg(feature = "command-log")]
+use crate::csr8645::command_log::CommandLog;
+use crate::csr8645::line_reader::LineReader;
+use crate::csr8645::parser::{self, ParseError};
+use crate::csr8645::ring_buffered_receiver::RingBufferedReceiver;
+
+/// The maximum number of received bytes buffered while waiting for a complete line.
+const LINE_BUFFER_CAPACITY: usize = 256;
+
+/// The response returned for every command while in dry-run mode.
+const DRY_RUN_RESPONSE: &[u8] = b"OK\r\n";
+
+/// The maximum number of bytes of scan results kept, so a busy environment cannot exhaust the heap.
+const MAX_SCAN_RESPONSE_LEN: usize = 4096;
+
+/// The longest time a scan may take, from the query to the terminating `OK`.
+const SCAN_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// The time `connect` waits for the module to confirm the link by default.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The prefixes of the replies to `AT+BAUD?`, which some firmware revisions send as `OK+Get:`.
+const BAUDRATE_PREFIXES: &[&str] = &["OK+BAUD", "OK+Get:"];
+
+/// The longest time waited for each line of the reply to a query.
+const REPLY_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The number of unrelated lines discarded while waiting for the reply to a query.
+const MAX_UNRELATED_LINES: u8 = 4;
+
+/// The line with which the module rejects a command its firmware does not know.
+const ERROR_REPLY: &str = "ERROR";
+
+/// The longest time waited for the module to answer the notification query, and then the
+/// notification setting.
+const NOTIFICATION_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The time waited for the actual response after the module echoed a command back.
+const ECHO_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The start of the reply to `AT+CON?`, distinct from the `OK+CONN` notification.
+const CON_REPLY_PREFIX: &str = "OK+CON:";
+
+/// The time between two link state queries when the firmware cannot notify link changes.
+const STATE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The longest time a write may go without progress before it is abandoned, on top of the time
+/// the bytes take on the line.
+const WRITE_STALL_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The most bytes handed to the channel in a single write.
+const WRITE_CHUNK_LEN: usize = 256;
+
+/// The number of bits on the line per byte, with one start and one stop bit.
+const BITS_PER_BYTE: u64 = 10;
+
+/// The delay before retrying a write that accepted no bytes.
+const WRITE_RETRY_DELAY: Duration = Duration::from_millis(1);
+
+/// The number of times `resync` tries to get an `OK` out of the module.
+const RESYNC_ATTEMPTS: u8 = 3;
+
+/// The time `resync` waits for stray bytes, and then for the answer to `AT`.
+const RESYNC_WINDOW: Duration = Duration::from_millis(50);
+
+/// The time the module takes to switch to a new baud rate after acknowledging `AT+BAUD=`.
+const BAUD_SETTLE_TIME: Duration = Duration::from_millis(20);
+
+/// The time waited for the module to answer `AT` at a new baud rate.
+const BAUD_VERIFY_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// The number of times a read is retried after a recoverable UART error.
+const UART_RETRY_LIMIT: u8 = 3;
+
+/// The shortest pairing PIN accepted by the CSR8645 module.
+pub const MIN_PIN_LEN: usize = 4;
+
+/// The longest pairing PIN accepted by the CSR8645 module.
+pub const MAX_PIN_LEN: usize = 6;
+
+/// The number of devices the CSR8645 module keeps connected at once in multipoint mode.
+pub const MAX_MULTIPOINT_PEERS: usize = 2;
+
+/// The shortest inquiry or page scan window accepted by the module, in 0.625 ms slots (11.25 ms).
+pub const MIN_SCAN_WINDOW: u16 = 0x0012;
+
+/// The longest inquiry or page scan window accepted by the module, in 0.625 ms slots (2.56 s).
+pub const MAX_SCAN_WINDOW: u16 = 0x1000;
+
+/// The highest PIO pin index exposed by the CSR8645 module.
+pub const MAX_PIO_PIN: u8 = 15;
 
 /// Represents an error that can occur in the CSR8645 module.
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, defmt::Format)]
 pub enum Csr8645Error {
-    UartError(dyn defmt::Format),
+    /// A UART error that retrying cannot fix.
+    UartError(Error),
+    /// An overrun, framing or noise error that persisted after the bounded retries.
+    UartRecoverableError(Error),
+    /// The UART could not be re-opened with the requested configuration.
+    UartConfigError(ConfigError),
+    /// The UART is shared, and could not be re-opened during another user's exchange.
+    UartBusy,
     InvalidResponse,
+    /// An argument was rejected before being sent to the module.
+    InvalidParameter,
+    /// A call command was issued while no matching call is in progress.
+    NoActiveCall,
+    /// A command requiring a connected device was issued while disconnected.
+    NotConnected,
+    /// The module did not answer in time.
+    Timeout,
+    /// The command does not exist in the firmware of the module.
+    Unsupported,
 }
 
-impl From<dyn defmt::Format> for Csr8645Error {
-    fn from(err: Box<dyn defmt::Format>) -> Csr8645Error {
-        Csr8645Error::UartError(err)
+impl From<ParseError> for Csr8645Error {
+    fn from(_: ParseError) -> Csr8645Error {
+        Csr8645Error::InvalidResponse
     }
 }
 
-/// Represents a CSR8645 Bluetooth module.
-pub struct Csr8645<'a> {
-    uart: Uart<'a, interrupt::USART1, NoDma, NoDma>,
+impl From<ReconfigureError> for Csr8645Error {
+    fn from(err: ReconfigureError) -> Csr8645Error {
+        match err {
+            ReconfigureError::Config(err) => Csr8645Error::UartConfigError(err),
+            ReconfigureError::Busy => Csr8645Error::UartBusy,
+        }
+    }
 }
 
-impl Csr8645 {
-    /// Creates a new instance of `Csr8645`.
+impl From<Error> for Csr8645Error {
+    fn from(err: Error) -> Csr8645Error {
+        match err {
+            Error::Overrun | Error::Framing | Error::Noise => {
+                Csr8645Error::UartRecoverableError(err)
+            }
+            _ => Csr8645Error::UartError(err),
+        }
+    }
+}
+
+/// The first firmware version answering `AT+CODEC`.
+const CODEC_SINCE: FirmwareVersion = FirmwareVersion::new(3, 0);
+
+/// The first firmware version answering `AT+BASS`.
+const EQUALIZER_SINCE: FirmwareVersion = FirmwareVersion::new(2, 0);
+
+/// The first firmware version answering `AT+MULTI`.
+const MULTIPOINT_SINCE: FirmwareVersion = FirmwareVersion::new(3, 1);
+
+/// Represents the firmware version reported by the CSR8645 module, e.g. `V3.1`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub struct FirmwareVersion {
+    /// The major version number.
+    pub major: u8,
+    /// The minor version number.
+    pub minor: u8,
+}
+
+impl FirmwareVersion {
+    /// Creates a new instance of `FirmwareVersion`.
     ///
     /// # Arguments
     ///
-    /// * `uart` - An instance of `Uart`.
+    /// * `major` - The major version number.
+    /// * `minor` - The minor version number.
     ///
     /// # Returns
     ///
-    /// * `Csr8645` - A new instance of `Csr8645`.
-    /// * `Csr8645Error` - An error occurred while creating the `Csr8645` instance.
-    pub fn new(uart: Uart<interrupt::USART1, NoDma, NoDma>) -> Result<Self, Csr8645Error> {
-        Ok(Self { uart })
+    /// * `Self` - The new `FirmwareVersion` instance.
+    pub const fn new(major: u8, minor: u8) -> Self {
+        Self { major, minor }
     }
+}
 
-    /// Sends a command to the CSR8645 module.
+/// `Capabilities` lists the advanced commands the firmware of the module answers.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct Capabilities {
+    /// Whether the A2DP codec can be selected with `AT+CODEC`.
+    pub codec: bool,
+    /// Whether the equalizer can be adjusted with `AT+BASS`.
+    pub equalizer: bool,
+    /// Whether multipoint can be enabled with `AT+MULTI`.
+    pub multipoint: bool,
+}
+
+impl Capabilities {
+    /// Returns the advanced commands answered by the given firmware version.
     ///
     /// # Arguments
     ///
-    /// * `command` - The command to send.
+    /// * `version` - The firmware version reported by the module.
     ///
     /// # Returns
     ///
-    /// * `()` - The command was sent successfully.
-    /// * `Csr8645Error` - An error occurred while sending the command.
-    async fn send_command(self: Pin<&mut Self>, command: &[u8]) -> Result<(), Csr8645Error> {
-        self.uart.write(command).await.map_err(Csr8645Error::from)
+    /// * `Self` - The commands introduced up to that version.
+    pub fn for_version(version: FirmwareVersion) -> Self {
+        Self {
+            codec: version >= CODEC_SINCE,
+            equalizer: version >= EQUALIZER_SINCE,
+            multipoint: version >= MULTIPOINT_SINCE,
+        }
     }
+}
 
-    /// Reads the response from the CSR8645 module.
+/// Represents an audio codec supported by the CSR8645 module.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub enum AudioCodec {
+    Sbc,
+    Aac,
+    AptX,
+}
+
+impl AudioCodec {
+    /// Returns the codec index used by the `AT+CODEC` command.
+    fn index(&self) -> u8 {
+        match self {
+            AudioCodec::Sbc => 0,
+            AudioCodec::Aac => 1,
+            AudioCodec::AptX => 2,
+        }
+    }
+}
+
+/// Represents an AVRCP passthrough command forwarded to the connected phone.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub enum AvrcpCommand {
+    Play,
+    Pause,
+    Next,
+    Prev,
+}
+
+impl AvrcpCommand {
+    /// Returns the AT command emitting this passthrough command.
+    fn command(&self) -> &'static [u8] {
+        match self {
+            AvrcpCommand::Play => b"AT+PLAY\r\n",
+            AvrcpCommand::Pause => b"AT+PAUSE\r\n",
+            AvrcpCommand::Next => b"AT+FORWARD\r\n",
+            AvrcpCommand::Prev => b"AT+BACKWARD\r\n",
+        }
+    }
+}
+
+/// Represents the state of the link between the CSR8645 module and a remote device.
+///
+/// In multipoint mode the module is `Connected` as long as at least one peer is connected; the
+/// peers themselves are reported by `Csr8645Driver::connected_devices`.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub enum ConnectionState {
+    Disconnected,
+    Connected,
+}
+
+/// Represents how the driver learns about link changes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub enum EventMode {
+    /// The module notifies link changes with `OK+CONN` and `OK+LOST`.
+    #[default]
+    Notifications,
+    /// Notifications are unavailable, so the link state is queried periodically instead.
+    Polling,
+}
+
+/// `ConnectionStatus` holds the link state reported by the module in answer to `AT+CON?`.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct ConnectionStatus {
+    /// Whether a device is connected.
+    pub connected: bool,
+    /// The address of the connected device, if the module reported it.
+    pub peer: Option<BtAddr>,
+}
+
+/// Represents a link change notified by the CSR8645 module while notifications are enabled.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub enum BtEvent {
+    /// A device connected to the module.
+    Connected,
+    /// The link with the connected device was lost.
+    Disconnected,
+}
+
+/// Represents the state reported by the CSR8645 module.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ModuleState {
+    Initialized,
+    Ready,
+    Pairable,
+    Pairing,
+    Connected,
+    Disconnected,
+    /// A call is ringing on the connected phone.
+    IncomingCall,
+    /// The connected phone is dialing out.
+    OutgoingCall,
+    /// A call is in progress on the connected phone.
+    ActiveCall,
+    /// A state the driver does not recognize, holding the raw response text.
+    Unknown(String),
+}
+
+/// Represents a device found by an inquiry scan.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScannedDevice {
+    /// The address of the device.
+    pub address: BtAddr,
+    /// The name of the device, if it advertised one.
+    pub name: Option<String>,
+}
+
+/// Checks that a pairing PIN is made of `MIN_PIN_LEN` to `MAX_PIN_LEN` ASCII digits.
+///
+/// # Arguments
+///
+/// * `pin` - The PIN to check.
+///
+/// # Returns
+///
+/// * `()` - The PIN is accepted by the module.
+/// * `Csr8645Error` - `InvalidParameter` if the PIN has the wrong length or a non-digit.
+fn validate_pin(pin: &str) -> Result<(), Csr8645Error> {
+    let valid_len = (MIN_PIN_LEN..=MAX_PIN_LEN).contains(&pin.len());
+    if !valid_len || !pin.bytes().all(|b| b.is_ascii_digit()) {
+        error!("Rejected PIN of length {}", pin.len());
+        return Err(Csr8645Error::InvalidParameter);
+    }
+
+    Ok(())
+}
+
+/// `ScanParams` holds how long the module listens for inquiries and pages in each scan interval.
+///
+/// Wider windows make the module faster to discover and to reconnect to, at the cost of a
+/// higher radio duty cycle and thus power draw. Both windows are expressed in 0.625 ms slots,
+/// between `MIN_SCAN_WINDOW` and `MAX_SCAN_WINDOW`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct ScanParams {
+    /// The inquiry scan window, governing how fast new devices discover the module.
+    pub inquiry_window: u16,
+    /// The page scan window, governing how fast paired devices reconnect.
+    pub page_window: u16,
+}
+
+/// `InitConfig` bundles the settings applied when bringing up the CSR8645 module.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InitConfig {
+    /// The Bluetooth name of the module.
+    pub name: String,
+    /// The pairing PIN of the module.
+    pub pin: String,
+    /// The baud rate of the module UART.
+    pub baudrate: u32,
+    /// Whether the module sends event notifications.
+    pub notifications: bool,
+}
+
+impl Default for InitConfig {
+    fn default() -> Self {
+        Self {
+            name: "DMZ Sound Booster".to_string(),
+            pin: "0000".to_string(),
+            baudrate: 115200,
+            notifications: true,
+        }
+    }
+}
+
+/// `RetryPolicy` controls how queries are re-issued after a transient failure.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct RetryPolicy {
+    /// The maximum number of times a query is issued, including the first attempt.
+    pub attempts: u8,
+    /// The delay between two attempts.
+    pub delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 2,
+            delay: Duration::from_millis(50),
+        }
+    }
+}
+
+/// `CommandPacing` slows the commands down for clones that drop bytes when they arrive too fast.
+///
+/// Both delays default to zero, sending commands as fast as the UART allows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, defmt::Format)]
+pub struct CommandPacing {
+    /// The shortest time between the starts of two successive commands.
+    pub inter_command_delay: Duration,
+    /// The delay between two bytes of a command.
+    pub inter_byte_delay: Duration,
+}
+
+/// Represents the mode of a module with separate command and transparent data modes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum WorkMode {
+    /// The module interprets the received bytes as AT commands.
+    At,
+    /// The module forwards the received bytes to the connected device.
+    Data,
+}
+
+/// `WorkModeConfig` holds how an HC-05-style module is switched between its work modes.
+///
+/// Such modules enter the AT mode while their KEY pin is held high, and often expect the
+/// commands at a different baud rate than the transparent data.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct WorkModeConfig {
+    /// The baud rate of the UART in AT mode.
+    pub at_baudrate: u32,
+    /// The baud rate of the UART in data mode.
+    pub data_baudrate: u32,
+    /// The time the module takes to switch modes after the KEY pin changes.
+    pub settle_time: Duration,
+}
+
+impl Default for WorkModeConfig {
+    fn default() -> Self {
+        Self {
+            at_baudrate: 38_400,
+            data_baudrate: 115_200,
+            settle_time: Duration::from_millis(50),
+        }
+    }
+}
+
+/// The byte channel of the CSR8645 module on the board UART.
+pub type Csr8645Uart<'a> =
+    RingBufferedReceiver<'a, peripherals::USART1, peripherals::DMA2_CH7, peripherals::DMA2_CH2>;
+
+/// Represents a CSR8645 Bluetooth module wired to the board UART.
+///
+/// Commands are sent and responses received through DMA, so the end of each response is
+/// detected by the idle line.
+pub type Csr8645<'a> = Csr8645Driver<Csr8645Uart<'a>>;
+
+/// Represents a CSR8645 module shared between the services that talk to it.
+///
+/// The module is on the board UART unless another byte channel is given, e.g. a loopback
+/// channel to exercise the services on the host.
+pub type SharedCsr8645<'a, C = Csr8645Uart<'a>> = Mutex<CriticalSectionRawMutex, Csr8645Driver<C>>;
+
+/// `Csr8645Exchange` holds a `SharedCsr8645` and its byte channel for one command/response
+/// cycle.
+///
+/// The channel is released along with the driver when the exchange is dropped.
+pub struct Csr8645Exchange<'g, C: ByteChannel> {
+    /// The lock on the shared driver.
+    driver: MutexGuard<'g, CriticalSectionRawMutex, Csr8645Driver<C>>,
+}
+
+impl<'g, C: ByteChannel> Csr8645Exchange<'g, C> {
+    /// Locks a shared driver and its byte channel.
     ///
     /// # Arguments
     ///
-    /// * `buf` - The buffer where the response will be stored.
+    /// * `csr8645` - The shared driver.
     ///
     /// # Returns
     ///
-    /// * `()` - The response was read successfully.
-    /// * `Csr8645Error` - An error occurred while reading the response.
-    async fn read_response(self: Pin<&mut Self>, buf: &mut [u8]) -> Result<(), Csr8645Error> {
-        self.uart.read(buf).await.map_err(Csr8645Error::from)
+    /// * `Self` - The exchange holding both locks.
+    pub async fn lock(csr8645: &'g SharedCsr8645<'_, C>) -> Self {
+        let mut driver = csr8645.lock().await;
+        driver.begin_exchange().await;
+        Self { driver }
     }
+}
 
-    // Sets the name of the CSR8645 module.
+impl<'g, C: ByteChannel> Deref for Csr8645Exchange<'g, C> {
+    type Target = Csr8645Driver<C>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.driver
+    }
+}
+
+impl<'g, C: ByteChannel> DerefMut for Csr8645Exchange<'g, C> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.driver
+    }
+}
+
+impl<'g, C: ByteChannel> Drop for Csr8645Exchange<'g, C> {
+    fn drop(&mut self) {
+        self.driver.end_exchange();
+    }
+}
+
+/// Represents a CSR8645 Bluetooth module.
+///
+/// The driver is generic over the byte channel it talks through, so command/response cycles
+/// can be exercised over an in-memory loopback as well as the real UART.
+///
+/// Nothing in the driver is self-referential and the UART is `Unpin`, so the methods take
+/// `&mut self` rather than a pinned reference. The driver is owned by a `SharedCsr8645` mutex,
+/// and each service locks it for the duration of a command/response cycle so exchanges issued
+/// from different services never interleave on the wire:
+///
+/// `BluetoothController::alter_behavior` -> `BluetoothServiceImpl::set_volume` ->
+/// `SharedCsr8645::lock` -> `Csr8645::set_volume` -> UART.
+///
+/// The tests of `bluetooth_service` run this chain on the host over a loopback channel.
+///
+/// When the UART is also shared with other drivers, the channel is a `SharedChannel` and the
+/// service locks it through a `Csr8645Exchange`, which also brackets the cycle with
+/// `begin_exchange` and `end_exchange`, so the bus stays locked until the response has been
+/// read.
+///
+/// Dropping the driver cannot talk to the module, since there is no async `Drop`, so the
+/// module would be left connected and awake. A driver that is going away must be shut down
+/// with `close`; dropping it without doing so logs a warning. In the firmware the driver lives
+/// in a static `SharedCsr8645` and is never dropped, so it is closed through the mutex by the
+/// Bluetooth shutdown on ignition-off.
+pub struct Csr8645Driver<C: ByteChannel, K: OutputPin<Error = Infallible> = Output<'static>> {
+    /// The byte channel connected to the module.
+    channel: C,
+    /// Buffers received bytes so responses arriving in a single burst are not lost.
+    line_reader: LineReader,
+    /// When true, commands are recorded instead of being written to the UART.
+    dry_run: bool,
+    /// The commands recorded while in dry-run mode.
+    recorded_commands: Vec<Vec<u8>>,
+    /// The last non-zero volume level set on the module.
+    volume: u8,
+    /// The volume to restore on unmute, set while the module is muted.
+    muted_volume: Option<u8>,
+    /// The state of the link with the remote device.
+    connection_state: ConnectionState,
+    /// Whether the module keeps up to `MAX_MULTIPOINT_PEERS` devices connected at once.
+    multipoint: bool,
+    /// The addresses of the connected devices, oldest first.
+    connected_peers: Vec<BtAddr>,
+    /// The beeps played by `play_confirmation`.
+    confirmation_tones: ConfirmationTones,
+    /// The format of the audio stream sent to the module, which the generated tones match.
+    audio_format: AudioFormat,
+    /// How queries are re-issued after a garbled or missing response.
+    retry_policy: RetryPolicy,
+    /// The time `connect` waits for the module to confirm the link.
+    connect_timeout: Duration,
+    /// How fast commands are sent to the module.
+    pacing: CommandPacing,
+    /// The time the last command started being sent.
+    last_command_at: Option<Instant>,
+    /// The baud rate requested from the module without a clean acknowledgement, until the next
+    /// `get_baudrate`.
+    pending_baudrate: Option<u32>,
+    /// The baud rate the UART runs at, as last set by the driver.
+    uart_baudrate: u32,
+    /// The KEY pin selecting the work mode, if the module has separate modes.
+    key_pin: Option<K>,
+    /// How the module is switched between its work modes.
+    work_mode_config: WorkModeConfig,
+    /// The current work mode of the module, `None` until it is first switched.
+    work_mode: Option<WorkMode>,
+    /// Whether `close` has been called.
+    closed: bool,
+    /// The advanced commands the firmware answers, `None` until `initialize` identified it.
+    capabilities: Option<Capabilities>,
+    /// How link changes are detected.
+    event_mode: EventMode,
+    /// The time the link state was last queried in polling mode.
+    last_state_poll: Option<Instant>,
+    /// The most recent AT exchanges, kept for post-mortem debugging.
+    #[cfg(feature = "command-log")]
+    command_log: CommandLog,
+}
+
+impl<C: ByteChannel, K: OutputPin<Error = Infallible>> Csr8645Driver<C, K> {
+    /// Creates a new instance of `Csr8645`.
     ///
     /// # Arguments
     ///
-    /// * `name` - The new name for the module.
+    /// * `channel` - The byte channel connected to the module, usually a `RingBufferedReceiver`.
+    /// * `uart_baudrate` - The baud rate the channel was opened at, which a failed baud rate
+    ///   change rolls back to.
     ///
     /// # Returns
     ///
-    /// * `()` - The name was set successfully.
-    /// * `Csr8645Error` - An error occurred while setting the name.
-    pub async fn set_name(self: Pin<&mut Self>, name: &str) -> Result<(), Csr8645Error> {
-        let command = format!("AT+NAME={}\r\n", name);
-        self.send_command(command.as_bytes()).await
+    /// * `Csr8645` - A new instance of `Csr8645`.
+    /// * `Csr8645Error` - An error occurred while creating the `Csr8645` instance.
+    pub fn new(channel: C, uart_baudrate: u32) -> Result<Self, Csr8645Error> {
+        Ok(Self {
+            channel,
+            line_reader: LineReader::new(LINE_BUFFER_CAPACITY),
+            dry_run: false,
+            recorded_commands: Vec::new(),
+            volume: 0,
+            muted_volume: None,
+            connection_state: ConnectionState::Disconnected,
+            multipoint: false,
+            connected_peers: Vec::new(),
+            confirmation_tones: ConfirmationTones::default(),
+            audio_format: AudioFormat::A2DP_STEREO,
+            retry_policy: RetryPolicy::default(),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            pacing: CommandPacing::default(),
+            last_command_at: None,
+            pending_baudrate: None,
+            uart_baudrate,
+            key_pin: None,
+            work_mode_config: WorkModeConfig::default(),
+            work_mode: None,
+            closed: false,
+            capabilities: None,
+            event_mode: EventMode::default(),
+            last_state_poll: None,
+            #[cfg(feature = "command-log")]
+            command_log: CommandLog::new(),
+        })
     }
 
-    /// Gets the name of the CSR8645 module.
+    /// Enables or disables dry-run mode.
     ///
-    /// # Returns
+    /// In dry-run mode commands are recorded instead of being written to the UART, and every
+    /// read returns a canned `OK` response. This allows bench development without a module.
     ///
-    /// * `()` - The name was obtained successfully.
-    /// * `Csr8645Error` - An error occurred while getting the name.
-    pub async fn get_name(self: Pin<&mut Self>) -> Result<(), Csr8645Error> {
-        let command = b"AT+NAME?\r\n";
-        self.send_command(command).await?;
-
-        let mut buf = [0u8; 64];
-        self.read_response(&mut buf).await?;
-
-        info!("Received: {=[u8]}", &buf);
-
-        Ok(())
+    /// # Arguments
+    ///
+    /// * `enable` - True to enable dry-run mode, false to disable it.
+    pub fn set_dry_run(&mut self, enable: bool) {
+        self.dry_run = enable;
     }
 
-    /// Sets the PIN of the CSR8645 module.
+    /// Sets how queries are re-issued after a garbled or missing response.
     ///
     /// # Arguments
     ///
-    /// * `pin` - The new PIN for the module.
+    /// * `retry_policy` - The new retry policy.
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
+
+    /// Sets the time `connect` waits for the module to confirm the link.
     ///
-    /// # Returns
+    /// # Arguments
     ///
-    /// * `()` - The PIN was set successfully.
-    /// * `Csr8645Error` - An error occurred while setting the PIN.
-    pub async fn set_pin(self: Pin<&mut Self>, pin: &str) -> Result<(), Csr8645Error> {
-        let command = format!("AT+PIN={}\r\n", pin);
-        self.send_command(command.as_bytes()).await
+    /// * `timeout` - The new connection timeout.
+    pub fn set_connect_timeout(&mut self, timeout: Duration) {
+        self.connect_timeout = timeout;
     }
 
-    /// Gets the PIN of the CSR8645 module.
+    /// Sets how fast commands are sent to the module.
     ///
-    /// # Returns
+    /// # Arguments
     ///
-    /// * `String` - The PIN of the module.
-    /// * `Csr8645Error` - An error occurred while getting the PIN.
-    pub async fn get_pin(self: Pin<&mut Self>) -> Result<String, Csr8645Error> {
-        let command = b"AT+PIN?\r\n";
-        self.send_command(command).await?;
-
-        let mut buf = [0u8; 64];
-        self.read_response(&mut buf).await?;
-
-        // Convert the response to a string and return it
-        let pin = String::from_utf8(buf.to_vec()).map_err(|_| Csr8645Error::InvalidResponse)?;
-        Ok(pin)
+    /// * `pacing` - The new command pacing.
+    pub fn set_command_pacing(&mut self, pacing: CommandPacing) {
+        self.pacing = pacing;
     }
 
-    /// Sets the baud rate of the CSR8645 module.
+    /// Sets the KEY pin of a module with separate AT and data modes.
+    ///
+    /// Once set, commands switch the module to AT mode and data transfers switch it to data
+    /// mode as needed. Without a KEY pin the module takes both on the same UART, as the CSR8645
+    /// does, and no switching happens.
     ///
     /// # Arguments
     ///
-    /// * `baudrate` - The new baud rate for the module.
+    /// * `key_pin` - The output driving the KEY pin, high in AT mode, usually an `Output`.
+    /// * `config` - How the module is switched between its work modes.
+    pub fn set_key_pin(&mut self, key_pin: K, config: WorkModeConfig) {
+        self.key_pin = Some(key_pin);
+        self.work_mode_config = config;
+        // Force the next switch so the pin and the baud rate match the tracked mode
+        self.work_mode = None;
+    }
+
+    /// Returns the current work mode of the module, or `None` if the module has no separate
+    /// modes or has not been switched yet.
+    pub fn work_mode(&self) -> Option<WorkMode> {
+        self.work_mode
+    }
+
+    /// Takes exclusive use of the byte channel for a command/response cycle.
+    ///
+    /// This only matters when the channel is a bus shared with other drivers, see
+    /// `SharedChannel`. The channel is held until `end_exchange`.
+    pub async fn begin_exchange(&mut self) {
+        self.channel.begin_exchange().await;
+    }
+
+    /// Releases the byte channel taken by `begin_exchange`.
+    pub fn end_exchange(&mut self) {
+        self.channel.end_exchange();
+    }
+
+    /// Switches the module to AT mode, if it is not in it already.
     ///
     /// # Returns
     ///
-    /// * `()` - The baud rate was set successfully.
-    /// * `Csr8645Error` - An error occurred while setting the baud rate.
-    pub async fn set_baudrate(self: Pin<&mut Self>, baudrate: u32) -> Result<(), Csr8645Error> {
-        let command = format!("AT+BAUD={}\r\n", baudrate);
-        self.send_command(command.as_bytes()).await
+    /// * `()` - The module is in AT mode.
+    /// * `Csr8645Error` - The UART could not be re-opened at the AT baud rate.
+    pub async fn enter_at_mode(&mut self) -> Result<(), Csr8645Error> {
+        self.switch_work_mode(WorkMode::At).await
     }
 
-    /// Gets the baud rate of the CSR8645 module.
+    /// Switches the module to data mode, if it is not in it already.
     ///
     /// # Returns
     ///
-    /// * `u32` - The baud rate of the module.
-    /// * `Csr8645Error` - An error occurred while getting the baud rate.
-    pub async fn get_baudrate(self: Pin<&mut Self>) -> Result<u32, Csr8645Error> {
-        let command = b"AT+BAUD?\r\n";
-        self.send_command(command).await?;
-
-        let mut buf = [0u8; 64];
-        self.read_response(&mut buf).await?;
-
-        // Convert the response to an u32 and return it
-        let baudrate = u32::from_str_radix(core::str::from_utf8(&buf).unwrap().trim(), 10)
-            .map_err(|_| Csr8645Error::InvalidResponse)?;
-        Ok(baudrate)
+    /// * `()` - The module is in data mode.
+    /// * `Csr8645Error` - The UART could not be re-opened at the data baud rate.
+    pub async fn enter_data_mode(&mut self) -> Result<(), Csr8645Error> {
+        self.switch_work_mode(WorkMode::Data).await
     }
 
-    /// Connects to a device.
+    /// Drives the KEY pin and re-opens the UART at the baud rate of the given work mode.
     ///
     /// # Arguments
     ///
-    /// * `address` - The address of the device to connect to.
+    /// * `mode` - The work mode to switch to.
     ///
     /// # Returns
     ///
-    /// * `()` - The device was connected successfully.
-    /// * `Csr8645Error` - An error occurred while connecting to the device.
-    pub async fn connect(self: Pin<&mut Self>, address: &str) -> Result<(), Csr8645Error> {
-        let command = format!("AT+CON{}\r\n", address);
-        self.send_command(command.as_bytes()).await
+    /// * `()` - The module is in the given mode.
+    /// * `Csr8645Error` - The UART could not be re-opened at the baud rate of the mode.
+    async fn switch_work_mode(&mut self, mode: WorkMode) -> Result<(), Csr8645Error> {
+        if self.work_mode == Some(mode) {
+            return Ok(());
+        }
+        let Some(key_pin) = self.key_pin.as_mut() else {
+            return Ok(());
+        };
+
+        if !self.dry_run {
+            // Driving the pin cannot fail, so its result is ignored
+            let baudrate = match mode {
+                WorkMode::At => {
+                    let _ = key_pin.set_high();
+                    self.work_mode_config.at_baudrate
+                }
+                WorkMode::Data => {
+                    let _ = key_pin.set_low();
+                    self.work_mode_config.data_baudrate
+                }
+            };
+            self.channel.set_baudrate(baudrate).map_err(|e| {
+                error!("Failed to re-open the UART: {:?}", e);
+                Csr8645Error::from(e)
+            })?;
+            self.uart_baudrate = baudrate;
+
+            Timer::after(self.work_mode_config.settle_time).await;
+            self.flush_rx();
+        }
+
+        info!("Switched to {} mode", mode);
+        self.work_mode = Some(mode);
+        Ok(())
     }
 
-    /// Disconnects from the current device.
+    /// Returns the commands recorded while in dry-run mode, in the order they were issued.
+    pub fn recorded_commands(&self) -> &[Vec<u8>] {
+        &self.recorded_commands
+    }
+
+    /// Returns the command log holding the most recent AT exchanges.
+    #[cfg(feature = "command-log")]
+    pub fn command_log(&self) -> &CommandLog {
+        &self.command_log
+    }
+
+    /// Logs the most recent AT exchanges.
+    #[cfg(feature = "command-log")]
+    pub fn dump_command_log(&self) {
+        self.command_log.dump();
+    }
+
+    /// Records the failure of the current exchange and dumps the command log.
     ///
-    /// # Returns
+    /// # Arguments
     ///
-    /// * `()` - The device was disconnected successfully.
-    /// * `Csr8645Error` - An error occurred while disconnecting from the device.
-    pub async fn disconnect(self: Pin<&mut Self>) -> Result<(), Csr8645Error> {
-        let command = b"AT";
-        self.send_command(command).await
+    /// * `err` - The error the exchange failed with.
+    #[cfg(feature = "command-log")]
+    fn log_failure(&mut self, err: Csr8645Error) {
+        self.command_log.fail(err);
+        self.command_log.dump();
     }
 
-    /// Checks if the CSR8645 module is connected to a device.
+    /// Sends a command to the CSR8645 module.
+    ///
+    /// The command pacing is honored: the command waits for the inter-command delay since the
+    /// previous one, and its bytes are spaced by the inter-byte delay. The transmitter is
+    /// flushed before returning, so the whole command has left the UART by the time the caller
+    /// starts reading the response.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command to send.
     ///
     /// # Returns
     ///
-    /// * `bool` - True if the module is connected to a device, false otherwise.
-    /// * `Csr8645Error` - An error occurred while checking the connection status.
-    pub async fn check_connection_status(self: Pin<&mut Self>) -> Result<bool, Csr8645Error> {
-        let command = b"AT+CON?\r\n";
-        self.send_command(command).await?;
+    /// * `()` - The command was sent successfully.
+    /// * `Csr8645Error` - An error occurred while sending the command.
+    async fn send_command(&mut self, command: &[u8]) -> Result<(), Csr8645Error> {
+        self.enter_at_mode().await?;
 
-        let mut buf = [0u8; 64];
-        self.read_response(&mut buf).await?;
+        #[cfg(feature = "command-log")]
+        self.command_log.begin(command);
 
-        let response =
-            String::from_utf8(buf.to_vec()).map_err(|_| Csr8645Error::InvalidResponse)?;
-        Ok(response.contains("OK+CON"))
-    }
+        if self.dry_run {
+            info!("Dry run: {=[u8]:a}", command);
+            self.recorded_commands.push(command.to_vec());
+            return Ok(());
+        }
 
-    /// Scans for nearby devices.
-    ///
-    /// # Returns
-    ///
-    /// * `Vec<String>` - A list of the addresses of the nearby devices.
-    /// * `Csr8645Error` - An error occurred while scanning for devices.
-    pub async fn scan(self: Pin<&mut Self>) -> Result<Vec<String>, Csr8645Error> {
-        let command = b"AT+DISC?\r\n";
-        self.send_command(command).await?;
+        if let Some(last) = self.last_command_at {
+            Timer::at(last + self.pacing.inter_command_delay).await;
+        }
+        self.last_command_at = Some(Instant::now());
+
+        let written = if self.pacing.inter_byte_delay.as_ticks() == 0 {
+            self.write_all(command).await
+        } else {
+            self.write_paced(command).await
+        };
+        let result = match written {
+            Ok(()) => self.channel.flush_tx().await.map_err(Csr8645Error::from),
+            Err(err) => Err(err),
+        };
 
-        let mut buf = [0u8; 512];
-        self.read_response(&mut buf).await?;
+        #[cfg(feature = "command-log")]
+        if let Err(err) = result {
+            self.log_failure(err);
+        }
 
-        let response =
-            String::from_utf8(buf.to_vec()).map_err(|_| Csr8645Error::InvalidResponse)?;
-        let addresses = response.split('\n').map(|s| s.to_string()).collect();
-        Ok(addresses)
+        result
     }
 
-    /// Sends data to the connected device.
+    /// Returns the time the given number of bytes takes on the line at the current baud rate.
     ///
     /// # Arguments
     ///
-    /// * `data` - The data to send.
+    /// * `len` - The number of bytes.
     ///
     /// # Returns
     ///
-    /// * `()` - The data was sent successfully.
-    /// * `Csr8645Error` - An error occurred while sending the data.
-    pub async fn send_data(self: Pin<&mut Self>, data: &[u8]) -> Result<(), Csr8645Error> {
-        self.uart.write(data).await.map_err(Csr8645Error::from)
+    /// * `Duration` - The transmission time.
+    fn transmit_time(&self, len: usize) -> Duration {
+        let baudrate = self.uart_baudrate.max(1) as u64;
+        Duration::from_micros(len as u64 * BITS_PER_BYTE * 1_000_000 / baudrate)
     }
 
-    /// Receives data from the connected device.
+    /// Writes all the given bytes, looping over partial writes.
+    ///
+    /// The bytes are handed to the channel in chunks of at most `WRITE_CHUNK_LEN`, each given
+    /// its transmission time plus `WRITE_STALL_TIMEOUT` to complete. A chunk running past that
+    /// is not re-sent, since an unknown part of it may already be on the line.
     ///
     /// # Arguments
     ///
-    /// * `buf` - The buffer where the received data will be stored.
+    /// * `data` - The bytes to write.
     ///
     /// # Returns
     ///
-    /// * `()` - The data was received successfully.
-    /// * `Csr8645Error` - An error occurred while receiving the data.
-    pub async fn receive_data(self: Pin<&mut Self>, buf: &mut [u8]) -> Result<(), Csr8645Error> {
-        self.uart.read(buf).await.map_err(Csr8645Error::from)
+    /// * `()` - All the bytes were written.
+    /// * `Csr8645Error::Timeout` - A chunk did not complete in time, or no progress was made for
+    ///   `WRITE_STALL_TIMEOUT`.
+    /// * `Csr8645Error` - Another error occurred while writing.
+    async fn write_all(&mut self, mut data: &[u8]) -> Result<(), Csr8645Error> {
+        let mut last_progress = Instant::now();
+        while !data.is_empty() {
+            let chunk = &data[..data.len().min(WRITE_CHUNK_LEN)];
+            let timeout = self.transmit_time(chunk.len()) + WRITE_STALL_TIMEOUT;
+            let written = match with_timeout(timeout, self.channel.write_some(chunk)).await {
+                Ok(written) => written?,
+                Err(_) => {
+                    error!("Write timed out with {} bytes left", data.len());
+                    return Err(Csr8645Error::Timeout);
+                }
+            };
+
+            if written > 0 {
+                data = &data[written.min(data.len())..];
+                last_progress = Instant::now();
+            } else if last_progress.elapsed() >= WRITE_STALL_TIMEOUT {
+                error!("Write stalled with {} bytes left", data.len());
+                return Err(Csr8645Error::Timeout);
+            } else {
+                Timer::after(WRITE_RETRY_DELAY).await;
+            }
+        }
+
+        Ok(())
     }
 
-    /// Plays audio data.
+    /// Writes the given bytes one at a time, spaced by the inter-byte delay.
     ///
     /// # Arguments
     ///
-    /// * `data` - The audio data to play.
+    /// * `data` - The bytes to write.
     ///
     /// # Returns
     ///
-    /// * `()` - The audio data was played successfully.
-    /// * `Csr8645Error` - An error occurred while playing the audio data.
-    pub async fn play_audio(self: Pin<&mut Self>, data: &[u8]) -> Result<(), Csr8645Error> {
-        // Send the audio data to the CSR8645 module
-        self.uart.write(data).await.map_err(Csr8645Error::from)
+    /// * `()` - All the bytes were written.
+    /// * `Csr8645Error` - An error occurred while writing.
+    async fn write_paced(&mut self, data: &[u8]) -> Result<(), Csr8645Error> {
+        for (i, byte) in data.iter().enumerate() {
+            if i > 0 {
+                Timer::after(self.pacing.inter_byte_delay).await;
+            }
+            self.write_all(core::slice::from_ref(byte)).await?;
+        }
+
+        Ok(())
     }
 
-    /// Receives audio data.
+    /// Reads the response from the CSR8645 module.
+    ///
+    /// The read completes as soon as the line goes idle, so a response of any length up to the
+    /// buffer size is captured in one call. UART errors are recovered from as in
+    /// `read_with_recovery`, and in dry-run mode the canned response is returned instead.
     ///
     /// # Arguments
     ///
-    /// * `buf` - The buffer where the received audio data will be stored.
+    /// * `buf` - The buffer where the response will be stored.
     ///
     /// # Returns
     ///
-    /// * `()` - The audio data was received successfully.
-    /// * `Csr8645Error` - An error occurred while receiving the audio data.
-    pub async fn receive_audio(self: Pin<&mut Self>, buf: &mut [u8]) -> Result<(), Csr8645Error> {
-        self.uart.read(buf).await.map_err(Csr8645Error::from)
+    /// * `usize` - The number of bytes received.
+    /// * `Csr8645Error` - An error occurred while reading the response.
+    async fn read_response(&mut self, buf: &mut [u8]) -> Result<usize, Csr8645Error> {
+        if self.dry_run {
+            let len = DRY_RUN_RESPONSE.len().min(buf.len());
+            buf[..len].copy_from_slice(&DRY_RUN_RESPONSE[..len]);
+            return Ok(len);
+        }
+
+        self.read_with_recovery(buf).await
     }
 
-    /// Gets the current status of the CSR8645 module.
+    /// Reads from the UART until the line goes idle, recovering from overrun, framing and noise
+    /// errors.
+    ///
+    /// The driver clears the error flag when reporting the error; the stale received bytes are
+    /// then flushed and the read is retried up to `UART_RETRY_LIMIT` times before failing.
+    ///
+    /// # Arguments
+    ///
+    /// * `buf` - The buffer where the received data will be stored.
     ///
     /// # Returns
     ///
-    /// * `String` - The current status of the module.
-    /// * `Csr8645Error` - An error occurred while getting the status.
-    pub async fn get_status(self: Pin<&mut Self>) -> Result<String, Csr8645Error> {
-        let command = b"AT+STATE?\r\n";
-        self.send_command(command).await?;
-
-        let mut buf = [0u8; 64];
-        self.read_response(&mut buf).await?;
-
-        // Convert the response to a string and return it
-        let status = String::from_utf8(buf.to_vec()).map_err(|_| Csr8645Error::InvalidResponse)?;
-        Ok(status)
+    /// * `usize` - The number of bytes read.
+    /// * `Csr8645Error` - A fatal error occurred, or a recoverable one persisted.
+    async fn read_with_recovery(&mut self, buf: &mut [u8]) -> Result<usize, Csr8645Error> {
+        let mut attempt = 0;
+        loop {
+            match self.channel.read_until_idle(buf).await {
+                Ok(len) => return Ok(len),
+                Err(err) => self.recover(err.into(), &mut attempt)?,
+            }
+        }
     }
 
-    /// Enables or disables notifications.
+    /// Decides whether a failed read is retried, flushing the stale received bytes if so.
     ///
     /// # Arguments
     ///
-    /// * `enable` - True to enable notifications, false to disable them.
+    /// * `err` - The error the read failed with.
+    /// * `attempt` - The number of retries made so far, incremented on retry.
     ///
     /// # Returns
     ///
-    /// * `()` - The notification setting was changed successfully.
-    /// * `Csr8645Error` - An error occurred while changing the notification setting.
-    pub async fn set_notifications(self: Pin<&mut Self>, enable: bool) -> Result<(), Csr8645Error> {
-        let command = if enable {
-            b"AT+NOTI1\r\n"
+    /// * `()` - The read should be retried.
+    /// * `Csr8645Error` - The error is fatal, or the retries are exhausted.
+    fn recover(&mut self, err: Csr8645Error, attempt: &mut u8) -> Result<(), Csr8645Error> {
+        if !matches!(err, Csr8645Error::UartRecoverableError(_)) || *attempt >= UART_RETRY_LIMIT {
+            error!("UART read failed: {:?}", err);
+            return Err(err);
+        }
+
+        *attempt += 1;
+        warn!("Recovering from UART error {:?}, attempt {}", err, attempt);
+        self.flush_rx();
+        Ok(())
+    }
+
+    /// Discards all the received bytes that have not been consumed yet.
+    fn flush_rx(&mut self) {
+        self.line_reader.clear();
+        self.channel.flush_rx();
+    }
+
+    /// Reads the next response line and checks that the module acknowledged the last command.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The module answered `OK`.
+    /// * `Csr8645Error` - The module answered something else, or the read failed.
+    async fn expect_ok(&mut self) -> Result<(), Csr8645Error> {
+        let response = self.read_line().await?;
+        if response.trim().starts_with("OK") {
+            Ok(())
         } else {
-            b"AT+NOTI0\r\n"
+            error!("Expected OK, received: {=str}", response.as_str());
+            #[cfg(feature = "command-log")]
+            self.log_failure(Csr8645Error::InvalidResponse);
+            Err(Csr8645Error::InvalidResponse)
+        }
+    }
+
+    /// Sends a query and parses its response line, re-issuing it after a transient failure.
+    ///
+    /// The module occasionally answers with garbage right after waking up. A response that fails
+    /// to parse, or a read that times out, is retried according to the retry policy, resyncing
+    /// with the module before the next attempt. Lines not starting with one of the expected
+    /// prefixes, such as a stale reply to an earlier query, are discarded rather than parsed.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The query to send.
+    /// * `prefixes` - The texts the reply to the query may start with, e.g. `OK+BAUD`.
+    /// * `parse` - The parser applied to the response line.
+    ///
+    /// # Returns
+    ///
+    /// * `T` - The parsed response.
+    /// * `Csr8645Error` - The last attempt failed, or a non-transient error occurred.
+    async fn with_retry<T>(
+        &mut self,
+        command: &[u8],
+        prefixes: &[&str],
+        parse: fn(&[u8]) -> Result<T, ParseError>,
+    ) -> Result<T, Csr8645Error> {
+        let RetryPolicy { attempts, delay } = self.retry_policy;
+
+        let mut attempt = 1;
+        loop {
+            self.send_command(command).await?;
+            let result = match self.read_reply(prefixes).await {
+                Ok(response) => parse(&response).map_err(Csr8645Error::from),
+                Err(err) => Err(err),
+            };
+
+            match result {
+                Err(Csr8645Error::InvalidResponse | Csr8645Error::Timeout)
+                    if attempt < attempts =>
+                {
+                    warn!("Query {=[u8]:a} failed, attempt {}", command, attempt);
+                    attempt += 1;
+                    if let Err(err) = self.resync().await {
+                        warn!("Retrying without resync: {:?}", err);
+                    }
+                    Timer::after(delay).await;
+                }
+                result => {
+                    #[cfg(feature = "command-log")]
+                    if let Err(err) = result {
+                        self.log_failure(err);
+                    }
+                    return result;
+                }
+            }
+        }
+    }
+
+    /// Reads the reply to a query, discarding the lines that do not belong to it.
+    ///
+    /// Up to `MAX_UNRELATED_LINES` lines are discarded before giving up, and each line must
+    /// arrive within `REPLY_TIMEOUT`, so a module that stays silent lets `with_retry` retry. An
+    /// `ERROR` line means the firmware does not know the query, which is not retried. In dry-run
+    /// mode the canned response is accepted as the reply.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefixes` - The texts the reply may start with.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<u8>` - The reply line without its `\r\n` terminator.
+    /// * `Csr8645Error::Timeout` - A line did not arrive in time.
+    /// * `Csr8645Error::Unsupported` - The module answered `ERROR`.
+    /// * `Csr8645Error` - `InvalidResponse` if no line matched, or the read failed.
+    async fn read_reply(&mut self, prefixes: &[&str]) -> Result<Vec<u8>, Csr8645Error> {
+        for _ in 0..=MAX_UNRELATED_LINES {
+            let line = match with_timeout(REPLY_TIMEOUT, self.read_raw_line()).await {
+                Ok(line) => line?,
+                Err(_) => {
+                    warn!("No reply within {} ms", REPLY_TIMEOUT.as_millis());
+                    return Err(Csr8645Error::Timeout);
+                }
+            };
+            if self.dry_run || parser::has_prefix(&line, prefixes) {
+                return Ok(line);
+            }
+            if parser::has_prefix(&line, &[ERROR_REPLY]) {
+                warn!("Query rejected: {=[u8]:a}", line.as_slice());
+                return Err(Csr8645Error::Unsupported);
+            }
+            warn!("Discarding unrelated line: {=[u8]:a}", line.as_slice());
+        }
+
+        Err(Csr8645Error::InvalidResponse)
+    }
+
+    /// Reads the next response line and checks that it starts with the given confirmation.
+    ///
+    /// In dry-run mode the canned response is accepted as the confirmation.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - The text the response must start with, e.g. `OK+DISC`.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The module sent the expected confirmation.
+    /// * `Csr8645Error` - The module answered something else, or the read failed.
+    async fn expect_prefix(&mut self, prefix: &str) -> Result<(), Csr8645Error> {
+        let dry_run = self.dry_run;
+        let response = self.read_line().await?;
+        if dry_run || response.trim().starts_with(prefix) {
+            Ok(())
+        } else {
+            error!(
+                "Expected {=str}, received: {=str}",
+                prefix,
+                response.as_str()
+            );
+            #[cfg(feature = "command-log")]
+            self.log_failure(Csr8645Error::InvalidResponse);
+            Err(Csr8645Error::InvalidResponse)
+        }
+    }
+
+    /// Reads the next response line from the CSR8645 module.
+    ///
+    /// Lines already buffered from a previous read are returned first, so consecutive getters
+    /// stay in sync with the module even if it sent several responses at once.
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The response line without its `\r\n` terminator.
+    /// * `Csr8645Error` - An error occurred while reading the response.
+    async fn read_line(&mut self) -> Result<String, Csr8645Error> {
+        let line = self.read_raw_line().await?;
+        String::from_utf8(line).map_err(|_| Csr8645Error::InvalidResponse)
+    }
+
+    /// Reads the next response line from the CSR8645 module without decoding it.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<u8>` - The response line without its `\r\n` terminator.
+    /// * `Csr8645Error` - An error occurred while reading the response.
+    async fn read_raw_line(&mut self) -> Result<Vec<u8>, Csr8645Error> {
+        loop {
+            if let Some(line) = self.line_reader.next_line() {
+                #[cfg(feature = "command-log")]
+                self.command_log.append_response(&line);
+                return Ok(line);
+            }
+
+            let mut chunk = [0u8; 64];
+            let len = match self.read_response(&mut chunk).await {
+                Ok(len) => len,
+                Err(err) => {
+                    #[cfg(feature = "command-log")]
+                    self.log_failure(err);
+                    return Err(err);
+                }
+            };
+            self.line_reader.push(&chunk[..len]);
+        }
+    }
+
+    // Sets the name of the CSR8645 module.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The new name for the module.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The module acknowledged the new name.
+    /// * `Csr8645Error` - An error occurred while setting the name.
+    pub async fn set_name(&mut self, name: &str) -> Result<(), Csr8645Error> {
+        let command = format!("AT+NAME={}\r\n", name);
+        self.send_command(command.as_bytes()).await?;
+        self.expect_ok().await
+    }
+
+    /// Checks that the module answers a bare `AT` with `OK`.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The module answered.
+    /// * `Csr8645Error` - The module answered something else, or did not answer.
+    pub async fn ping(&mut self) -> Result<(), Csr8645Error> {
+        let command = b"AT\r\n";
+        self.send_command(command).await?;
+        self.expect_ok().await
+    }
+
+    /// Brings the module back in step with the driver after the link was corrupted.
+    ///
+    /// After EMI-induced garbage the module may be stuck mid-frame and ignore the next command.
+    /// The received bytes are discarded and a lone `\r\n` terminates whatever partial frame the
+    /// module holds. Its answer is drained, then `AT` is sent expecting `OK`, up to
+    /// `RESYNC_ATTEMPTS` times.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The module answered `OK`.
+    /// * `Csr8645Error` - The module never answered `OK`, with the last error.
+    pub async fn resync(&mut self) -> Result<(), Csr8645Error> {
+        if self.dry_run {
+            return Ok(());
+        }
+
+        let mut last_err = Csr8645Error::Timeout;
+        for attempt in 1..=RESYNC_ATTEMPTS {
+            self.flush_rx();
+            self.send_command(b"\r\n").await?;
+
+            let mut chunk = [0u8; 64];
+            // Drain the answer to the lone terminator until the line stays quiet
+            loop {
+                match with_timeout(RESYNC_WINDOW, self.read_response(&mut chunk)).await {
+                    Ok(Ok(_)) => continue,
+                    _ => break,
+                }
+            }
+            self.line_reader.clear();
+
+            match with_timeout(RESYNC_WINDOW, self.ping()).await {
+                Ok(Ok(())) => {
+                    info!("Resynchronized with the module");
+                    return Ok(());
+                }
+                Ok(Err(err)) => last_err = err,
+                Err(_) => last_err = Csr8645Error::Timeout,
+            }
+            warn!("Resync attempt {} failed: {:?}", attempt, last_err);
+        }
+
+        error!("Failed to resynchronize with the module");
+        Err(last_err)
+    }
+
+    /// Returns the advanced commands the firmware answers, or `None` if `initialize` could not
+    /// identify the firmware.
+    pub fn capabilities(&self) -> Option<Capabilities> {
+        self.capabilities
+    }
+
+    /// Checks that the firmware answers an advanced command before it is sent.
+    ///
+    /// Commands are let through while the firmware has not been identified, leaving the module
+    /// to reject them.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The name of the command, for logging.
+    /// * `supported` - Tells whether the command is among the capabilities.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The command may be sent.
+    /// * `Csr8645Error::Unsupported` - The firmware lacks the command.
+    fn require(
+        &self,
+        command: &str,
+        supported: fn(&Capabilities) -> bool,
+    ) -> Result<(), Csr8645Error> {
+        match self.capabilities {
+            Some(capabilities) if !supported(&capabilities) => {
+                warn!("Firmware lacks {=str}", command);
+                Err(Csr8645Error::Unsupported)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Gets the firmware version of the CSR8645 module.
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The version reported by the module, e.g. `V3.1`.
+    /// * `Csr8645Error` - An error occurred while getting the version.
+    pub async fn get_version(&mut self) -> Result<String, Csr8645Error> {
+        let command = b"AT+VER?\r\n";
+        self.with_retry(command, &["OK+VER"], |r| {
+            parser::parse_value(r).map(|v| v.to_string())
+        })
+        .await
+    }
+
+    /// Gets the name of the CSR8645 module.
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The name of the module.
+    /// * `Csr8645Error` - An error occurred while getting the name.
+    pub async fn get_name(&mut self) -> Result<String, Csr8645Error> {
+        let command = b"AT+NAME?\r\n";
+        let name = self
+            .with_retry(command, &["OK+NAME"], |r| {
+                parser::parse_value(r).map(|v| v.to_string())
+            })
+            .await?;
+
+        info!("Received: {=str}", name.as_str());
+
+        Ok(name)
+    }
+
+    /// Sets the PIN of the CSR8645 module.
+    ///
+    /// # Arguments
+    ///
+    /// * `pin` - The new PIN for the module, made of `MIN_PIN_LEN` to `MAX_PIN_LEN` digits.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The module acknowledged the new PIN.
+    /// * `Csr8645Error` - The PIN is malformed, or an error occurred while setting it.
+    pub async fn set_pin(&mut self, pin: &str) -> Result<(), Csr8645Error> {
+        validate_pin(pin)?;
+
+        let command = format!("AT+PIN={}\r\n", pin);
+        self.send_command(command.as_bytes()).await?;
+        self.expect_ok().await
+    }
+
+    /// Gets the PIN of the CSR8645 module.
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The PIN of the module.
+    /// * `Csr8645Error` - An error occurred while getting the PIN.
+    pub async fn get_pin(&mut self) -> Result<String, Csr8645Error> {
+        let command = b"AT+PIN?\r\n";
+        self.with_retry(command, &["OK+PIN"], |r| {
+            parser::parse_value(r).map(|v| v.to_string())
+        })
+        .await
+    }
+
+    /// Sets the baud rate of the CSR8645 module and re-opens the UART at it.
+    ///
+    /// Once the module acknowledged `AT+BAUD=` with `OK`, the UART is re-opened at the new rate
+    /// after the module had time to switch. The module is not asked to confirm the new rate;
+    /// see `change_baudrate` to also verify the change and roll it back on failure.
+    ///
+    /// If the acknowledgement is garbled, or does not arrive within `BAUD_VERIFY_TIMEOUT`, the
+    /// module may have switched already, so the next `get_baudrate` re-opens the UART at the
+    /// requested rate should its reply be garbled too.
+    ///
+    /// # Arguments
+    ///
+    /// * `baudrate` - The new baud rate for the module.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The module acknowledged the new rate and the UART runs at it.
+    /// * `Csr8645Error` - An error occurred while setting the baud rate.
+    pub async fn set_baudrate(&mut self, baudrate: u32) -> Result<(), Csr8645Error> {
+        let command = format!("AT+BAUD={}\r\n", baudrate);
+        self.send_command(command.as_bytes()).await?;
+        if self.dry_run {
+            return self.expect_ok().await;
+        }
+
+        let acknowledged = match with_timeout(BAUD_VERIFY_TIMEOUT, self.expect_ok()).await {
+            Ok(result) => result,
+            Err(_) => Err(Csr8645Error::Timeout),
         };
-        self.send_command(command).await
+        if let Err(err) = acknowledged {
+            self.pending_baudrate = Some(baudrate);
+            return Err(err);
+        }
+
+        Timer::after(BAUD_SETTLE_TIME).await;
+        self.reopen_uart(baudrate)
+    }
+
+    /// Changes the baud rate of the module and of the UART together.
+    ///
+    /// The module must acknowledge `AT+BAUD=` with `OK`, after which the UART is re-opened at
+    /// the new rate by `set_baudrate` and the change is verified with `AT`. If the module does
+    /// not answer at the new rate, both are rolled back to the previous one.
+    ///
+    /// # Arguments
+    ///
+    /// * `new` - The new baud rate.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The module and the UART run at the new baud rate.
+    /// * `Csr8645Error` - The module refused the change, or did not answer at the new rate and
+    ///   the change was rolled back.
+    pub async fn change_baudrate(&mut self, new: u32) -> Result<(), Csr8645Error> {
+        if self.dry_run {
+            return self.set_baudrate(new).await;
+        }
+
+        let old = self.uart_baudrate;
+        self.set_baudrate(new).await?;
+
+        let verified = match with_timeout(BAUD_VERIFY_TIMEOUT, self.ping()).await {
+            Ok(result) => result,
+            Err(_) => Err(Csr8645Error::Timeout),
+        };
+
+        if let Err(err) = verified {
+            warn!("No answer at {} baud, rolling back to {}", new, old);
+            self.roll_back_baudrate(old).await;
+            return Err(err);
+        }
+
+        info!("Switched to {} baud", new);
+        Ok(())
+    }
+
+    /// Brings the module and the UART back to the previous baud rate after a failed change.
+    ///
+    /// Whether the module switched is unknown, so it is asked to go back at the new rate before
+    /// the UART is re-opened at the previous one. Failures are only logged, as the caller
+    /// reports the error of the change itself.
+    ///
+    /// # Arguments
+    ///
+    /// * `old` - The baud rate to go back to.
+    async fn roll_back_baudrate(&mut self, old: u32) {
+        // The module may not be listening at the new rate, so its answer is not waited for
+        let command = format!("AT+BAUD={}\r\n", old);
+        if let Err(e) = self.send_command(command.as_bytes()).await {
+            warn!("Failed to request the previous baud rate: {:?}", e);
+        }
+        Timer::after(BAUD_SETTLE_TIME).await;
+
+        if self.reopen_uart(old).is_err() {
+            return;
+        }
+        match with_timeout(BAUD_VERIFY_TIMEOUT, self.ping()).await {
+            Ok(Ok(())) => info!("Rolled back to {} baud", old),
+            _ => error!("Module not answering after rolling back to {} baud", old),
+        }
+    }
+
+    /// Re-opens the UART at another baud rate, discarding the bytes received at the old one.
+    ///
+    /// # Arguments
+    ///
+    /// * `baudrate` - The new baud rate of the UART.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The UART runs at the new baud rate.
+    /// * `Csr8645Error` - `UartConfigError` if the UART rejected the baud rate, or `UartBusy` if
+    ///   another user of a shared UART was in the middle of an exchange.
+    fn reopen_uart(&mut self, baudrate: u32) -> Result<(), Csr8645Error> {
+        self.channel.set_baudrate(baudrate).map_err(|e| {
+            error!("Failed to re-open the UART: {:?}", e);
+            Csr8645Error::from(e)
+        })?;
+        self.uart_baudrate = baudrate;
+        self.flush_rx();
+        Ok(())
+    }
+
+    /// Returns the baud rate the UART runs at, as last set by the driver.
+    pub fn uart_baudrate(&self) -> u32 {
+        self.uart_baudrate
+    }
+
+    /// Gets the baud rate of the CSR8645 module.
+    ///
+    /// After a `set_baudrate` whose acknowledgement was garbled, the module may already answer
+    /// at the new rate while the UART still runs at the old one, garbling the response. If the
+    /// first query after such a change fails to parse, the UART is re-opened at the requested
+    /// rate and the query is re-issued.
+    ///
+    /// # Returns
+    ///
+    /// * `u32` - The baud rate of the module.
+    /// * `Csr8645Error` - An error occurred while getting the baud rate.
+    pub async fn get_baudrate(&mut self) -> Result<u32, Csr8645Error> {
+        let command = b"AT+BAUD?\r\n";
+        let result = self
+            .with_retry(command, BAUDRATE_PREFIXES, parser::parse_baudrate)
+            .await;
+
+        match (result, self.pending_baudrate.take()) {
+            (Err(Csr8645Error::InvalidResponse), Some(baudrate)) => {
+                warn!("Re-opening the UART at {} baud", baudrate);
+                self.reopen_uart(baudrate)?;
+                self.with_retry(command, BAUDRATE_PREFIXES, parser::parse_baudrate)
+                    .await
+            }
+            (result, _) => result,
+        }
+    }
+
+    /// Connects to a device.
+    ///
+    /// The link is only considered up once the module confirms it with `OK+CONN` or `OK+CONNA`
+    /// within the connection timeout.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address of the device to connect to.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The device was connected successfully.
+    /// * `Csr8645Error::Timeout` - The module did not answer within the connection timeout.
+    /// * `Csr8645Error::InvalidResponse` - The module reported that the connection failed.
+    /// * `Csr8645Error` - Another error occurred while connecting to the device.
+    pub async fn connect(&mut self, address: &BtAddr) -> Result<(), Csr8645Error> {
+        if self.multipoint
+            && self.connected_peers.len() >= MAX_MULTIPOINT_PEERS
+            && !self.connected_peers.contains(address)
+        {
+            error!("Already connected to {} devices", MAX_MULTIPOINT_PEERS);
+            return Err(Csr8645Error::InvalidParameter);
+        }
+
+        let command = format!("AT+CON{}\r\n", address);
+        self.send_command(command.as_bytes()).await?;
+
+        let dry_run = self.dry_run;
+        let response = match with_timeout(self.connect_timeout, self.read_line()).await {
+            Ok(response) => response?,
+            Err(_) => {
+                error!("Timed out connecting to {}", address);
+                #[cfg(feature = "command-log")]
+                self.log_failure(Csr8645Error::Timeout);
+                return Err(Csr8645Error::Timeout);
+            }
+        };
+
+        match response.trim() {
+            "OK+CONN" | "OK+CONNA" => {}
+            _ if dry_run => {}
+            response => {
+                error!("Failed to connect to {}: {=str}", address, response);
+                #[cfg(feature = "command-log")]
+                self.log_failure(Csr8645Error::InvalidResponse);
+                return Err(Csr8645Error::InvalidResponse);
+            }
+        }
+
+        if !self.multipoint {
+            self.connected_peers.clear();
+        }
+        if !self.connected_peers.contains(address) {
+            self.connected_peers.push(*address);
+        }
+        self.connection_state = ConnectionState::Connected;
+        Ok(())
+    }
+
+    /// Disconnects from the current device.
+    ///
+    /// The link is only considered down once the module confirms it with `OK+DISC`.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The device was disconnected successfully.
+    /// * `Csr8645Error` - An error occurred while disconnecting from the device.
+    pub async fn disconnect(&mut self) -> Result<(), Csr8645Error> {
+        let command = b"AT+DISC\r\n";
+        self.send_command(command).await?;
+        self.expect_prefix("OK+DISC").await?;
+
+        self.connected_peers.clear();
+        self.connection_state = ConnectionState::Disconnected;
+        Ok(())
+    }
+
+    /// Returns the state of the link with the remote device.
+    pub fn connection_state(&self) -> ConnectionState {
+        self.connection_state
+    }
+
+    /// Enables or disables multipoint, letting two devices, e.g. the driver's and the
+    /// passenger's phones, stay connected at once.
+    ///
+    /// Multipoint requires a CSR8645 firmware built with the multipoint feature, which answers
+    /// `AT+MULTI`; other firmwares reject the command. The module arbitrates the audio itself:
+    /// the peer that started streaming most recently wins, so the audio path receives a single
+    /// stream either way. When multipoint is disabled, only the most recent peer stays tracked.
+    ///
+    /// # Arguments
+    ///
+    /// * `on` - True to enable multipoint, false to disable it.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The setting was applied successfully.
+    /// * `Csr8645Error::Unsupported` - The firmware lacks multipoint.
+    /// * `Csr8645Error` - An error occurred while applying the setting.
+    pub async fn enable_multipoint(&mut self, on: bool) -> Result<(), Csr8645Error> {
+        self.require("AT+MULTI", |c| c.multipoint)?;
+        let command: &[u8] = if on {
+            b"AT+MULTI=1\r\n"
+        } else {
+            b"AT+MULTI=0\r\n"
+        };
+        self.send_command(command).await?;
+        self.expect_ok().await?;
+
+        self.multipoint = on;
+        if !on && self.connected_peers.len() > 1 {
+            let excess = self.connected_peers.len() - 1;
+            self.connected_peers.drain(..excess);
+        }
+        Ok(())
+    }
+
+    /// Returns the addresses of the connected devices, oldest first.
+    pub fn connected_devices(&self) -> Vec<BtAddr> {
+        self.connected_peers.clone()
+    }
+
+    /// Returns how link changes are detected, as settled by `initialize`.
+    pub fn event_mode(&self) -> EventMode {
+        self.event_mode
+    }
+
+    /// Waits for a link change notification from the module.
+    ///
+    /// The module reports link changes with `OK+CONN` and `OK+LOST` while notifications are
+    /// enabled. The wait is bounded so the shared driver is not held while the link is quiet;
+    /// other lines received meanwhile are discarded. The connection state is updated from the
+    /// notification.
+    ///
+    /// In polling mode the link state is instead queried with `AT+CON?` at most once per
+    /// `STATE_POLL_INTERVAL`, and an event is synthesized when it changed.
+    ///
+    /// # Arguments
+    ///
+    /// * `window` - The longest time to wait for a notification.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<BtEvent>` - The notified event, or `None` if none arrived within the window.
+    /// * `Csr8645Error` - An error occurred while reading from the module.
+    pub async fn poll_event(&mut self, window: Duration) -> Result<Option<BtEvent>, Csr8645Error> {
+        if self.dry_run {
+            return Ok(None);
+        }
+        if self.event_mode == EventMode::Polling {
+            return self.poll_state().await;
+        }
+
+        let line = match with_timeout(window, self.read_raw_line()).await {
+            Ok(line) => line?,
+            Err(_) => return Ok(None),
+        };
+
+        let event = parser::parse_event(&line);
+        match event {
+            Some(BtEvent::Connected) => self.connection_state = ConnectionState::Connected,
+            Some(BtEvent::Disconnected) => {
+                self.connected_peers.clear();
+                self.connection_state = ConnectionState::Disconnected;
+            }
+            None => warn!("Discarded unsolicited line: {=[u8]:a}", line.as_slice()),
+        }
+
+        Ok(event)
+    }
+
+    /// Queries the link state if it is due, synthesizing the event a notification would have
+    /// reported.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<BtEvent>` - The link change, or `None` if the query is not due or the link did
+    ///   not change.
+    /// * `Csr8645Error` - An error occurred while querying the link state.
+    async fn poll_state(&mut self) -> Result<Option<BtEvent>, Csr8645Error> {
+        if self
+            .last_state_poll
+            .is_some_and(|last| last.elapsed() < STATE_POLL_INTERVAL)
+        {
+            return Ok(None);
+        }
+        self.last_state_poll = Some(Instant::now());
+
+        let previous = self.connection_state;
+        let status = self.check_connection_status().await?;
+        match (previous, status.connected) {
+            (ConnectionState::Disconnected, true) => Ok(Some(BtEvent::Connected)),
+            (ConnectionState::Connected, false) => {
+                self.connected_peers.clear();
+                Ok(Some(BtEvent::Disconnected))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Gets the address and name of the device currently connected.
+    ///
+    /// Lines other than the `OK+RNAME` reply are discarded, and a module that does not answer
+    /// fails the query with `Timeout` once the retries are exhausted.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<ScannedDevice>` - The connected device, or `None` if no device is connected.
+    /// * `Csr8645Error` - An error occurred while querying the connected device.
+    pub async fn connected_device(&mut self) -> Result<Option<ScannedDevice>, Csr8645Error> {
+        if self.connection_state == ConnectionState::Disconnected {
+            return Ok(None);
+        }
+
+        let command = b"AT+RNAME?\r\n";
+        self.with_retry(command, &["OK+RNAME"], |r| Ok(parser::parse_peer(r)))
+            .await
+    }
+
+    /// Checks if the CSR8645 module is connected to a device.
+    ///
+    /// A command echoed back by the module is skipped, and an echo that is not followed by an
+    /// actual reply within `ECHO_TIMEOUT` counts as disconnected. Unrelated lines are discarded
+    /// and the query is retried like the other getters, so a silent module fails with `Timeout`
+    /// instead of holding the driver. The connection state is updated from the reply.
+    ///
+    /// # Returns
+    ///
+    /// * `ConnectionStatus` - Whether a device is connected, and its address if reported.
+    /// * `Csr8645Error::Timeout` - The module did not answer.
+    /// * `Csr8645Error` - Another error occurred while checking the connection status.
+    pub async fn check_connection_status(&mut self) -> Result<ConnectionStatus, Csr8645Error> {
+        let command = b"AT+CON?\r\n";
+        let mut response = self
+            .with_retry(command, &[CON_REPLY_PREFIX, "AT+CON?"], |r| Ok(r.to_vec()))
+            .await?;
+        if parser::is_echo(&response, command) {
+            let reply = self.read_reply(&[CON_REPLY_PREFIX]);
+            response = match with_timeout(ECHO_TIMEOUT, reply).await {
+                Ok(response) => response?,
+                Err(_) => Vec::new(),
+            };
+        }
+
+        let status = parser::parse_connection_status(&response);
+        self.connection_state = if status.connected {
+            ConnectionState::Connected
+        } else {
+            ConnectionState::Disconnected
+        };
+        Ok(status)
+    }
+
+    /// Scans for nearby devices.
+    ///
+    /// The response is read line by line until the terminating `OK`, so it may span any number
+    /// of reads. Results past `MAX_SCAN_RESPONSE_LEN` bytes are discarded, though still read up
+    /// to the `OK` so it is not taken as the reply to the next command.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<ScannedDevice>` - A list of the nearby devices.
+    /// * `Csr8645Error::Timeout` - The module did not end the scan within `SCAN_TIMEOUT`.
+    /// * `Csr8645Error` - Another error occurred while scanning for devices.
+    pub async fn scan(&mut self) -> Result<Vec<ScannedDevice>, Csr8645Error> {
+        let command = b"AT+DISC?\r\n";
+        self.send_command(command).await?;
+
+        let collect = async {
+            let mut response = Vec::new();
+            let mut truncated = false;
+            loop {
+                let line = self.read_raw_line().await?;
+                if parser::is_ok(&line) {
+                    return Ok(response);
+                }
+
+                if truncated || response.len() + line.len() + 1 > MAX_SCAN_RESPONSE_LEN {
+                    if !truncated {
+                        warn!(
+                            "Scan results exceed {} bytes, dropping the rest",
+                            MAX_SCAN_RESPONSE_LEN
+                        );
+                        truncated = true;
+                    }
+                    continue;
+                }
+                response.extend_from_slice(&line);
+                response.push(b'\n');
+            }
+        };
+
+        let response = match with_timeout(SCAN_TIMEOUT, collect).await {
+            Ok(response) => response?,
+            Err(_) => {
+                error!("Scan not finished within {} s", SCAN_TIMEOUT.as_secs());
+                #[cfg(feature = "command-log")]
+                self.log_failure(Csr8645Error::Timeout);
+                return Err(Csr8645Error::Timeout);
+            }
+        };
+
+        Ok(parser::parse_scan(&response))
+    }
+
+    /// Scans for nearby devices whose name starts with the given prefix.
+    ///
+    /// The comparison is case-insensitive, and devices that did not advertise a name are
+    /// excluded.
+    ///
+    /// # Arguments
+    ///
+    /// * `name_prefix` - The prefix the device names must start with.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<ScannedDevice>` - A list of the matching nearby devices.
+    /// * `Csr8645Error` - An error occurred while scanning for devices.
+    pub async fn scan_filtered(
+        &mut self,
+        name_prefix: &str,
+    ) -> Result<Vec<ScannedDevice>, Csr8645Error> {
+        let prefix = name_prefix.to_lowercase();
+        let devices = self.scan().await?;
+
+        Ok(devices
+            .into_iter()
+            .filter(|device| {
+                device
+                    .name
+                    .as_ref()
+                    .is_some_and(|name| name.to_lowercase().starts_with(&prefix))
+            })
+            .collect())
+    }
+
+    /// Sends data to the connected device.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The data to send.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The data was sent successfully.
+    /// * `Csr8645Error` - An error occurred while sending the data.
+    pub async fn send_data(&mut self, data: &[u8]) -> Result<(), Csr8645Error> {
+        self.enter_data_mode().await?;
+        self.write_all(data).await
+    }
+
+    /// Receives data from the connected device.
+    ///
+    /// The read returns as soon as the line goes idle, so fewer bytes than `buf.len()` may be
+    /// received. A read filling the whole buffer may have been truncated: the caller must treat
+    /// `n == buf.len()` as possibly incomplete and read again for the remaining bytes.
+    ///
+    /// # Arguments
+    ///
+    /// * `buf` - The buffer where the received data will be stored.
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The number of bytes received, at the start of `buf`.
+    /// * `Csr8645Error` - An error occurred while receiving the data.
+    pub async fn receive_data(&mut self, buf: &mut [u8]) -> Result<usize, Csr8645Error> {
+        self.enter_data_mode().await?;
+        self.read_with_recovery(buf).await
+    }
+
+    /// Receives data from the connected device until the buffer is full.
+    ///
+    /// # Arguments
+    ///
+    /// * `buf` - The buffer to fill with the received data.
+    /// * `timeout` - How long to wait for the whole buffer to be filled.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The buffer was filled.
+    /// * `Csr8645Error` - The buffer was not filled within the timeout, or a read failed.
+    pub async fn receive_exact(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<(), Csr8645Error> {
+        self.enter_data_mode().await?;
+
+        let fill = async {
+            let mut filled = 0;
+            while filled < buf.len() {
+                filled += self.read_with_recovery(&mut buf[filled..]).await?;
+            }
+            Ok(())
+        };
+
+        with_timeout(timeout, fill)
+            .await
+            .map_err(|_| Csr8645Error::Timeout)?
+    }
+
+    /// Plays audio data.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The audio data to play.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The audio data was played successfully.
+    /// * `Csr8645Error` - An error occurred while playing the audio data.
+    pub async fn play_audio(&mut self, data: &[u8]) -> Result<(), Csr8645Error> {
+        // Send the audio data to the CSR8645 module
+        self.enter_data_mode().await?;
+        self.write_all(data).await
+    }
+
+    /// Customizes the beep played for a confirmation event.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The event to customize.
+    /// * `spec` - The beep to play for it.
+    pub fn set_confirmation_tone(&mut self, kind: ConfirmationTone, spec: ToneSpec) {
+        self.confirmation_tones.set_spec(kind, spec);
+    }
+
+    /// Sets the format of the audio stream sent to the module.
+    ///
+    /// The confirmation beeps and the sweeps are rendered in this format, so they play at their
+    /// nominal pitch and length alongside the streamed audio.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The format expected by the module, `AudioFormat::A2DP_STEREO` by default.
+    pub fn set_audio_format(&mut self, format: AudioFormat) {
+        self.audio_format = format;
+    }
+
+    /// Plays a short beep confirming an event, such as a successful pairing.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The event to confirm.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The beep was played successfully.
+    /// * `Csr8645Error` - An error occurred while playing the beep.
+    pub async fn play_confirmation(&mut self, kind: ConfirmationTone) -> Result<(), Csr8645Error> {
+        let pcm = self.confirmation_tones.spec(kind).render(self.audio_format);
+        self.play_audio(&pcm).await
+    }
+
+    /// Plays a logarithmic sine sweep, e.g. to find rattles or check the speaker response.
+    ///
+    /// The sweep is rendered and streamed in small chunks, so its duration is not limited by
+    /// the available memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `start_hz` - The frequency at the start of the sweep, in Hz.
+    /// * `end_hz` - The frequency at the end of the sweep, in Hz.
+    /// * `duration` - The length of the sweep.
+    /// * `gain` - The gain of the sweep, between 0.0 and 1.0.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The sweep was played successfully.
+    /// * `Csr8645Error` - `InvalidParameter` if a frequency is out of range, or an error
+    ///   occurred while playing the sweep.
+    pub async fn play_sweep(
+        &mut self,
+        start_hz: f32,
+        end_hz: f32,
+        duration: Duration,
+        gain: f32,
+    ) -> Result<(), Csr8645Error> {
+        let Some(mut sweep) = SineSweep::new(start_hz, end_hz, duration, gain, self.audio_format)
+        else {
+            error!("Rejected sweep from {} Hz to {} Hz", start_hz, end_hz);
+            return Err(Csr8645Error::InvalidParameter);
+        };
+
+        let mut chunk = [0u8; 512];
+        loop {
+            let len = sweep.fill(&mut chunk);
+            if len == 0 {
+                return Ok(());
+            }
+            self.play_audio(&chunk[..len]).await?;
+        }
+    }
+
+    /// Waits until all the audio data written so far has been sent to the module.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The audio data was flushed successfully.
+    /// * `Csr8645Error` - An error occurred while flushing the audio data.
+    pub async fn flush_audio(&mut self) -> Result<(), Csr8645Error> {
+        if self.dry_run {
+            return Ok(());
+        }
+
+        self.channel.flush_tx().await.map_err(Csr8645Error::from)
+    }
+
+    /// Puts the CSR8645 module into its low-power sleep mode.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The module is going to sleep.
+    /// * `Csr8645Error` - An error occurred while putting the module to sleep.
+    pub async fn sleep(&mut self) -> Result<(), Csr8645Error> {
+        let command = b"AT+SLEEP\r\n";
+        self.send_command(command).await
+    }
+
+    /// Disconnects from the current device and puts the module to sleep.
+    ///
+    /// This must be called before the driver is dropped, and the driver should not be used
+    /// afterwards. It takes `&mut self` so a driver shared behind a mutex can be closed too. The
+    /// module is put to sleep even if disconnecting fails.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The module was disconnected and put to sleep.
+    /// * `Csr8645Error` - The first error that occurred while shutting the module down.
+    pub async fn close(&mut self) -> Result<(), Csr8645Error> {
+        self.closed = true;
+
+        let disconnected = if self.connection_state == ConnectionState::Connected {
+            self.disconnect().await
+        } else {
+            Ok(())
+        };
+        if let Err(e) = disconnected {
+            error!("Failed to disconnect before closing: {:?}", e);
+        }
+        let slept = self.sleep().await;
+
+        disconnected.and(slept)
+    }
+
+    /// Routes the analog line input of the module to its output, or back to the A2DP stream.
+    ///
+    /// # Arguments
+    ///
+    /// * `enable` - True to play the line input, false to play the A2DP stream.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The module acknowledged the input selection.
+    /// * `Csr8645Error` - An error occurred while selecting the input.
+    pub async fn set_line_in(&mut self, enable: bool) -> Result<(), Csr8645Error> {
+        let command = if enable {
+            b"AT+LINEIN=1\r\n"
+        } else {
+            b"AT+LINEIN=0\r\n"
+        };
+        self.send_command(command).await?;
+        self.expect_ok().await
+    }
+
+    /// Receives audio data.
+    ///
+    /// As with `receive_data`, fewer bytes than `buf.len()` may be received, and a read filling
+    /// the whole buffer must be treated as possibly truncated.
+    ///
+    /// # Arguments
+    ///
+    /// * `buf` - The buffer where the received audio data will be stored.
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The number of bytes received, at the start of `buf`.
+    /// * `Csr8645Error` - An error occurred while receiving the audio data.
+    ///
+    /// A `Csr8645Error::UartRecoverableError` wrapping `Error::Overrun` means audio was lost
+    /// because it was not drained in time; see `rx_overruns`.
+    pub async fn receive_audio(&mut self, buf: &mut [u8]) -> Result<usize, Csr8645Error> {
+        self.enter_data_mode().await?;
+        self.read_with_recovery(buf).await
+    }
+
+    /// Returns the number of times received bytes were lost because they were not read in time.
+    pub fn rx_overruns(&self) -> u32 {
+        self.channel.overruns()
+    }
+
+    /// Gets the current status of the CSR8645 module.
+    ///
+    /// # Returns
+    ///
+    /// * `ModuleState` - The current state of the module.
+    /// * `Csr8645Error` - An error occurred while getting the status.
+    pub async fn get_status(&mut self) -> Result<ModuleState, Csr8645Error> {
+        let command = b"AT+STATE?\r\n";
+        self.with_retry(command, parser::STATE_REPLY_PREFIXES, |r| {
+            Ok(parser::parse_state(r))
+        })
+        .await
+    }
+
+    /// Sets the output volume of the CSR8645 module.
+    ///
+    /// # Arguments
+    ///
+    /// * `volume` - The new volume level.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The module acknowledged the new volume.
+    /// * `Csr8645Error` - An error occurred while setting the volume.
+    pub async fn set_volume(&mut self, volume: u8) -> Result<(), Csr8645Error> {
+        let command = format!("AT+VOL={}\r\n", volume);
+        self.send_command(command.as_bytes()).await?;
+        self.expect_ok().await?;
+
+        if volume > 0 {
+            self.volume = volume;
+        }
+        Ok(())
+    }
+
+    /// Mutes the CSR8645 module, remembering the last non-zero volume.
+    ///
+    /// Muting an already muted module does nothing, so the volume saved by the first call is
+    /// kept.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The module was muted successfully.
+    /// * `Csr8645Error` - An error occurred while muting the module.
+    pub async fn mute(&mut self) -> Result<(), Csr8645Error> {
+        if self.muted_volume.is_some() {
+            return Ok(());
+        }
+
+        let volume = self.volume;
+        self.set_volume(0).await?;
+
+        self.muted_volume = Some(volume);
+        Ok(())
+    }
+
+    /// Unmutes the CSR8645 module, restoring the volume it had before muting.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The module was unmuted successfully, or was not muted.
+    /// * `Csr8645Error` - An error occurred while unmuting the module.
+    pub async fn unmute(&mut self) -> Result<(), Csr8645Error> {
+        let Some(volume) = self.muted_volume else {
+            return Ok(());
+        };
+
+        self.set_volume(volume).await?;
+
+        self.muted_volume = None;
+        Ok(())
+    }
+
+    /// Returns whether the module is muted.
+    pub fn is_muted(&self) -> bool {
+        self.muted_volume.is_some()
+    }
+
+    /// Sets the bass level of the CSR8645 module equalizer.
+    ///
+    /// # Arguments
+    ///
+    /// * `bass` - The new bass level.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The module acknowledged the new bass level.
+    /// * `Csr8645Error::Unsupported` - The firmware lacks the equalizer.
+    /// * `Csr8645Error` - An error occurred while setting the bass level.
+    pub async fn set_bass(&mut self, bass: u8) -> Result<(), Csr8645Error> {
+        self.require("AT+BASS", |c| c.equalizer)?;
+        let command = format!("AT+BASS={}\r\n", bass);
+        self.send_command(command.as_bytes()).await?;
+        self.expect_ok().await
+    }
+
+    /// Enables or disables notifications.
+    ///
+    /// # Arguments
+    ///
+    /// * `enable` - True to enable notifications, false to disable them.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The module acknowledged the notification setting.
+    /// * `Csr8645Error` - An error occurred while changing the notification setting.
+    pub async fn set_notifications(&mut self, enable: bool) -> Result<(), Csr8645Error> {
+        let command = if enable {
+            b"AT+NOTI1\r\n"
+        } else {
+            b"AT+NOTI0\r\n"
+        };
+        self.send_command(command).await?;
+        self.expect_ok().await
+    }
+
+    /// Gets the signal strength of the current connection.
+    ///
+    /// # Returns
+    ///
+    /// * `i8` - The RSSI of the connection, in dBm.
+    /// * `Csr8645Error` - An error occurred while getting the RSSI.
+    pub async fn get_rssi(&mut self) -> Result<i8, Csr8645Error> {
+        // The module answers with `OK+RSSI:<dBm>`, keep only the value
+        let command = b"AT+RSSI?\r\n";
+        self.with_retry(command, &["OK+RSSI"], parser::parse_rssi)
+            .await
+    }
+
+    /// Sets the audio codec used for A2DP streaming.
+    ///
+    /// # Arguments
+    ///
+    /// * `codec` - The codec to use.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The module acknowledged the new codec.
+    /// * `Csr8645Error::Unsupported` - The firmware lacks codec selection.
+    /// * `Csr8645Error` - An error occurred while setting the codec.
+    pub async fn set_codec(&mut self, codec: AudioCodec) -> Result<(), Csr8645Error> {
+        self.require("AT+CODEC", |c| c.codec)?;
+        let command = format!("AT+CODEC={}\r\n", codec.index());
+        self.send_command(command.as_bytes()).await?;
+        self.expect_ok().await
+    }
+
+    /// Gets whether event notifications are enabled.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if notifications are enabled, false otherwise.
+    /// * `Csr8645Error` - An error occurred while getting the notification setting.
+    pub async fn get_notifications(&mut self) -> Result<bool, Csr8645Error> {
+        let command = b"AT+NOTI?\r\n";
+        self.with_retry(command, &["OK+NOTI"], parser::parse_flag)
+            .await
+    }
+
+    /// Brings up the CSR8645 module with the given settings.
+    ///
+    /// The firmware version is read first to learn which advanced commands the module answers;
+    /// afterwards those it lacks fail with `Csr8645Error::Unsupported` without being sent. Older
+    /// firmware that does not answer `AT+VER?` at all, or a version that cannot be parsed,
+    /// leaves the advanced commands unchecked.
+    ///
+    /// If the module does not accept `AT+NOTI`, or notifications are disabled, link changes are
+    /// detected by polling the link state instead; see `event_mode`.
+    ///
+    /// Each setting is queried first and only written if it differs, so running the sequence
+    /// again on an already configured module is a no-op. Every write must be acknowledged with
+    /// `OK`.
+    ///
+    /// # Arguments
+    ///
+    /// * `cfg` - The settings to apply.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The module is configured as requested.
+    /// * `Csr8645Error` - An error occurred while configuring the module.
+    pub async fn initialize(&mut self, cfg: &InitConfig) -> Result<(), Csr8645Error> {
+        validate_pin(&cfg.pin)?;
+
+        self.capabilities = match self.get_version().await {
+            Ok(version) => match parser::parse_firmware_version(&version) {
+                Ok(firmware) => {
+                    let capabilities = Capabilities::for_version(firmware);
+                    info!("CSR8645 firmware {:?}: {:?}", firmware, capabilities);
+                    Some(capabilities)
+                }
+                Err(_) => {
+                    warn!("Unrecognized firmware version {=str}", version.as_str());
+                    None
+                }
+            },
+            Err(err) => {
+                warn!("Firmware version query failed: {:?}", err);
+                None
+            }
+        };
+        if self.get_pin().await? != cfg.pin {
+            self.set_pin(&cfg.pin).await?;
+        }
+
+        if self.get_baudrate().await? != cfg.baudrate {
+            self.change_baudrate(cfg.baudrate).await?;
+        }
+
+        if self.get_name().await? != cfg.name {
+            self.set_name(&cfg.name).await?;
+        }
+
+        self.event_mode = self.configure_notifications(cfg.notifications).await?;
+        info!("CSR8645 link changes detected by {:?}", self.event_mode);
+
+        info!("CSR8645 initialized");
+        Ok(())
+    }
+
+    /// Applies the notification setting, checking whether the firmware supports it.
+    ///
+    /// Firmware without `AT+NOTI` answers the query with `ERROR`, or not at all, in which case
+    /// link changes are polled. Both the query and the setting are bounded by
+    /// `NOTIFICATION_TIMEOUT`, so a module that ignores them cannot stall `initialize`.
+    ///
+    /// # Arguments
+    ///
+    /// * `enable` - True to enable notifications, false to disable them.
+    ///
+    /// # Returns
+    ///
+    /// * `EventMode` - How link changes are detected from now on.
+    /// * `Csr8645Error` - An error other than an unanswered command occurred.
+    async fn configure_notifications(&mut self, enable: bool) -> Result<EventMode, Csr8645Error> {
+        let current = match with_timeout(NOTIFICATION_TIMEOUT, self.get_notifications()).await {
+            Ok(Ok(current)) => Some(current),
+            Ok(Err(Csr8645Error::Unsupported)) => {
+                warn!("Notifications unsupported, polling the link state");
+                return Ok(EventMode::Polling);
+            }
+            Ok(Err(Csr8645Error::InvalidResponse | Csr8645Error::Timeout)) => None,
+            Ok(Err(e)) => return Err(e),
+            Err(_) => {
+                // The rest of a late reply must not answer the next command
+                self.flush_rx();
+                None
+            }
+        };
+
+        if current != Some(enable) {
+            let set = with_timeout(NOTIFICATION_TIMEOUT, self.set_notifications(enable)).await;
+            match set.unwrap_or(Err(Csr8645Error::Timeout)) {
+                Ok(()) => {}
+                Err(Csr8645Error::InvalidResponse | Csr8645Error::Timeout) => {
+                    warn!("Notifications unsupported, polling the link state");
+                    self.flush_rx();
+                    return Ok(EventMode::Polling);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(if enable {
+            EventMode::Notifications
+        } else {
+            EventMode::Polling
+        })
+    }
+
+    /// Sets the inquiry and page scan windows.
+    ///
+    /// See `ScanParams` for the power/latency trade-off.
+    ///
+    /// # Arguments
+    ///
+    /// * `inquiry_window` - The inquiry scan window, in 0.625 ms slots.
+    /// * `page_window` - The page scan window, in 0.625 ms slots.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The windows were set successfully.
+    /// * `Csr8645Error` - A window is out of range, or an error occurred while setting them.
+    pub async fn set_scan_params(
+        &mut self,
+        inquiry_window: u16,
+        page_window: u16,
+    ) -> Result<(), Csr8645Error> {
+        let range = MIN_SCAN_WINDOW..=MAX_SCAN_WINDOW;
+        if !range.contains(&inquiry_window) || !range.contains(&page_window) {
+            error!(
+                "Scan windows {} and {} out of range",
+                inquiry_window, page_window
+            );
+            return Err(Csr8645Error::InvalidParameter);
+        }
+
+        let command = format!("AT+SCANP={},{}\r\n", inquiry_window, page_window);
+        self.send_command(command.as_bytes()).await?;
+        self.expect_ok().await
+    }
+
+    /// Gets the inquiry and page scan windows.
+    ///
+    /// # Returns
+    ///
+    /// * `ScanParams` - The current scan windows.
+    /// * `Csr8645Error` - An error occurred while querying the scan windows.
+    pub async fn get_scan_params(&mut self) -> Result<ScanParams, Csr8645Error> {
+        let command = b"AT+SCANP?\r\n";
+        self.with_retry(command, &["OK+SCANP"], parser::parse_scan_params)
+            .await
+    }
+
+    /// Drives one of the module PIO pins, e.g. an external amplifier shutdown line.
+    ///
+    /// # Arguments
+    ///
+    /// * `pin` - The PIO pin index, up to `MAX_PIO_PIN`.
+    /// * `high` - True to drive the pin high, false to drive it low.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The module acknowledged the new level.
+    /// * `Csr8645Error` - The pin index is out of range, or an error occurred while setting it.
+    pub async fn set_pio(&mut self, pin: u8, high: bool) -> Result<(), Csr8645Error> {
+        if pin > MAX_PIO_PIN {
+            return Err(Csr8645Error::InvalidParameter);
+        }
+
+        let command = format!("AT+PIO={},{}\r\n", pin, high as u8);
+        self.send_command(command.as_bytes()).await?;
+        self.expect_ok().await
+    }
+
+    /// Gets the level of one of the module PIO pins.
+    ///
+    /// # Arguments
+    ///
+    /// * `pin` - The PIO pin index, up to `MAX_PIO_PIN`.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the pin is high, false if it is low.
+    /// * `Csr8645Error` - The pin index is out of range, or an error occurred while reading it.
+    pub async fn get_pio(&mut self, pin: u8) -> Result<bool, Csr8645Error> {
+        if pin > MAX_PIO_PIN {
+            return Err(Csr8645Error::InvalidParameter);
+        }
+
+        // The module answers with `OK+PIO:<pin>,<level>`
+        let command = format!("AT+PIO={}?\r\n", pin);
+        self.with_retry(command.as_bytes(), &["OK+PIO"], parser::parse_pio_level)
+            .await
+    }
+
+    /// Sends an HFP call command if the module reports one of the expected states.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The HFP command to forward to the phone.
+    /// * `expected` - The states in which the command is allowed.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The module acknowledged the command.
+    /// * `Csr8645Error` - No matching call is in progress, or an error occurred.
+    async fn send_call_command(
+        &mut self,
+        command: &[u8],
+        expected: &[ModuleState],
+    ) -> Result<(), Csr8645Error> {
+        let state = self.get_status().await?;
+        if !expected.contains(&state) {
+            warn!(
+                "Ignoring call command in state {:?}",
+                defmt::Debug2Format(&state)
+            );
+            return Err(Csr8645Error::NoActiveCall);
+        }
+
+        self.send_command(command).await?;
+        self.expect_ok().await
+    }
+
+    /// Answers the incoming call.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The call was answered successfully.
+    /// * `Csr8645Error` - No call is ringing, or an error occurred while answering it.
+    pub async fn answer_call(&mut self) -> Result<(), Csr8645Error> {
+        self.send_call_command(b"ATA\r\n", &[ModuleState::IncomingCall])
+            .await
+    }
+
+    /// Rejects the incoming call.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The call was rejected successfully.
+    /// * `Csr8645Error` - No call is ringing, or an error occurred while rejecting it.
+    pub async fn reject_call(&mut self) -> Result<(), Csr8645Error> {
+        self.send_call_command(b"AT+CHUP\r\n", &[ModuleState::IncomingCall])
+            .await
+    }
+
+    /// Ends the call in progress.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The call was ended successfully.
+    /// * `Csr8645Error` - No call is in progress, or an error occurred while ending it.
+    pub async fn end_call(&mut self) -> Result<(), Csr8645Error> {
+        self.send_call_command(
+            b"AT+CHUP\r\n",
+            &[ModuleState::ActiveCall, ModuleState::OutgoingCall],
+        )
+        .await
+    }
+
+    /// Forwards a track control command to the connected phone over AVRCP.
+    ///
+    /// # Arguments
+    ///
+    /// * `cmd` - The passthrough command to forward.
+    ///
+    /// # Returns
+    ///
+    /// * `()` - The command was acknowledged by the module.
+    /// * `Csr8645Error::NotConnected` - No device is connected.
+    /// * `Csr8645Error` - An error occurred while sending the command.
+    pub async fn avrcp(&mut self, cmd: AvrcpCommand) -> Result<(), Csr8645Error> {
+        if self.connection_state != ConnectionState::Connected {
+            warn!("Ignoring {:?} while disconnected", cmd);
+            return Err(Csr8645Error::NotConnected);
+        }
+
+        self.send_command(cmd.command()).await?;
+        self.expect_ok().await
+    }
+}
+
+impl<C: ByteChannel, K: OutputPin<Error = Infallible>> Drop for Csr8645Driver<C, K> {
+    fn drop(&mut self) {
+        if !self.closed {
+            warn!("CSR8645 driver dropped without close, the module is left connected and awake");
+            self.channel.dropped_unclosed();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csr8645::loopback::LoopbackChannel;
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+    use embassy_futures::block_on;
+
+    /// The baud rate the loopback channel is opened at.
+    const BAUDRATE: u32 = 115_200;
+
+    /// Returns a driver talking through the given loopback channel.
+    fn driver(channel: LoopbackChannel) -> Csr8645Driver<LoopbackChannel> {
+        Csr8645Driver::new(channel, BAUDRATE).unwrap()
+    }
+
+    /// Returns a driver initialized against a module reporting the given firmware version, whose
+    /// other settings already match the defaults.
+    fn initialized_driver(version: &str) -> Csr8645Driver<LoopbackChannel> {
+        initialized_with_notifications(version, b"OK+NOTI:1\r\n")
+    }
+
+    /// Returns a driver initialized against a module answering the notification commands with
+    /// the given lines, whose other settings already match the defaults.
+    fn initialized_with_notifications(
+        version: &str,
+        notifications: &[u8],
+    ) -> Csr8645Driver<LoopbackChannel> {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(format!("OK+VER:{}\r\n", version).as_bytes());
+        channel.enqueue_response(b"OK+PIN:0000\r\nOK+BAUD:115200\r\n");
+        channel.enqueue_response(b"OK+NAME:DMZ Sound Booster\r\n");
+        channel.enqueue_response(notifications);
+        let mut csr8645 = driver(channel);
+
+        block_on(csr8645.initialize(&InitConfig::default())).unwrap();
+        csr8645
+    }
+
+    /// Returns whether the given command was written to the module.
+    fn was_sent(csr8645: &Csr8645Driver<LoopbackChannel>, command: &[u8]) -> bool {
+        csr8645
+            .channel
+            .written()
+            .windows(command.len())
+            .any(|window| window == command)
+    }
+
+    #[test]
+    fn send_data_round_trips_through_an_echo_peer() {
+        let mut channel = LoopbackChannel::new();
+        channel.set_echo(true);
+        // A few bytes per write, so the payload goes out in many partial writes
+        channel.set_max_write_len(Some(7));
+        let mut csr8645 = driver(channel);
+        let payload: Vec<u8> = (0..=255).collect();
+        let mut received = [0u8; 512];
+
+        let len = block_on(async {
+            csr8645.send_data(&payload).await.unwrap();
+            csr8645.receive_data(&mut received).await.unwrap()
+        });
+
+        assert_eq!(&received[..len], &payload[..]);
+        assert_eq!(csr8645.channel.written(), &payload[..]);
+    }
+
+    #[test]
+    fn receive_data_leaves_the_rest_of_a_long_echo_for_the_next_read() {
+        let mut channel = LoopbackChannel::new();
+        channel.set_echo(true);
+        let mut csr8645 = driver(channel);
+        let payload: Vec<u8> = (0..100).collect();
+        let mut received = [0u8; 64];
+
+        block_on(async {
+            csr8645.send_data(&payload).await.unwrap();
+            let len = csr8645.receive_data(&mut received).await.unwrap();
+            assert_eq!(len, received.len());
+            assert_eq!(&received[..], &payload[..64]);
+
+            let len = csr8645.receive_data(&mut received).await.unwrap();
+            assert_eq!(&received[..len], &payload[64..]);
+        });
+    }
+
+    #[test]
+    fn a_short_read_returns_the_bytes_received() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(&[0x11; 10]);
+        let mut csr8645 = driver(channel);
+        let mut data = [0u8; 64];
+        let mut audio = [0u8; 64];
+
+        let (data_len, audio_len) = block_on(async {
+            let data_len = csr8645.receive_data(&mut data).await.unwrap();
+            csr8645.channel.enqueue_response(&[0x22; 6]);
+            let audio_len = csr8645.receive_audio(&mut audio).await.unwrap();
+            (data_len, audio_len)
+        });
+
+        assert_eq!(&data[..data_len], &[0x11; 10]);
+        assert_eq!(&audio[..audio_len], &[0x22; 6]);
+    }
+
+    #[test]
+    fn receive_exact_fills_the_whole_buffer() {
+        let mut channel = LoopbackChannel::new();
+        let payload: Vec<u8> = (0..20).collect();
+        channel.enqueue_response(&payload);
+        let mut csr8645 = driver(channel);
+        let mut received = [0u8; 16];
+        let mut rest = [0u8; 16];
+
+        let rest_len = block_on(async {
+            csr8645
+                .receive_exact(&mut received, Duration::from_millis(100))
+                .await
+                .unwrap();
+            csr8645.receive_data(&mut rest).await.unwrap()
+        });
+
+        assert_eq!(&received[..], &payload[..16]);
+        assert_eq!(&rest[..rest_len], &payload[16..]);
+    }
+
+    #[test]
+    fn receive_exact_times_out_on_a_partial_buffer() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(&[0x33; 5]);
+        let mut csr8645 = driver(channel);
+        let mut received = [0u8; 16];
+        let timeout = Duration::from_millis(50);
+        let start = Instant::now();
+
+        let result = block_on(csr8645.receive_exact(&mut received, timeout));
+
+        assert!(matches!(result, Err(Csr8645Error::Timeout)));
+        assert!(Instant::now() - start >= timeout);
+        assert_eq!(&received[..5], &[0x33; 5]);
+    }
+
+    #[test]
+    fn capabilities_follow_the_firmware_version() {
+        let old = Capabilities::for_version(FirmwareVersion::new(1, 5));
+        assert!(!old.codec && !old.equalizer && !old.multipoint);
+
+        let equalizer_only = Capabilities::for_version(FirmwareVersion::new(2, 9));
+        assert!(!equalizer_only.codec && equalizer_only.equalizer);
+
+        let latest = Capabilities::for_version(FirmwareVersion::new(3, 1));
+        assert!(latest.codec && latest.equalizer && latest.multipoint);
+    }
+
+    #[test]
+    fn an_old_firmware_rejects_set_codec_without_sending_it() {
+        let mut csr8645 = initialized_driver("V2.0");
+
+        let result = block_on(csr8645.set_codec(AudioCodec::Aac));
+
+        assert!(matches!(result, Err(Csr8645Error::Unsupported)));
+        assert!(!was_sent(&csr8645, b"AT+CODEC"));
+    }
+
+    #[test]
+    fn a_new_firmware_allows_set_codec() {
+        let mut csr8645 = initialized_driver("V3.0");
+        assert!(csr8645.capabilities().is_some_and(|c| c.codec));
+        csr8645.channel.enqueue_response(b"OK\r\n");
+
+        block_on(csr8645.set_codec(AudioCodec::Aac)).unwrap();
+
+        assert!(was_sent(&csr8645, b"AT+CODEC=1\r\n"));
+    }
+
+    #[test]
+    fn an_unrecognized_firmware_leaves_set_codec_to_the_module() {
+        let mut csr8645 = initialized_driver("CSR-BETA");
+        assert_eq!(csr8645.capabilities(), None);
+        csr8645.channel.enqueue_response(b"OK\r\n");
+
+        block_on(csr8645.set_codec(AudioCodec::Sbc)).unwrap();
+    }
+
+    #[test]
+    fn supported_notifications_report_link_changes() {
+        let mut csr8645 = initialized_driver("V3.1");
+        assert_eq!(csr8645.event_mode(), EventMode::Notifications);
+        let window = Duration::from_millis(20);
+
+        block_on(async {
+            csr8645.channel.enqueue_response(b"OK+CONN\r\n");
+            assert_eq!(
+                csr8645.poll_event(window).await.unwrap(),
+                Some(BtEvent::Connected)
+            );
+            assert_eq!(csr8645.connection_state, ConnectionState::Connected);
+
+            csr8645.channel.enqueue_response(b"OK+LOST\r\n");
+            assert_eq!(
+                csr8645.poll_event(window).await.unwrap(),
+                Some(BtEvent::Disconnected)
+            );
+            assert_eq!(csr8645.poll_event(window).await.unwrap(), None);
+        });
+
+        assert!(!was_sent(&csr8645, b"AT+CON?"));
+    }
+
+    #[test]
+    fn notifications_enabled_during_initialize_are_used() {
+        let csr8645 = initialized_with_notifications("V3.1", b"OK+NOTI:0\r\nOK\r\n");
+
+        assert_eq!(csr8645.event_mode(), EventMode::Notifications);
+        assert!(was_sent(&csr8645, b"AT+NOTI1\r\n"));
+    }
+
+    #[test]
+    fn unsupported_notifications_fall_back_to_polling_link_changes() {
+        let mut csr8645 = initialized_with_notifications("V1.0", b"ERROR\r\n");
+        assert_eq!(csr8645.event_mode(), EventMode::Polling);
+        assert!(!was_sent(&csr8645, b"AT+NOTI1"));
+        let window = Duration::from_millis(20);
+
+        block_on(async {
+            csr8645.channel.enqueue_response(b"OK+CON:1\r\n");
+            assert_eq!(
+                csr8645.poll_event(window).await.unwrap(),
+                Some(BtEvent::Connected)
+            );
+            // The next query is not due yet
+            assert_eq!(csr8645.poll_event(window).await.unwrap(), None);
+
+            csr8645.last_state_poll = None;
+            csr8645.channel.enqueue_response(b"OK+CON:NONE\r\n");
+            assert_eq!(
+                csr8645.poll_event(window).await.unwrap(),
+                Some(BtEvent::Disconnected)
+            );
+        });
+    }
+
+    #[test]
+    fn a_silent_module_times_the_link_state_poll_out() {
+        let mut csr8645 = initialized_with_notifications("V1.0", b"ERROR\r\n");
+        assert_eq!(csr8645.event_mode(), EventMode::Polling);
+        csr8645.set_retry_policy(RetryPolicy {
+            attempts: 1,
+            delay: Duration::from_ticks(0),
+        });
+        let start = Instant::now();
+
+        let result = block_on(csr8645.poll_event(Duration::from_millis(20)));
+
+        assert!(matches!(result, Err(Csr8645Error::Timeout)));
+        assert!(Instant::now() - start >= REPLY_TIMEOUT);
+        assert!(was_sent(&csr8645, b"AT+CON?\r\n"));
+    }
+
+    #[test]
+    fn a_rejected_notification_setting_falls_back_to_polling() {
+        let csr8645 = initialized_with_notifications("V3.1", b"OK+NOTI:0\r\nERROR\r\n");
+
+        assert_eq!(csr8645.event_mode(), EventMode::Polling);
+    }
+
+    #[test]
+    fn change_baudrate_switches_once_the_module_answers_at_the_new_rate() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK\r\n");
+        channel.enqueue_response_at(921_600, b"OK\r\n");
+        let mut csr8645 = driver(channel);
+
+        block_on(csr8645.change_baudrate(921_600)).unwrap();
+
+        assert_eq!(csr8645.uart_baudrate(), 921_600);
+        assert_eq!(csr8645.channel.baudrate(), Some(921_600));
+        assert_eq!(csr8645.channel.written(), b"AT+BAUD=921600\r\nAT\r\n");
+    }
+
+    #[test]
+    fn change_baudrate_rolls_back_when_the_module_is_silent_at_the_new_rate() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK\r\n");
+        // The module only answers again once the UART is back at the previous rate
+        channel.enqueue_response_at(BAUDRATE, b"OK\r\n");
+        let mut csr8645 = driver(channel);
+
+        let result = block_on(csr8645.change_baudrate(921_600));
+
+        assert!(matches!(result, Err(Csr8645Error::Timeout)));
+        assert_eq!(csr8645.uart_baudrate(), BAUDRATE);
+        assert_eq!(csr8645.channel.baudrate(), Some(BAUDRATE));
+        assert!(was_sent(&csr8645, b"AT+BAUD=115200\r\n"));
+        assert!(csr8645.channel.written().ends_with(b"AT\r\n"));
+    }
+
+    #[test]
+    fn change_baudrate_rolls_back_on_a_garbled_answer_at_the_new_rate() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK\r\n");
+        channel.enqueue_response_at(921_600, b"\xF8\x80\r\n");
+        channel.enqueue_response_at(BAUDRATE, b"OK\r\n");
+        let mut csr8645 = driver(channel);
+
+        let result = block_on(csr8645.change_baudrate(921_600));
+
+        assert!(matches!(result, Err(Csr8645Error::InvalidResponse)));
+        assert_eq!(csr8645.uart_baudrate(), BAUDRATE);
+        assert_eq!(csr8645.channel.baudrate(), Some(BAUDRATE));
+    }
+
+    #[test]
+    fn change_baudrate_keeps_the_uart_when_the_module_refuses() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"ERROR\r\n");
+        let mut csr8645 = driver(channel);
+
+        assert!(block_on(csr8645.change_baudrate(921_600)).is_err());
+
+        assert_eq!(csr8645.uart_baudrate(), BAUDRATE);
+        assert_eq!(csr8645.channel.baudrate(), None);
+        assert_eq!(csr8645.channel.written(), b"AT+BAUD=921600\r\n");
+    }
+
+    #[test]
+    fn each_command_is_flushed_before_its_reply_is_read() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK\r\nOK+NAME:DMZ\r\n");
+        // A few bytes per write, so the flush must wait for the last one
+        channel.set_max_write_len(Some(3));
+        let mut csr8645 = driver(channel);
+
+        block_on(async {
+            csr8645.ping().await.unwrap();
+            assert_eq!(csr8645.channel.flushes(), [4]);
+            assert_eq!(csr8645.get_name().await.unwrap(), "DMZ");
+        });
+
+        assert_eq!(csr8645.channel.written(), b"AT\r\nAT+NAME?\r\n");
+        assert_eq!(csr8645.channel.flushes(), [4, 14]);
+    }
+
+    #[test]
+    fn a_behavior_applied_in_dry_run_is_recorded_in_order() {
+        let mut csr8645 = driver(LoopbackChannel::new());
+        csr8645.set_dry_run(true);
+
+        block_on(async {
+            // The volume then the bass, as `BluetoothController::alter_behavior` applies them
+            csr8645.set_volume(9).await.unwrap();
+            csr8645.set_bass(4).await.unwrap();
+        });
+
+        assert_eq!(
+            csr8645.recorded_commands(),
+            [b"AT+VOL=9\r\n".to_vec(), b"AT+BASS=4\r\n".to_vec()]
+        );
+        assert!(csr8645.channel.written().is_empty());
+    }
+
+    #[test]
+    fn a_read_recovers_from_an_overrun() {
+        let mut channel = LoopbackChannel::new();
+        channel.inject_error(Error::Overrun);
+        channel.enqueue_response(b"OK+NAME:DMZ\r\n");
+        let mut csr8645 = driver(channel);
+
+        assert_eq!(block_on(csr8645.get_name()).unwrap(), "DMZ");
+    }
+
+    #[test]
+    fn a_persistent_overrun_fails_the_read() {
+        let mut channel = LoopbackChannel::new();
+        for _ in 0..=UART_RETRY_LIMIT {
+            channel.inject_error(Error::Overrun);
+        }
+        channel.enqueue_response(b"OK+NAME:DMZ\r\n");
+        let mut csr8645 = driver(channel);
+
+        assert!(matches!(
+            block_on(csr8645.get_name()),
+            Err(Csr8645Error::UartRecoverableError(Error::Overrun))
+        ));
+    }
+
+    /// Returns a deterministic stream of audio bytes, distinct over several rings.
+    fn audio_stream(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn a_steady_stream_is_received_without_gaps() {
+        let stream = audio_stream(1024);
+        let mut channel = LoopbackChannel::new();
+        channel.set_ring_len(Some(256));
+        let mut csr8645 = driver(channel);
+
+        let mut received = Vec::new();
+        for chunk in stream.chunks(64) {
+            csr8645.channel.enqueue_response(chunk);
+            let mut audio = [0; 64];
+            let len = block_on(csr8645.receive_audio(&mut audio)).unwrap();
+            received.extend_from_slice(&audio[..len]);
+        }
+
+        assert_eq!(received, stream);
+        assert_eq!(csr8645.rx_overruns(), 0);
+    }
+
+    #[test]
+    fn draining_slowly_reports_an_overrun() {
+        let stream = audio_stream(512);
+        let mut channel = LoopbackChannel::new();
+        channel.set_ring_len(Some(256));
+        // The CPU falls behind while twice the ring arrives
+        for chunk in stream.chunks(64) {
+            channel.enqueue_response(chunk);
+        }
+        let mut csr8645 = driver(channel);
+
+        let mut audio = [0; 512];
+        let len = block_on(csr8645.receive_audio(&mut audio)).unwrap();
+
+        // The oldest bytes were overwritten, and the read recovers with the newest ones
+        assert_eq!(&audio[..len], &stream[256..]);
+        assert_eq!(csr8645.rx_overruns(), 1);
+
+        // Draining in time again does not report any further overrun
+        csr8645.channel.enqueue_response(&stream[..64]);
+        let len = block_on(csr8645.receive_audio(&mut audio)).unwrap();
+
+        assert_eq!(&audio[..len], &stream[..64]);
+        assert_eq!(csr8645.rx_overruns(), 1);
+    }
+
+    #[test]
+    fn a_parity_error_is_not_retried() {
+        let mut channel = LoopbackChannel::new();
+        channel.inject_error(Error::Parity);
+        channel.enqueue_response(b"OK+NAME:DMZ\r\n");
+        let mut csr8645 = driver(channel);
+
+        assert!(matches!(
+            block_on(csr8645.get_name()),
+            Err(Csr8645Error::UartError(Error::Parity))
+        ));
+        assert_eq!(csr8645.channel.written(), b"AT+NAME?\r\n");
+    }
+
+    #[test]
+    fn initialize_leaves_the_settings_that_already_match() {
+        let csr8645 = initialized_driver("V3.1");
+
+        assert_eq!(
+            csr8645.channel.written(),
+            b"AT+VER?\r\nAT+PIN?\r\nAT+BAUD?\r\nAT+NAME?\r\nAT+NOTI?\r\n"
+        );
+    }
+
+    #[test]
+    fn initialize_writes_the_settings_that_differ() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK+VER:V3.1\r\nOK+PIN:1234\r\nOK\r\n");
+        channel.enqueue_response(b"OK+BAUD:115200\r\nOK+NAME:HC-05\r\nOK\r\n");
+        channel.enqueue_response(b"OK+NOTI:1\r\n");
+        let mut csr8645 = driver(channel);
+
+        block_on(csr8645.initialize(&InitConfig::default())).unwrap();
+
+        assert!(was_sent(&csr8645, b"AT+PIN=0000\r\n"));
+        assert!(was_sent(&csr8645, b"AT+NAME=DMZ Sound Booster\r\n"));
+        // The baud rate and the notifications already match
+        assert!(!was_sent(&csr8645, b"AT+BAUD="));
+        assert!(!was_sent(&csr8645, b"AT+NOTI1"));
+    }
+
+    #[test]
+    fn initialize_fails_when_a_write_is_not_acknowledged() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK+VER:V3.1\r\nOK+PIN:1234\r\nERROR\r\n");
+        let mut csr8645 = driver(channel);
+
+        let result = block_on(csr8645.initialize(&InitConfig::default()));
+
+        assert!(matches!(result, Err(Csr8645Error::InvalidResponse)));
+        assert!(!was_sent(&csr8645, b"AT+BAUD?"));
+    }
+
+    #[test]
+    fn initialize_rejects_a_malformed_pin_before_talking_to_the_module() {
+        let mut csr8645 = driver(LoopbackChannel::new());
+        let config = InitConfig {
+            pin: "12a4".to_string(),
+            ..InitConfig::default()
+        };
+
+        let result = block_on(csr8645.initialize(&config));
+
+        assert!(matches!(result, Err(Csr8645Error::InvalidParameter)));
+        assert!(csr8645.channel.written().is_empty());
+    }
+
+    #[test]
+    fn get_baudrate_is_driven_through_the_loopback() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK+Get:115200\r\n");
+        let mut csr8645 = driver(channel);
+
+        assert_eq!(block_on(csr8645.get_baudrate()).unwrap(), 115_200);
+        assert_eq!(csr8645.channel.written(), b"AT+BAUD?\r\n");
+    }
+
+    #[test]
+    fn get_baudrate_skips_a_stale_reply_to_an_earlier_query() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK+NAME:DMZ Sound Booster\r\nOK+BAUD:38400\r\n");
+        let mut csr8645 = driver(channel);
+
+        assert_eq!(block_on(csr8645.get_baudrate()).unwrap(), 38_400);
+        // The stale line was discarded without re-issuing the query
+        assert_eq!(csr8645.channel.written(), b"AT+BAUD?\r\n");
+    }
+
+    #[test]
+    fn get_baudrate_gives_up_after_too_many_unrelated_lines() {
+        let mut channel = LoopbackChannel::new();
+        for _ in 0..=MAX_UNRELATED_LINES {
+            channel.enqueue_response(b"OK+NAME:DMZ Sound Booster\r\n");
+        }
+        channel.enqueue_response(b"OK+BAUD:38400\r\n");
+        let mut csr8645 = driver(channel);
+        csr8645.set_retry_policy(RetryPolicy {
+            attempts: 1,
+            delay: Duration::from_ticks(0),
+        });
+
+        let result = block_on(csr8645.get_baudrate());
+
+        assert!(matches!(result, Err(Csr8645Error::InvalidResponse)));
+    }
+
+    #[test]
+    fn an_error_reply_to_a_query_is_not_misattributed() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK+NAME:DMZ Sound Booster\r\nERROR\r\n");
+        let mut csr8645 = driver(channel);
+
+        let result = block_on(csr8645.get_baudrate());
+
+        assert!(matches!(result, Err(Csr8645Error::Unsupported)));
+    }
+
+    #[test]
+    fn scan_filtered_keeps_the_named_devices_matching_the_prefix() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK+DISC:A1B2C3D4E5F6,Pixel 7\r\n");
+        channel.enqueue_response(b"OK+DISC:112233445566\r\n");
+        channel.enqueue_response(b"OK+DISC:0A0B0C0D0E0F,Galaxy Buds\r\n");
+        channel.enqueue_response(b"OK+DISC:AABBCCDDEEFF,pixel Watch\r\n");
+        channel.enqueue_response(b"OK+DISC:665544332211,\r\nOK\r\n");
+        let mut csr8645 = driver(channel);
+
+        let devices = block_on(csr8645.scan_filtered("PIXEL")).unwrap();
+
+        assert_eq!(
+            devices,
+            [
+                ScannedDevice {
+                    address: BtAddr([0xA1, 0xB2, 0xC3, 0xD4, 0xE5, 0xF6]),
+                    name: Some("Pixel 7".to_string()),
+                },
+                ScannedDevice {
+                    address: BtAddr([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]),
+                    name: Some("pixel Watch".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn scan_filtered_excludes_unnamed_devices_even_with_an_empty_prefix() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK+DISC:A1B2C3D4E5F6,Pixel 7\r\n");
+        channel.enqueue_response(b"OK+DISC:112233445566\r\nOK\r\n");
+        let mut csr8645 = driver(channel);
+
+        let devices = block_on(csr8645.scan_filtered("")).unwrap();
+
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].name.as_deref(), Some("Pixel 7"));
+    }
+
+    #[test]
+    fn unmute_restores_the_volume_exactly() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK\r\nOK\r\nOK\r\n");
+        let mut csr8645 = driver(channel);
+
+        block_on(async {
+            csr8645.set_volume(9).await.unwrap();
+            csr8645.mute().await.unwrap();
+            assert!(csr8645.is_muted());
+            // Muting twice keeps the volume saved by the first call
+            csr8645.mute().await.unwrap();
+            csr8645.unmute().await.unwrap();
+        });
+
+        assert!(!csr8645.is_muted());
+        assert_eq!(
+            csr8645.channel.written(),
+            b"AT+VOL=9\r\nAT+VOL=0\r\nAT+VOL=9\r\n"
+        );
+    }
+
+    #[test]
+    fn set_pio_writes_the_pin_and_the_level() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK\r\nOK\r\n");
+        let mut csr8645 = driver(channel);
+
+        block_on(async {
+            csr8645.set_pio(0, true).await.unwrap();
+            csr8645.set_pio(MAX_PIO_PIN, false).await.unwrap();
+        });
+
+        assert_eq!(csr8645.channel.written(), b"AT+PIO=0,1\r\nAT+PIO=15,0\r\n");
+    }
+
+    #[test]
+    fn get_pio_reads_the_level_of_the_pin() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK+PIO:3,1\r\nOK+PIO:4,0\r\n");
+        let mut csr8645 = driver(channel);
+
+        let levels = block_on(async {
+            (
+                csr8645.get_pio(3).await.unwrap(),
+                csr8645.get_pio(4).await.unwrap(),
+            )
+        });
+
+        assert_eq!(levels, (true, false));
+        assert_eq!(csr8645.channel.written(), b"AT+PIO=3?\r\nAT+PIO=4?\r\n");
+    }
+
+    #[test]
+    fn pio_pins_past_the_last_one_are_rejected_without_writing() {
+        let mut csr8645 = driver(LoopbackChannel::new());
+
+        let (set, get) = block_on(async {
+            (
+                csr8645.set_pio(MAX_PIO_PIN + 1, true).await,
+                csr8645.get_pio(MAX_PIO_PIN + 1).await,
+            )
+        });
+
+        assert!(matches!(set, Err(Csr8645Error::InvalidParameter)));
+        assert!(matches!(get, Err(Csr8645Error::InvalidParameter)));
+        assert!(csr8645.channel.written().is_empty());
+    }
+
+    #[test]
+    fn call_commands_are_sent_in_the_matching_call_state() {
+        let mut channel = LoopbackChannel::new();
+        for state in [
+            &b"STATE:INCOMING_CALL\r\n"[..],
+            b"STATE:INCOMING_CALL\r\n",
+            b"STATE:ACTIVE_CALL\r\n",
+            b"STATE:OUTGOING_CALL\r\n",
+        ] {
+            channel.enqueue_response(state);
+            channel.enqueue_response(b"OK\r\n");
+        }
+        channel.enqueue_response(b"OK\r\nERROR\r\n");
+        let mut csr8645 = driver(channel);
+
+        let (volume, bass) = block_on(async {
+            csr8645.answer_call().await.unwrap();
+            csr8645.reject_call().await.unwrap();
+            csr8645.end_call().await.unwrap();
+            csr8645.end_call().await.unwrap();
+            (csr8645.set_volume(9).await, csr8645.set_bass(4).await)
+        });
+
+        assert_eq!(
+            csr8645.channel.written(),
+            b"AT+STATE?\r\nATA\r\n\
+              AT+STATE?\r\nAT+CHUP\r\n\
+              AT+STATE?\r\nAT+CHUP\r\n\
+              AT+STATE?\r\nAT+CHUP\r\n\
+              AT+VOL=9\r\nAT+BASS=4\r\n"
+        );
+        // Each call command consumed its own OK, so the setters still get their own replies
+        assert!(volume.is_ok());
+        assert!(matches!(bass, Err(Csr8645Error::InvalidResponse)));
+    }
+
+    #[test]
+    fn call_commands_without_a_matching_call_are_not_sent() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"STATE:CONNECTED\r\n");
+        channel.enqueue_response(b"STATE:ACTIVE_CALL\r\n");
+        channel.enqueue_response(b"STATE:INCOMING_CALL\r\n");
+        let mut csr8645 = driver(channel);
+
+        let results = block_on(async {
+            [
+                csr8645.answer_call().await,
+                csr8645.reject_call().await,
+                csr8645.end_call().await,
+            ]
+        });
+
+        for result in results {
+            assert!(matches!(result, Err(Csr8645Error::NoActiveCall)));
+        }
+        assert_eq!(
+            csr8645.channel.written(),
+            b"AT+STATE?\r\nAT+STATE?\r\nAT+STATE?\r\n"
+        );
+    }
+
+    /// Returns the scan results of `count` devices, without the terminating `OK`.
+    fn scan_results(count: usize) -> Vec<u8> {
+        (0..count)
+            .flat_map(|i| format!("OK+DISC:{:012X},Café {}\r\n", i, i).into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn scan_reads_results_spanning_many_reads() {
+        let mut channel = LoopbackChannel::new();
+        let results = scan_results(40);
+        // Far more than one read, with multi-byte names straddling the reads
+        assert!(results.len() > 512);
+        channel.enqueue_response(&results);
+        channel.enqueue_response(b"OK\r\n");
+        let mut csr8645 = driver(channel);
+
+        let devices = block_on(csr8645.scan()).unwrap();
+
+        assert_eq!(devices.len(), 40);
+        for (i, device) in devices.iter().enumerate() {
+            assert_eq!(device.address.0[5], i as u8);
+            assert_eq!(device.name, Some(format!("Café {}", i)));
+        }
+    }
+
+    #[test]
+    fn scan_drops_the_results_past_the_size_bound() {
+        let mut channel = LoopbackChannel::new();
+        let results = scan_results(400);
+        assert!(results.len() > MAX_SCAN_RESPONSE_LEN);
+        channel.enqueue_response(&results);
+        channel.enqueue_response(b"OK\r\nOK+Get:115200\r\n");
+        let mut csr8645 = driver(channel);
+
+        let (devices, baudrate) =
+            block_on(async { (csr8645.scan().await, csr8645.get_baudrate().await) });
+
+        let devices = devices.unwrap();
+        assert!(!devices.is_empty() && devices.len() < 400);
+        for (i, device) in devices.iter().enumerate() {
+            assert_eq!(device.name, Some(format!("Café {}", i)));
+        }
+        // The dropped results are still consumed up to the OK
+        assert_eq!(baudrate.unwrap(), 115_200);
+    }
+
+    #[test]
+    fn connected_device_reports_the_peer_name_and_address() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK+RNAME:A1B2C3D4E5F6,Pixel 7\r\nOK+RNAME:NONE\r\n");
+        let mut csr8645 = driver(channel);
+        csr8645.connection_state = ConnectionState::Connected;
+
+        let (peer, none) = block_on(async {
+            (
+                csr8645.connected_device().await.unwrap(),
+                csr8645.connected_device().await.unwrap(),
+            )
+        });
+
+        assert_eq!(
+            peer,
+            Some(ScannedDevice {
+                address: BtAddr([0xA1, 0xB2, 0xC3, 0xD4, 0xE5, 0xF6]),
+                name: Some("Pixel 7".to_string()),
+            })
+        );
+        // The link may drop before the state is updated, and the module then reports no peer
+        assert_eq!(none, None);
+        assert_eq!(csr8645.channel.written(), b"AT+RNAME?\r\nAT+RNAME?\r\n");
+    }
+
+    #[test]
+    fn connected_device_skips_an_unrelated_line() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK+CONN\r\nOK+RNAME:A1B2C3D4E5F6,Pixel 7\r\n");
+        let mut csr8645 = driver(channel);
+        csr8645.connection_state = ConnectionState::Connected;
+
+        let peer = block_on(csr8645.connected_device()).unwrap();
+
+        assert_eq!(peer.map(|peer| peer.address), Some(PEER));
+        assert_eq!(csr8645.channel.written(), b"AT+RNAME?\r\n");
+    }
+
+    #[test]
+    fn connected_device_is_none_without_querying_while_disconnected() {
+        let mut csr8645 = driver(LoopbackChannel::new());
+
+        assert_eq!(block_on(csr8645.connected_device()).unwrap(), None);
+        assert!(csr8645.channel.written().is_empty());
+    }
+
+    #[test]
+    fn disconnect_sends_the_teardown_command_and_waits_for_its_confirmation() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK+DISC\r\n");
+        let mut csr8645 = driver(channel);
+        csr8645.connection_state = ConnectionState::Connected;
+
+        block_on(csr8645.disconnect()).unwrap();
+
+        assert_eq!(csr8645.channel.written(), b"AT+DISC\r\n");
+        assert_eq!(csr8645.connection_state(), ConnectionState::Disconnected);
+    }
+
+    #[test]
+    fn disconnect_without_a_confirmation_keeps_the_link() {
+        for reply in [&b"OK\r\n"[..], b"ERROR\r\n"] {
+            let mut channel = LoopbackChannel::new();
+            channel.enqueue_response(reply);
+            let mut csr8645 = driver(channel);
+            csr8645.connection_state = ConnectionState::Connected;
+
+            let result = block_on(csr8645.disconnect());
+
+            assert!(matches!(result, Err(Csr8645Error::InvalidResponse)));
+            assert_eq!(csr8645.connection_state(), ConnectionState::Connected);
+        }
+    }
+
+    #[test]
+    fn a_garbled_reply_is_retried_and_the_retried_value_returned() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_reply(b"AT+PIO=3?\r\n", b"OK+PIO:3,\xA7\r\n");
+        // The resync between the attempts pings the module
+        channel.enqueue_reply(b"AT\r\n", b"OK\r\n");
+        channel.enqueue_reply(b"AT+PIO=3?\r\n", b"OK+PIO:3,1\r\n");
+        let mut csr8645 = driver(channel);
+
+        assert!(block_on(csr8645.get_pio(3)).unwrap());
+        assert_eq!(
+            csr8645.channel.written(),
+            b"AT+PIO=3?\r\n\r\nAT\r\nAT+PIO=3?\r\n"
+        );
+    }
+
+    #[test]
+    fn a_silent_module_is_asked_again() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_reply(b"AT\r\n", b"OK\r\n");
+        channel.enqueue_reply(b"AT+PIO=3?\r\n", b"OK+PIO:3,0\r\n");
+        let mut csr8645 = driver(channel);
+
+        assert!(!block_on(csr8645.get_pio(3)).unwrap());
+    }
+
+    #[test]
+    fn the_retry_policy_bounds_the_attempts() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_reply(b"AT+PIO=3?\r\n", b"OK+PIO:3,x\r\n");
+        channel.enqueue_reply(b"AT\r\n", b"OK\r\n");
+        channel.enqueue_reply(b"AT+PIO=3?\r\n", b"OK+PIO:3,y\r\n");
+        channel.enqueue_reply(b"AT+PIO=3?\r\n", b"OK+PIO:3,1\r\n");
+        let mut csr8645 = driver(channel);
+
+        // The default policy gives up after the second garbled reply
+        let result = block_on(csr8645.get_pio(3));
+        assert!(matches!(result, Err(Csr8645Error::InvalidResponse)));
+
+        csr8645.set_retry_policy(RetryPolicy {
+            attempts: 1,
+            delay: Duration::from_millis(0),
+        });
+        assert!(block_on(csr8645.get_pio(3)).unwrap());
+    }
+
+    #[test]
+    fn a_longer_retry_policy_outlasts_more_garbage() {
+        let mut channel = LoopbackChannel::new();
+        for _ in 0..3 {
+            channel.enqueue_reply(b"AT+PIO=3?\r\n", b"OK+PIO:3,x\r\n");
+            channel.enqueue_reply(b"AT\r\n", b"OK\r\n");
+        }
+        channel.enqueue_reply(b"AT+PIO=3?\r\n", b"OK+PIO:3,1\r\n");
+        let mut csr8645 = driver(channel);
+        let delay = Duration::from_millis(20);
+        csr8645.set_retry_policy(RetryPolicy { attempts: 4, delay });
+        let start = Instant::now();
+
+        assert!(block_on(csr8645.get_pio(3)).unwrap());
+        // One delay before each of the three retries
+        assert!(Instant::now() - start >= delay * 3);
+    }
+
+    #[test]
+    fn set_pin_sends_a_valid_pin() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK\r\nOK\r\n");
+        let mut csr8645 = driver(channel);
+
+        block_on(async {
+            csr8645.set_pin("1234").await.unwrap();
+            csr8645.set_pin("098765").await.unwrap();
+        });
+
+        assert_eq!(
+            csr8645.channel.written(),
+            b"AT+PIN=1234\r\nAT+PIN=098765\r\n"
+        );
+    }
+
+    #[test]
+    fn set_pin_rejects_malformed_pins_without_writing() {
+        let mut csr8645 = driver(LoopbackChannel::new());
+
+        for pin in ["1234567", "123", "", "12a4", "12 34", "+1234", "１２３４"] {
+            let result = block_on(csr8645.set_pin(pin));
+            assert!(
+                matches!(result, Err(Csr8645Error::InvalidParameter)),
+                "{:?}",
+                pin
+            );
+        }
+        assert!(csr8645.channel.written().is_empty());
+    }
+
+    /// The address the connection tests connect to.
+    const PEER: BtAddr = BtAddr([0xA1, 0xB2, 0xC3, 0xD4, 0xE5, 0xF6]);
+
+    #[test]
+    fn connect_waits_for_the_link_confirmation() {
+        for confirmation in [&b"OK+CONN\r\n"[..], b"OK+CONNA\r\n"] {
+            let mut channel = LoopbackChannel::new();
+            channel.enqueue_response(confirmation);
+            let mut csr8645 = driver(channel);
+
+            block_on(csr8645.connect(&PEER)).unwrap();
+
+            assert_eq!(csr8645.channel.written(), b"AT+CONA1B2C3D4E5F6\r\n");
+            assert_eq!(csr8645.connection_state(), ConnectionState::Connected);
+            assert_eq!(csr8645.connected_devices(), [PEER]);
+        }
+    }
+
+    #[test]
+    fn a_failed_connection_keeps_the_link_down() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK+CONNF\r\n");
+        let mut csr8645 = driver(channel);
+
+        let result = block_on(csr8645.connect(&PEER));
+
+        assert!(matches!(result, Err(Csr8645Error::InvalidResponse)));
+        assert_eq!(csr8645.connection_state(), ConnectionState::Disconnected);
+        assert!(csr8645.connected_devices().is_empty());
+    }
+
+    #[test]
+    fn a_silent_module_times_the_connection_out() {
+        let mut csr8645 = driver(LoopbackChannel::new());
+        let timeout = Duration::from_millis(50);
+        csr8645.set_connect_timeout(timeout);
+        let start = Instant::now();
+
+        let result = block_on(csr8645.connect(&PEER));
+
+        assert!(matches!(result, Err(Csr8645Error::Timeout)));
+        assert!(Instant::now() - start >= timeout);
+        assert_eq!(csr8645.connection_state(), ConnectionState::Disconnected);
+    }
+
+    #[cfg(feature = "command-log")]
+    #[test]
+    fn the_command_log_keeps_the_latest_exchanges_and_their_errors() {
+        use crate::csr8645::command_log::COMMAND_LOG_CAPACITY;
+
+        let mut channel = LoopbackChannel::new();
+        for _ in 0..COMMAND_LOG_CAPACITY + 2 {
+            channel.enqueue_response(b"OK\r\n");
+        }
+        channel.enqueue_response(b"ERROR\r\n");
+        let mut csr8645 = driver(channel);
+
+        block_on(async {
+            for index in 0..COMMAND_LOG_CAPACITY + 2 {
+                csr8645.set_pin(&format!("{}", 1000 + index)).await.unwrap();
+            }
+            assert!(csr8645.set_pin("9999").await.is_err());
+        });
+
+        let records: Vec<_> = csr8645.command_log().records().collect();
+        assert_eq!(records.len(), COMMAND_LOG_CAPACITY);
+        assert_eq!(records[0].command.as_slice(), b"AT+PIN=1003\r\n");
+        assert_eq!(records[0].response.as_slice(), b"OK\n");
+        assert!(records[0].result.is_ok());
+        let failed = records.last().unwrap();
+        assert_eq!(failed.command.as_slice(), b"AT+PIN=9999\r\n");
+        assert_eq!(failed.response.as_slice(), b"ERROR\n");
+        assert!(matches!(failed.result, Err(Csr8645Error::InvalidResponse)));
+    }
+
+    #[test]
+    fn enable_multipoint_sends_the_setting() {
+        let mut csr8645 = initialized_driver("V3.1");
+        csr8645.channel.enqueue_response(b"OK\r\nOK\r\n");
+
+        block_on(async {
+            csr8645.enable_multipoint(true).await.unwrap();
+            assert!(was_sent(&csr8645, b"AT+MULTI=1\r\n"));
+            csr8645.enable_multipoint(false).await.unwrap();
+            assert!(was_sent(&csr8645, b"AT+MULTI=0\r\n"));
+        });
+    }
+
+    #[test]
+    fn an_old_firmware_rejects_multipoint_without_sending_it() {
+        let mut csr8645 = initialized_driver("V3.0");
+
+        let result = block_on(csr8645.enable_multipoint(true));
+
+        assert!(matches!(result, Err(Csr8645Error::Unsupported)));
+        assert!(!was_sent(&csr8645, b"AT+MULTI"));
+    }
+
+    #[test]
+    fn multipoint_tracks_two_connected_devices() {
+        let passenger = BtAddr([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+        let third = BtAddr([0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F]);
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK\r\nOK+CONN\r\nOK+CONNA\r\nOK\r\n");
+        let mut csr8645 = driver(channel);
+
+        block_on(async {
+            csr8645.enable_multipoint(true).await.unwrap();
+            csr8645.connect(&PEER).await.unwrap();
+            csr8645.connect(&passenger).await.unwrap();
+            assert_eq!(csr8645.connected_devices(), [PEER, passenger]);
+
+            // Both slots are taken, so a third device is refused before anything is sent
+            let result = csr8645.connect(&third).await;
+            assert!(matches!(result, Err(Csr8645Error::InvalidParameter)));
+            assert!(!was_sent(&csr8645, b"AT+CON0A0B0C0D0E0F"));
+
+            // Without multipoint only the most recent peer stays tracked
+            csr8645.enable_multipoint(false).await.unwrap();
+        });
+
+        assert_eq!(csr8645.connected_devices(), [passenger]);
+    }
+
+    #[test]
+    fn set_scan_params_sends_windows_within_range() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK\r\nOK\r\n");
+        let mut csr8645 = driver(channel);
+
+        block_on(async {
+            csr8645.set_scan_params(0x0800, 0x0012).await.unwrap();
+            csr8645
+                .set_scan_params(MIN_SCAN_WINDOW, MAX_SCAN_WINDOW)
+                .await
+                .unwrap();
+        });
+
+        assert_eq!(
+            csr8645.channel.written(),
+            b"AT+SCANP=2048,18\r\nAT+SCANP=18,4096\r\n"
+        );
+    }
+
+    #[test]
+    fn set_scan_params_rejects_windows_out_of_range_without_writing() {
+        let mut csr8645 = driver(LoopbackChannel::new());
+
+        for (inquiry_window, page_window) in [
+            (MIN_SCAN_WINDOW - 1, 0x0800),
+            (0x0800, MAX_SCAN_WINDOW + 1),
+            (0, 0),
+            (u16::MAX, u16::MAX),
+        ] {
+            let result = block_on(csr8645.set_scan_params(inquiry_window, page_window));
+            assert!(
+                matches!(result, Err(Csr8645Error::InvalidParameter)),
+                "{} {}",
+                inquiry_window,
+                page_window
+            );
+        }
+        assert!(csr8645.channel.written().is_empty());
+    }
+
+    #[test]
+    fn get_scan_params_queries_both_windows() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK+SCANP:2048,18\r\n");
+        let mut csr8645 = driver(channel);
+
+        let params = block_on(csr8645.get_scan_params()).unwrap();
+
+        assert_eq!(
+            params,
+            ScanParams {
+                inquiry_window: 2048,
+                page_window: 18,
+            }
+        );
+        assert_eq!(csr8645.channel.written(), b"AT+SCANP?\r\n");
+    }
+
+    #[test]
+    fn a_short_frame_is_read_in_a_single_call() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK+VER:V3.1\r\n");
+        let mut csr8645 = driver(channel);
+
+        assert_eq!(block_on(csr8645.get_version()).unwrap(), "V3.1");
+        // The read returns as soon as the line goes idle, without waiting for a full buffer
+        assert_eq!(csr8645.channel.idle_reads(), [13]);
+    }
+
+    #[test]
+    fn a_frame_longer_than_the_buffer_is_read_in_buffer_sized_calls() {
+        let name = "DMZ Sound Booster ".repeat(4);
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(format!("OK+NAME:{}\r\n", name).as_bytes());
+        let mut csr8645 = driver(channel);
+
+        assert_eq!(block_on(csr8645.get_name()).unwrap(), name.trim());
+        assert_eq!(csr8645.channel.idle_reads(), [64, 18]);
+    }
+
+    #[test]
+    fn avrcp_sends_each_passthrough_command() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK\r\nOK\r\nOK\r\nOK\r\n");
+        let mut csr8645 = driver(channel);
+        csr8645.connection_state = ConnectionState::Connected;
+
+        block_on(async {
+            for cmd in [
+                AvrcpCommand::Play,
+                AvrcpCommand::Pause,
+                AvrcpCommand::Next,
+                AvrcpCommand::Prev,
+            ] {
+                csr8645.avrcp(cmd).await.unwrap();
+            }
+        });
+
+        assert_eq!(
+            csr8645.channel.written(),
+            b"AT+PLAY\r\nAT+PAUSE\r\nAT+FORWARD\r\nAT+BACKWARD\r\n"
+        );
+    }
+
+    #[test]
+    fn avrcp_requires_a_connected_phone() {
+        let mut csr8645 = driver(LoopbackChannel::new());
+
+        let result = block_on(csr8645.avrcp(AvrcpCommand::Play));
+
+        assert!(matches!(result, Err(Csr8645Error::NotConnected)));
+        assert!(csr8645.channel.written().is_empty());
+    }
+
+    #[test]
+    fn get_baudrate_reopens_the_uart_after_a_garbled_baud_change() {
+        let mut channel = LoopbackChannel::new();
+        // The module switches to the new rate before its acknowledgement is fully out
+        channel.enqueue_reply(b"AT+BAUD=9600\r\n", b"\xF8\x80\r\n");
+        channel.enqueue_reply(b"AT+BAUD?\r\n", b"OK+BAUD:\xF8\x80\r\n");
+        channel.enqueue_reply(b"AT\r\n", b"OK\r\n");
+        channel.enqueue_reply(b"AT+BAUD?\r\n", b"\xC0\x80\r\n");
+        channel.enqueue_response_at(9600, b"OK+BAUD:9600\r\n");
+        let mut csr8645 = driver(channel);
+
+        let result = block_on(csr8645.set_baudrate(9600));
+        assert!(matches!(result, Err(Csr8645Error::InvalidResponse)));
+        assert_eq!(csr8645.uart_baudrate(), BAUDRATE);
+
+        assert_eq!(block_on(csr8645.get_baudrate()).unwrap(), 9600);
+        assert_eq!(csr8645.uart_baudrate(), 9600);
+        assert_eq!(csr8645.channel.baudrate(), Some(9600));
+        assert!(csr8645.channel.written().ends_with(b"AT+BAUD?\r\n"));
+    }
+
+    #[test]
+    fn get_baudrate_keeps_the_uart_without_a_pending_baud_change() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_reply(b"AT+BAUD?\r\n", b"OK+BAUD:\xF8\x80\r\n");
+        channel.enqueue_reply(b"AT\r\n", b"OK\r\n");
+        channel.enqueue_reply(b"AT+BAUD?\r\n", b"\xC0\x80\r\n");
+        let mut csr8645 = driver(channel);
+
+        let result = block_on(csr8645.get_baudrate());
+
+        assert!(matches!(result, Err(Csr8645Error::InvalidResponse)));
+        assert_eq!(csr8645.uart_baudrate(), BAUDRATE);
+        assert_eq!(csr8645.channel.baudrate(), None);
+    }
+
+    #[test]
+    fn check_connection_status_reports_the_connected_peer() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK+CON:A1B2C3D4E5F6\r\n");
+        let mut csr8645 = driver(channel);
+
+        let status = block_on(csr8645.check_connection_status()).unwrap();
+
+        assert_eq!(
+            status,
+            ConnectionStatus {
+                connected: true,
+                peer: Some(PEER),
+            }
+        );
+        assert_eq!(csr8645.connection_state(), ConnectionState::Connected);
+        assert_eq!(csr8645.channel.written(), b"AT+CON?\r\n");
+    }
+
+    #[test]
+    fn check_connection_status_reports_a_disconnected_module() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK+CON:0\r\n");
+        let mut csr8645 = driver(channel);
+        csr8645.connection_state = ConnectionState::Connected;
+
+        let status = block_on(csr8645.check_connection_status()).unwrap();
+
+        assert!(!status.connected);
+        assert_eq!(status.peer, None);
+        assert_eq!(csr8645.connection_state(), ConnectionState::Disconnected);
+    }
+
+    #[test]
+    fn an_echoed_query_alone_reads_as_disconnected() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"AT+CON?\r\n");
+        let mut csr8645 = driver(channel);
+        csr8645.connection_state = ConnectionState::Connected;
+
+        let status = block_on(csr8645.check_connection_status()).unwrap();
+
+        assert!(!status.connected);
+        assert_eq!(csr8645.connection_state(), ConnectionState::Disconnected);
+    }
+
+    #[test]
+    fn the_reply_following_an_echo_is_parsed() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"AT+CON?\r\nOK+CON:1\r\n");
+        let mut csr8645 = driver(channel);
+
+        let status = block_on(csr8645.check_connection_status()).unwrap();
+
+        assert!(status.connected);
+        assert_eq!(csr8645.connection_state(), ConnectionState::Connected);
+    }
+
+    #[test]
+    fn a_command_is_delivered_whole_through_single_byte_writes() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"OK\r\n");
+        channel.set_max_write_len(Some(1));
+        let mut csr8645 = driver(channel);
+
+        block_on(csr8645.set_name("DMZ Sound Booster")).unwrap();
+
+        assert_eq!(csr8645.channel.written(), b"AT+NAME=DMZ Sound Booster\r\n");
+        // The transmitter is only flushed once the last byte is written
+        assert_eq!(csr8645.channel.flushes(), [27]);
+    }
+
+    #[test]
+    fn a_write_making_no_progress_times_out() {
+        let mut channel = LoopbackChannel::new();
+        channel.set_max_write_len(Some(0));
+        let mut csr8645 = driver(channel);
+        let start = Instant::now();
+
+        let (command, data) = block_on(async {
+            (
+                csr8645.set_name("DMZ").await,
+                csr8645.send_data(&[0x55; 16]).await,
+            )
+        });
+
+        assert!(matches!(command, Err(Csr8645Error::Timeout)));
+        assert!(matches!(data, Err(Csr8645Error::Timeout)));
+        assert!(Instant::now() - start >= WRITE_STALL_TIMEOUT * 2);
+        assert!(csr8645.channel.written().is_empty());
+    }
+
+    #[test]
+    fn resync_drains_garbage_and_pings_the_module() {
+        let mut channel = LoopbackChannel::new();
+        // Line noise left over from the corrupted exchange
+        channel.enqueue_response(b"\xA5Z\x00\xFFOK+PI");
+        channel.enqueue_reply(b"\r\n", b"ERROR\r\n");
+        channel.enqueue_reply(b"AT\r\n", b"OK\r\n");
+        let mut csr8645 = driver(channel);
+
+        block_on(csr8645.resync()).unwrap();
+
+        assert_eq!(csr8645.channel.written(), b"\r\nAT\r\n");
+    }
+
+    #[test]
+    fn resync_tries_again_when_the_ping_is_garbled() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_reply(b"AT\r\n", b"\xFF\xFE\r\n");
+        channel.enqueue_reply(b"AT\r\n", b"OK\r\n");
+        let mut csr8645 = driver(channel);
+
+        block_on(csr8645.resync()).unwrap();
+
+        assert_eq!(csr8645.channel.written(), b"\r\nAT\r\n\r\nAT\r\n");
+    }
+
+    #[test]
+    fn resync_gives_up_after_its_attempts() {
+        let mut csr8645 = driver(LoopbackChannel::new());
+
+        let result = block_on(csr8645.resync());
+
+        assert!(matches!(result, Err(Csr8645Error::Timeout)));
+        assert_eq!(
+            csr8645.channel.written(),
+            b"\r\nAT\r\n".repeat(RESYNC_ATTEMPTS as usize)
+        );
+    }
+
+    #[test]
+    fn the_inter_command_delay_is_awaited_between_two_commands() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_reply(b"AT+PIN=1234\r\n", b"OK\r\n");
+        channel.enqueue_reply(b"AT+PIN=5678\r\n", b"OK\r\n");
+        let mut csr8645 = driver(channel);
+        let delay = Duration::from_millis(40);
+        csr8645.set_command_pacing(CommandPacing {
+            inter_command_delay: delay,
+            ..CommandPacing::default()
+        });
+
+        let start = Instant::now();
+        block_on(csr8645.set_pin("1234")).unwrap();
+        // The first command is not held back
+        assert!(Instant::now() - start < delay);
+        block_on(csr8645.set_pin("5678")).unwrap();
+
+        assert!(Instant::now() - start >= delay);
+        assert_eq!(csr8645.channel.written(), b"AT+PIN=1234\r\nAT+PIN=5678\r\n");
+    }
+
+    #[test]
+    fn the_inter_byte_delay_spaces_the_bytes_of_a_command() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_reply(b"AT+PIN=1234\r\n", b"OK\r\n");
+        let mut csr8645 = driver(channel);
+        let delay = Duration::from_millis(2);
+        csr8645.set_command_pacing(CommandPacing {
+            inter_byte_delay: delay,
+            ..CommandPacing::default()
+        });
+
+        let start = Instant::now();
+        block_on(csr8645.set_pin("1234")).unwrap();
+
+        let command = b"AT+PIN=1234\r\n";
+        assert!(Instant::now() - start >= delay * (command.len() as u32 - 1));
+        assert_eq!(csr8645.channel.written(), command);
+    }
+
+    /// `RecordingKeyPin` is a KEY pin recording the levels it is driven to.
+    #[derive(Clone, Default)]
+    struct RecordingKeyPin {
+        /// The levels driven so far, true for high.
+        levels: Rc<RefCell<Vec<bool>>>,
+    }
+
+    impl embedded_hal::digital::ErrorType for RecordingKeyPin {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for RecordingKeyPin {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.levels.borrow_mut().push(false);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.levels.borrow_mut().push(true);
+            Ok(())
+        }
+    }
+
+    /// Returns a driver for an HC-05-style module, along with its KEY pin.
+    fn work_mode_driver(
+        channel: LoopbackChannel,
+    ) -> (
+        Csr8645Driver<LoopbackChannel, RecordingKeyPin>,
+        RecordingKeyPin,
+    ) {
+        let mut csr8645 = Csr8645Driver::new(channel, BAUDRATE).unwrap();
+        let key_pin = RecordingKeyPin::default();
+        csr8645.set_key_pin(
+            key_pin.clone(),
+            WorkModeConfig {
+                settle_time: Duration::from_ticks(0),
+                ..WorkModeConfig::default()
+            },
+        );
+        (csr8645, key_pin)
+    }
+
+    #[test]
+    fn a_getter_switches_the_module_to_at_mode() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_reply(b"AT+PIO=3?\r\n", b"OK+PIO:3,1\r\n");
+        let (mut csr8645, key_pin) = work_mode_driver(channel);
+        assert_eq!(csr8645.work_mode(), None);
+
+        assert!(block_on(csr8645.get_pio(3)).unwrap());
+
+        assert_eq!(csr8645.work_mode(), Some(WorkMode::At));
+        assert_eq!(*key_pin.levels.borrow(), [true]);
+        assert_eq!(
+            csr8645.channel.baudrate(),
+            Some(WorkModeConfig::default().at_baudrate)
+        );
+    }
+
+    #[test]
+    fn send_data_switches_the_module_to_data_mode_and_back() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_reply(b"AT+PIO=3?\r\n", b"OK+PIO:3,0\r\n");
+        let (mut csr8645, key_pin) = work_mode_driver(channel);
+
+        block_on(csr8645.send_data(b"payload")).unwrap();
+
+        assert_eq!(csr8645.work_mode(), Some(WorkMode::Data));
+        assert_eq!(*key_pin.levels.borrow(), [false]);
+        assert_eq!(
+            csr8645.channel.baudrate(),
+            Some(WorkModeConfig::default().data_baudrate)
+        );
+        assert_eq!(csr8645.channel.written(), b"payload");
+
+        // A further transfer stays in data mode, and the next command switches back
+        block_on(csr8645.send_data(b"more")).unwrap();
+        assert_eq!(*key_pin.levels.borrow(), [false]);
+        assert!(!block_on(csr8645.get_pio(3)).unwrap());
+        assert_eq!(*key_pin.levels.borrow(), [false, true]);
+        assert_eq!(csr8645.work_mode(), Some(WorkMode::At));
+    }
+
+    #[test]
+    fn a_module_without_a_key_pin_is_never_switched() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_reply(b"AT+PIO=3?\r\n", b"OK+PIO:3,1\r\n");
+        let mut csr8645 = driver(channel);
+
+        block_on(async {
+            csr8645.send_data(b"payload").await.unwrap();
+            csr8645.get_pio(3).await.unwrap();
+        });
+
+        assert_eq!(csr8645.work_mode(), None);
+        assert_eq!(csr8645.channel.baudrate(), None);
+    }
+
+    #[test]
+    fn close_disconnects_and_sleeps_the_module() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_reply(b"AT+CONA1B2C3D4E5F6\r\n", b"OK+CONN\r\n");
+        channel.enqueue_reply(b"AT+DISC\r\n", b"OK+DISC\r\n");
+        let unclosed_drops = channel.unclosed_drops();
+        let mut csr8645 = driver(channel);
+
+        block_on(async {
+            csr8645.connect(&PEER).await.unwrap();
+            csr8645.close().await.unwrap();
+        });
+
+        assert_eq!(
+            csr8645.channel.written(),
+            b"AT+CONA1B2C3D4E5F6\r\nAT+DISC\r\nAT+SLEEP\r\n"
+        );
+        drop(csr8645);
+        assert_eq!(unclosed_drops.get(), 0);
+    }
+
+    #[test]
+    fn a_shared_driver_is_closed_through_its_mutex() {
+        let channel = LoopbackChannel::new();
+        let unclosed_drops = channel.unclosed_drops();
+        let shared: Mutex<CriticalSectionRawMutex, Csr8645Driver<LoopbackChannel>> =
+            Mutex::new(driver(channel));
+
+        block_on(async { shared.lock().await.close().await.unwrap() });
+
+        assert_eq!(
+            shared.try_lock().unwrap().channel.written(),
+            b"AT+SLEEP\r\n"
+        );
+        drop(shared);
+        assert_eq!(unclosed_drops.get(), 0);
+    }
+
+    #[test]
+    fn dropping_a_driver_without_close_warns() {
+        let channel = LoopbackChannel::new();
+        let unclosed_drops = channel.unclosed_drops();
+
+        drop(driver(channel));
+
+        assert_eq!(unclosed_drops.get(), 1);
     }
 }
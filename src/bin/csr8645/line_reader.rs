@@ -0,0 +1,142 @@
+#![no_std]
+#![no_main]
+
+use alloc::vec::Vec;
+
+/// The terminator that ends every response line of the CSR8645 module.
+const LINE_TERMINATOR: &[u8] = b"\r\n";
+
+/// `LineReader` accumulates bytes received from the UART and yields complete lines.
+///
+/// A single UART read may contain several responses, or only part of one. Bytes following the
+/// first complete line are kept so the next read consumes them instead of discarding them.
+pub struct LineReader {
+    /// The bytes received but not yet consumed as a line.
+    pending: Vec<u8>,
+    /// The maximum number of pending bytes kept.
+    capacity: usize,
+}
+
+impl LineReader {
+    /// Creates a new instance of `LineReader`.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - The maximum number of pending bytes kept.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `LineReader` instance.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            pending: Vec::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Appends received bytes to the pending data.
+    ///
+    /// NUL bytes left over in partially filled read buffers are skipped. If the capacity is
+    /// exceeded, the oldest bytes are dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The bytes received from the UART.
+    pub fn push(&mut self, data: &[u8]) {
+        self.pending.extend(data.iter().copied().filter(|&b| b != 0));
+
+        if self.pending.len() > self.capacity {
+            let excess = self.pending.len() - self.capacity;
+            self.pending.drain(..excess);
+        }
+    }
+
+    /// Takes the next complete line from the pending data.
+    ///
+    /// Empty lines are skipped.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Vec<u8>>` - The next line without its terminator, or `None` if no complete line
+    ///   has been received yet.
+    pub fn next_line(&mut self) -> Option<Vec<u8>> {
+        loop {
+            let end = self
+                .pending
+                .windows(LINE_TERMINATOR.len())
+                .position(|w| w == LINE_TERMINATOR)?;
+
+            let line: Vec<u8> = self.pending.drain(..end + LINE_TERMINATOR.len()).collect();
+            if end > 0 {
+                return Some(line[..end].to_vec());
+            }
+        }
+    }
+
+    /// Discards all pending data.
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn two_responses_in_one_chunk_are_read_in_order() {
+        let mut reader = LineReader::new(64);
+
+        reader.push(b"OK+NAME:DMZ\r\nOK+BAUD:115200\r\n");
+
+        assert_eq!(reader.next_line().as_deref(), Some(&b"OK+NAME:DMZ"[..]));
+        assert_eq!(reader.next_line().as_deref(), Some(&b"OK+BAUD:115200"[..]));
+        assert_eq!(reader.next_line(), None);
+    }
+
+    #[test]
+    fn a_line_split_across_chunks_is_joined() {
+        let mut reader = LineReader::new(64);
+
+        reader.push(b"OK+VE");
+        assert_eq!(reader.next_line(), None);
+        reader.push(b"R:V3.1\r");
+        assert_eq!(reader.next_line(), None);
+        reader.push(b"\nOK");
+
+        assert_eq!(reader.next_line().as_deref(), Some(&b"OK+VER:V3.1"[..]));
+        assert_eq!(reader.next_line(), None);
+        reader.push(b"\r\n");
+        assert_eq!(reader.next_line().as_deref(), Some(&b"OK"[..]));
+    }
+
+    #[test]
+    fn empty_lines_and_nul_padding_are_skipped() {
+        let mut reader = LineReader::new(64);
+
+        reader.push(b"\r\n\r\nO\0K\r\n\0\0\0");
+
+        assert_eq!(reader.next_line().as_deref(), Some(&b"OK"[..]));
+        assert_eq!(reader.next_line(), None);
+    }
+
+    #[test]
+    fn the_oldest_bytes_are_dropped_past_the_capacity() {
+        let mut reader = LineReader::new(8);
+
+        reader.push(b"GARBAGE-OK\r\n");
+
+        assert_eq!(reader.next_line().as_deref(), Some(&b"AGE-OK"[..]));
+    }
+
+    #[test]
+    fn clear_discards_a_partial_line() {
+        let mut reader = LineReader::new(64);
+        reader.push(b"OK+NA");
+
+        reader.clear();
+        reader.push(b"OK\r\n");
+
+        assert_eq!(reader.next_line().as_deref(), Some(&b"OK"[..]));
+    }
+}
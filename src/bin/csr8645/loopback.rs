@@ -0,0 +1,269 @@
+#![no_std]
+#![no_main]
+
+use crate::csr8645::byte_channel::{ByteChannel, ReconfigureError};
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::Cell;
+use embassy_stm32::usart::Error;
+
+/// `LoopbackChannel` is an in-memory `ByteChannel` used to exercise the driver without hardware.
+///
+/// Canned responses are enqueued up front and served to reads, while every write is captured
+/// for inspection. Reading past the enqueued responses waits forever, as a UART does once the
+/// module stops answering, so the driver's own timeouts decide when to give up. Reads until idle
+/// serve every enqueued byte that fits as one frame, and their lengths are recorded. Writes can be
+/// limited to a few bytes per call to exercise partial writes, and echoed back to the reads like
+/// the bench peer of the `uart_echo` example does. Responses can also be held back until the
+/// channel is re-opened at a given baud rate, like a module that only answers at its new rate,
+/// or until a given command is written, like a module answering each command in turn.
+/// UART errors can be injected ahead of the responses, like line noise would raise. The reads can
+/// also be served from a ring of limited size, like the `RingBufferedReceiver` does, so enqueuing
+/// more bytes than the ring holds before draining them overruns it. Drivers dropped without being
+/// closed are counted, in a counter that outlives the channel.
+pub struct LoopbackChannel {
+    /// The bytes served to subsequent reads.
+    responses: VecDeque<u8>,
+    /// The bytes written so far.
+    written: Vec<u8>,
+    /// The baud rate the channel was last re-opened at, if any.
+    baudrate: Option<u32>,
+    /// The most bytes accepted by a single write, or `None` to accept them all.
+    max_write_len: Option<usize>,
+    /// Whether the written bytes are also served to subsequent reads.
+    echo: bool,
+    /// The responses held back until the channel is re-opened at their baud rate.
+    responses_at: Vec<(u32, Vec<u8>)>,
+    /// The replies held back until their command is written, in order.
+    replies: VecDeque<(Vec<u8>, Vec<u8>)>,
+    /// The number of bytes written so far at each TX flush.
+    flushes: Vec<usize>,
+    /// The errors returned by the next reads, before any response is served.
+    errors: VecDeque<Error>,
+    /// The number of bytes served by each read until idle, in order.
+    idle_reads: Vec<usize>,
+    /// The most unread bytes held before the ring overruns, or `None` for an unbounded buffer.
+    ring_len: Option<usize>,
+    /// The number of times the ring overran.
+    overruns: u32,
+    /// The number of drivers dropped without being closed, shared with the tests.
+    unclosed_drops: Rc<Cell<u32>>,
+}
+
+impl LoopbackChannel {
+    /// Creates a new instance of `LoopbackChannel` with no enqueued responses.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `LoopbackChannel` instance.
+    pub fn new() -> Self {
+        Self {
+            responses: VecDeque::new(),
+            written: Vec::new(),
+            baudrate: None,
+            max_write_len: None,
+            echo: false,
+            responses_at: Vec::new(),
+            replies: VecDeque::new(),
+            flushes: Vec::new(),
+            errors: VecDeque::new(),
+            idle_reads: Vec::new(),
+            ring_len: None,
+            overruns: 0,
+            unclosed_drops: Rc::new(Cell::new(0)),
+        }
+    }
+
+    /// Enqueues a canned response served to subsequent reads.
+    ///
+    /// # Arguments
+    ///
+    /// * `response` - The response bytes, including their `\r\n` terminator.
+    pub fn enqueue_response(&mut self, response: &[u8]) {
+        self.responses.extend(response.iter().copied());
+    }
+
+    /// Enqueues a canned response served once the channel is re-opened at the given baud rate.
+    ///
+    /// # Arguments
+    ///
+    /// * `baudrate` - The baud rate the response is sent at.
+    /// * `response` - The response bytes, including their `\r\n` terminator.
+    pub fn enqueue_response_at(&mut self, baudrate: u32, response: &[u8]) {
+        self.responses_at.push((baudrate, response.to_vec()));
+    }
+
+    /// Enqueues a canned reply served once the given command has been written.
+    ///
+    /// Replies are released in the order they were enqueued, each by the first write completing
+    /// its command after the previous reply was released.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command the reply answers, including its `\r\n` terminator.
+    /// * `reply` - The reply bytes, including their `\r\n` terminator.
+    pub fn enqueue_reply(&mut self, command: &[u8], reply: &[u8]) {
+        self.replies.push_back((command.to_vec(), reply.to_vec()));
+    }
+
+    /// Injects a UART error, returned by the next read instead of the enqueued responses.
+    ///
+    /// # Arguments
+    ///
+    /// * `err` - The error the read fails with.
+    pub fn inject_error(&mut self, err: Error) {
+        self.errors.push_back(err);
+    }
+
+    /// Limits the number of bytes accepted by a single write.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_write_len` - The most bytes accepted per write, or `None` to accept them all.
+    pub fn set_max_write_len(&mut self, max_write_len: Option<usize>) {
+        self.max_write_len = max_write_len;
+    }
+
+    /// Enables or disables echoing the written bytes back to subsequent reads.
+    ///
+    /// # Arguments
+    ///
+    /// * `echo` - True to serve every written byte to the reads, after the enqueued responses.
+    pub fn set_echo(&mut self, echo: bool) {
+        self.echo = echo;
+    }
+
+    /// Limits the number of unread bytes held before the ring overruns.
+    ///
+    /// Once more bytes are enqueued than the ring holds, the next read fails with
+    /// `Error::Overrun` and only the newest bytes are kept, as the DMA overwrote the oldest ones.
+    ///
+    /// # Arguments
+    ///
+    /// * `ring_len` - The size of the ring, or `None` for an unbounded buffer.
+    pub fn set_ring_len(&mut self, ring_len: Option<usize>) {
+        self.ring_len = ring_len;
+    }
+
+    /// Fails with `Error::Overrun` if the unread bytes no longer fit in the ring, keeping only
+    /// the newest ones.
+    fn check_overrun(&mut self) -> Result<(), Error> {
+        match self.ring_len {
+            Some(ring_len) if self.responses.len() > ring_len => {
+                let lost = self.responses.len() - ring_len;
+                self.responses.drain(..lost);
+                self.overruns += 1;
+                Err(Error::Overrun)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the number of drivers dropped without being closed while owning the channel.
+    ///
+    /// The counter is shared, so it can still be read once the driver and the channel are gone.
+    pub fn unclosed_drops(&self) -> Rc<Cell<u32>> {
+        Rc::clone(&self.unclosed_drops)
+    }
+
+    /// Returns the bytes written so far.
+    pub fn written(&self) -> &[u8] {
+        &self.written
+    }
+
+    /// Returns the number of bytes written so far at each TX flush, in order.
+    pub fn flushes(&self) -> &[usize] {
+        &self.flushes
+    }
+
+    /// Returns the number of bytes served by each read until idle, in order.
+    pub fn idle_reads(&self) -> &[usize] {
+        &self.idle_reads
+    }
+
+    /// Returns the baud rate the channel was last re-opened at, if any.
+    pub fn baudrate(&self) -> Option<u32> {
+        self.baudrate
+    }
+}
+
+impl ByteChannel for LoopbackChannel {
+    async fn write_some(&mut self, data: &[u8]) -> Result<usize, Error> {
+        let len = self
+            .max_write_len
+            .map_or(data.len(), |max| max.min(data.len()));
+        self.written.extend_from_slice(&data[..len]);
+        if self.echo {
+            self.responses.extend(data[..len].iter().copied());
+        }
+        if let Some((command, _)) = self.replies.front() {
+            if self.written.ends_with(command) {
+                let (_, reply) = self.replies.pop_front().unwrap();
+                self.responses.extend(reply);
+            }
+        }
+        Ok(len)
+    }
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        if let Some(err) = self.errors.pop_front() {
+            return Err(err);
+        }
+        self.check_overrun()?;
+        if self.responses.is_empty() {
+            return core::future::pending().await;
+        }
+
+        // Pad short responses with NUL bytes, which the line reader skips
+        for byte in buf.iter_mut() {
+            *byte = self.responses.pop_front().unwrap_or(0);
+        }
+        Ok(())
+    }
+
+    async fn read_until_idle(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if let Some(err) = self.errors.pop_front() {
+            return Err(err);
+        }
+        self.check_overrun()?;
+        if self.responses.is_empty() {
+            return core::future::pending().await;
+        }
+
+        // The line goes idle once the enqueued responses have been served
+        let len = self.responses.len().min(buf.len());
+        for (byte, response) in buf.iter_mut().zip(self.responses.drain(..len)) {
+            *byte = response;
+        }
+        self.idle_reads.push(len);
+        Ok(len)
+    }
+
+    fn flush_rx(&mut self) {}
+
+    async fn flush_tx(&mut self) -> Result<(), Error> {
+        self.flushes.push(self.written.len());
+        Ok(())
+    }
+
+    fn set_baudrate(&mut self, baudrate: u32) -> Result<(), ReconfigureError> {
+        self.baudrate = Some(baudrate);
+        let (due, held): (Vec<_>, Vec<_>) = core::mem::take(&mut self.responses_at)
+            .into_iter()
+            .partition(|(at, _)| *at == baudrate);
+        self.responses_at = held;
+        for (_, response) in due {
+            self.responses.extend(response);
+        }
+        Ok(())
+    }
+
+    fn overruns(&self) -> u32 {
+        self.overruns
+    }
+
+    fn dropped_unclosed(&mut self) {
+        self.unclosed_drops.set(self.unclosed_drops.get() + 1);
+    }
+}
@@ -1 +1,9 @@
+pub mod bt_addr;
+pub mod byte_channel;
+#[cfg(feature = "command-log")]
+pub mod command_log;
 pub mod csr8645;
+pub mod line_reader;
+pub mod loopback;
+pub mod parser;
+pub mod ring_buffered_receiver;
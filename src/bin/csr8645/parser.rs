@@ -0,0 +1,712 @@
+#![no_std]
+#![no_main]
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::str;
+
+use crate::csr8645::bt_addr::BtAddr;
+use crate::csr8645::csr8645::{
+    BtEvent, ConnectionStatus, FirmwareVersion, ModuleState, ScanParams, ScannedDevice,
+};
+
+/// Represents an error that can occur while parsing a response of the CSR8645 module.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub enum ParseError {
+    /// The response is not valid UTF-8.
+    InvalidUtf8,
+    /// The response does not hold a valid number.
+    InvalidNumber,
+    /// The response holds a value outside the expected set.
+    UnexpectedValue,
+    /// The text is not a valid Bluetooth address.
+    InvalidAddress,
+}
+
+/// Decodes a response line as text, ignoring NUL padding and surrounding whitespace.
+///
+/// # Arguments
+///
+/// * `response` - The raw response bytes.
+///
+/// # Returns
+///
+/// A `Result` containing the trimmed text or an error if it is not valid UTF-8.
+fn as_text(response: &[u8]) -> Result<&str, ParseError> {
+    let text = str::from_utf8(response).map_err(|_| ParseError::InvalidUtf8)?;
+    Ok(text.trim_matches(char::from(0)).trim())
+}
+
+/// Extracts the value of a `OK+<KEY>:<value>` style response.
+///
+/// # Arguments
+///
+/// * `response` - The raw response bytes.
+///
+/// # Returns
+///
+/// A `Result` containing the text following the last `:`, or the whole line if it has none.
+pub fn parse_value(response: &[u8]) -> Result<&str, ParseError> {
+    let text = as_text(response)?;
+    Ok(text.rsplit(':').next().unwrap_or(text).trim())
+}
+
+/// Parses a firmware version such as `V3.1`.
+///
+/// The leading `V` is optional and a missing minor number reads as zero.
+///
+/// # Arguments
+///
+/// * `version` - The version reported by the module.
+///
+/// # Returns
+///
+/// A `Result` containing the version or an error if it is not made of one or two numbers.
+pub fn parse_firmware_version(version: &str) -> Result<FirmwareVersion, ParseError> {
+    let version = version.trim();
+    let version = version.strip_prefix(['V', 'v']).unwrap_or(version);
+
+    let mut numbers = version.splitn(2, '.');
+    let major = numbers
+        .next()
+        .and_then(|n| n.parse().ok())
+        .ok_or(ParseError::InvalidNumber)?;
+    let minor = match numbers.next() {
+        Some(minor) => minor.parse().map_err(|_| ParseError::InvalidNumber)?,
+        None => 0,
+    };
+
+    Ok(FirmwareVersion::new(major, minor))
+}
+
+/// Checks whether a response line is a bare `OK` acknowledgement.
+///
+/// # Arguments
+///
+/// * `response` - The raw response line.
+///
+/// # Returns
+///
+/// * `bool` - True if the line is exactly `OK`, false otherwise.
+pub fn is_ok(response: &[u8]) -> bool {
+    as_text(response).is_ok_and(|text| text == "OK")
+}
+
+/// Checks whether a response line starts with one of the given prefixes.
+///
+/// # Arguments
+///
+/// * `response` - The raw response line.
+/// * `prefixes` - The accepted prefixes, e.g. `OK+BAUD`.
+///
+/// # Returns
+///
+/// * `bool` - True if the line starts with one of the prefixes.
+pub fn has_prefix(response: &[u8], prefixes: &[&str]) -> bool {
+    as_text(response).is_ok_and(|text| prefixes.iter().any(|prefix| text.starts_with(prefix)))
+}
+
+/// Parses an `AT+BAUD?` response.
+///
+/// # Arguments
+///
+/// * `response` - The raw response, e.g. `OK+BAUD:115200`.
+///
+/// # Returns
+///
+/// A `Result` containing the baud rate or an error.
+pub fn parse_baudrate(response: &[u8]) -> Result<u32, ParseError> {
+    parse_value(response)?
+        .parse::<u32>()
+        .map_err(|_| ParseError::InvalidNumber)
+}
+
+/// Parses an `AT+RSSI?` response.
+///
+/// # Arguments
+///
+/// * `response` - The raw response, e.g. `OK+RSSI:-67`.
+///
+/// # Returns
+///
+/// A `Result` containing the RSSI in dBm or an error.
+pub fn parse_rssi(response: &[u8]) -> Result<i8, ParseError> {
+    parse_value(response)?
+        .parse::<i8>()
+        .map_err(|_| ParseError::InvalidNumber)
+}
+
+/// Parses a response holding a `0` or `1` flag, such as `AT+NOTI?`.
+///
+/// # Arguments
+///
+/// * `response` - The raw response, e.g. `OK+NOTI:1`.
+///
+/// # Returns
+///
+/// A `Result` containing the flag or an error.
+pub fn parse_flag(response: &[u8]) -> Result<bool, ParseError> {
+    match parse_value(response)? {
+        "1" => Ok(true),
+        "0" => Ok(false),
+        _ => Err(ParseError::UnexpectedValue),
+    }
+}
+
+/// Parses an `AT+PIO=<pin>?` response.
+///
+/// # Arguments
+///
+/// * `response` - The raw response, e.g. `OK+PIO:4,1`.
+///
+/// # Returns
+///
+/// A `Result` containing true if the pin is high, false if it is low, or an error.
+pub fn parse_pio_level(response: &[u8]) -> Result<bool, ParseError> {
+    match parse_value(response)?.rsplit(',').next() {
+        Some("1") => Ok(true),
+        Some("0") => Ok(false),
+        _ => Err(ParseError::UnexpectedValue),
+    }
+}
+
+/// Parses an `AT+SCANP?` response.
+///
+/// # Arguments
+///
+/// * `response` - The raw response, e.g. `OK+SCANP:18,18`.
+///
+/// # Returns
+///
+/// A `Result` containing the inquiry and page scan windows or an error.
+pub fn parse_scan_params(response: &[u8]) -> Result<ScanParams, ParseError> {
+    let (inquiry, page) = parse_value(response)?
+        .split_once(',')
+        .ok_or(ParseError::UnexpectedValue)?;
+    let parse_window = |text: &str| {
+        text.trim()
+            .parse::<u16>()
+            .map_err(|_| ParseError::InvalidNumber)
+    };
+
+    Ok(ScanParams {
+        inquiry_window: parse_window(inquiry)?,
+        page_window: parse_window(page)?,
+    })
+}
+
+/// The texts an `AT+STATE?` reply may start with.
+///
+/// Some firmwares answer with the bare state token, e.g. `CONNECTED`, instead of
+/// `STATE:CONNECTED`, so the tokens known to `parse_state` are accepted as well.
+pub const STATE_REPLY_PREFIXES: &[&str] = &[
+    "STATE:",
+    "INITIALIZED",
+    "READY",
+    "PAIRABLE",
+    "PAIRING",
+    "CONNECTED",
+    "DISCONNECTED",
+    "INCOMING_CALL",
+    "OUTGOING_CALL",
+    "ACTIVE_CALL",
+];
+
+/// Parses an `AT+STATE?` response into a `ModuleState`.
+///
+/// # Arguments
+///
+/// * `response` - The raw response, e.g. `STATE:CONNECTED` or `CONNECTED`.
+///
+/// # Returns
+///
+/// * `ModuleState` - The parsed state, or `ModuleState::Unknown` holding the raw text.
+pub fn parse_state(response: &[u8]) -> ModuleState {
+    let Ok(raw) = as_text(response) else {
+        return ModuleState::Unknown(String::from_utf8_lossy(response).to_string());
+    };
+    let token = raw.strip_prefix("STATE:").unwrap_or(raw).trim();
+
+    match token {
+        "INITIALIZED" => ModuleState::Initialized,
+        "READY" => ModuleState::Ready,
+        "PAIRABLE" => ModuleState::Pairable,
+        "PAIRING" => ModuleState::Pairing,
+        "CONNECTED" => ModuleState::Connected,
+        "DISCONNECTED" => ModuleState::Disconnected,
+        "INCOMING_CALL" => ModuleState::IncomingCall,
+        "OUTGOING_CALL" => ModuleState::OutgoingCall,
+        "ACTIVE_CALL" => ModuleState::ActiveCall,
+        _ => ModuleState::Unknown(raw.to_string()),
+    }
+}
+
+/// Checks whether a response line is the echo of the command that was sent.
+///
+/// # Arguments
+///
+/// * `line` - The response line.
+/// * `command` - The command sent, with or without its terminator.
+///
+/// # Returns
+///
+/// * `bool` - True if the line repeats the command.
+pub fn is_echo(line: &[u8], command: &[u8]) -> bool {
+    match (as_text(line), str::from_utf8(command)) {
+        (Ok(line), Ok(command)) => !line.is_empty() && line == command.trim(),
+        _ => false,
+    }
+}
+
+/// Parses an `AT+CON?` response into a `ConnectionStatus`.
+///
+/// Only an `OK+CON:` reply reports a link; anything else, including an echo of the query,
+/// is treated as disconnected.
+///
+/// # Arguments
+///
+/// * `response` - The raw response, e.g. `OK+CON:AABBCCDDEEFF`, `OK+CON:1` or `OK+CON:NONE`.
+///
+/// # Returns
+///
+/// * `ConnectionStatus` - The parsed link state.
+pub fn parse_connection_status(response: &[u8]) -> ConnectionStatus {
+    let disconnected = ConnectionStatus {
+        connected: false,
+        peer: None,
+    };
+    let Some(value) = as_text(response)
+        .ok()
+        .and_then(|text| text.strip_prefix("OK+CON:"))
+    else {
+        return disconnected;
+    };
+
+    match value.trim() {
+        "0" | "NONE" | "" => disconnected,
+        "1" => ConnectionStatus {
+            connected: true,
+            peer: None,
+        },
+        address => ConnectionStatus {
+            connected: true,
+            peer: address.parse::<BtAddr>().ok(),
+        },
+    }
+}
+
+/// Parses an unsolicited notification line into a `BtEvent`.
+///
+/// # Arguments
+///
+/// * `line` - The notification line, e.g. `OK+LOST`.
+///
+/// # Returns
+///
+/// * `Option<BtEvent>` - The notified event, or `None` if the line is not a link notification.
+pub fn parse_event(line: &[u8]) -> Option<BtEvent> {
+    match as_text(line).ok()?.trim() {
+        "OK+CONN" | "OK+CONNA" => Some(BtEvent::Connected),
+        "OK+LOST" => Some(BtEvent::Disconnected),
+        _ => None,
+    }
+}
+
+/// Parses one line of an `AT+DISC?` response into a `ScannedDevice`.
+///
+/// # Arguments
+///
+/// * `line` - The response line, e.g. `OK+DISC:AABBCCDDEEFF,My Phone`.
+///
+/// # Returns
+///
+/// * `Option<ScannedDevice>` - The parsed device, or `None` if the line does not describe one.
+pub fn parse_scanned_device(line: &[u8]) -> Option<ScannedDevice> {
+    let line = as_text(line).ok()?;
+    let entry = line
+        .strip_prefix("OK+DISC:")
+        .or_else(|| line.strip_prefix("+DISC:"))?;
+
+    parse_device_entry(entry)
+}
+
+/// Parses an `AT+RNAME?` response into the device currently connected.
+///
+/// # Arguments
+///
+/// * `response` - The raw response, e.g. `OK+RNAME:AABBCCDDEEFF,My Phone`.
+///
+/// # Returns
+///
+/// * `Option<ScannedDevice>` - The connected device, or `None` if the module reports no peer.
+pub fn parse_peer(response: &[u8]) -> Option<ScannedDevice> {
+    let entry = as_text(response).ok()?.strip_prefix("OK+RNAME:")?;
+    if entry.trim() == "NONE" {
+        return None;
+    }
+
+    parse_device_entry(entry)
+}
+
+/// Parses an `<address>[,<name>]` device entry.
+///
+/// # Arguments
+///
+/// * `entry` - The entry text following the response prefix.
+///
+/// # Returns
+///
+/// * `Option<ScannedDevice>` - The parsed device, or `None` if the address is malformed.
+fn parse_device_entry(entry: &str) -> Option<ScannedDevice> {
+    let (address, name) = match entry.split_once(',') {
+        Some((address, name)) => (address.trim(), Some(name.trim())),
+        None => (entry.trim(), None),
+    };
+
+    Some(ScannedDevice {
+        address: address.parse::<BtAddr>().ok()?,
+        name: name.filter(|n| !n.is_empty()).map(|n| n.to_string()),
+    })
+}
+
+/// Parses a complete `AT+DISC?` response into the list of devices it describes.
+///
+/// Lines that do not describe a device, or are not valid UTF-8, are skipped. A final line
+/// without a terminator is assumed to be truncated and is dropped.
+///
+/// # Arguments
+///
+/// * `response` - The raw response, one device per line.
+///
+/// # Returns
+///
+/// * `Vec<ScannedDevice>` - The devices found in the response.
+pub fn parse_scan(response: &[u8]) -> Vec<ScannedDevice> {
+    let complete = match response.iter().rposition(|&b| b == b'\n') {
+        Some(end) => &response[..end],
+        None => return Vec::new(),
+    };
+
+    complete
+        .split(|&b| b == b'\n')
+        .filter_map(parse_scanned_device)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_state_maps_each_known_token() {
+        let tokens = [
+            ("INITIALIZED", ModuleState::Initialized),
+            ("READY", ModuleState::Ready),
+            ("PAIRABLE", ModuleState::Pairable),
+            ("PAIRING", ModuleState::Pairing),
+            ("CONNECTED", ModuleState::Connected),
+            ("DISCONNECTED", ModuleState::Disconnected),
+            ("INCOMING_CALL", ModuleState::IncomingCall),
+            ("OUTGOING_CALL", ModuleState::OutgoingCall),
+            ("ACTIVE_CALL", ModuleState::ActiveCall),
+        ];
+
+        for (token, state) in tokens {
+            assert_eq!(parse_state(format!("STATE:{}", token).as_bytes()), state);
+            // Some firmwares answer with the bare token
+            assert_eq!(parse_state(token.as_bytes()), state);
+            assert!(has_prefix(token.as_bytes(), STATE_REPLY_PREFIXES));
+        }
+    }
+
+    #[test]
+    fn parse_state_keeps_an_unrecognized_token() {
+        assert_eq!(
+            parse_state(b"STATE:SNIFF\r\n"),
+            ModuleState::Unknown("STATE:SNIFF".to_string())
+        );
+        assert_eq!(
+            parse_state(b"\xFFSTATE"),
+            ModuleState::Unknown("\u{FFFD}STATE".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_scan_drops_a_truncated_final_line() {
+        let devices = parse_scan(b"OK+DISC:A1B2C3D4E5F6,Pixel 7\nOK+DISC:AABBCCDDEEFF,Caf\xC3");
+
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].name.as_deref(), Some("Pixel 7"));
+        assert!(parse_scan(b"OK+DISC:A1B2C3D4E5F6,Pixel 7").is_empty());
+    }
+
+    #[test]
+    fn parse_scan_params_reads_both_windows() {
+        assert_eq!(
+            parse_scan_params(b"OK+SCANP:18, 4096"),
+            Ok(ScanParams {
+                inquiry_window: 18,
+                page_window: 4096,
+            })
+        );
+        assert_eq!(
+            parse_scan_params(b"OK+SCANP:18"),
+            Err(ParseError::UnexpectedValue)
+        );
+        assert_eq!(
+            parse_scan_params(b"OK+SCANP:18,-1"),
+            Err(ParseError::InvalidNumber)
+        );
+    }
+
+    #[test]
+    fn parse_connection_status_reads_the_state_reply() {
+        let disconnected = ConnectionStatus {
+            connected: false,
+            peer: None,
+        };
+
+        assert_eq!(
+            parse_connection_status(b"OK+CON:A1B2C3D4E5F6"),
+            ConnectionStatus {
+                connected: true,
+                peer: Some(BtAddr([0xA1, 0xB2, 0xC3, 0xD4, 0xE5, 0xF6])),
+            }
+        );
+        assert_eq!(
+            parse_connection_status(b"OK+CON:1"),
+            ConnectionStatus {
+                connected: true,
+                peer: None,
+            }
+        );
+        for response in [&b"OK+CON:0"[..], b"OK+CON:NONE", b"OK+CON:", b"ERROR", b""] {
+            assert_eq!(parse_connection_status(response), disconnected);
+        }
+    }
+
+    #[test]
+    fn an_echoed_query_is_not_a_connection() {
+        assert!(is_echo(b"AT+CON?", b"AT+CON?\r\n"));
+        assert!(!is_echo(b"OK+CON:1", b"AT+CON?\r\n"));
+        assert_eq!(
+            parse_connection_status(b"AT+CON?"),
+            ConnectionStatus {
+                connected: false,
+                peer: None,
+            }
+        );
+    }
+
+    #[test]
+    fn has_prefix_matches_any_of_the_expected_prefixes() {
+        let prefixes = &["OK+BAUD", "OK+Get:"];
+
+        assert!(has_prefix(b"OK+BAUD:115200", prefixes));
+        assert!(has_prefix(b"OK+Get:9600", prefixes));
+        assert!(!has_prefix(b"OK+NAME:DMZ Sound Booster", prefixes));
+        assert!(!has_prefix(b"OK", prefixes));
+        assert!(!has_prefix(&[0xFF, 0xFE], prefixes));
+    }
+
+    /// The address used by the device tables.
+    const ADDRESS: BtAddr = BtAddr([0xA1, 0xB2, 0xC3, 0xD4, 0xE5, 0xF6]);
+
+    /// Returns the device at `ADDRESS` with the given name.
+    fn device(name: Option<&str>) -> Option<ScannedDevice> {
+        Some(ScannedDevice {
+            address: ADDRESS,
+            name: name.map(|name| name.to_string()),
+        })
+    }
+
+    #[test]
+    fn parse_value_edge_inputs() {
+        let table: [(&[u8], Result<&str, ParseError>); 9] = [
+            (b"", Ok("")),
+            // Without a `:` the whole line is the value
+            (b"OK", Ok("OK")),
+            (b"OK+NAME:", Ok("")),
+            (b"OK+NAME:\xFF", Err(ParseError::InvalidUtf8)),
+            (b"OK+NAME:DMZ\r\n", Ok("DMZ")),
+            (b"\0\0OK+VER: V3.1 \r\n", Ok("V3.1")),
+            (b"OK+VER:V3.1\r\n\0\0", Ok("V3.1")),
+            // Only the text after the last `:` is kept
+            (b"OK+TIME:12:30", Ok("30")),
+            (b"OK+NAME:DMZ Sound Booster", Ok("DMZ Sound Booster")),
+        ];
+
+        for (response, expected) in table {
+            assert_eq!(parse_value(response), expected, "{:?}", response);
+        }
+    }
+
+    #[test]
+    fn parse_baudrate_edge_inputs() {
+        let table: [(&[u8], Result<u32, ParseError>); 10] = [
+            (b"", Err(ParseError::InvalidNumber)),
+            (b"OK+BAUD", Err(ParseError::InvalidNumber)),
+            (b"115200", Ok(115_200)),
+            (b"OK+BAUD:\xFF", Err(ParseError::InvalidUtf8)),
+            (b"OK+BAUD:4294967296", Err(ParseError::InvalidNumber)),
+            (b"OK+BAUD:4294967295", Ok(u32::MAX)),
+            (b"OK+BAUD:-9600", Err(ParseError::InvalidNumber)),
+            (b"\0\0OK+BAUD:9600\r\n", Ok(9600)),
+            (b"OK+BAUD: 38400 \r\n\0", Ok(38_400)),
+            (b"OK+BAUD:9600bps", Err(ParseError::InvalidNumber)),
+        ];
+
+        for (response, expected) in table {
+            assert_eq!(parse_baudrate(response), expected, "{:?}", response);
+        }
+    }
+
+    #[test]
+    fn parse_rssi_edge_inputs() {
+        let table: [(&[u8], Result<i8, ParseError>); 9] = [
+            (b"", Err(ParseError::InvalidNumber)),
+            (b"OK+RSSI", Err(ParseError::InvalidNumber)),
+            (b"OK+RSSI:\xC3", Err(ParseError::InvalidUtf8)),
+            (b"OK+RSSI:-129", Err(ParseError::InvalidNumber)),
+            (b"OK+RSSI:128", Err(ParseError::InvalidNumber)),
+            (b"OK+RSSI:-128", Ok(i8::MIN)),
+            (b"OK+RSSI:-67\r\n\0\0", Ok(-67)),
+            (b"\0OK+RSSI:-67\r\n", Ok(-67)),
+            (b"OK+RSSI:-67dBm", Err(ParseError::InvalidNumber)),
+        ];
+
+        for (response, expected) in table {
+            assert_eq!(parse_rssi(response), expected, "{:?}", response);
+        }
+    }
+
+    #[test]
+    fn parse_flag_edge_inputs() {
+        let table: [(&[u8], Result<bool, ParseError>); 9] = [
+            (b"", Err(ParseError::UnexpectedValue)),
+            (b"OK+NOTI", Err(ParseError::UnexpectedValue)),
+            (b"1", Ok(true)),
+            (b"OK+NOTI:\xFF", Err(ParseError::InvalidUtf8)),
+            (b"OK+NOTI:10", Err(ParseError::UnexpectedValue)),
+            (b"\0OK+NOTI:1\r\n", Ok(true)),
+            (b"OK+NOTI: 0 \r\n\0", Ok(false)),
+            (b"OK+NOTI:1x", Err(ParseError::UnexpectedValue)),
+            (b"OK+NOTI:true", Err(ParseError::UnexpectedValue)),
+        ];
+
+        for (response, expected) in table {
+            assert_eq!(parse_flag(response), expected, "{:?}", response);
+        }
+    }
+
+    #[test]
+    fn parse_pio_level_edge_inputs() {
+        let table: [(&[u8], Result<bool, ParseError>); 9] = [
+            (b"", Err(ParseError::UnexpectedValue)),
+            (b"OK+PIO", Err(ParseError::UnexpectedValue)),
+            (b"OK+PIO:4,\xFF", Err(ParseError::InvalidUtf8)),
+            (b"OK+PIO:4,2", Err(ParseError::UnexpectedValue)),
+            (b"OK+PIO:4,10", Err(ParseError::UnexpectedValue)),
+            (b"OK+PIO:4,1\r\n\0", Ok(true)),
+            (b"\0\0OK+PIO:4,0\r\n", Ok(false)),
+            (b"OK+PIO:4,1;", Err(ParseError::UnexpectedValue)),
+            (b"OK+PIO:4,", Err(ParseError::UnexpectedValue)),
+        ];
+
+        for (response, expected) in table {
+            assert_eq!(parse_pio_level(response), expected, "{:?}", response);
+        }
+    }
+
+    #[test]
+    fn parse_firmware_version_edge_inputs() {
+        let table: [(&str, Result<FirmwareVersion, ParseError>); 11] = [
+            ("", Err(ParseError::InvalidNumber)),
+            ("V", Err(ParseError::InvalidNumber)),
+            ("V3.1", Ok(FirmwareVersion::new(3, 1))),
+            ("v2", Ok(FirmwareVersion::new(2, 0))),
+            (" V3.1\r\n", Ok(FirmwareVersion::new(3, 1))),
+            ("V256.0", Err(ParseError::InvalidNumber)),
+            ("V3.256", Err(ParseError::InvalidNumber)),
+            ("V3.", Err(ParseError::InvalidNumber)),
+            ("V3.1a", Err(ParseError::InvalidNumber)),
+            ("V3.1.2", Err(ParseError::InvalidNumber)),
+            ("V-3.1", Err(ParseError::InvalidNumber)),
+        ];
+
+        for (version, expected) in table {
+            assert_eq!(parse_firmware_version(version), expected, "{:?}", version);
+        }
+    }
+
+    #[test]
+    fn parse_event_edge_inputs() {
+        let table: [(&[u8], Option<BtEvent>); 10] = [
+            (b"", None),
+            (b"OK+CONN", Some(BtEvent::Connected)),
+            (b"OK+CONNA", Some(BtEvent::Connected)),
+            (b"OK+LOST", Some(BtEvent::Disconnected)),
+            (b"\0OK+LOST\r\n", Some(BtEvent::Disconnected)),
+            (b"OK+CONN\r\n\0\0", Some(BtEvent::Connected)),
+            (b"OK+LOST\xFF", None),
+            (b"OK+CONNX", None),
+            (b"OK+CON:1", None),
+            (b"ok+lost", None),
+        ];
+
+        for (line, expected) in table {
+            assert_eq!(parse_event(line), expected, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn parse_peer_edge_inputs() {
+        let table: [(&[u8], Option<ScannedDevice>); 11] = [
+            (b"", None),
+            (b"OK+RNAME", None),
+            (b"OK+RNAME:NONE", None),
+            (b"OK+RNAME:A1B2C3D4E5F6,Pixel 7", device(Some("Pixel 7"))),
+            (
+                b"\0OK+RNAME:A1B2C3D4E5F6,Pixel 7\r\n\0",
+                device(Some("Pixel 7")),
+            ),
+            (b"OK+RNAME:A1B2C3D4E5F6", device(None)),
+            (b"OK+RNAME:A1B2C3D4E5F6,", device(None)),
+            (b"OK+RNAME:A1B2C3D4E5F6,\xFF", None),
+            (b"OK+RNAME:A1B2C3D4E5F6FF,Pixel 7", None),
+            (b"OK+RNAME:A1B2C3D4E5FG,Pixel 7", None),
+            (b"OK+RNAME:A1B2C3D4E5F6x,Pixel 7", None),
+        ];
+
+        for (response, expected) in table {
+            assert_eq!(parse_peer(response), expected, "{:?}", response);
+        }
+    }
+
+    #[test]
+    fn parse_scanned_device_edge_inputs() {
+        let table: [(&[u8], Option<ScannedDevice>); 11] = [
+            (b"", None),
+            (b"OK+DISC", None),
+            (b"OK+DISC:A1B2C3D4E5F6,My Phone", device(Some("My Phone"))),
+            (
+                b"+DISC:A1:B2:C3:D4:E5:F6,My Phone",
+                device(Some("My Phone")),
+            ),
+            (
+                b"\0OK+DISC:A1B2C3D4E5F6, My Phone \r\n",
+                device(Some("My Phone")),
+            ),
+            (b"OK+DISC:A1B2C3D4E5F6\r\n\0\0", device(None)),
+            (b"OK+DISC:A1B2C3D4E5F6,Caf\xC3", None),
+            (b"OK+DISC:A1B2C3D4E5F6FF", None),
+            (b"OK+DISC:A1B2C3D4E5", None),
+            (b"OK+DISC:A1B2C3D4E5F6x", None),
+            (b"OK+DISCS", None),
+        ];
+
+        for (line, expected) in table {
+            assert_eq!(parse_scanned_device(line), expected, "{:?}", line);
+        }
+    }
+}
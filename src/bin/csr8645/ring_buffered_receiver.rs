@@ -0,0 +1,129 @@
+#![no_std]
+#![no_main]
+
+use crate::csr8645::byte_channel::{ByteChannel, ReconfigureError};
+use defmt::warn;
+use embassy_stm32::usart::{
+    BasicInstance, Config, Error, RingBufferedUartRx, RxDma, TxDma, Uart, UartTx,
+};
+
+/// `RingBufferedReceiver` is a byte channel whose receiver drains a circular DMA buffer.
+///
+/// Once started, the DMA keeps filling the ring in the background, so the bytes arriving
+/// between two reads are kept instead of being lost while the next transfer is set up. This
+/// keeps the audio intake continuous. If the CPU falls behind and the DMA catches up with the
+/// unread bytes, the read fails with `Error::Overrun`, the overrun is counted, and reception
+/// restarts on the next read. Writes go through the TX DMA as well, so the executor keeps
+/// running while a command or a PCM frame is sent.
+pub struct RingBufferedReceiver<'d, T: BasicInstance, TxD, RxD: RxDma<T>> {
+    /// The transmitting half of the UART.
+    tx: UartTx<'d, T, TxD>,
+    /// The receiving half of the UART, filling the ring in the background.
+    rx: RingBufferedUartRx<'d, T, RxD>,
+    /// The configuration the UART currently runs with.
+    config: Config,
+    /// The number of times the ring overflowed since the receiver was created.
+    overruns: u32,
+}
+
+impl<'d, T: BasicInstance, TxD, RxD: RxDma<T>> RingBufferedReceiver<'d, T, TxD, RxD> {
+    /// Creates a new instance of `RingBufferedReceiver`.
+    ///
+    /// Reception starts with the first read.
+    ///
+    /// # Arguments
+    ///
+    /// * `uart` - The UART to split into its transmitting and ring-buffered receiving halves.
+    /// * `ring` - The buffer the DMA fills, large enough to hold the bytes arriving while the
+    ///   CPU is busy elsewhere.
+    /// * `config` - The configuration the UART was opened with.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `RingBufferedReceiver` instance.
+    pub fn new(uart: Uart<'d, T, TxD, RxD>, ring: &'d mut [u8], config: Config) -> Self {
+        let (tx, rx) = uart.split();
+
+        Self {
+            tx,
+            rx: rx.into_ring_buffered(ring),
+            config,
+            overruns: 0,
+        }
+    }
+
+    /// Reads the bytes available in the ring, counting the overruns.
+    ///
+    /// # Arguments
+    ///
+    /// * `buf` - The buffer where the received bytes will be stored.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the number of bytes received or an error.
+    async fn receive(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        match self.rx.read(buf).await {
+            Err(Error::Overrun) => {
+                self.overruns = self.overruns.saturating_add(1);
+                warn!("RX ring overrun, {} so far", self.overruns);
+                Err(Error::Overrun)
+            }
+            result => result,
+        }
+    }
+}
+
+impl<'d, T: BasicInstance, TxD: TxDma<T>, RxD: RxDma<T>> ByteChannel
+    for RingBufferedReceiver<'d, T, TxD, RxD>
+{
+    /// Writes all the given bytes through the TX DMA, waiting for the transfer to complete.
+    ///
+    /// The transfer is awaited rather than busy-waited on, so a write timeout can cancel it.
+    async fn write_some(&mut self, data: &[u8]) -> Result<usize, Error> {
+        self.tx.write(data).await?;
+        Ok(data.len())
+    }
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            filled += self.receive(&mut buf[filled..]).await?;
+        }
+        Ok(())
+    }
+
+    /// Reads the bytes received so far, waiting for the line to go idle or the ring to fill
+    /// halfway if there are none.
+    async fn read_until_idle(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        self.receive(buf).await
+    }
+
+    /// Discards the bytes in the ring by restarting reception.
+    fn flush_rx(&mut self) {
+        if let Err(e) = self.rx.start() {
+            warn!("Failed to restart the RX ring: {:?}", e);
+        }
+    }
+
+    /// Waits for the last byte to leave the shift register once the DMA transfer is done; this
+    /// takes at most one character time.
+    async fn flush_tx(&mut self) -> Result<(), Error> {
+        self.tx.blocking_flush()
+    }
+
+    /// Re-opens the UART at another baud rate, keeping the rest of its configuration.
+    ///
+    /// Both halves share the peripheral, so reconfiguring the receiver applies to the
+    /// transmitter as well. Reception restarts with the next read.
+    fn set_baudrate(&mut self, baudrate: u32) -> Result<(), ReconfigureError> {
+        let mut config = self.config;
+        config.baudrate = baudrate;
+        self.rx.set_config(&config)?;
+        self.config = config;
+        Ok(())
+    }
+
+    fn overruns(&self) -> u32 {
+        self.overruns
+    }
+}
@@ -0,0 +1 @@
+pub mod self_test;
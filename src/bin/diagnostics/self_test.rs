@@ -0,0 +1,79 @@
+#![no_std]
+#![no_main]
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use defmt::{error, info};
+
+/// `SelfTestReport` holds the outcome of the boot self-test of each subsystem.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SelfTestReport {
+    /// Whether the CSR8645 module answered `AT` over the UART.
+    pub uart_ok: bool,
+    /// Whether the CSR8645 module reported its firmware version.
+    pub bt_ok: bool,
+    /// Whether the OBD-II adapter answered `ATI`.
+    pub obd_ok: bool,
+    /// The versions reported and the failures encountered, one entry per check.
+    pub details: Vec<String>,
+}
+
+impl SelfTestReport {
+    /// Returns whether every subsystem passed.
+    pub fn all_ok(&self) -> bool {
+        self.uart_ok && self.bt_ok && self.obd_ok
+    }
+
+    /// Logs a summary of the report.
+    pub fn log(&self) {
+        let status = |ok: bool| if ok { "pass" } else { "FAIL" };
+        info!(
+            "Self-test: UART {=str}, Bluetooth {=str}, OBD-II {=str}",
+            status(self.uart_ok),
+            status(self.bt_ok),
+            status(self.obd_ok)
+        );
+
+        for detail in &self.details {
+            if self.all_ok() {
+                info!("  {=str}", detail.as_str());
+            } else {
+                error!("  {=str}", detail.as_str());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_ok_requires_every_subsystem_to_pass() {
+        let passed = SelfTestReport {
+            uart_ok: true,
+            bt_ok: true,
+            obd_ok: true,
+            details: Vec::new(),
+        };
+        assert!(passed.all_ok());
+
+        for failed in [
+            SelfTestReport {
+                uart_ok: false,
+                ..passed.clone()
+            },
+            SelfTestReport {
+                bt_ok: false,
+                ..passed.clone()
+            },
+            SelfTestReport {
+                obd_ok: false,
+                ..passed.clone()
+            },
+        ] {
+            assert!(!failed.all_ok(), "{:?}", failed);
+        }
+        assert!(!SelfTestReport::default().all_ok());
+    }
+}
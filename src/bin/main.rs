@@ -1,73 +1,277 @@
 #![no_std]
 #![no_main]
+// The module files repeat `#![no_std]` and `#![no_main]`, which only take effect at the crate
+// root; their warnings are allowed so that clippy -D warnings passes
+#![allow(unused_attributes)]
 
 extern crate alloc;
 
-use defmt::{error, info};
+use core::cell::RefCell;
+use defmt::{error, info, warn};
 use defmt_rtt as _;
 use embassy_executor::Spawner;
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::flash::{Blocking, Flash};
+use embassy_stm32::gpio::Pull;
 use embassy_stm32::init;
-use embassy_stm32::Config;
+use embassy_stm32::peripherals::{DMA1_CH5, DMA1_CH6, DMA2_CH2, DMA2_CH7, USART1, USART2};
+use embassy_stm32::rtc::{Rtc, RtcConfig};
+use embassy_stm32::usart::{self, Uart};
+use embassy_stm32::{bind_interrupts, Config};
+use embassy_sync::mutex::Mutex;
+use embassy_time::{with_timeout, Duration, Timer};
 use panic_probe as _;
+use static_cell::StaticCell;
 
+mod app;
 mod audio;
 mod bluetooth;
+mod config;
 mod csr8645;
+mod diagnostics;
+mod obd;
+mod storage;
+mod telemetry;
 mod uart;
 
+use app::{
+    App, AUDIO_LINK_STATE, AUDIO_REDLINE, AUDIO_UPDATES, BT_EVENTS, CALIBRATION_REQUESTED,
+    IGNITION_OFF, PRESET_BUTTON_PRESSED, VOLUME_TRIM_STEPS,
+};
+use audio::audio_controller::AudioController;
+use audio::audio_preset::PresetManager;
+use audio::audio_service::AudioServiceImpl;
+use audio::engine_tone::{EngineTone, FOUR_STROKE_FIRING_FACTOR};
+use audio::redline_warning::RedlineWarning;
+use audio::sample_format::AudioFormat;
 use bluetooth::bluetooth_controller::BluetoothController;
-use csr8645::csr8645::Csr8645;
+use bluetooth::bluetooth_service::BluetoothServiceImpl;
+use bluetooth::event_bus;
+use config::app_config::{AppConfig, MAX_CSR8645_RX_RING_LEN};
+use csr8645::byte_channel::UartChannel;
+use csr8645::csr8645::{ConnectionState, Csr8645, Csr8645Exchange, SharedCsr8645};
+use csr8645::ring_buffered_receiver::RingBufferedReceiver;
 use obd::obd_controller::ObdController;
+use obd::obd_service::{ObdError, ObdServiceImpl};
+use storage::config_store::{ConfigStore, SharedConfigStore};
+use uart::uart_service::Irqs as Usart1Irqs;
 
-/// The `App` struct represents the main application.
+bind_interrupts!(struct Usart2Irqs {
+    USART2 => usart::InterruptHandler<USART2>;
+});
+
+/// How long the dashboard button is held to start a calibration instead of switching presets.
+const LONG_PRESS: Duration = Duration::from_secs(2);
+
+/// The highest gain the engine tone is mixed at.
+const ENGINE_TONE_GAIN_CEILING: f32 = 0.5;
+
+/// The time the audio task waits before the next frame after a failed transmission.
+const AUDIO_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The ring the DMA fills with the bytes received from the CSR8645 module.
+///
+/// Only the first `AppConfig::csr8645_rx_ring_len` bytes are handed to the DMA.
+static CSR8645_RX_RING: StaticCell<[u8; MAX_CSR8645_RX_RING_LEN]> = StaticCell::new();
+
+/// The CSR8645 module, shared between the Bluetooth and audio services.
+static CSR8645: StaticCell<SharedCsr8645<'static>> = StaticCell::new();
+
+/// The internal flash, holding the configuration records.
+static FLASH: StaticCell<Flash<'static, Blocking>> = StaticCell::new();
+
+/// Holds the configuration store shared by the app and the Bluetooth controller.
+static CONFIG_STORE: StaticCell<SharedConfigStore<'static>> = StaticCell::new();
+
+/// The Bluetooth controller, shared by the app and the behavior sink.
+static BLUETOOTH: StaticCell<BluetoothController<'static, BluetoothServiceImpl<'static>>> =
+    StaticCell::new();
+
+/// Publishes the link notifications of the CSR8645 module on `BT_EVENTS`.
+///
+/// # Arguments
 ///
-/// It contains all the components of the application, such as the Bluetooth module and the OBD-II Module.
-struct App<'a> {
-    bluetooth_module: BluetoothController<'a, Csr8645>,
-    obd_module: ObdController,
+/// * `csr8645` - The shared CSR8645 module.
+#[embassy_executor::task]
+async fn bt_events(csr8645: &'static SharedCsr8645<'static>) {
+    event_bus::publish_events(&BluetoothServiceImpl::new(csr8645), &BT_EVENTS).await
 }
 
-impl<'a> App<'a> {
-    /// Creates a new `App` instance.
-    ///
-    /// Initializes the Bluetooth module and the OBD-II device.
-    ///
-    /// # Arguments
-    ///
-    /// * `bluetooth_module` - An instance of `BluetoothController`.
-    /// * `obd_module` - An instance of `ObdController`.
-    fn new(bluetooth_module: BluetoothController<'a, Csr8645>, obd_module: ObdController) -> Self {
-        Self {
-            bluetooth_module,
-            obd_module,
+/// Pumps the audio through the CSR8645 module, following the updates sent by the app.
+///
+/// The transmission paces itself to the frame period, so the updates are picked up once per
+/// frame.
+///
+/// # Arguments
+///
+/// * `audio_module` - The audio pipeline.
+#[embassy_executor::task]
+async fn audio_pump(mut audio_module: AudioController<'static, AudioServiceImpl<'static>>) {
+    loop {
+        if let Some(update) = AUDIO_UPDATES.try_take() {
+            audio_module.apply_behavior(update.behavior, update.rpm);
+            audio_module.set_speed(update.speed);
+        }
+        if let Some(state) = AUDIO_LINK_STATE.try_take() {
+            audio_module.set_connection_state(state);
+        }
+        if let Some(redline) = AUDIO_REDLINE.try_take() {
+            audio_module.set_redline(redline);
         }
-    }
 
-    /// Runs the main logic of the application.
-    ///
-    /// In a loop, it reads the speed and RPM data from the OBD-II device, determines how to alter the audio behavior based on this data, and then alters the audio behavior.
-    async fn run(&self) {
-        loop {
-            let speed = self.obd_module.read_speed().await;
-            let rpm = self.obd_module.read_rpm().await;
-            let audio_behavior = map_sensor_data_to_audio_behavior(speed, rpm);
-            self.bluetooth_module
-                .alter_behavior(audio_behavior)
-                .await
-                .unwrap();
+        if let Err(e) = audio_module.handle_audio_transmission().await {
+            error!("Failed to transmit audio: {:?}", e);
+            Timer::after(AUDIO_ERROR_BACKOFF).await;
         }
     }
 }
 
+/// Brings up the modules and runs the app.
+///
+/// # Arguments
+///
+/// * `spawner` - The spawner starting the Bluetooth event and audio tasks.
+/// * `csr8645_channel` - The byte channel over the UART wired to the CSR8645 module.
+/// * `obd_channel` - The byte channel over the UART wired to the ELM327 adapter.
+/// * `config_store` - The store persisting the settings across power cycles.
+/// * `rtc` - The real-time clock driving the volume schedule.
+/// * `config` - The settings of the firmware, also used to open both UARTs.
 #[embassy_executor::task]
-async fn run_app() {
-    let csr8645 = Csr8645::new().unwrap();
-    let bluetooth_module = BluetoothController::new(csr8645);
-    let obd_module = ObdController::new();
-    let app = App::new(bluetooth_module, obd_module);
+async fn run_app(
+    spawner: Spawner,
+    csr8645_channel: RingBufferedReceiver<'static, USART1, DMA2_CH7, DMA2_CH2>,
+    obd_channel: UartChannel<'static, USART2, DMA1_CH6, DMA1_CH5>,
+    config_store: ConfigStore<'static>,
+    rtc: Rtc,
+    config: AppConfig,
+) {
+    // The UART was opened at the rate the module is configured to
+    let mut csr8645_driver = Csr8645::new(csr8645_channel, config.csr8645_init.baudrate).unwrap();
+    csr8645_driver.set_retry_policy(config.csr8645_retry_policy);
+    csr8645_driver.set_command_pacing(config.csr8645_pacing);
+    csr8645_driver.set_connect_timeout(config.connect_timeout);
+    let csr8645 = CSR8645.init(Mutex::new(csr8645_driver));
+    if let Err(e) = Csr8645Exchange::lock(csr8645)
+        .await
+        .initialize(&config.csr8645_init)
+        .await
+    {
+        error!("Failed to initialize the CSR8645 module: {:?}", e);
+    }
+    let config_store = CONFIG_STORE.init(RefCell::new(config_store));
+    let bluetooth_module = BLUETOOTH.init(BluetoothController::new(
+        BluetoothServiceImpl::new(csr8645),
+        config_store,
+    ));
+    match bluetooth_module.auto_reconnect().await {
+        Ok(true) => {
+            info!("Reconnected to the last device");
+            AUDIO_LINK_STATE.signal(ConnectionState::Connected);
+        }
+        Ok(false) => info!("Waiting for a device to connect"),
+        Err(e) => error!("Failed to reconnect to the last device: {:?}", e),
+    }
+    if let Err(e) = spawner.spawn(bt_events(csr8645)) {
+        error!("Failed to start Bluetooth event task: {:?}", e);
+    }
+    let engine_tone = EngineTone::new(
+        AudioFormat::A2DP_STEREO,
+        config.engine_cylinders,
+        FOUR_STROKE_FIRING_FACTOR,
+        ENGINE_TONE_GAIN_CEILING,
+    );
+    let mut audio_module = AudioController::new(AudioServiceImpl::new(csr8645), engine_tone);
+    audio_module.set_idle_timeout(config.idle_timeout);
+    if let Some(warning_config) = config.redline_warning {
+        // Relative to the learned rev ceiling, or the one of the engine type until calibrated
+        let profile = config_store.borrow().vehicle_profile();
+        let redline = profile.unwrap_or(config.mapping.vehicle).max_rpm;
+        audio_module.set_redline_warning(Some(RedlineWarning::new(
+            AudioFormat::A2DP_STEREO,
+            redline,
+            warning_config,
+        )));
+    }
+    if let Err(e) = spawner.spawn(audio_pump(audio_module)) {
+        error!("Failed to start audio task: {:?}", e);
+    }
+    let mut obd_module = ObdController::new(ObdServiceImpl::new(obd_channel));
+    match obd_module.init().await {
+        Ok(()) => info!("OBD-II adapter ready"),
+        Err(ObdError::VehicleOff) => warn!("OBD-II adapter found, but the vehicle is off"),
+        Err(e) => error!("Failed to initialize the OBD-II adapter: {:?}", e),
+    }
+    obd_module.set_poll_schedule(config.poll_schedule.clone());
+    let preset_manager = PresetManager::new(config.default_preset);
+    // The controller is also the behavior sink, so the mute, volume and trim state stay in sync
+    let mut app = App::new(
+        bluetooth_module,
+        bluetooth_module,
+        obd_module,
+        preset_manager,
+        rtc,
+        config_store,
+        &config,
+    );
+    if !app.self_test().await.all_ok() {
+        warn!("Self-test failed, running degraded");
+    }
     app.run().await;
 }
 
+/// Waits for presses of the dashboard button and notifies the app.
+///
+/// A short press switches presets, while holding the button for `LONG_PRESS` requests a
+/// calibration of the vehicle profile.
+///
+/// # Arguments
+///
+/// * `button` - The EXTI input the button is wired to.
+#[embassy_executor::task]
+async fn preset_button(mut button: ExtiInput<'static>) {
+    loop {
+        button.wait_for_rising_edge().await;
+        match with_timeout(LONG_PRESS, button.wait_for_falling_edge()).await {
+            Ok(()) => PRESET_BUTTON_PRESSED.signal(()),
+            Err(_) => {
+                CALIBRATION_REQUESTED.signal(());
+                button.wait_for_falling_edge().await;
+            }
+        }
+    }
+}
+
+/// Waits for presses of a volume trim button and passes its step on to the app.
+///
+/// Steps pressed before the app gets to them add up, so quick presses are not lost.
+///
+/// # Arguments
+///
+/// * `button` - The EXTI input the button is wired to.
+/// * `step` - The change of the volume trim per press, in levels.
+#[embassy_executor::task(pool_size = 2)]
+async fn trim_button(mut button: ExtiInput<'static>, step: i8) {
+    loop {
+        button.wait_for_rising_edge().await;
+        let pending = VOLUME_TRIM_STEPS.try_take().unwrap_or(0);
+        VOLUME_TRIM_STEPS.signal(pending.saturating_add(step));
+    }
+}
+
+/// Waits for the ignition to be switched off and notifies the app.
+///
+/// # Arguments
+///
+/// * `ignition` - The EXTI input the ignition sense line is wired to, high while the ignition
+///   is on.
+#[embassy_executor::task]
+async fn ignition_sense(mut ignition: ExtiInput<'static>) {
+    loop {
+        ignition.wait_for_falling_edge().await;
+        IGNITION_OFF.signal(());
+    }
+}
+
 /// The `main` function is the main entry point for the application.
 ///
 /// It initializes the peripherals, creates a new `App` instance, and runs the main logic of the application.
@@ -85,13 +289,79 @@ async fn run_app() {
 async fn main(spawner: Spawner) {
     let config = Config::default();
 
-    if let Err(e) = init(config) {
-        error!("Failed to initialize peripherals: {:?}", e);
-        return;
-    };
+    let p = init(config);
     info!("Peripherals initialized successfully");
 
-    if let Err(e) = spawner.spawn(run_app()).unwrap() {
+    let button = ExtiInput::new(p.PC13, p.EXTI13, Pull::Down);
+    if let Err(e) = spawner.spawn(preset_button(button)) {
+        error!("Failed to start preset button task: {:?}", e);
+    }
+
+    let trim_up = ExtiInput::new(p.PC2, p.EXTI2, Pull::Down);
+    if let Err(e) = spawner.spawn(trim_button(trim_up, 1)) {
+        error!("Failed to start trim up button task: {:?}", e);
+    }
+
+    let trim_down = ExtiInput::new(p.PC3, p.EXTI3, Pull::Down);
+    if let Err(e) = spawner.spawn(trim_button(trim_down, -1)) {
+        error!("Failed to start trim down button task: {:?}", e);
+    }
+
+    let ignition = ExtiInput::new(p.PA0, p.EXTI0, Pull::None);
+    if let Err(e) = spawner.spawn(ignition_sense(ignition)) {
+        error!("Failed to start ignition sense task: {:?}", e);
+    }
+
+    let app_config = AppConfig::default();
+
+    let mut csr8645_config = usart::Config::default();
+    csr8645_config.baudrate = app_config.csr8645_init.baudrate;
+    let csr8645_uart = match Uart::new(
+        p.USART1,
+        p.PA10,
+        p.PA9,
+        Usart1Irqs,
+        p.DMA2_CH7,
+        p.DMA2_CH2,
+        csr8645_config,
+    ) {
+        Ok(uart) => uart,
+        Err(e) => {
+            error!("Failed to initialize the CSR8645 UART: {:?}", e);
+            return;
+        }
+    };
+    let ring = CSR8645_RX_RING.init([0; MAX_CSR8645_RX_RING_LEN]);
+    let ring_len = app_config
+        .csr8645_rx_ring_len
+        .clamp(1, MAX_CSR8645_RX_RING_LEN);
+    let csr8645_channel =
+        RingBufferedReceiver::new(csr8645_uart, &mut ring[..ring_len], csr8645_config);
+
+    let mut obd_config = usart::Config::default();
+    obd_config.baudrate = app_config.obd_baudrate;
+    // Both directions go through DMA, which the async byte channel of the adapter relies on
+    let obd_uart = match Uart::new(
+        p.USART2, p.PD6, p.PD5, Usart2Irqs, p.DMA1_CH6, p.DMA1_CH5, obd_config,
+    ) {
+        Ok(uart) => uart,
+        Err(e) => {
+            error!("Failed to initialize the OBD-II UART: {:?}", e);
+            return;
+        }
+    };
+    let obd_channel = UartChannel::new(obd_uart, obd_config);
+
+    let config_store = ConfigStore::new(FLASH.init(Flash::new_blocking(p.FLASH)));
+    let rtc = Rtc::new(p.RTC, RtcConfig::default());
+    if let Err(e) = spawner.spawn(run_app(
+        spawner,
+        csr8645_channel,
+        obd_channel,
+        config_store,
+        rtc,
+        app_config,
+    )) {
         error!("Failed to run app: {:?}", e);
     }
     info!("App started successfully");
@@ -0,0 +1,172 @@
+#![no_std]
+#![no_main]
+
+use embassy_time::{Duration, Instant};
+
+/// Represents the state of the engine, derived from the engine speed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, defmt::Format)]
+pub enum EngineState {
+    /// The engine is not turning.
+    #[default]
+    Off,
+    /// The starter is turning the engine, which has not caught yet.
+    Cranking,
+    /// The engine runs on its own.
+    Running,
+}
+
+/// `EngineStateConfig` holds the thresholds used to track the engine state.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct EngineStateConfig {
+    /// The engine speed, in revolutions per minute, from which a starting engine is running.
+    pub running_rpm: u16,
+    /// The time the engine speed must stay at zero before the engine is considered off.
+    pub off_after: Duration,
+}
+
+impl Default for EngineStateConfig {
+    fn default() -> Self {
+        Self {
+            running_rpm: 500,
+            off_after: Duration::from_secs(3),
+        }
+    }
+}
+
+/// `EngineStateTracker` tells whether the engine is running, cranking or off.
+///
+/// A zero engine speed must last for the debounce period before the engine is considered off,
+/// so a momentary stall or a dropped reading does not switch the app to its parked behavior.
+/// Starting from off, a non-zero engine speed below `running_rpm` is the starter cranking the
+/// engine, which is running once it passes that speed.
+pub struct EngineStateTracker {
+    /// The thresholds used to track the engine state.
+    config: EngineStateConfig,
+    /// The current engine state.
+    state: EngineState,
+    /// The time at which the engine speed dropped to zero, if it is zero.
+    stopped_since: Option<Instant>,
+}
+
+impl EngineStateTracker {
+    /// Creates a new instance of `EngineStateTracker`, starting with the engine off.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The thresholds used to track the engine state.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `EngineStateTracker` instance.
+    pub fn new(config: EngineStateConfig) -> Self {
+        Self {
+            config,
+            state: EngineState::Off,
+            stopped_since: None,
+        }
+    }
+
+    /// Returns the current engine state.
+    pub fn state(&self) -> EngineState {
+        self.state
+    }
+
+    /// Feeds the latest engine speed reading.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The time the reading was taken.
+    /// * `rpm` - The engine speed, in revolutions per minute.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<EngineState>` - The new engine state, or `None` if it did not change.
+    pub fn update(&mut self, now: Instant, rpm: u16) -> Option<EngineState> {
+        let state = if rpm == 0 {
+            let stopped_since = *self.stopped_since.get_or_insert(now);
+            if now - stopped_since >= self.config.off_after {
+                EngineState::Off
+            } else {
+                self.state
+            }
+        } else {
+            self.stopped_since = None;
+            match self.state {
+                EngineState::Running => EngineState::Running,
+                _ if rpm >= self.config.running_rpm => EngineState::Running,
+                _ => EngineState::Cranking,
+            }
+        };
+
+        if state == self.state {
+            return None;
+        }
+        self.state = state;
+        Some(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the time at the given number of milliseconds.
+    fn at(millis: u64) -> Instant {
+        Instant::from_millis(millis)
+    }
+
+    /// Returns a tracker whose engine is already running.
+    fn running_tracker() -> EngineStateTracker {
+        let mut tracker = EngineStateTracker::new(EngineStateConfig::default());
+        assert_eq!(tracker.update(at(0), 800), Some(EngineState::Running));
+        tracker
+    }
+
+    #[test]
+    fn a_clean_shutdown_turns_the_engine_off_after_the_debounce_period() {
+        let mut tracker = running_tracker();
+
+        assert_eq!(tracker.update(at(1000), 0), None);
+        assert_eq!(tracker.update(at(3999), 0), None);
+        assert_eq!(tracker.state(), EngineState::Running);
+        assert_eq!(tracker.update(at(4000), 0), Some(EngineState::Off));
+        assert_eq!(tracker.update(at(5000), 0), None);
+    }
+
+    #[test]
+    fn a_momentary_stall_keeps_the_engine_running() {
+        let mut tracker = running_tracker();
+
+        assert_eq!(tracker.update(at(1000), 0), None);
+        assert_eq!(tracker.update(at(2500), 0), None);
+        assert_eq!(tracker.update(at(3000), 300), None);
+        // The debounce restarts from the next zero reading
+        assert_eq!(tracker.update(at(4500), 0), None);
+        assert_eq!(tracker.update(at(7000), 0), None);
+        assert_eq!(tracker.state(), EngineState::Running);
+    }
+
+    #[test]
+    fn a_crank_start_goes_through_cranking_to_running() {
+        let mut tracker = EngineStateTracker::new(EngineStateConfig::default());
+        assert_eq!(tracker.state(), EngineState::Off);
+
+        assert_eq!(tracker.update(at(0), 0), None);
+        assert_eq!(tracker.update(at(100), 200), Some(EngineState::Cranking));
+        // The engine speed briefly dips to zero while the starter turns
+        assert_eq!(tracker.update(at(200), 0), None);
+        assert_eq!(tracker.update(at(300), 250), None);
+        assert_eq!(tracker.update(at(400), 900), Some(EngineState::Running));
+        // A running engine idling low is still running
+        assert_eq!(tracker.update(at(500), 450), None);
+    }
+
+    #[test]
+    fn a_failed_crank_turns_the_engine_off_again() {
+        let mut tracker = EngineStateTracker::new(EngineStateConfig::default());
+
+        assert_eq!(tracker.update(at(0), 200), Some(EngineState::Cranking));
+        assert_eq!(tracker.update(at(500), 0), None);
+        assert_eq!(tracker.update(at(3500), 0), Some(EngineState::Off));
+    }
+}
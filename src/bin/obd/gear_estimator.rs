@@ -0,0 +1,142 @@
+#![no_std]
+#![no_main]
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Represents the gear the vehicle is estimated to be in.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub enum Gear {
+    /// No gear is engaged, or the clutch is disengaged.
+    Neutral,
+    /// A forward gear, starting at 1.
+    Forward(u8),
+}
+
+/// `GearBand` is the range of speed to RPM ratios, in km/h per 1000 RPM, matching one gear.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct GearBand {
+    /// The lowest ratio of the band, inclusive.
+    pub min_ratio: f32,
+    /// The highest ratio of the band, exclusive.
+    pub max_ratio: f32,
+}
+
+/// `GearEstimator` infers the engaged gear from the vehicle speed and the engine speed.
+///
+/// Many vehicles do not report the gear over OBD-II, but each gear has a nearly fixed ratio
+/// between road speed and engine speed.
+pub struct GearEstimator {
+    /// The ratio bands of the gears, from first gear upwards.
+    bands: Vec<GearBand>,
+}
+
+impl Default for GearEstimator {
+    fn default() -> Self {
+        Self::new(vec![
+            GearBand { min_ratio: 5.0, max_ratio: 11.0 },
+            GearBand { min_ratio: 11.0, max_ratio: 17.5 },
+            GearBand { min_ratio: 17.5, max_ratio: 24.5 },
+            GearBand { min_ratio: 24.5, max_ratio: 31.5 },
+            GearBand { min_ratio: 31.5, max_ratio: 38.5 },
+            GearBand { min_ratio: 38.5, max_ratio: 50.0 },
+        ])
+    }
+}
+
+impl GearEstimator {
+    /// Creates a new instance of `GearEstimator`.
+    ///
+    /// # Arguments
+    ///
+    /// * `bands` - The ratio bands of the gears, from first gear upwards.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `GearEstimator` instance.
+    pub fn new(bands: Vec<GearBand>) -> Self {
+        Self { bands }
+    }
+
+    /// Estimates the engaged gear.
+    ///
+    /// At idle, with the vehicle stopped or the engine not turning, the gear is reported as
+    /// neutral. A ratio outside every band, such as while the clutch slips, is also neutral.
+    ///
+    /// # Arguments
+    ///
+    /// * `speed` - The vehicle speed, in km/h.
+    /// * `rpm` - The engine speed, in revolutions per minute.
+    ///
+    /// # Returns
+    ///
+    /// * `Gear` - The estimated gear.
+    pub fn estimate(&self, speed: u8, rpm: u16) -> Gear {
+        if speed == 0 || rpm == 0 {
+            return Gear::Neutral;
+        }
+
+        let ratio = speed as f32 * 1000.0 / rpm as f32;
+
+        self.bands
+            .iter()
+            .position(|band| ratio >= band.min_ratio && ratio < band.max_ratio)
+            .map(|index| Gear::Forward(index as u8 + 1))
+            .unwrap_or(Gear::Neutral)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speed_and_rpm_pairs_map_to_their_gear() {
+        let estimator = GearEstimator::default();
+        let pairs = [
+            (20, 3000, Gear::Forward(1)),
+            (40, 3000, Gear::Forward(2)),
+            (60, 3000, Gear::Forward(3)),
+            (80, 3000, Gear::Forward(4)),
+            (100, 3000, Gear::Forward(5)),
+            (120, 2800, Gear::Forward(6)),
+        ];
+
+        for (speed, rpm, gear) in pairs {
+            assert_eq!(
+                estimator.estimate(speed, rpm),
+                gear,
+                "{} km/h at {} rpm",
+                speed,
+                rpm
+            );
+        }
+    }
+
+    #[test]
+    fn a_band_includes_its_lowest_ratio() {
+        let estimator = GearEstimator::default();
+
+        assert_eq!(estimator.estimate(11, 1000), Gear::Forward(2));
+        assert_eq!(estimator.estimate(5, 1000), Gear::Forward(1));
+    }
+
+    #[test]
+    fn idling_is_neutral() {
+        let estimator = GearEstimator::default();
+
+        // Stopped with the engine idling, and rolling with the engine off
+        assert_eq!(estimator.estimate(0, 800), Gear::Neutral);
+        assert_eq!(estimator.estimate(30, 0), Gear::Neutral);
+        assert_eq!(estimator.estimate(0, 0), Gear::Neutral);
+    }
+
+    #[test]
+    fn a_ratio_outside_every_band_is_neutral() {
+        let estimator = GearEstimator::default();
+
+        // Slipping the clutch at pull away, and coasting with the clutch down
+        assert_eq!(estimator.estimate(3, 3000), Gear::Neutral);
+        assert_eq!(estimator.estimate(150, 2000), Gear::Neutral);
+    }
+}
@@ -0,0 +1,9 @@
+pub mod engine_state;
+pub mod gear_estimator;
+pub mod obd_controller;
+pub mod obd_service;
+pub mod pid_registry;
+pub mod poll_schedule;
+#[cfg(feature = "simulation")]
+pub mod simulated_obd;
+pub mod vehicle_profile;
@@ -0,0 +1,2025 @@
+#![no_std]
+#![no_main]
+
+use crate::obd::obd_service::{ErrorContext, ObdError, ObdService};
+use crate::obd::pid_registry::{PidDefinition, DEFAULT_PID_DEFINITIONS};
+use crate::obd::poll_schedule::PollSchedule;
+use crate::obd::vehicle_profile::VehicleProfile;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use defmt::{info, warn};
+use embassy_time::{with_timeout, Duration, Instant, Ticker, Timer};
+use futures::stream::{self, Stream};
+
+/// The OBD-II mode used to request current data.
+const MODE_CURRENT_DATA: u8 = 0x01;
+
+/// The OBD-II mode used to request freeze frame data.
+const MODE_FREEZE_FRAME: u8 = 0x02;
+
+/// The freeze frame read by `read_freeze_frame`, the one stored with the first DTC.
+const FREEZE_FRAME_NUMBER: u8 = 0x00;
+
+/// The offset added to the mode in the echo of a positive response.
+const RESPONSE_MODE_OFFSET: u8 = 0x40;
+
+/// The number of times `init` sends `ATZ` before concluding that no adapter is present.
+const RESET_ATTEMPTS: u8 = 3;
+
+/// The time the adapter is given to answer `ATZ`, which reboots it.
+const RESET_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The time between two RPM samples taken while calibrating.
+const CALIBRATION_SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The lengths, in hex digits, of the CAN headers accepted by `ATSH`: 11-bit, 29-bit without
+/// priority and 29-bit with priority.
+const HEADER_LENGTHS: [usize; 3] = [3, 6, 8];
+
+/// The largest number of flow control data bytes accepted by `ATFCSD`.
+const MAX_FLOW_CONTROL_DATA: usize = 5;
+
+/// The highest flow control mode accepted by `ATFCSM`.
+const MAX_FLOW_CONTROL_MODE: u8 = 2;
+
+/// The PID of the vehicle speed, in km/h.
+pub const PID_SPEED: u8 = 0x0D;
+
+/// The PID of the engine speed, in revolutions per minute.
+pub const PID_RPM: u8 = 0x0C;
+
+/// The PID of the engine coolant temperature, in degrees Celsius.
+pub const PID_COOLANT_TEMP: u8 = 0x05;
+
+/// The offset subtracted from the raw coolant temperature byte.
+const COOLANT_TEMP_OFFSET: i16 = 40;
+
+/// The PID of the throttle position, in percent.
+pub const PID_THROTTLE: u8 = 0x11;
+
+/// The PID of the mass air flow rate, in grams per second.
+pub const PID_MAF: u8 = 0x10;
+
+/// The duration of one `ATST` timeout unit, in microseconds.
+const TIMEOUT_UNIT_US: u32 = 4096;
+
+/// The most `ATST` units the timeout can be set to.
+const MAX_TIMEOUT_UNITS: u32 = 0xFF;
+
+/// The most data bytes carried by a classic CAN frame.
+const MAX_CAN_DATA: usize = 8;
+
+/// The lines with which the adapter reports that it stopped monitoring by itself.
+const MONITOR_STOPPED: [&str; 2] = ["STOPPED", "BUFFER FULL"];
+
+/// Represents a range of 32 PIDs whose support is reported by a single query.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub enum PidRange {
+    /// PIDs 0x01 to 0x20, reported by PID 0x00.
+    Pids01To20,
+    /// PIDs 0x21 to 0x40, reported by PID 0x20.
+    Pids21To40,
+    /// PIDs 0x41 to 0x60, reported by PID 0x40.
+    Pids41To60,
+}
+
+impl PidRange {
+    /// All the ranges, in query order.
+    pub const ALL: [PidRange; 3] = [
+        PidRange::Pids01To20,
+        PidRange::Pids21To40,
+        PidRange::Pids41To60,
+    ];
+
+    /// Returns the PID used to query the support of this range.
+    pub fn query_pid(&self) -> u8 {
+        match self {
+            PidRange::Pids01To20 => 0x00,
+            PidRange::Pids21To40 => 0x20,
+            PidRange::Pids41To60 => 0x40,
+        }
+    }
+}
+
+/// `PidBitmap` holds the support bitmap of a range of 32 PIDs.
+///
+/// The most significant bit corresponds to the first PID of the range.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct PidBitmap {
+    /// The range described by this bitmap.
+    pub range: PidRange,
+    /// The raw support bits.
+    pub bits: u32,
+}
+
+impl PidBitmap {
+    /// Decodes a support bitmap from the four data bytes of the response.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The range described by the bitmap.
+    /// * `data` - The data bytes of the response, without the mode and PID echo.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the decoded bitmap or an error if fewer than four bytes were given.
+    pub fn decode(range: PidRange, data: &[u8]) -> Result<Self, ObdError> {
+        let [a, b, c, d, ..] = *data else {
+            return Err(ObdError::Malformed(ErrorContext::new(
+                MODE_CURRENT_DATA,
+                range.query_pid(),
+                data,
+            )));
+        };
+
+        let bits = u32::from_be_bytes([a, b, c, d]);
+        Ok(Self { range, bits })
+    }
+
+    /// Checks whether the bitmap covers the given PID.
+    pub fn covers(&self, pid: u8) -> bool {
+        let base = self.range.query_pid();
+        pid > base && pid <= base + 0x20
+    }
+
+    /// Checks whether the given PID is supported.
+    ///
+    /// # Arguments
+    ///
+    /// * `pid` - The PID to check.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the bitmap covers the PID and marks it supported, false otherwise.
+    pub fn is_supported(&self, pid: u8) -> bool {
+        if !self.covers(pid) {
+            return false;
+        }
+
+        let offset = pid - self.range.query_pid() - 1;
+        self.bits & (1 << (31 - offset)) != 0
+    }
+}
+
+/// `AdapterInfo` describes the ELM327 adapter and the optional features it implements.
+///
+/// Clones report a genuine-looking version but often leave commands out, so features are
+/// probed rather than inferred from the version.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdapterInfo {
+    /// The version reported by `ATI`, e.g. `ELM327 v1.5`.
+    pub version: String,
+    /// The device description set with `AT@2` and reported by `AT@1`, if implemented.
+    pub description: Option<String>,
+    /// Whether the adapter accepts `ATH1`, required to address a specific ECU.
+    pub headers: bool,
+    /// Whether the adapter accepts `ATAL`, allowing messages longer than 7 bytes.
+    pub long_messages: bool,
+}
+
+/// Parses the response to `AT@1`.
+///
+/// # Arguments
+///
+/// * `response` - The response text.
+///
+/// # Returns
+///
+/// * `Option<String>` - The device description, or `None` if the adapter rejected the command
+///   with `?` or has no description.
+fn parse_description(response: &str) -> Option<String> {
+    let description = response.trim();
+    if description.is_empty() || description == "?" {
+        return None;
+    }
+
+    Some(description.to_string())
+}
+
+/// `CanFrame` is a frame received from the CAN bus while monitoring it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CanFrame {
+    /// The identifier of the frame, 11 or 29 bits long.
+    pub id: u32,
+    /// The data bytes of the frame, up to `MAX_CAN_DATA`.
+    pub data: Vec<u8>,
+}
+
+/// Parses a frame printed by the adapter in monitor mode with headers on.
+///
+/// 11-bit identifiers are printed as 3 hex digits, e.g. `7E8 03 41 0D 00`, and 29-bit ones as
+/// four bytes, e.g. `18 DA F1 10 03 41 0D 00`.
+///
+/// # Arguments
+///
+/// * `line` - The line printed by the adapter.
+///
+/// # Returns
+///
+/// A `Result` containing the frame or `ObdError::Malformed` if the line is not a frame.
+pub fn parse_can_frame(line: &str) -> Result<CanFrame, ObdError> {
+    let mut tokens = line.split_whitespace();
+    let first = tokens.next().ok_or_else(ObdError::malformed)?;
+
+    let id = match first.len() {
+        3 => parse_hex(first)?,
+        2 => {
+            let mut id = parse_hex(first)?;
+            for _ in 0..3 {
+                let token = tokens.next().filter(|t| t.len() == 2);
+                id = id << 8 | parse_hex(token.ok_or_else(ObdError::malformed)?)?;
+            }
+            id
+        }
+        _ => return Err(ObdError::malformed()),
+    };
+
+    let mut data = Vec::new();
+    for token in tokens {
+        if token.len() != 2 || data.len() >= MAX_CAN_DATA {
+            return Err(ObdError::malformed());
+        }
+        data.push(parse_hex(token)? as u8);
+    }
+
+    Ok(CanFrame { id, data })
+}
+
+/// Decodes a group of hexadecimal digits.
+///
+/// # Arguments
+///
+/// * `digits` - The digits, up to 8.
+///
+/// # Returns
+///
+/// A `Result` containing the value or `ObdError::Malformed` if a digit is not hexadecimal.
+fn parse_hex(digits: &str) -> Result<u32, ObdError> {
+    digits.bytes().try_fold(0u32, |value, digit| {
+        Ok(value << 4 | hex_nibble(digit)? as u32)
+    })
+}
+
+/// `VehicleSnapshot` holds the vehicle data read in one polling cycle.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct VehicleSnapshot {
+    /// The vehicle speed, in km/h.
+    pub speed: u8,
+    /// The engine speed, in revolutions per minute.
+    pub rpm: u16,
+    /// The engine coolant temperature, in degrees Celsius, if it has been read.
+    pub coolant_temp: Option<i16>,
+    /// The mass air flow rate, in grams per second, if it has been read.
+    pub maf: Option<f32>,
+    /// The throttle position, in percent, if it has been read.
+    pub throttle: Option<f32>,
+    /// The time at which the data was read.
+    pub timestamp: Instant,
+}
+
+/// Decodes a single hexadecimal digit.
+///
+/// # Arguments
+///
+/// * `digit` - The ASCII digit.
+///
+/// # Returns
+///
+/// A `Result` containing the value of the digit or `ObdError::Malformed` if it is not hexadecimal.
+fn hex_nibble(digit: u8) -> Result<u8, ObdError> {
+    match digit {
+        b'0'..=b'9' => Ok(digit - b'0'),
+        b'a'..=b'f' => Ok(digit - b'a' + 10),
+        b'A'..=b'F' => Ok(digit - b'A' + 10),
+        _ => Err(ObdError::malformed()),
+    }
+}
+
+/// Decodes a hexadecimal ELM327 response such as `41 0D 3C` into bytes.
+///
+/// Bytes may also be packed without spaces, as sent once spaces are turned off with `ATS0`.
+/// Garbage from a flaky adapter, such as non-hex characters or a dangling nibble, is reported as
+/// an error rather than decoded.
+///
+/// # Arguments
+///
+/// * `response` - The response text.
+///
+/// # Returns
+///
+/// A `Result` containing the decoded bytes or `ObdError::Malformed` if the text is not a whole
+/// number of hexadecimal bytes.
+fn decode_hex_response(response: &str) -> Result<Vec<u8>, ObdError> {
+    if response.contains("NO DATA") {
+        return Err(ObdError::NoData);
+    }
+
+    let mut bytes = Vec::new();
+    for token in response.split_whitespace() {
+        let digits = token.as_bytes();
+        if digits.len() % 2 != 0 {
+            return Err(ObdError::malformed());
+        }
+
+        for pair in digits.chunks_exact(2) {
+            bytes.push(hex_nibble(pair[0])? << 4 | hex_nibble(pair[1])?);
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// Returns the error reported when a current data response is too short for its PID.
+///
+/// # Arguments
+///
+/// * `pid` - The PID read.
+/// * `data` - The data bytes received, without the mode and PID echo.
+///
+/// # Returns
+///
+/// * `ObdError` - A `Malformed` error carrying the request and the data received.
+fn short_data(pid: u8, data: &[u8]) -> ObdError {
+    ObdError::Malformed(ErrorContext::new(MODE_CURRENT_DATA, pid, data))
+}
+
+/// Checks that a CAN header is made of 3, 6 or 8 hexadecimal digits.
+///
+/// # Arguments
+///
+/// * `header` - The header text.
+///
+/// # Returns
+///
+/// A `Result` indicating whether the header is valid, or `ObdError::InvalidParameter`.
+fn validate_header(header: &str) -> Result<(), ObdError> {
+    if !HEADER_LENGTHS.contains(&header.len()) || !header.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(ObdError::InvalidParameter);
+    }
+
+    Ok(())
+}
+
+/// Converts a response timeout into the argument of `ATST`.
+///
+/// The adapter counts the timeout in units of 4.096 ms. The timeout is rounded up to whole
+/// units, so the adapter never gives up sooner than requested.
+///
+/// # Arguments
+///
+/// * `ms` - The timeout, in milliseconds.
+///
+/// # Returns
+///
+/// A `Result` containing the timeout in `ATST` units, or `ObdError::InvalidParameter` if it is
+/// zero or longer than 255 units, about 1044 ms.
+fn encode_timeout(ms: u16) -> Result<u8, ObdError> {
+    let units = (ms as u32 * 1000).div_ceil(TIMEOUT_UNIT_US);
+    if units == 0 || units > MAX_TIMEOUT_UNITS {
+        return Err(ObdError::InvalidParameter);
+    }
+
+    Ok(units as u8)
+}
+
+/// `ObdController` is a struct that reads vehicle data through an OBD-II adapter.
+///
+/// It uses an instance of a type that implements the `ObdService` trait to talk to the adapter.
+pub struct ObdController<T: ObdService> {
+    /// An instance of a type that implements the `ObdService` trait.
+    obd_service: T,
+    /// The support bitmaps reported by the vehicle, once queried.
+    supported_pids: Vec<PidBitmap>,
+    /// The per-PID intervals used by the scheduled readings.
+    poll_schedule: PollSchedule,
+    /// The most recent value of each scheduled signal.
+    latest: VehicleSnapshot,
+    /// The definitions used to decode PIDs read through `read`.
+    pid_registry: Vec<PidDefinition>,
+    /// The adapter and its optional features, once identified.
+    adapter_info: Option<AdapterInfo>,
+    /// Whether the adapter is monitoring the CAN bus.
+    monitoring: bool,
+}
+
+impl<T: ObdService> ObdController<T> {
+    /// Creates a new instance of `ObdController`.
+    ///
+    /// # Arguments
+    ///
+    /// * `obd_service` - An instance of a type that implements the `ObdService` trait.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `ObdController` instance.
+    pub fn new(obd_service: T) -> Self {
+        Self {
+            obd_service,
+            supported_pids: Vec::new(),
+            poll_schedule: PollSchedule::default(),
+            latest: VehicleSnapshot {
+                speed: 0,
+                rpm: 0,
+                coolant_temp: None,
+                maf: None,
+                throttle: None,
+                timestamp: Instant::from_ticks(0),
+            },
+            pid_registry: DEFAULT_PID_DEFINITIONS.to_vec(),
+            adapter_info: None,
+            monitoring: false,
+        }
+    }
+
+    /// Brings up the adapter and checks that it reaches the vehicle.
+    ///
+    /// Some ELM327 clones ignore the first command after power-up, so `ATZ` is re-sent, with
+    /// the line flushed in between, until the adapter identifies itself or `RESET_ATTEMPTS`
+    /// is reached. Echo is then disabled, the adapter is identified, the protocol is detected
+    /// automatically and the supported PIDs are queried to confirm the vehicle answers.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation, with
+    /// `ObdError::AdapterNotPresent` if the adapter never answered and `ObdError::VehicleOff`
+    /// if it answered but the vehicle did not.
+    pub async fn init(&mut self) -> Result<(), ObdError> {
+        self.reset().await?;
+        self.send_at("ATE0").await?;
+        if let Err(e) = self.identify().await {
+            warn!("Failed to identify the adapter: {:?}", e);
+        }
+        self.send_at("ATSP0").await?;
+
+        match self.refresh_supported_pids().await {
+            Ok(()) => Ok(()),
+            Err(ObdError::UartError(err)) => Err(ObdError::UartError(err)),
+            Err(err) => {
+                warn!("Vehicle did not answer: {:?}", err);
+                Err(ObdError::VehicleOff)
+            }
+        }
+    }
+
+    /// Resets the adapter with `ATZ`, retrying until it identifies itself.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success of the reset, or `ObdError::AdapterNotPresent`.
+    async fn reset(&mut self) -> Result<(), ObdError> {
+        for attempt in 1..=RESET_ATTEMPTS {
+            match with_timeout(RESET_TIMEOUT, self.request("ATZ")).await {
+                Ok(Ok(response)) if response.contains("ELM") => {
+                    info!("Adapter reset: {=str}", response.trim());
+                    return Ok(());
+                }
+                Ok(Ok(response)) => warn!(
+                    "Unrecognized reset response {=str}, attempt {}",
+                    response.trim(),
+                    attempt
+                ),
+                Ok(Err(err)) => warn!("Reset failed: {:?}, attempt {}", err, attempt),
+                Err(_) => warn!("Reset timed out, attempt {}", attempt),
+            }
+            self.obd_service.flush().await;
+        }
+
+        Err(ObdError::AdapterNotPresent)
+    }
+
+    /// Registers the definition of a PID, replacing any previous definition of the same PID.
+    ///
+    /// # Arguments
+    ///
+    /// * `definition` - The definition used to decode the PID.
+    pub fn register_pid(&mut self, definition: PidDefinition) {
+        match self
+            .pid_registry
+            .iter_mut()
+            .find(|known| known.pid == definition.pid)
+        {
+            Some(known) => *known = definition,
+            None => self.pid_registry.push(definition),
+        }
+    }
+
+    /// Returns the definition of a PID, if it has been registered.
+    pub fn pid_definition(&self, pid: u8) -> Option<&PidDefinition> {
+        self.pid_registry.iter().find(|known| known.pid == pid)
+    }
+
+    /// Reads a registered PID and decodes it into its physical value.
+    ///
+    /// # Arguments
+    ///
+    /// * `pid` - The PID to read.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the decoded value, in the unit of the PID definition, or an error if
+    /// the PID is not registered, not supported, or its response is too short.
+    pub async fn read(&mut self, pid: u8) -> Result<f32, ObdError> {
+        let definition = *self
+            .pid_definition(pid)
+            .ok_or_else(|| ObdError::Unsupported(ErrorContext::new(MODE_CURRENT_DATA, pid, &[])))?;
+
+        let data = self.read_pid(pid).await?;
+        if data.len() < definition.bytes {
+            return Err(short_data(pid, &data));
+        }
+
+        Ok((definition.decode)(&data))
+    }
+
+    /// Reads a registered PID from the freeze frame, the snapshot the vehicle captured when a DTC
+    /// was set.
+    ///
+    /// The request carries the frame number after the PID, and the response echoes both before
+    /// the data. A vehicle without a stored freeze frame answers `NO DATA` or with the echo alone.
+    ///
+    /// # Arguments
+    ///
+    /// * `pid` - The PID to read.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the decoded value, in the unit of the PID definition, or
+    /// `ObdError::NoData` if no freeze frame is stored, or another error if the PID is not
+    /// registered or the response does not match the request.
+    pub async fn read_freeze_frame(&mut self, pid: u8) -> Result<f32, ObdError> {
+        let definition = *self
+            .pid_definition(pid)
+            .ok_or_else(|| ObdError::Unsupported(ErrorContext::new(MODE_FREEZE_FRAME, pid, &[])))?;
+
+        let command = format!(
+            "{:02X}{:02X}{:02X}",
+            MODE_FREEZE_FRAME, pid, FREEZE_FRAME_NUMBER
+        );
+        let text = self.request(&command).await?;
+        let malformed =
+            || ObdError::Malformed(ErrorContext::new(MODE_FREEZE_FRAME, pid, text.as_bytes()));
+        let response = decode_hex_response(&text)
+            .map_err(|err| err.with_context(MODE_FREEZE_FRAME, pid, text.as_bytes()))?;
+
+        let data = match response.as_slice() {
+            [echo_mode, echo_pid, echo_frame, data @ ..]
+                if *echo_mode == MODE_FREEZE_FRAME + RESPONSE_MODE_OFFSET
+                    && *echo_pid == pid
+                    && *echo_frame == FREEZE_FRAME_NUMBER =>
+            {
+                data
+            }
+            [_, _, _, ..] => return Err(ObdError::FrameMismatch),
+            _ => return Err(malformed()),
+        };
+
+        if data.is_empty() {
+            info!("No freeze frame stored");
+            return Err(ObdError::NoData);
+        }
+        if data.len() < definition.bytes {
+            return Err(malformed());
+        }
+
+        Ok((definition.decode)(data))
+    }
+
+    /// Replaces the per-PID intervals used by the scheduled readings.
+    ///
+    /// # Arguments
+    ///
+    /// * `poll_schedule` - The new schedule.
+    pub fn set_poll_schedule(&mut self, poll_schedule: PollSchedule) {
+        self.poll_schedule = poll_schedule;
+    }
+
+    /// Queries a PID and decodes the response bytes.
+    ///
+    /// The response must echo the requested mode plus 0x40 followed by the requested PID,
+    /// otherwise it belongs to a stale or unrelated frame and is rejected. Modes above 0xBF
+    /// have no valid echo, so their responses are always rejected.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - The OBD-II mode.
+    /// * `pid` - The PID to query.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the data bytes, without the mode and PID echo, or an error.
+    pub async fn query_pid(&mut self, mode: u8, pid: u8) -> Result<Vec<u8>, ObdError> {
+        let command = format!("{:02X}{:02X}", mode, pid);
+        let text = self.request(&command).await?;
+        let response = decode_hex_response(&text)
+            .map_err(|err| err.with_context(mode, pid, text.as_bytes()))?;
+
+        match response.as_slice() {
+            [echo_mode, echo_pid, data @ ..]
+                if Some(*echo_mode) == mode.checked_add(RESPONSE_MODE_OFFSET)
+                    && *echo_pid == pid =>
+            {
+                Ok(data.to_vec())
+            }
+            [_, _, ..] => Err(ObdError::FrameMismatch),
+            _ => Err(ObdError::Malformed(ErrorContext::new(
+                mode,
+                pid,
+                text.as_bytes(),
+            ))),
+        }
+    }
+
+    /// Sends a command to the adapter and reads its response.
+    ///
+    /// Every request goes through here, so none is sent while the adapter is monitoring the CAN
+    /// bus, where the command would just stop the monitor mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command to send, without the trailing carriage return.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the response text, or `ObdError::Monitoring` while monitoring.
+    async fn request(&mut self, command: &str) -> Result<String, ObdError> {
+        if self.monitoring {
+            return Err(ObdError::Monitoring);
+        }
+
+        self.obd_service.send_command(command).await
+    }
+
+    /// Sends an AT command to the adapter and checks that it answers `OK`.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command to send.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    async fn send_at(&mut self, command: &str) -> Result<(), ObdError> {
+        let response = self.request(command).await?;
+        if !response.contains("OK") {
+            warn!(
+                "Adapter rejected {=str}: {=str}",
+                command,
+                response.as_str()
+            );
+            return Err(ObdError::malformed());
+        }
+
+        Ok(())
+    }
+
+    /// Sets the CAN header of the following requests with `ATSH`, to address a specific ECU.
+    ///
+    /// # Arguments
+    ///
+    /// * `header` - The header as 3 hex digits for 11-bit CAN, or 6 or 8 hex digits for
+    ///   29-bit CAN, e.g. `7E0` or `DA10F1`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation, with
+    /// `ObdError::Unsupported` if the adapter was identified without header support.
+    pub async fn set_header(&mut self, header: &str) -> Result<(), ObdError> {
+        validate_header(header)?;
+        self.require_headers()?;
+
+        self.send_at(&format!("ATSH{}", header)).await
+    }
+
+    /// Sets how long the adapter waits for the vehicle to answer a request, with `ATST`.
+    ///
+    /// The default of about 200 ms keeps slow ECUs working but stalls the audio loop whenever a
+    /// PID goes unanswered; fast-responding vehicles can use a much tighter timeout.
+    ///
+    /// # Arguments
+    ///
+    /// * `ms` - The timeout, in milliseconds, rounded up to a multiple of 4.096 ms.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation, with
+    /// `ObdError::InvalidParameter` if the timeout is zero or longer than about 1044 ms.
+    pub async fn set_obd_timeout(&mut self, ms: u16) -> Result<(), ObdError> {
+        let units = encode_timeout(ms)?;
+
+        self.send_at(&format!("ATST{:02X}", units)).await
+    }
+
+    /// Sets the flow control frames sent by the adapter during multi-frame responses.
+    ///
+    /// Extended-addressing vehicles expect the flow control frames to carry a specific header
+    /// and data, set with `ATFCSH` and `ATFCSD`, which only take effect with `ATFCSM1`. Mode 0
+    /// restores the automatic flow control.
+    ///
+    /// # Arguments
+    ///
+    /// * `header` - The header of the flow control frames, in the same format as `set_header`.
+    /// * `data` - The data bytes of the flow control frames, up to 5.
+    /// * `mode` - The flow control mode: 0 automatic, 1 user header and data, 2 user data.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation, with
+    /// `ObdError::Unsupported` if the adapter was identified without header support.
+    pub async fn set_flow_control(
+        &mut self,
+        header: &str,
+        data: &[u8],
+        mode: u8,
+    ) -> Result<(), ObdError> {
+        validate_header(header)?;
+        self.require_headers()?;
+        if data.is_empty() || data.len() > MAX_FLOW_CONTROL_DATA || mode > MAX_FLOW_CONTROL_MODE {
+            return Err(ObdError::InvalidParameter);
+        }
+
+        let data: String = data.iter().map(|byte| format!("{:02X}", byte)).collect();
+        self.send_at(&format!("ATFCSH{}", header)).await?;
+        self.send_at(&format!("ATFCSD{}", data)).await?;
+        self.send_at(&format!("ATFCSM{}", mode)).await
+    }
+
+    /// Reads the version of the adapter with `ATI`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the version string, e.g. `ELM327 v1.5`, or an error.
+    pub async fn adapter_version(&mut self) -> Result<String, ObdError> {
+        let response = self.request("ATI").await?;
+        let version = response.trim();
+        if version.is_empty() {
+            return Err(ObdError::malformed());
+        }
+
+        Ok(version.to_string())
+    }
+
+    /// Identifies the adapter and probes the optional features it implements.
+    ///
+    /// The features found gate the methods relying on them, e.g. `set_header`. Headers are
+    /// switched back off and long messages back to the normal length after probing.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the adapter information, or an error if `ATI` failed.
+    pub async fn identify(&mut self) -> Result<AdapterInfo, ObdError> {
+        let version = self.adapter_version().await?;
+        let description = match self.request("AT@1").await {
+            Ok(response) => parse_description(&response),
+            Err(ObdError::UartError(err)) => return Err(ObdError::UartError(err)),
+            Err(_) => None,
+        };
+
+        let headers = self.send_at("ATH1").await.is_ok();
+        if headers {
+            self.send_at("ATH0").await?;
+        }
+        let long_messages = self.send_at("ATAL").await.is_ok();
+        if long_messages {
+            self.send_at("ATNL").await?;
+        }
+
+        let info = AdapterInfo {
+            version,
+            description,
+            headers,
+            long_messages,
+        };
+        info!(
+            "Adapter {=str}, headers: {}, long messages: {}",
+            info.version.as_str(),
+            info.headers,
+            info.long_messages
+        );
+        self.adapter_info = Some(info.clone());
+        Ok(info)
+    }
+
+    /// Returns the adapter and its optional features, if it has been identified.
+    pub fn adapter_info(&self) -> Option<&AdapterInfo> {
+        self.adapter_info.as_ref()
+    }
+
+    /// Checks that the adapter supports custom headers, assuming it does until identified.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating whether headers are supported, or `ObdError::Unsupported`.
+    fn require_headers(&self) -> Result<(), ObdError> {
+        match &self.adapter_info {
+            Some(info) if !info.headers => Err(ObdError::unsupported()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Puts the adapter in monitor mode with `ATMA`, to read the CAN bus without transmitting.
+    ///
+    /// Some vehicles misbehave when an unexpected device sends requests on their bus, while
+    /// they broadcast much of their data anyway. Headers are turned on so each frame carries
+    /// its identifier. The frames are then read from `monitored_frames`, and no other request
+    /// may be issued until `disable_monitor` is called.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation, with
+    /// `ObdError::Unsupported` if the adapter was identified without header support or the
+    /// transport cannot stream.
+    pub async fn enable_monitor(&mut self) -> Result<(), ObdError> {
+        if self.monitoring {
+            return Ok(());
+        }
+        self.require_headers()?;
+
+        self.send_at("ATH1").await?;
+        self.obd_service.write_command("ATMA").await?;
+        self.monitoring = true;
+        info!("Monitoring the CAN bus");
+        Ok(())
+    }
+
+    /// Stops the monitor mode started by `enable_monitor`.
+    ///
+    /// Any character sent stops the monitor mode. The frames received in the meantime are
+    /// discarded along with the prompt, and headers are turned back off.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    pub async fn disable_monitor(&mut self) -> Result<(), ObdError> {
+        if !self.monitoring {
+            return Ok(());
+        }
+
+        self.obd_service.write_command("").await?;
+        self.obd_service.flush().await;
+        self.monitoring = false;
+        info!("Stopped monitoring the CAN bus");
+
+        self.send_at("ATH0").await
+    }
+
+    /// Returns true while the adapter is monitoring the CAN bus.
+    pub fn is_monitoring(&self) -> bool {
+        self.monitoring
+    }
+
+    /// Reads the next frame received while monitoring the CAN bus.
+    ///
+    /// When the adapter stops by itself, headers are turned back off before the end of the
+    /// frames is reported.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Result<CanFrame, ObdError>>` - The frame or the error reading it, or `None` once
+    ///   the adapter is no longer monitoring.
+    async fn next_monitored_frame(&mut self) -> Option<Result<CanFrame, ObdError>> {
+        while self.monitoring {
+            let line = match self.obd_service.read_line().await {
+                Ok(line) => line,
+                Err(err) => return Some(Err(err)),
+            };
+            let line = line.trim();
+
+            if line == ">" || MONITOR_STOPPED.contains(&line) {
+                warn!("Adapter stopped monitoring: {=str}", line);
+                self.obd_service.flush().await;
+                self.monitoring = false;
+                // Later replies would otherwise carry headers and fail to parse
+                if let Err(err) = self.send_at("ATH0").await {
+                    return Some(Err(err));
+                }
+            } else if !line.is_empty() {
+                return Some(parse_can_frame(line));
+            }
+        }
+
+        None
+    }
+
+    /// Returns a stream of the frames received while monitoring the CAN bus.
+    ///
+    /// The adapter streams frames until told to stop, so the stream only ends if the adapter
+    /// stops by itself, e.g. when its buffer fills up. Dropping the stream cancels the read in
+    /// progress, keeping the part of the line already received for the next read, after which
+    /// `disable_monitor` stops the adapter. A line that is not a frame is
+    /// yielded as an error and monitoring continues.
+    ///
+    /// # Returns
+    ///
+    /// * `impl Stream` - The stream of frames or per-line errors.
+    pub fn monitored_frames(&mut self) -> impl Stream<Item = Result<CanFrame, ObdError>> + '_ {
+        stream::unfold(self, |controller| async move {
+            let frame = controller.next_monitored_frame().await?;
+            Some((frame, controller))
+        })
+    }
+
+    /// Queries the support bitmap of a range of PIDs.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The range of PIDs to query.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the support bitmap or an error.
+    pub async fn supported_pids(&mut self, range: PidRange) -> Result<PidBitmap, ObdError> {
+        let data = self.query_pid(MODE_CURRENT_DATA, range.query_pid()).await?;
+
+        PidBitmap::decode(range, &data)
+    }
+
+    /// Queries and caches the support bitmaps of all the ranges the vehicle reports.
+    ///
+    /// The last PID of each range tells whether the next range is available, so querying stops
+    /// at the first range that does not advertise a successor.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    pub async fn refresh_supported_pids(&mut self) -> Result<(), ObdError> {
+        self.supported_pids.clear();
+
+        for range in PidRange::ALL {
+            let bitmap = self.supported_pids(range).await?;
+            self.supported_pids.push(bitmap);
+
+            if !bitmap.is_supported(range.query_pid() + 0x20) {
+                break;
+            }
+        }
+
+        info!("Supported PIDs: {:?}", self.supported_pids.as_slice());
+        Ok(())
+    }
+
+    /// Checks whether the vehicle supports the given PID.
+    ///
+    /// PIDs are assumed to be supported until the support bitmaps have been queried.
+    ///
+    /// # Arguments
+    ///
+    /// * `pid` - The PID to check.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the PID is supported or support is still unknown, false otherwise.
+    pub fn is_supported(&self, pid: u8) -> bool {
+        if self.supported_pids.is_empty() {
+            return true;
+        }
+
+        self.supported_pids
+            .iter()
+            .any(|bitmap| bitmap.is_supported(pid))
+    }
+
+    /// Reads a current data PID, skipping the query if the vehicle does not support it.
+    ///
+    /// # Arguments
+    ///
+    /// * `pid` - The PID to read.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the data bytes, without the mode and PID echo, or an error.
+    async fn read_pid(&mut self, pid: u8) -> Result<Vec<u8>, ObdError> {
+        if !self.is_supported(pid) {
+            return Err(ObdError::Unsupported(ErrorContext::new(
+                MODE_CURRENT_DATA,
+                pid,
+                &[],
+            )));
+        }
+
+        self.query_pid(MODE_CURRENT_DATA, pid).await
+    }
+
+    /// Reads the vehicle speed.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the vehicle speed in km/h or an error.
+    pub async fn read_speed(&mut self) -> Result<u8, ObdError> {
+        let data = self.read_pid(PID_SPEED).await?;
+        data.first()
+            .copied()
+            .ok_or_else(|| short_data(PID_SPEED, &data))
+    }
+
+    /// Reads the engine speed.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the engine speed in revolutions per minute or an error.
+    pub async fn read_rpm(&mut self) -> Result<u16, ObdError> {
+        let data = self.read_pid(PID_RPM).await?;
+        let [a, b, ..] = data[..] else {
+            return Err(short_data(PID_RPM, &data));
+        };
+
+        Ok(u16::from_be_bytes([a, b]) / 4)
+    }
+
+    /// Reads the engine coolant temperature.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the coolant temperature in degrees Celsius or an error.
+    pub async fn read_coolant_temp(&mut self) -> Result<i16, ObdError> {
+        let data = self.read_pid(PID_COOLANT_TEMP).await?;
+        let raw = data
+            .first()
+            .copied()
+            .ok_or_else(|| short_data(PID_COOLANT_TEMP, &data))?;
+
+        Ok(raw as i16 - COOLANT_TEMP_OFFSET)
+    }
+
+    /// Reads the mass air flow rate.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the mass air flow rate in grams per second or an error.
+    pub async fn read_maf(&mut self) -> Result<f32, ObdError> {
+        let data = self.read_pid(PID_MAF).await?;
+        let [a, b, ..] = data[..] else {
+            return Err(short_data(PID_MAF, &data));
+        };
+
+        Ok(u16::from_be_bytes([a, b]) as f32 / 100.0)
+    }
+
+    /// Reads the throttle position.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the throttle position in percent or an error.
+    pub async fn read_throttle(&mut self) -> Result<f32, ObdError> {
+        let data = self.read_pid(PID_THROTTLE).await?;
+        let raw = data
+            .first()
+            .copied()
+            .ok_or_else(|| short_data(PID_THROTTLE, &data))?;
+
+        Ok(raw as f32 * 100.0 / 255.0)
+    }
+
+    /// Forgets the cached value of an optional signal.
+    ///
+    /// # Arguments
+    ///
+    /// * `pid` - The PID of the signal.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the PID is an optional signal, false if it is speed, RPM or unknown.
+    fn clear_optional_signal(&mut self, pid: u8) -> bool {
+        match pid {
+            PID_COOLANT_TEMP => self.latest.coolant_temp = None,
+            PID_MAF => self.latest.maf = None,
+            PID_THROTTLE => self.latest.throttle = None,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Learns the engine speed range of the vehicle.
+    ///
+    /// The RPM is sampled for the given period, during which the engine should be left idling
+    /// and then revved to its ceiling. Failed samples are skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `duration` - The length of the sampling period.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the profile holding the lowest and highest RPM observed, or
+    /// `ObdError::NoData` if no sample could be read.
+    pub async fn calibrate(&mut self, duration: Duration) -> Result<VehicleProfile, ObdError> {
+        let deadline = Instant::now() + duration;
+        let mut ticker = Ticker::every(CALIBRATION_SAMPLE_INTERVAL);
+        let mut range: Option<(u16, u16)> = None;
+
+        while Instant::now() < deadline {
+            match self.read_rpm().await {
+                Ok(rpm) => {
+                    range = Some(match range {
+                        Some((min, max)) => (min.min(rpm), max.max(rpm)),
+                        None => (rpm, rpm),
+                    });
+                }
+                Err(e) => warn!("Skipping calibration sample: {:?}", e),
+            }
+            ticker.next().await;
+        }
+
+        let (idle_rpm, max_rpm) = range.ok_or(ObdError::NoData)?;
+        info!(
+            "Calibrated idle at {} RPM, ceiling at {} RPM",
+            idle_rpm, max_rpm
+        );
+
+        Ok(VehicleProfile { idle_rpm, max_rpm })
+    }
+
+    /// Reads the vehicle speed and the engine speed.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the snapshot of the vehicle data or an error.
+    pub async fn read_snapshot(&mut self) -> Result<VehicleSnapshot, ObdError> {
+        let speed = self.read_speed().await?;
+        let rpm = self.read_rpm().await?;
+
+        Ok(VehicleSnapshot {
+            speed,
+            rpm,
+            coolant_temp: None,
+            maf: None,
+            throttle: None,
+            timestamp: Instant::now(),
+        })
+    }
+
+    /// Waits for the next PID due in the poll schedule, queries it and updates the cached values.
+    ///
+    /// An optional signal the vehicle has no data for is reported as absent rather than zero,
+    /// and one the vehicle does not support is also dropped from the poll schedule.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the snapshot of the most recent value of each signal, or an error if
+    /// the query failed or no PID is scheduled.
+    pub async fn poll_next(&mut self) -> Result<VehicleSnapshot, ObdError> {
+        let (pid, due) = self
+            .poll_schedule
+            .next()
+            .ok_or_else(ObdError::unsupported)?;
+        Timer::at(due).await;
+        self.poll_schedule.mark_polled(pid, Instant::now());
+
+        let result = match pid {
+            PID_SPEED => self
+                .read_speed()
+                .await
+                .map(|speed| self.latest.speed = speed),
+            PID_RPM => self.read_rpm().await.map(|rpm| self.latest.rpm = rpm),
+            PID_COOLANT_TEMP => self
+                .read_coolant_temp()
+                .await
+                .map(|temperature| self.latest.coolant_temp = Some(temperature)),
+            PID_MAF => self.read_maf().await.map(|maf| self.latest.maf = Some(maf)),
+            PID_THROTTLE => self
+                .read_throttle()
+                .await
+                .map(|throttle| self.latest.throttle = Some(throttle)),
+            _ => self.read_pid(pid).await.map(|_| ()),
+        };
+
+        if let Err(err @ (ObdError::NoData | ObdError::Unsupported(_))) = &result {
+            if self.clear_optional_signal(pid) && matches!(err, ObdError::Unsupported(_)) {
+                warn!("PID {=u8:#04x} is not supported, no longer polling it", pid);
+                self.poll_schedule.remove(pid);
+            }
+        }
+        result?;
+
+        self.latest.timestamp = Instant::now();
+        Ok(self.latest)
+    }
+
+    /// Discards the remainder of a response left behind by a cancelled query.
+    ///
+    /// Call this after dropping a `poll_next` or `read` future before it completed, e.g. on a
+    /// timeout, so the next query does not parse the stale bytes.
+    pub async fn discard_pending(&mut self) {
+        self.obd_service.flush().await;
+    }
+
+    /// Returns a stream of vehicle snapshots read at a fixed cadence.
+    ///
+    /// A failed read is yielded as an error and polling continues on the next tick, so the
+    /// stream never terminates.
+    ///
+    /// # Arguments
+    ///
+    /// * `interval` - The time between two reads.
+    ///
+    /// # Returns
+    ///
+    /// * `impl Stream` - The stream of snapshots or per-read errors.
+    pub fn readings(
+        &mut self,
+        interval: Duration,
+    ) -> impl Stream<Item = Result<VehicleSnapshot, ObdError>> + '_ {
+        let ticker = Ticker::every(interval);
+
+        stream::unfold((self, ticker), |(controller, mut ticker)| async move {
+            ticker.next().await;
+            let reading = controller.read_snapshot().await;
+            Some((reading, (controller, ticker)))
+        })
+    }
+
+    /// Returns a stream of vehicle snapshots driven by the poll schedule.
+    ///
+    /// Each item follows the query of a single PID and carries the most recent cached value of
+    /// the other signals. A failed query is yielded as an error and polling continues.
+    ///
+    /// # Returns
+    ///
+    /// * `impl Stream` - The stream of snapshots or per-query errors.
+    pub fn scheduled_readings(
+        &mut self,
+    ) -> impl Stream<Item = Result<VehicleSnapshot, ObdError>> + '_ {
+        stream::unfold(self, |controller| async move {
+            let reading = controller.poll_next().await;
+            Some((reading, controller))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::VecDeque;
+    use core::pin::pin;
+    use embassy_futures::block_on;
+    use futures::StreamExt;
+
+    /// `ScriptedObd` is an `ObdService` answering from a script, recording every command.
+    #[derive(Default)]
+    struct ScriptedObd {
+        /// The replies to the next commands, `OK` once they run out.
+        replies: VecDeque<String>,
+        /// The lines streamed by the adapter, read forever once they run out.
+        lines: VecDeque<String>,
+        /// The commands sent or written so far.
+        commands: Vec<String>,
+    }
+
+    impl ScriptedObd {
+        /// Creates a new instance of `ScriptedObd` streaming the given lines.
+        fn streaming(lines: &[&str]) -> Self {
+            Self {
+                lines: lines.iter().map(|line| line.to_string()).collect(),
+                ..Self::default()
+            }
+        }
+
+        /// Creates a new instance of `ScriptedObd` answering the next commands with the given
+        /// replies.
+        fn replying(replies: &[&str]) -> Self {
+            Self {
+                replies: replies.iter().map(|reply| reply.to_string()).collect(),
+                ..Self::default()
+            }
+        }
+    }
+
+    impl ObdService for ScriptedObd {
+        async fn send_command(&mut self, command: &str) -> Result<String, ObdError> {
+            self.commands.push(command.to_string());
+            Ok(self.replies.pop_front().unwrap_or_else(|| "OK".to_string()))
+        }
+
+        async fn flush(&mut self) {
+            self.lines.clear();
+        }
+
+        async fn write_command(&mut self, command: &str) -> Result<(), ObdError> {
+            self.commands.push(command.to_string());
+            Ok(())
+        }
+
+        async fn read_line(&mut self) -> Result<String, ObdError> {
+            match self.lines.pop_front() {
+                Some(line) => Ok(line),
+                None => core::future::pending().await,
+            }
+        }
+    }
+
+    /// Returns a frame with the given identifier and data.
+    fn frame(id: u32, data: &[u8]) -> CanFrame {
+        CanFrame {
+            id,
+            data: data.to_vec(),
+        }
+    }
+
+    /// Returns the commands the controller sent to its adapter.
+    fn commands(controller: &ObdController<ScriptedObd>) -> &[String] {
+        &controller.obd_service.commands
+    }
+
+    #[test]
+    fn parse_can_frame_reads_11_and_29_bit_frames() {
+        assert_eq!(
+            parse_can_frame("7E8 03 41 0D 32").unwrap(),
+            frame(0x7E8, &[0x03, 0x41, 0x0D, 0x32])
+        );
+        assert_eq!(
+            parse_can_frame("18 DA F1 10 03 41 0C 1A F8").unwrap(),
+            frame(0x18DA_F110, &[0x03, 0x41, 0x0C, 0x1A, 0xF8])
+        );
+        assert_eq!(parse_can_frame("7E8").unwrap(), frame(0x7E8, &[]));
+    }
+
+    #[test]
+    fn parse_can_frame_rejects_lines_that_are_not_frames() {
+        for line in [
+            "",
+            "NO DATA",
+            "7E80 01",
+            "18 DA F1",
+            "7E8 0D3",
+            "7E8 01 02 03 04 05 06 07 08 09",
+        ] {
+            assert!(
+                matches!(parse_can_frame(line), Err(ObdError::Malformed(_))),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn monitored_frames_are_streamed_in_order() {
+        let lines = ["7E8 03 41 0D 32", "", "SEARCHING...", "7E9 02 01 0C"];
+        let mut controller = ObdController::new(ScriptedObd::streaming(&lines));
+
+        block_on(async {
+            controller.enable_monitor().await.unwrap();
+            let mut frames = pin!(controller.monitored_frames());
+
+            let first = frames.next().await.unwrap().unwrap();
+            assert_eq!(first, frame(0x7E8, &[0x03, 0x41, 0x0D, 0x32]));
+            // A line that is not a frame is reported without ending the stream
+            assert!(matches!(frames.next().await, Some(Err(_))));
+            let last = frames.next().await.unwrap().unwrap();
+            assert_eq!(last, frame(0x7E9, &[0x02, 0x01, 0x0C]));
+        });
+
+        assert_eq!(commands(&controller), ["ATH1", "ATMA"]);
+    }
+
+    #[test]
+    fn requests_are_refused_while_monitoring() {
+        let mut controller = ObdController::new(ScriptedObd::default());
+
+        block_on(async {
+            controller.enable_monitor().await.unwrap();
+            assert!(matches!(
+                controller.send_at("ATSP0").await,
+                Err(ObdError::Monitoring)
+            ));
+        });
+
+        assert_eq!(commands(&controller), ["ATH1", "ATMA"]);
+    }
+
+    #[test]
+    fn a_cancelled_monitor_stops_cleanly() {
+        let mut controller = ObdController::new(ScriptedObd::streaming(&["7E8 01 02"]));
+
+        block_on(async {
+            controller.enable_monitor().await.unwrap();
+            {
+                let mut frames = pin!(controller.monitored_frames());
+                assert!(frames.next().await.unwrap().is_ok());
+                // No frame follows, so the read is cancelled half way
+                let next = with_timeout(Duration::from_millis(20), frames.next()).await;
+                assert!(next.is_err());
+            }
+
+            controller.disable_monitor().await.unwrap();
+            assert!(!controller.is_monitoring());
+            // Requests go through again once the monitor is stopped
+            controller.send_at("ATSP0").await.unwrap();
+        });
+
+        assert_eq!(commands(&controller), ["ATH1", "ATMA", "", "ATH0", "ATSP0"]);
+    }
+
+    #[test]
+    fn the_stream_ends_when_the_adapter_stops_by_itself() {
+        let mut controller =
+            ObdController::new(ScriptedObd::streaming(&["7E8 01 02", "BUFFER FULL"]));
+
+        block_on(async {
+            controller.enable_monitor().await.unwrap();
+            let mut frames = pin!(controller.monitored_frames());
+            assert!(frames.next().await.unwrap().is_ok());
+            assert!(frames.next().await.is_none());
+        });
+
+        assert!(!controller.is_monitoring());
+        assert_eq!(commands(&controller), ["ATH1", "ATMA", "ATH0"]);
+    }
+
+    #[test]
+    fn encode_timeout_rounds_up_to_whole_units() {
+        assert_eq!(encode_timeout(1).unwrap(), 0x01);
+        assert_eq!(encode_timeout(4).unwrap(), 0x01);
+        assert_eq!(encode_timeout(5).unwrap(), 0x02);
+        assert_eq!(encode_timeout(100).unwrap(), 0x19);
+        assert_eq!(encode_timeout(200).unwrap(), 0x31);
+        assert_eq!(encode_timeout(1044).unwrap(), 0xFF);
+    }
+
+    #[test]
+    fn encode_timeout_rejects_timeouts_out_of_range() {
+        for ms in [0, 1045, u16::MAX] {
+            assert!(
+                matches!(encode_timeout(ms), Err(ObdError::InvalidParameter)),
+                "{} ms",
+                ms
+            );
+        }
+    }
+
+    #[test]
+    fn set_obd_timeout_sends_the_hex_encoded_units() {
+        let mut controller = ObdController::new(ScriptedObd::default());
+
+        block_on(async {
+            controller.set_obd_timeout(100).await.unwrap();
+            controller.set_obd_timeout(40).await.unwrap();
+            assert!(controller.set_obd_timeout(2000).await.is_err());
+        });
+
+        assert_eq!(commands(&controller), ["ATST19", "ATST0A"]);
+    }
+
+    #[test]
+    fn a_support_bitmap_decodes_speed_and_rpm_without_throttle() {
+        // Coolant temperature, RPM and speed, with no successor range
+        let bitmap = PidBitmap::decode(PidRange::Pids01To20, &[0x08, 0x18, 0x00, 0x00]).unwrap();
+
+        assert!(bitmap.is_supported(PID_COOLANT_TEMP));
+        assert!(bitmap.is_supported(PID_RPM));
+        assert!(bitmap.is_supported(PID_SPEED));
+        assert!(!bitmap.is_supported(PID_THROTTLE));
+        assert!(!bitmap.is_supported(PID_MAF));
+        assert!(!bitmap.is_supported(0x20));
+        // PIDs outside the range are never reported supported
+        assert!(!bitmap.covers(0x00));
+        assert!(!bitmap.is_supported(0x2C));
+    }
+
+    #[test]
+    fn a_short_support_bitmap_is_malformed() {
+        assert!(matches!(
+            PidBitmap::decode(PidRange::Pids21To40, &[0xFF, 0xFF, 0xFF]),
+            Err(ObdError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn unsupported_pids_are_not_queried() {
+        let mut controller =
+            ObdController::new(ScriptedObd::replying(&["41 00 08 18 00 00", "41 0C 1A F8"]));
+
+        block_on(async {
+            controller.refresh_supported_pids().await.unwrap();
+            assert!(controller.is_supported(PID_RPM));
+            assert!(!controller.is_supported(PID_THROTTLE));
+
+            assert!(matches!(
+                controller.read(PID_THROTTLE).await,
+                Err(ObdError::Unsupported(_))
+            ));
+            assert_eq!(controller.read(PID_RPM).await.unwrap(), 1726.0);
+        });
+
+        // The range does not advertise a successor, so it is the only one queried
+        assert_eq!(commands(&controller), ["0100", "010C"]);
+    }
+
+    #[test]
+    fn the_support_of_chained_ranges_is_queried_in_turn() {
+        let mut controller = ObdController::new(ScriptedObd::replying(&[
+            "41 00 08 18 00 01",
+            "41 20 80 00 00 00",
+        ]));
+
+        block_on(controller.refresh_supported_pids()).unwrap();
+
+        assert!(controller.is_supported(0x21));
+        assert!(!controller.is_supported(0x22));
+        assert_eq!(commands(&controller), ["0100", "0120"]);
+    }
+
+    #[test]
+    fn every_pid_is_assumed_supported_until_queried() {
+        let controller = ObdController::new(ScriptedObd::default());
+
+        assert!(controller.is_supported(PID_THROTTLE));
+        assert!(controller.is_supported(PID_MAF));
+    }
+
+    #[test]
+    fn readings_are_yielded_at_the_interval_and_survive_a_failed_read() {
+        let interval = Duration::from_millis(20);
+        let mut controller = ObdController::new(ScriptedObd::replying(&[
+            "41 0D 32",
+            "41 0C 1A F8",
+            "NO DATA",
+            "41 0D 3C",
+            "41 0C 0F A0",
+        ]));
+
+        let (items, arrivals) = block_on(async {
+            let start = Instant::now();
+            let mut readings = pin!(controller.readings(interval));
+            let mut items = Vec::new();
+            let mut arrivals = Vec::new();
+            for _ in 0..3 {
+                items.push(readings.next().await.unwrap());
+                arrivals.push(Instant::now() - start);
+            }
+            (items, arrivals)
+        });
+
+        let first = items[0].as_ref().unwrap();
+        assert_eq!((first.speed, first.rpm), (50, 1726));
+        assert!(matches!(items[1], Err(ObdError::NoData)));
+        let last = items[2].as_ref().unwrap();
+        assert_eq!((last.speed, last.rpm), (60, 1000));
+        assert!(last.timestamp > first.timestamp);
+        for (tick, arrival) in arrivals.iter().enumerate() {
+            assert!(*arrival >= interval * (tick as u32 + 1), "{:?}", arrivals);
+        }
+        // The failed speed read skips the RPM read of the same tick
+        assert_eq!(
+            commands(&controller),
+            ["010D", "010C", "010D", "010D", "010C"]
+        );
+    }
+
+    #[test]
+    fn query_pid_strips_a_matching_echo() {
+        let mut controller = ObdController::new(ScriptedObd::replying(&["41 0C 1A F8"]));
+
+        let data = block_on(controller.query_pid(0x01, 0x0C)).unwrap();
+
+        assert_eq!(data, [0x1A, 0xF8]);
+    }
+
+    #[test]
+    fn query_pid_rejects_an_echo_of_another_pid() {
+        let mut controller = ObdController::new(ScriptedObd::replying(&["41 0D 32"]));
+
+        let result = block_on(controller.query_pid(0x01, 0x0C));
+
+        assert!(matches!(result, Err(ObdError::FrameMismatch)));
+    }
+
+    #[test]
+    fn query_pid_rejects_an_echo_of_another_mode() {
+        let mut controller = ObdController::new(ScriptedObd::replying(&["42 0C 1A F8", "41 0C"]));
+
+        let mismatch = block_on(controller.query_pid(0x01, 0x0C));
+        // The echo of a mode above 0xBF would overflow, so it never matches
+        let overflow = block_on(controller.query_pid(0xC1, 0x0C));
+
+        assert!(matches!(mismatch, Err(ObdError::FrameMismatch)));
+        assert!(matches!(overflow, Err(ObdError::FrameMismatch)));
+    }
+
+    #[test]
+    fn query_pid_rejects_a_response_too_short_for_the_echo() {
+        let mut controller = ObdController::new(ScriptedObd::replying(&["41"]));
+
+        let result = block_on(controller.query_pid(0x01, 0x0C));
+
+        assert!(matches!(result, Err(ObdError::Malformed(_))));
+    }
+
+    #[test]
+    fn read_decodes_a_registered_pid() {
+        let mut controller = ObdController::new(ScriptedObd::replying(&["41 05 5A", "41 0B 65"]));
+        controller.register_pid(PidDefinition {
+            pid: 0x0B,
+            name: "Intake manifold pressure",
+            bytes: 1,
+            decode: |data| data[0] as f32,
+            unit: "kPa",
+        });
+
+        let coolant = block_on(controller.read(PID_COOLANT_TEMP)).unwrap();
+        let pressure = block_on(controller.read(0x0B)).unwrap();
+
+        assert_eq!((coolant, pressure), (50.0, 101.0));
+        assert_eq!(commands(&controller), ["0105", "010B"]);
+    }
+
+    #[test]
+    fn read_refuses_an_unregistered_pid_without_querying() {
+        let mut controller = ObdController::new(ScriptedObd::replying(&[]));
+
+        let result = block_on(controller.read(0x0B));
+
+        assert!(matches!(result, Err(ObdError::Unsupported(_))));
+        assert!(commands(&controller).is_empty());
+    }
+
+    #[test]
+    fn read_rejects_data_shorter_than_the_definition() {
+        let mut controller = ObdController::new(ScriptedObd::replying(&["41 0C 1A"]));
+
+        let result = block_on(controller.read(PID_RPM));
+
+        assert!(matches!(result, Err(ObdError::Malformed(_))));
+    }
+
+    #[test]
+    fn a_malformed_decode_carries_the_request_and_the_raw_response() {
+        let mut controller = ObdController::new(ScriptedObd::replying(&["41 0C 1G F8"]));
+
+        let result = block_on(controller.query_pid(0x01, PID_RPM));
+
+        let ObdError::Malformed(context) = result.unwrap_err() else {
+            panic!("not a malformed error");
+        };
+        assert_eq!(context.mode, Some(0x01));
+        assert_eq!(context.pid, Some(PID_RPM));
+        assert_eq!(context.raw.as_slice(), b"41 0C 1G F8");
+    }
+
+    #[test]
+    fn a_short_reading_carries_the_data_received() {
+        let mut controller = ObdController::new(ScriptedObd::replying(&["41 0C 1A"]));
+
+        let result = block_on(controller.read(PID_RPM));
+
+        let ObdError::Malformed(context) = result.unwrap_err() else {
+            panic!("not a malformed error");
+        };
+        assert_eq!(context, ErrorContext::new(0x01, PID_RPM, &[0x1A]));
+    }
+
+    #[test]
+    fn an_unregistered_pid_carries_the_request_without_a_response() {
+        let mut controller = ObdController::new(ScriptedObd::default());
+
+        let result = block_on(controller.read(0x99));
+
+        let ObdError::Unsupported(context) = result.unwrap_err() else {
+            panic!("not an unsupported error");
+        };
+        assert_eq!((context.mode, context.pid), (Some(0x01), Some(0x99)));
+        assert!(context.raw.is_empty());
+    }
+
+    #[test]
+    fn read_maf_decodes_hundredths_of_grams_per_second() {
+        let mut controller =
+            ObdController::new(ScriptedObd::replying(&["41 10 01 F4", "41 10 FF FF"]));
+
+        let idle = block_on(controller.read_maf()).unwrap();
+        let full_scale = block_on(controller.read_maf()).unwrap();
+
+        assert_eq!(idle, 5.0);
+        assert_eq!(full_scale, 655.35);
+        assert_eq!(commands(&controller), ["0110", "0110"]);
+    }
+
+    #[test]
+    fn read_maf_rejects_a_single_data_byte() {
+        let mut controller = ObdController::new(ScriptedObd::replying(&["41 10 01"]));
+
+        let result = block_on(controller.read_maf());
+
+        assert!(matches!(result, Err(ObdError::Malformed(_))));
+    }
+
+    /// The replies of an adapter identifying itself and detecting the protocol, after `ATZ`.
+    const BRING_UP: [&str; 9] = [
+        "ELM327 v1.5",
+        "OK",
+        "ELM327 v1.5",
+        "OBDII to RS232 Interpreter",
+        "OK",
+        "OK",
+        "OK",
+        "OK",
+        "OK",
+    ];
+
+    /// The commands sent by `init` after `ATZ`, up to the supported PIDs query.
+    const BRING_UP_COMMANDS: [&str; 9] = [
+        "ATE0", "ATI", "AT@1", "ATH1", "ATH0", "ATAL", "ATNL", "ATSP0", "0100",
+    ];
+
+    #[test]
+    fn init_retries_atz_until_the_adapter_answers() {
+        // The adapter ignores the first command after power-up
+        let replies = [&[""][..], &BRING_UP, &["41 00 BE 1F A8 12"]].concat();
+        let mut controller = ObdController::new(ScriptedObd::replying(&replies));
+
+        block_on(controller.init()).unwrap();
+
+        let expected = [&["ATZ", "ATZ"][..], &BRING_UP_COMMANDS].concat();
+        assert_eq!(commands(&controller), expected);
+        assert!(controller.is_supported(PID_RPM));
+    }
+
+    #[test]
+    fn init_reports_a_missing_adapter_after_the_last_attempt() {
+        let mut controller = ObdController::new(ScriptedObd::default());
+
+        let result = block_on(controller.init());
+
+        assert!(matches!(result, Err(ObdError::AdapterNotPresent)));
+        assert_eq!(commands(&controller), ["ATZ"; RESET_ATTEMPTS as usize]);
+    }
+
+    #[test]
+    fn init_tells_a_vehicle_that_is_off_from_a_missing_adapter() {
+        let replies = [&BRING_UP[..], &["NO DATA"]].concat();
+        let mut controller = ObdController::new(ScriptedObd::replying(&replies));
+
+        let result = block_on(controller.init());
+
+        assert!(matches!(result, Err(ObdError::VehicleOff)));
+        assert_eq!(
+            commands(&controller).last().map(String::as_str),
+            Some("0100")
+        );
+    }
+
+    #[test]
+    fn read_freeze_frame_decodes_the_stored_rpm() {
+        let mut controller = ObdController::new(ScriptedObd::replying(&["42 0C 00 1A F8"]));
+
+        let rpm = block_on(controller.read_freeze_frame(PID_RPM)).unwrap();
+
+        assert_eq!(rpm, 1726.0);
+        assert_eq!(commands(&controller), ["020C00"]);
+    }
+
+    #[test]
+    fn read_freeze_frame_reports_an_empty_freeze_frame() {
+        let mut controller = ObdController::new(ScriptedObd::replying(&[
+            "NO DATA",
+            "42 0C 00",
+            "42 0C 01 1A F8",
+        ]));
+
+        let no_data = block_on(controller.read_freeze_frame(PID_RPM));
+        let echo_alone = block_on(controller.read_freeze_frame(PID_RPM));
+        let other_frame = block_on(controller.read_freeze_frame(PID_RPM));
+
+        assert!(matches!(no_data, Err(ObdError::NoData)));
+        assert!(matches!(echo_alone, Err(ObdError::NoData)));
+        assert!(matches!(other_frame, Err(ObdError::FrameMismatch)));
+    }
+
+    #[test]
+    fn read_freeze_frame_rejects_an_unregistered_pid_without_sending() {
+        let mut controller = ObdController::new(ScriptedObd::default());
+
+        let result = block_on(controller.read_freeze_frame(0x99));
+
+        assert!(matches!(result, Err(ObdError::Unsupported(_))));
+        assert!(commands(&controller).is_empty());
+    }
+
+    #[test]
+    fn calibrate_learns_the_rpm_range_of_a_trace() {
+        // Idles around 800 RPM, revs to 4200 RPM and settles back, with a dropped sample
+        let mut controller = ObdController::new(ScriptedObd::replying(&[
+            "41 0C 0D 48",
+            "41 0C 0C 30",
+            "NO DATA",
+            "41 0C 2E E0",
+            "41 0C 41 A0",
+            "41 0C 0C 80",
+        ]));
+
+        let profile = block_on(controller.calibrate(Duration::from_millis(1000))).unwrap();
+
+        assert_eq!(
+            profile,
+            VehicleProfile {
+                idle_rpm: 780,
+                max_rpm: 4200,
+            }
+        );
+        // The trace is sampled throughout the period, the adapter answering OK once it runs out
+        assert!(commands(&controller).len() >= 6);
+        assert!(commands(&controller)
+            .iter()
+            .all(|command| command == "010C"));
+    }
+
+    #[test]
+    fn calibrate_without_a_single_sample_reports_no_data() {
+        let mut controller = ObdController::new(ScriptedObd::replying(&["NO DATA", "NO DATA"]));
+
+        let result = block_on(controller.calibrate(Duration::from_millis(300)));
+
+        assert!(matches!(result, Err(ObdError::NoData)));
+    }
+
+    #[test]
+    fn decode_hex_response_accepts_spaced_and_packed_bytes() {
+        assert_eq!(decode_hex_response("41 0d 3C").unwrap(), [0x41, 0x0D, 0x3C]);
+        assert_eq!(decode_hex_response("410D3C").unwrap(), [0x41, 0x0D, 0x3C]);
+        assert!(decode_hex_response("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn decode_hex_response_rejects_garbage_without_panicking() {
+        for garbage in [
+            "41 0D 3",
+            "410D3",
+            "41 0G 3C",
+            "41 0D ??",
+            "SEARCHING...",
+            "41 \u{e9}0",
+            "41 \u{e9}\u{e9}",
+            "41 0D\u{0}3C",
+        ] {
+            assert!(
+                matches!(decode_hex_response(garbage), Err(ObdError::Malformed(_))),
+                "{:?}",
+                garbage
+            );
+        }
+    }
+
+    #[test]
+    fn the_controller_recovers_after_a_burst_of_garbage() {
+        let mut controller = ObdController::new(ScriptedObd::replying(&[
+            "41 0C 1",
+            "41 0C ZZ ZZ",
+            "\u{FFFD}\u{FFFD} 0C",
+            "41 0C 1A F8",
+        ]));
+
+        block_on(async {
+            for _ in 0..3 {
+                let result = controller.read_rpm().await;
+                assert!(
+                    matches!(result, Err(ObdError::Malformed(_))),
+                    "{:?}",
+                    result
+                );
+            }
+            assert_eq!(controller.read_rpm().await.unwrap(), 1726);
+        });
+    }
+
+    #[test]
+    fn set_header_issues_atsh_before_the_next_query() {
+        let mut controller =
+            ObdController::new(ScriptedObd::replying(&["OK", "OK", "OK", "41 0D 3C"]));
+
+        block_on(async {
+            for header in ["7E0", "DA10F1", "18DA10F1"] {
+                controller.set_header(header).await.unwrap();
+            }
+            assert_eq!(controller.read_speed().await.unwrap(), 60);
+        });
+
+        assert_eq!(
+            commands(&controller),
+            ["ATSH7E0", "ATSHDA10F1", "ATSH18DA10F1", "010D"]
+        );
+    }
+
+    #[test]
+    fn set_header_rejects_malformed_headers_without_sending() {
+        let mut controller = ObdController::new(ScriptedObd::replying(&[]));
+
+        for header in [
+            "",
+            "7E",
+            "7E0F",
+            "18DA10F",
+            "18DA10F1A",
+            "7G0",
+            " 7E0",
+            "7é",
+        ] {
+            let result = block_on(controller.set_header(header));
+            assert!(
+                matches!(result, Err(ObdError::InvalidParameter)),
+                "{:?}",
+                header
+            );
+        }
+        assert!(commands(&controller).is_empty());
+    }
+
+    #[test]
+    fn identify_reads_a_genuine_adapter() {
+        let mut controller = ObdController::new(ScriptedObd::replying(&[
+            "ELM327 v1.5",
+            "OBDII to RS232 Interpreter",
+        ]));
+
+        let info = block_on(controller.identify()).unwrap();
+
+        assert_eq!(
+            info,
+            AdapterInfo {
+                version: "ELM327 v1.5".to_string(),
+                description: Some("OBDII to RS232 Interpreter".to_string()),
+                headers: true,
+                long_messages: true,
+            }
+        );
+        assert_eq!(controller.adapter_info(), Some(&info));
+        // Both features are switched back off after probing
+        assert_eq!(
+            commands(&controller),
+            ["ATI", "AT@1", "ATH1", "ATH0", "ATAL", "ATNL"]
+        );
+    }
+
+    #[test]
+    fn identify_reads_a_clone_without_at_1_or_long_messages() {
+        let mut controller = ObdController::new(ScriptedObd::replying(&[
+            "ELM327 v2.1\r\n",
+            "?",
+            "OK",
+            "OK",
+            "?",
+        ]));
+
+        let info = block_on(controller.identify()).unwrap();
+
+        assert_eq!(info.version, "ELM327 v2.1");
+        assert_eq!(info.description, None);
+        assert!(info.headers);
+        assert!(!info.long_messages);
+        assert_eq!(
+            commands(&controller),
+            ["ATI", "AT@1", "ATH1", "ATH0", "ATAL"]
+        );
+    }
+
+    #[test]
+    fn identify_fails_without_a_version() {
+        let mut controller = ObdController::new(ScriptedObd::replying(&["  "]));
+
+        let result = block_on(controller.identify());
+
+        assert!(matches!(result, Err(ObdError::Malformed(_))));
+        assert_eq!(controller.adapter_info(), None);
+    }
+
+    #[test]
+    fn set_header_is_unsupported_without_header_support() {
+        // ATI, then AT@1, ATH1 and ATAL all rejected
+        let mut controller =
+            ObdController::new(ScriptedObd::replying(&["ELM327 v1.5", "?", "?", "?"]));
+        let info = block_on(controller.identify()).unwrap();
+        assert!(!info.headers);
+
+        let result = block_on(controller.set_header("7E0"));
+
+        assert!(matches!(result, Err(ObdError::Unsupported(_))));
+        assert!(!commands(&controller).iter().any(|c| c.starts_with("ATSH")));
+    }
+
+    #[test]
+    fn set_flow_control_sends_the_header_data_and_mode() {
+        let mut controller = ObdController::new(ScriptedObd::replying(&[]));
+
+        block_on(controller.set_flow_control("18DA10F1", &[0x30, 0x00, 0x00], 1)).unwrap();
+
+        assert_eq!(
+            commands(&controller),
+            ["ATFCSH18DA10F1", "ATFCSD300000", "ATFCSM1"]
+        );
+    }
+
+    #[test]
+    fn set_flow_control_rejects_invalid_parameters_without_sending() {
+        let mut controller = ObdController::new(ScriptedObd::replying(&[]));
+
+        block_on(async {
+            for result in [
+                controller.set_flow_control("18DA10F", &[0x30], 1).await,
+                controller.set_flow_control("7E0", &[], 1).await,
+                controller.set_flow_control("7E0", &[0; 6], 1).await,
+                controller.set_flow_control("7E0", &[0x30], 3).await,
+            ] {
+                assert!(matches!(result, Err(ObdError::InvalidParameter)));
+            }
+        });
+        assert!(commands(&controller).is_empty());
+    }
+}
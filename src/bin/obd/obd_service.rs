@@ -0,0 +1,438 @@
+#![no_std]
+#![no_main]
+
+use crate::csr8645::byte_channel::ByteChannel;
+use alloc::string::String;
+use alloc::vec::Vec;
+use embassy_stm32::usart::Error;
+use embassy_time::{with_timeout, Duration};
+
+/// The prompt the ELM327 adapter prints when it is ready for the next command.
+const PROMPT: u8 = b'>';
+
+/// The maximum length of a single adapter response.
+const MAX_RESPONSE_LEN: usize = 256;
+
+/// The time without a received byte after which `flush` considers the line quiet.
+const FLUSH_WINDOW: Duration = Duration::from_millis(50);
+
+/// The most response bytes kept in the context of an error.
+const MAX_CONTEXT_BYTES: usize = 16;
+
+/// `ErrorContext` identifies the request an OBD-II error occurred on, for field debugging.
+///
+/// Fields are `None` and the raw bytes empty when the error did not come from a PID request,
+/// e.g. for a rejected AT command.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ErrorContext {
+    /// The OBD-II mode requested.
+    pub mode: Option<u8>,
+    /// The PID requested.
+    pub pid: Option<u8>,
+    /// The start of the raw response, truncated to `MAX_CONTEXT_BYTES`.
+    pub raw: heapless::Vec<u8, MAX_CONTEXT_BYTES>,
+}
+
+impl ErrorContext {
+    /// Creates a new instance of `ErrorContext`.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - The OBD-II mode requested.
+    /// * `pid` - The PID requested.
+    /// * `raw` - The raw response, truncated to `MAX_CONTEXT_BYTES`.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `ErrorContext` instance.
+    pub fn new(mode: u8, pid: u8, raw: &[u8]) -> Self {
+        let raw = &raw[..raw.len().min(MAX_CONTEXT_BYTES)];
+
+        Self {
+            mode: Some(mode),
+            pid: Some(pid),
+            raw: heapless::Vec::from_slice(raw).unwrap_or_default(),
+        }
+    }
+
+    /// Returns true if the context does not identify a request.
+    pub fn is_empty(&self) -> bool {
+        self.mode.is_none() && self.pid.is_none() && self.raw.is_empty()
+    }
+}
+
+impl defmt::Format for ErrorContext {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "mode {=?} pid {=?} raw {=[u8]:02x}",
+            self.mode,
+            self.pid,
+            self.raw.as_slice()
+        );
+    }
+}
+
+/// Represents an error that can occur while talking to the OBD-II adapter.
+#[derive(Debug, defmt::Format)]
+pub enum ObdError {
+    /// The UART reported an error.
+    UartError(Error),
+    /// The adapter answered `NO DATA`.
+    NoData,
+    /// The response could not be decoded.
+    Malformed(ErrorContext),
+    /// The vehicle or the adapter does not support the request.
+    Unsupported(ErrorContext),
+    /// The mode or PID echoed in the response does not match the request.
+    FrameMismatch,
+    /// An argument was rejected before being sent to the adapter.
+    InvalidParameter,
+    /// The adapter did not answer the reset command.
+    AdapterNotPresent,
+    /// The adapter answered, but could not reach the vehicle, usually because the ignition is off.
+    VehicleOff,
+    /// The adapter is monitoring the CAN bus, during which it cannot take requests.
+    Monitoring,
+}
+
+impl ObdError {
+    /// Returns a `Malformed` error carrying no context.
+    pub fn malformed() -> Self {
+        ObdError::Malformed(ErrorContext::default())
+    }
+
+    /// Returns an `Unsupported` error carrying no context.
+    pub fn unsupported() -> Self {
+        ObdError::Unsupported(ErrorContext::default())
+    }
+
+    /// Attaches the request and its raw response to an error raised without them.
+    ///
+    /// Errors other than `Malformed` and `Unsupported`, or already carrying a context, are
+    /// returned unchanged.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - The OBD-II mode requested.
+    /// * `pid` - The PID requested.
+    /// * `raw` - The raw response.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The error with its context.
+    pub fn with_context(self, mode: u8, pid: u8, raw: &[u8]) -> Self {
+        match self {
+            ObdError::Malformed(context) if context.is_empty() => {
+                ObdError::Malformed(ErrorContext::new(mode, pid, raw))
+            }
+            ObdError::Unsupported(context) if context.is_empty() => {
+                ObdError::Unsupported(ErrorContext::new(mode, pid, raw))
+            }
+            err => err,
+        }
+    }
+}
+
+impl From<Error> for ObdError {
+    fn from(err: Error) -> ObdError {
+        ObdError::UartError(err)
+    }
+}
+
+/// `ObdService` is a trait that defines the methods necessary to talk to an ELM327 OBD-II adapter.
+pub trait ObdService {
+    /// Sends a command to the adapter and waits for its response.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command to send, without the trailing carriage return.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the response text, up to but excluding the `>` prompt, or an error.
+    async fn send_command(&mut self, command: &str) -> Result<String, ObdError>;
+
+    /// Discards the bytes received from the adapter that have not been consumed yet.
+    ///
+    /// This drops the remainder of a response left behind by a command that timed out.
+    async fn flush(&mut self) {}
+
+    /// Sends a command to the adapter without waiting for its response.
+    ///
+    /// This starts the commands answering with a continuous stream, such as `ATMA`, whose output
+    /// is then read with `read_line`. Transports that cannot stream report
+    /// `ObdError::Unsupported`.
+    ///
+    /// # Arguments
+    ///
+    /// * `_command` - The command to send, without the trailing carriage return.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    async fn write_command(&mut self, _command: &str) -> Result<(), ObdError> {
+        Err(ObdError::unsupported())
+    }
+
+    /// Reads the next line sent by the adapter.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the line without its carriage return, `>` alone if the adapter
+    /// printed its prompt, or an error.
+    async fn read_line(&mut self) -> Result<String, ObdError> {
+        Err(ObdError::unsupported())
+    }
+}
+
+/// `ObdServiceImpl` is a struct that implements the `ObdService` trait over a byte channel.
+///
+/// The channel is usually the UART wired to the adapter. It may also be a `SharedChannel` when
+/// the adapter shares its bus with another driver, in which case every command and its response
+/// are exchanged while holding the bus.
+pub struct ObdServiceImpl<C: ByteChannel> {
+    /// The byte channel connected to the ELM327 adapter.
+    channel: C,
+    /// The bytes of a line whose read was cancelled before the line ended.
+    partial_line: Vec<u8>,
+}
+
+/// `ObdExchange` holds the byte channel of the adapter for one command/response cycle.
+///
+/// The channel is released when the exchange is dropped, so a cycle cancelled by a timeout does
+/// not leave a shared bus locked.
+struct ObdExchange<'c, C: ByteChannel> {
+    /// The byte channel held for the exchange.
+    channel: &'c mut C,
+}
+
+impl<'c, C: ByteChannel> ObdExchange<'c, C> {
+    /// Takes the byte channel for an exchange.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The byte channel connected to the adapter.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The exchange holding the channel.
+    async fn begin(channel: &'c mut C) -> Self {
+        channel.begin_exchange().await;
+        Self { channel }
+    }
+
+    /// Writes all the given bytes, looping over partial writes.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The bytes to write.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    async fn write_all(&mut self, mut data: &[u8]) -> Result<(), ObdError> {
+        while !data.is_empty() {
+            let written = self.channel.write_some(data).await?;
+            data = &data[written.min(data.len())..];
+        }
+
+        Ok(())
+    }
+
+    /// Sends a command and reads its response.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command to send, without the trailing carriage return.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the response text, up to but excluding the `>` prompt, or an error.
+    async fn send_command(&mut self, command: &str) -> Result<String, ObdError> {
+        self.write_all(command.as_bytes()).await?;
+        self.write_all(b"\r").await?;
+
+        // The adapter terminates every response with the prompt character
+        let mut response = Vec::new();
+        let mut byte = [0u8; 1];
+        loop {
+            self.channel.read(&mut byte).await?;
+            if byte[0] == PROMPT {
+                break;
+            }
+            if response.len() >= MAX_RESPONSE_LEN {
+                return Err(ObdError::malformed());
+            }
+            response.push(byte[0]);
+        }
+
+        String::from_utf8(response).map_err(|_| ObdError::malformed())
+    }
+
+    /// Reads the next line sent by the adapter.
+    ///
+    /// The line is accumulated in the given buffer, so a read cancelled half way through a line
+    /// resumes where it stopped instead of returning the end of the line as a line of its own.
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The bytes of the line read so far, emptied once the line is complete.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the line without its carriage return, `>` alone if the adapter
+    /// printed its prompt, or an error.
+    async fn read_line(&mut self, line: &mut Vec<u8>) -> Result<String, ObdError> {
+        let mut byte = [0u8; 1];
+        loop {
+            self.channel.read(&mut byte).await?;
+            match byte[0] {
+                b'\r' if !line.is_empty() => break,
+                b'\r' | b'\n' => {}
+                PROMPT if line.is_empty() => return Ok(String::from(">")),
+                _ if line.len() >= MAX_RESPONSE_LEN => {
+                    line.clear();
+                    return Err(ObdError::malformed());
+                }
+                byte => line.push(byte),
+            }
+        }
+
+        String::from_utf8(core::mem::take(line)).map_err(|_| ObdError::malformed())
+    }
+}
+
+impl<'c, C: ByteChannel> Drop for ObdExchange<'c, C> {
+    fn drop(&mut self) {
+        self.channel.end_exchange();
+    }
+}
+
+impl<C: ByteChannel> ObdServiceImpl<C> {
+    /// Creates a new instance of `ObdServiceImpl`.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The byte channel connected to the ELM327 adapter, usually an instance of
+    ///   `UartChannel`.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `ObdServiceImpl` instance.
+    pub fn new(channel: C) -> Self {
+        Self {
+            channel,
+            partial_line: Vec::new(),
+        }
+    }
+}
+
+impl<C: ByteChannel> ObdService for ObdServiceImpl<C> {
+    async fn send_command(&mut self, command: &str) -> Result<String, ObdError> {
+        // A new request starts from a fresh line, whatever was left unread before it
+        self.partial_line.clear();
+        ObdExchange::begin(&mut self.channel)
+            .await
+            .send_command(command)
+            .await
+    }
+
+    async fn flush(&mut self) {
+        self.partial_line.clear();
+        let mut exchange = ObdExchange::begin(&mut self.channel).await;
+        let mut byte = [0u8; 1];
+        while let Ok(Ok(())) = with_timeout(FLUSH_WINDOW, exchange.channel.read(&mut byte)).await {}
+    }
+
+    async fn write_command(&mut self, command: &str) -> Result<(), ObdError> {
+        self.partial_line.clear();
+        let mut exchange = ObdExchange::begin(&mut self.channel).await;
+        exchange.write_all(command.as_bytes()).await?;
+        exchange.write_all(b"\r").await
+    }
+
+    async fn read_line(&mut self) -> Result<String, ObdError> {
+        ObdExchange::begin(&mut self.channel)
+            .await
+            .read_line(&mut self.partial_line)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csr8645::loopback::LoopbackChannel;
+    use embassy_futures::block_on;
+
+    #[test]
+    fn read_line_resumes_a_line_whose_read_was_cancelled() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"\r7E8 03");
+        let mut obd = ObdServiceImpl::new(channel);
+
+        block_on(async {
+            let read = with_timeout(Duration::from_millis(20), obd.read_line()).await;
+            assert!(read.is_err());
+
+            obd.channel.enqueue_response(b" 41 0D 32\r7E8 01\r");
+            assert_eq!(obd.read_line().await.unwrap(), "7E8 03 41 0D 32");
+            assert_eq!(obd.read_line().await.unwrap(), "7E8 01");
+        });
+    }
+
+    #[test]
+    fn a_command_drops_the_rest_of_a_cancelled_line() {
+        let mut channel = LoopbackChannel::new();
+        channel.enqueue_response(b"7E8 03");
+        let mut obd = ObdServiceImpl::new(channel);
+
+        block_on(async {
+            let read = with_timeout(Duration::from_millis(20), obd.read_line()).await;
+            assert!(read.is_err());
+
+            obd.channel.enqueue_response(b"OK\r\r>7E8 01\r");
+            assert_eq!(obd.send_command("ATH0").await.unwrap().trim(), "OK");
+            assert_eq!(obd.read_line().await.unwrap(), "7E8 01");
+        });
+
+        assert_eq!(obd.channel.written(), b"ATH0\r");
+    }
+
+    #[test]
+    fn the_raw_response_is_truncated_in_the_context() {
+        let raw = [0xAB; MAX_CONTEXT_BYTES + 4];
+
+        let context = ErrorContext::new(0x01, 0x0C, &raw);
+
+        assert_eq!(context.raw.as_slice(), &raw[..MAX_CONTEXT_BYTES]);
+        assert!(!context.is_empty());
+        assert!(ErrorContext::default().is_empty());
+    }
+
+    #[test]
+    fn with_context_only_fills_an_empty_context() {
+        let filled = ObdError::malformed().with_context(0x01, 0x0D, b"41 0D");
+        assert!(matches!(
+            filled,
+            ObdError::Malformed(ref context) if *context == ErrorContext::new(0x01, 0x0D, b"41 0D")
+        ));
+
+        let kept = filled.with_context(0x02, 0x0C, b"42 0C");
+        assert!(matches!(
+            kept,
+            ObdError::Malformed(ref context) if context.mode == Some(0x01)
+        ));
+
+        let unsupported = ObdError::unsupported().with_context(0x01, 0x99, &[]);
+        assert!(matches!(
+            unsupported,
+            ObdError::Unsupported(ref context) if context.pid == Some(0x99)
+        ));
+        assert!(matches!(
+            ObdError::NoData.with_context(0x01, 0x0C, b"NO DATA"),
+            ObdError::NoData
+        ));
+    }
+}
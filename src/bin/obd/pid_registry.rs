@@ -0,0 +1,100 @@
+#![no_std]
+#![no_main]
+
+use crate::obd::obd_controller::{PID_COOLANT_TEMP, PID_MAF, PID_RPM, PID_SPEED, PID_THROTTLE};
+
+/// `PidDefinition` describes how to decode a current data PID into a physical value.
+#[derive(Clone, Copy, Debug)]
+pub struct PidDefinition {
+    /// The PID.
+    pub pid: u8,
+    /// A human-readable name of the signal.
+    pub name: &'static str,
+    /// The number of data bytes of the response.
+    pub bytes: usize,
+    /// Decodes the data bytes, without the mode and PID echo, into the physical value.
+    pub decode: fn(&[u8]) -> f32,
+    /// The unit of the decoded value.
+    pub unit: &'static str,
+}
+
+/// Decodes a two-byte big-endian value.
+fn word(data: &[u8]) -> f32 {
+    ((data[0] as u16) << 8 | data[1] as u16) as f32
+}
+
+/// The definitions of the PIDs known out of the box.
+pub const DEFAULT_PID_DEFINITIONS: [PidDefinition; 5] = [
+    PidDefinition {
+        pid: PID_SPEED,
+        name: "Vehicle speed",
+        bytes: 1,
+        decode: |data| data[0] as f32,
+        unit: "km/h",
+    },
+    PidDefinition {
+        pid: PID_RPM,
+        name: "Engine speed",
+        bytes: 2,
+        decode: |data| word(data) / 4.0,
+        unit: "rpm",
+    },
+    PidDefinition {
+        pid: PID_COOLANT_TEMP,
+        name: "Coolant temperature",
+        bytes: 1,
+        decode: |data| data[0] as f32 - 40.0,
+        unit: "degC",
+    },
+    PidDefinition {
+        pid: PID_THROTTLE,
+        name: "Throttle position",
+        bytes: 1,
+        decode: |data| data[0] as f32 * 100.0 / 255.0,
+        unit: "%",
+    },
+    PidDefinition {
+        pid: PID_MAF,
+        name: "Mass air flow",
+        bytes: 2,
+        decode: |data| word(data) / 100.0,
+        unit: "g/s",
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes sample data bytes with the default definition of a PID.
+    fn decode(pid: u8, data: &[u8]) -> f32 {
+        let definition = DEFAULT_PID_DEFINITIONS
+            .iter()
+            .find(|definition| definition.pid == pid)
+            .unwrap();
+        assert_eq!(data.len(), definition.bytes, "{}", definition.name);
+        (definition.decode)(data)
+    }
+
+    #[test]
+    fn each_default_pid_decodes_to_its_physical_value() {
+        assert_eq!(decode(PID_SPEED, &[0x32]), 50.0);
+        assert_eq!(decode(PID_RPM, &[0x1A, 0xF8]), 1726.0);
+        assert_eq!(decode(PID_COOLANT_TEMP, &[0x5A]), 50.0);
+        assert_eq!(decode(PID_COOLANT_TEMP, &[0x00]), -40.0);
+        assert_eq!(decode(PID_THROTTLE, &[0xFF]), 100.0);
+        assert_eq!(decode(PID_THROTTLE, &[0x00]), 0.0);
+        assert_eq!(decode(PID_MAF, &[0x01, 0xF4]), 5.0);
+    }
+
+    #[test]
+    fn the_default_pids_are_defined_once() {
+        for definition in &DEFAULT_PID_DEFINITIONS {
+            let count = DEFAULT_PID_DEFINITIONS
+                .iter()
+                .filter(|other| other.pid == definition.pid)
+                .count();
+            assert_eq!(count, 1, "{}", definition.name);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+#![no_std]
+#![no_main]
+
+use crate::obd::obd_controller::{PID_COOLANT_TEMP, PID_MAF, PID_RPM, PID_SPEED, PID_THROTTLE};
+use alloc::vec::Vec;
+use embassy_time::{Duration, Instant};
+
+/// `PollEntry` holds the polling interval of a single PID.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct PollEntry {
+    /// The PID to poll.
+    pub pid: u8,
+    /// The time between two queries of the PID.
+    pub interval: Duration,
+    /// The time at which the PID should be queried next.
+    next_due: Instant,
+}
+
+/// `PollSchedule` decides which PID to query next based on per-PID intervals.
+///
+/// Fast-changing signals such as RPM can be polled often while slow ones such as the coolant
+/// temperature are only refreshed occasionally, so the adapter bandwidth goes where it matters.
+///
+/// The default schedule also polls the mass air flow driving the expander and the throttle
+/// position blended into the engine effort. A vehicle lacking one of them answers that the PID
+/// is unsupported, and the controller then drops it from the schedule.
+#[derive(Clone, Debug)]
+pub struct PollSchedule {
+    /// The scheduled PIDs.
+    entries: Vec<PollEntry>,
+}
+
+impl Default for PollSchedule {
+    fn default() -> Self {
+        let mut schedule = Self::new();
+        schedule.set_interval(PID_RPM, Duration::from_millis(50));
+        schedule.set_interval(PID_SPEED, Duration::from_millis(100));
+        schedule.set_interval(PID_THROTTLE, Duration::from_millis(100));
+        schedule.set_interval(PID_MAF, Duration::from_millis(200));
+        schedule.set_interval(PID_COOLANT_TEMP, Duration::from_secs(2));
+        schedule
+    }
+}
+
+impl PollSchedule {
+    /// Creates a new, empty instance of `PollSchedule`.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `PollSchedule` instance.
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Sets the polling interval of a PID, adding it to the schedule if needed.
+    ///
+    /// A newly added PID is due immediately.
+    ///
+    /// # Arguments
+    ///
+    /// * `pid` - The PID to poll.
+    /// * `interval` - The time between two queries of the PID.
+    pub fn set_interval(&mut self, pid: u8, interval: Duration) {
+        match self.entries.iter_mut().find(|entry| entry.pid == pid) {
+            Some(entry) => entry.interval = interval,
+            None => self.entries.push(PollEntry {
+                pid,
+                interval,
+                next_due: Instant::from_ticks(0),
+            }),
+        }
+    }
+
+    /// Removes a PID from the schedule.
+    ///
+    /// # Arguments
+    ///
+    /// * `pid` - The PID to stop polling.
+    pub fn remove(&mut self, pid: u8) {
+        self.entries.retain(|entry| entry.pid != pid);
+    }
+
+    /// Returns the scheduled PIDs.
+    pub fn entries(&self) -> &[PollEntry] {
+        &self.entries
+    }
+
+    /// Returns the PID to query next.
+    ///
+    /// The PID with the earliest due time wins. When several are due at the same time, the one
+    /// with the shortest interval goes first so fast signals are never starved.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<(u8, Instant)>` - The PID and the time it is due at, or `None` if the schedule is
+    ///   empty.
+    pub fn next(&self) -> Option<(u8, Instant)> {
+        self.entries
+            .iter()
+            .min_by_key(|entry| (entry.next_due, entry.interval))
+            .map(|entry| (entry.pid, entry.next_due))
+    }
+
+    /// Records that a PID has been queried and schedules its next query.
+    ///
+    /// The next query is kept on the original cadence unless the PID has fallen more than one
+    /// interval behind, in which case it is rescheduled from `now`.
+    ///
+    /// # Arguments
+    ///
+    /// * `pid` - The PID that was queried.
+    /// * `now` - The time of the query.
+    pub fn mark_polled(&mut self, pid: u8, now: Instant) {
+        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.pid == pid) {
+            let next_due = entry.next_due + entry.interval;
+            entry.next_due = if next_due < now {
+                now + entry.interval
+            } else {
+                next_due
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a schedule polling RPM every 50 ms, speed every 100 ms and the coolant every 2 s.
+    fn schedule() -> PollSchedule {
+        let mut schedule = PollSchedule::new();
+        schedule.set_interval(PID_COOLANT_TEMP, Duration::from_secs(2));
+        schedule.set_interval(PID_SPEED, Duration::from_millis(100));
+        schedule.set_interval(PID_RPM, Duration::from_millis(50));
+        schedule
+    }
+
+    /// Runs the schedule with queries taking no time and returns the PIDs queried, with the
+    /// millisecond they were queried at, before the end of the window.
+    fn queries(schedule: &mut PollSchedule, window_ms: u64) -> Vec<(u8, u64)> {
+        let mut queries = Vec::new();
+        while let Some((pid, due)) = schedule.next() {
+            if due >= Instant::from_millis(window_ms) {
+                break;
+            }
+            schedule.mark_polled(pid, due);
+            queries.push((pid, due.as_millis()));
+        }
+        queries
+    }
+
+    #[test]
+    fn fast_signals_go_first_and_are_queried_more_often() {
+        let mut schedule = schedule();
+
+        assert_eq!(
+            queries(&mut schedule, 200),
+            [
+                (PID_RPM, 0),
+                (PID_SPEED, 0),
+                (PID_COOLANT_TEMP, 0),
+                (PID_RPM, 50),
+                (PID_RPM, 100),
+                (PID_SPEED, 100),
+                (PID_RPM, 150),
+            ]
+        );
+    }
+
+    #[test]
+    fn each_pid_is_queried_at_its_own_rate() {
+        let mut schedule = schedule();
+
+        let queries = queries(&mut schedule, 2000);
+
+        let count = |pid| {
+            queries
+                .iter()
+                .filter(|(queried, _)| *queried == pid)
+                .count()
+        };
+        assert_eq!(count(PID_RPM), 40);
+        assert_eq!(count(PID_SPEED), 20);
+        assert_eq!(count(PID_COOLANT_TEMP), 1);
+    }
+
+    #[test]
+    fn a_pid_far_behind_is_rescheduled_from_now() {
+        let mut schedule = PollSchedule::new();
+        schedule.set_interval(PID_RPM, Duration::from_millis(50));
+
+        // One interval late keeps the cadence
+        schedule.mark_polled(PID_RPM, Instant::from_millis(40));
+        assert_eq!(schedule.next(), Some((PID_RPM, Instant::from_millis(50))));
+        // Further behind, the missed queries are skipped
+        schedule.mark_polled(PID_RPM, Instant::from_millis(500));
+        assert_eq!(schedule.next(), Some((PID_RPM, Instant::from_millis(550))));
+    }
+
+    #[test]
+    fn removed_pids_are_no_longer_queried() {
+        let mut schedule = schedule();
+        schedule.remove(PID_RPM);
+        schedule.remove(PID_COOLANT_TEMP);
+
+        let queries = queries(&mut schedule, 300);
+
+        assert_eq!(
+            queries,
+            [(PID_SPEED, 0), (PID_SPEED, 100), (PID_SPEED, 200)]
+        );
+        schedule.remove(PID_SPEED);
+        assert_eq!(schedule.next(), None);
+    }
+}
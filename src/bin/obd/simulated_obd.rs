@@ -0,0 +1,112 @@
+#![no_std]
+#![no_main]
+
+use crate::obd::obd_controller::{PID_RPM, PID_SPEED};
+use crate::obd::obd_service::{ObdError, ObdService};
+use alloc::format;
+use alloc::string::{String, ToString};
+use embassy_time::{Duration, Instant};
+
+/// `DriveSample` is a point of a scripted drive cycle.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct DriveSample {
+    /// The time since the start of the drive cycle from which the sample applies.
+    pub at: Duration,
+    /// The vehicle speed, in km/h.
+    pub speed: u8,
+    /// The engine speed, in revolutions per minute.
+    pub rpm: u16,
+}
+
+/// `SimulatedObdTransport` is an `ObdService` replaying a scripted drive cycle.
+///
+/// It answers speed and RPM queries as an ELM327 adapter would, with the values of the latest
+/// sample of the script, so the whole app can be exercised without a vehicle. Each sample is
+/// held until the next one applies, and the last one is held once the script is over.
+pub struct SimulatedObdTransport {
+    /// The drive cycle, ordered by time.
+    script: &'static [DriveSample],
+    /// The time the drive cycle started, set by the first query.
+    start: Option<Instant>,
+}
+
+impl SimulatedObdTransport {
+    /// Creates a new instance of `SimulatedObdTransport`.
+    ///
+    /// # Arguments
+    ///
+    /// * `script` - The drive cycle, ordered by time.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `SimulatedObdTransport` instance.
+    pub fn new(script: &'static [DriveSample]) -> Self {
+        Self {
+            script,
+            start: None,
+        }
+    }
+
+    /// Returns the length of the drive cycle.
+    pub fn duration(&self) -> Duration {
+        self.script
+            .last()
+            .map(|sample| sample.at)
+            .unwrap_or(Duration::from_ticks(0))
+    }
+
+    /// Returns the sample applying at the given time since the start of the drive cycle.
+    ///
+    /// # Arguments
+    ///
+    /// * `elapsed` - The time since the start of the drive cycle.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<DriveSample>` - The latest sample applying, or `None` if the script is empty.
+    fn sample_at(&self, elapsed: Duration) -> Option<DriveSample> {
+        self.script
+            .iter()
+            .take_while(|sample| sample.at <= elapsed)
+            .last()
+            .or(self.script.first())
+            .copied()
+    }
+}
+
+impl ObdService for SimulatedObdTransport {
+    async fn send_command(&mut self, command: &str) -> Result<String, ObdError> {
+        let command = command.trim();
+        match command {
+            "ATZ" | "ATI" => return Ok("ELM327 v1.5".to_string()),
+            "AT@1" => return Ok("?".to_string()),
+            _ => {}
+        }
+        if command.starts_with("AT") {
+            return Ok("OK".to_string());
+        }
+
+        let start = *self.start.get_or_insert_with(Instant::now);
+        let sample = self
+            .sample_at(Instant::now() - start)
+            .ok_or(ObdError::NoData)?;
+
+        let pid = command
+            .get(2..4)
+            .and_then(|pid| u8::from_str_radix(pid, 16).ok())
+            .ok_or_else(ObdError::malformed)?;
+
+        let response = match (command.get(..2), pid) {
+            // Only the speed and RPM PIDs are scripted
+            (Some("01"), 0x00) => "41 00 00 18 00 00".to_string(),
+            (Some("01"), PID_SPEED) => format!("41 {:02X} {:02X}", pid, sample.speed),
+            (Some("01"), PID_RPM) => {
+                let [a, b] = (sample.rpm * 4).to_be_bytes();
+                format!("41 {:02X} {:02X} {:02X}", pid, a, b)
+            }
+            _ => "NO DATA".to_string(),
+        };
+
+        Ok(response)
+    }
+}
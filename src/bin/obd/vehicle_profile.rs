@@ -0,0 +1,97 @@
+#![no_std]
+#![no_main]
+
+/// The idle speed assumed until the vehicle has been calibrated, in revolutions per minute.
+const DEFAULT_IDLE_RPM: u16 = 800;
+
+/// The rev ceiling assumed until the vehicle has been calibrated, in revolutions per minute.
+const DEFAULT_MAX_RPM: u16 = 7000;
+
+/// The typical idle speed of a diesel engine, in revolutions per minute.
+const DIESEL_IDLE_RPM: u16 = 750;
+
+/// The typical rev ceiling of a diesel engine, in revolutions per minute.
+const DIESEL_MAX_RPM: u16 = 4500;
+
+/// Represents the kind of engine fitted to the vehicle.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub enum EngineType {
+    /// A petrol engine, revving up to about 7000 RPM.
+    #[default]
+    Petrol,
+    /// A diesel engine, rarely passing 4500 RPM.
+    Diesel,
+}
+
+impl EngineType {
+    /// Returns the engine speed range typical of this engine type.
+    ///
+    /// # Returns
+    ///
+    /// * `VehicleProfile` - The profile assumed until the vehicle has been calibrated.
+    pub fn profile(self) -> VehicleProfile {
+        match self {
+            EngineType::Petrol => VehicleProfile::default(),
+            EngineType::Diesel => VehicleProfile {
+                idle_rpm: DIESEL_IDLE_RPM,
+                max_rpm: DIESEL_MAX_RPM,
+            },
+        }
+    }
+
+    /// Returns how strongly the engine effort drives the bass for this engine type.
+    ///
+    /// A diesel already rumbles low at part load, so its bass rises more gently over its
+    /// narrower range.
+    ///
+    /// # Returns
+    ///
+    /// * `f32` - The factor applied to the RPM to bass slope.
+    pub fn bass_slope(self) -> f32 {
+        match self {
+            EngineType::Petrol => 1.0,
+            EngineType::Diesel => 0.8,
+        }
+    }
+}
+
+/// `VehicleProfile` holds the engine speed range learned for a vehicle.
+///
+/// Idle speed and rev ceiling vary widely between engines, e.g. diesels rarely pass 4500 RPM,
+/// so the mapping normalizes the RPM against the profile instead of fixed thresholds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct VehicleProfile {
+    /// The lowest engine speed observed, in revolutions per minute.
+    pub idle_rpm: u16,
+    /// The highest engine speed observed, in revolutions per minute.
+    pub max_rpm: u16,
+}
+
+impl Default for VehicleProfile {
+    fn default() -> Self {
+        Self {
+            idle_rpm: DEFAULT_IDLE_RPM,
+            max_rpm: DEFAULT_MAX_RPM,
+        }
+    }
+}
+
+impl VehicleProfile {
+    /// Returns where an engine speed sits within the learned range.
+    ///
+    /// # Arguments
+    ///
+    /// * `rpm` - The engine speed, in revolutions per minute.
+    ///
+    /// # Returns
+    ///
+    /// * `f32` - 0.0 at idle and 1.0 at the rev ceiling, clamped to that range.
+    pub fn normalize(&self, rpm: u16) -> f32 {
+        if self.max_rpm <= self.idle_rpm {
+            return 0.0;
+        }
+
+        let span = (self.max_rpm - self.idle_rpm) as f32;
+        ((rpm as f32 - self.idle_rpm as f32) / span).clamp(0.0, 1.0)
+    }
+}
@@ -0,0 +1,415 @@
+#![no_std]
+#![no_main]
+
+use crate::audio::audio_mapping::{MappingConfig, MAX_BASS, MAX_VOLUME};
+use crate::audio::loudness_curve::LoudnessCurve;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+/// The most points of a loudness table kept in a descriptor.
+pub const MAX_CURVE_POINTS: usize = 8;
+
+/// The size of an encoded descriptor, including its version and checksum.
+pub const DESCRIPTOR_LEN: usize = 24 + MAX_CURVE_POINTS * POINT_LEN;
+
+/// The version of the descriptor layout.
+const DESCRIPTOR_VERSION: u8 = 1;
+
+/// The size of an encoded `(speed, volume_delta)` point.
+const POINT_LEN: usize = 5;
+
+/// Set in the flags byte when the soft knee is enabled.
+const FLAG_SOFT_KNEE: u8 = 0x01;
+
+/// Set in the flags byte when the expander is enabled.
+const FLAG_EXPANDER: u8 = 0x02;
+
+/// Set in the flags byte when a safety speed cap is set.
+const FLAG_SPEED_CAP: u8 = 0x04;
+
+/// Identifies the linear loudness curve.
+const CURVE_LINEAR: u8 = 0;
+
+/// Identifies the logarithmic loudness curve.
+const CURVE_LOGARITHMIC: u8 = 1;
+
+/// Identifies a loudness table.
+const CURVE_TABLE: u8 = 2;
+
+/// `BoostProfile` is the part of the `MappingConfig` shaping the speed and RPM to behavior
+/// curve, stored in flash so it can be tuned without recompiling.
+///
+/// The descriptor is a fixed-size little-endian record:
+///
+/// | Offset | Size | Field                                          |
+/// |--------|------|------------------------------------------------|
+/// | 0      | 1    | Layout version                                 |
+/// | 1      | 1    | Volume ceiling                                 |
+/// | 2      | 1    | Bass ceiling                                   |
+/// | 3      | 1    | Flags: soft knee, expander, speed cap          |
+/// | 4      | 4    | Soft knee width, `f32`                         |
+/// | 8      | 1    | Safety speed cap, in km/h                      |
+/// | 9      | 4    | RPM to bass slope, `f32`                       |
+/// | 13     | 4    | RPM weight, `f32`                              |
+/// | 17     | 4    | Throttle weight, `f32`                         |
+/// | 21     | 1    | Loudness curve: linear, logarithmic or table   |
+/// | 22     | 1    | Number of table points                         |
+/// | 23     | 40   | Table points, `u8` speed and `f32` delta each  |
+/// | 63     | 1    | Checksum, the wrapping sum of the other bytes  |
+///
+/// The vehicle RPM range is stored separately, as learned by calibration.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoostProfile {
+    /// The volume the limiter never exceeds.
+    pub max_volume: u8,
+    /// The bass level the limiter never exceeds.
+    pub max_bass: u8,
+    /// The width, in levels, of the soft knee below each ceiling, or `None` for a hard limit.
+    pub soft_knee: Option<f32>,
+    /// The curve translating speed into a volume offset.
+    pub loudness: LoudnessCurve,
+    /// Whether the expander raises the volume with the mass air flow.
+    pub expander: bool,
+    /// The factor applied to the RPM to bass slope.
+    pub bass_slope: f32,
+    /// The weight of the engine speed in the engine effort driving the bass.
+    pub rpm_weight: f32,
+    /// The weight of the throttle position in the engine effort.
+    pub throttle_weight: f32,
+    /// The speed, in km/h, above which boosting is disabled, or `None` to always boost.
+    pub safety_speed_cap: Option<u8>,
+}
+
+impl BoostProfile {
+    /// Captures the curve of a mapping configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The mapping settings.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Self>` - The profile, or `None` if the loudness table has more than
+    ///   `MAX_CURVE_POINTS` points.
+    pub fn from_mapping(config: &MappingConfig) -> Option<Self> {
+        if let LoudnessCurve::Table(points) = config.loudness {
+            if points.len() > MAX_CURVE_POINTS {
+                return None;
+            }
+        }
+
+        Some(Self {
+            max_volume: config.max_volume,
+            max_bass: config.max_bass,
+            soft_knee: config.soft_knee,
+            loudness: config.loudness,
+            expander: config.expander,
+            bass_slope: config.bass_slope,
+            rpm_weight: config.rpm_weight,
+            throttle_weight: config.throttle_weight,
+            safety_speed_cap: config.safety_speed_cap,
+        })
+    }
+
+    /// Applies the curve to a mapping configuration, keeping its other settings.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The mapping settings to update.
+    pub fn apply(&self, config: &mut MappingConfig) {
+        config.max_volume = self.max_volume;
+        config.max_bass = self.max_bass;
+        config.soft_knee = self.soft_knee;
+        config.loudness = self.loudness;
+        config.expander = self.expander;
+        config.bass_slope = self.bass_slope;
+        config.rpm_weight = self.rpm_weight;
+        config.throttle_weight = self.throttle_weight;
+        config.safety_speed_cap = self.safety_speed_cap;
+    }
+
+    /// Encodes the profile into a descriptor.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<[u8; DESCRIPTOR_LEN]>` - The descriptor, or `None` if the loudness table has
+    ///   more than `MAX_CURVE_POINTS` points.
+    pub fn encode(&self) -> Option<[u8; DESCRIPTOR_LEN]> {
+        let mut descriptor = [0u8; DESCRIPTOR_LEN];
+        descriptor[0] = DESCRIPTOR_VERSION;
+        descriptor[1] = self.max_volume;
+        descriptor[2] = self.max_bass;
+
+        let mut flags = 0;
+        if let Some(knee) = self.soft_knee {
+            flags |= FLAG_SOFT_KNEE;
+            descriptor[4..8].copy_from_slice(&knee.to_le_bytes());
+        }
+        if self.expander {
+            flags |= FLAG_EXPANDER;
+        }
+        if let Some(cap) = self.safety_speed_cap {
+            flags |= FLAG_SPEED_CAP;
+            descriptor[8] = cap;
+        }
+        descriptor[3] = flags;
+
+        descriptor[9..13].copy_from_slice(&self.bass_slope.to_le_bytes());
+        descriptor[13..17].copy_from_slice(&self.rpm_weight.to_le_bytes());
+        descriptor[17..21].copy_from_slice(&self.throttle_weight.to_le_bytes());
+
+        descriptor[21] = match self.loudness {
+            LoudnessCurve::Linear => CURVE_LINEAR,
+            LoudnessCurve::Logarithmic => CURVE_LOGARITHMIC,
+            LoudnessCurve::Table(points) => {
+                if points.len() > MAX_CURVE_POINTS {
+                    return None;
+                }
+                descriptor[22] = points.len() as u8;
+                for (i, &(speed, delta)) in points.iter().enumerate() {
+                    let offset = 23 + i * POINT_LEN;
+                    descriptor[offset] = speed;
+                    descriptor[offset + 1..offset + POINT_LEN]
+                        .copy_from_slice(&delta.to_le_bytes());
+                }
+                CURVE_TABLE
+            }
+        };
+
+        descriptor[DESCRIPTOR_LEN - 1] = checksum(&descriptor[..DESCRIPTOR_LEN - 1]);
+        Some(descriptor)
+    }
+
+    /// Decodes a descriptor.
+    ///
+    /// A loudness table is moved to a static allocation, since `LoudnessCurve` borrows its
+    /// points for the lifetime of the firmware. Descriptors are therefore only meant to be
+    /// decoded once, when loading the stored configuration at boot.
+    ///
+    /// # Arguments
+    ///
+    /// * `descriptor` - The encoded descriptor.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Self>` - The profile, or `None` if the descriptor is erased, corrupt, of an
+    ///   unknown version or holds settings out of range; see `is_valid`.
+    pub fn decode(descriptor: &[u8; DESCRIPTOR_LEN]) -> Option<Self> {
+        if descriptor[0] != DESCRIPTOR_VERSION
+            || descriptor[DESCRIPTOR_LEN - 1] != checksum(&descriptor[..DESCRIPTOR_LEN - 1])
+        {
+            return None;
+        }
+
+        let flags = descriptor[3];
+        let loudness = match descriptor[21] {
+            CURVE_LINEAR => LoudnessCurve::Linear,
+            CURVE_LOGARITHMIC => LoudnessCurve::Logarithmic,
+            CURVE_TABLE => {
+                let len = descriptor[22] as usize;
+                if len > MAX_CURVE_POINTS {
+                    return None;
+                }
+                let points: Vec<(u8, f32)> = (0..len)
+                    .map(|i| {
+                        let offset = 23 + i * POINT_LEN;
+                        (descriptor[offset], read_f32(descriptor, offset + 1))
+                    })
+                    .collect();
+                // Checked before leaking, so a rejected descriptor does not leak its table
+                if !is_valid_table(&points) {
+                    return None;
+                }
+                LoudnessCurve::Table(Box::leak(points.into_boxed_slice()))
+            }
+            _ => return None,
+        };
+
+        let profile = Self {
+            max_volume: descriptor[1],
+            max_bass: descriptor[2],
+            soft_knee: (flags & FLAG_SOFT_KNEE != 0).then(|| read_f32(descriptor, 4)),
+            loudness,
+            expander: flags & FLAG_EXPANDER != 0,
+            bass_slope: read_f32(descriptor, 9),
+            rpm_weight: read_f32(descriptor, 13),
+            throttle_weight: read_f32(descriptor, 17),
+            safety_speed_cap: (flags & FLAG_SPEED_CAP != 0).then_some(descriptor[8]),
+        };
+        profile.is_valid().then_some(profile)
+    }
+
+    /// Checks that every setting is in the range the mapping expects.
+    ///
+    /// The checksum only catches accidental corruption, so a descriptor written by hand can still
+    /// hold a ceiling above the module's scale, a negative knee, weights that are not finite or a
+    /// loudness table whose speeds are not increasing.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the profile can be applied to a `MappingConfig`.
+    pub fn is_valid(&self) -> bool {
+        let table_valid = match self.loudness {
+            LoudnessCurve::Table(points) => is_valid_table(points),
+            LoudnessCurve::Linear | LoudnessCurve::Logarithmic => true,
+        };
+
+        table_valid
+            && self.max_volume <= MAX_VOLUME
+            && self.max_bass <= MAX_BASS
+            && self
+                .soft_knee
+                .map_or(true, |knee| (0.0..=MAX_VOLUME as f32).contains(&knee))
+            && is_non_negative(self.bass_slope)
+            && is_non_negative(self.rpm_weight)
+            && is_non_negative(self.throttle_weight)
+    }
+}
+
+/// Checks that a loudness table has at most `MAX_CURVE_POINTS` points, sorted by strictly
+/// increasing speed, with finite deltas.
+fn is_valid_table(points: &[(u8, f32)]) -> bool {
+    points.len() <= MAX_CURVE_POINTS
+        && points.iter().all(|&(_, delta)| delta.is_finite())
+        && points.windows(2).all(|window| window[0].0 < window[1].0)
+}
+
+/// Tells whether a factor is finite and not negative.
+fn is_non_negative(value: f32) -> bool {
+    value.is_finite() && value >= 0.0
+}
+
+/// Reads a little-endian `f32` from a descriptor.
+fn read_f32(descriptor: &[u8], offset: usize) -> f32 {
+    f32::from_le_bytes([
+        descriptor[offset],
+        descriptor[offset + 1],
+        descriptor[offset + 2],
+        descriptor[offset + 3],
+    ])
+}
+
+/// Computes the checksum of a descriptor, the wrapping sum of its bytes.
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::audio_mapping::map_sensor_data_to_audio_behavior;
+    use crate::audio::audio_preset::AudioPreset;
+    use crate::obd::gear_estimator::Gear;
+
+    /// A loudness table boosting hard in town and staying flat on the highway.
+    static CUSTOM_TABLE: [(u8, f32); 4] = [(0, 0.0), (30, 4.0), (60, 5.5), (120, 6.0)];
+
+    /// Returns a profile departing from the defaults in every field.
+    fn custom_profile() -> BoostProfile {
+        BoostProfile {
+            max_volume: 13,
+            max_bass: 8,
+            soft_knee: Some(3.5),
+            loudness: LoudnessCurve::Table(&CUSTOM_TABLE),
+            expander: true,
+            bass_slope: 1.25,
+            rpm_weight: 0.6,
+            throttle_weight: 0.4,
+            safety_speed_cap: Some(180),
+        }
+    }
+
+    /// Encodes the custom profile, lets `tamper` edit the descriptor and fixes up its checksum,
+    /// as a descriptor written by hand would be.
+    fn tampered(tamper: impl FnOnce(&mut [u8; DESCRIPTOR_LEN])) -> [u8; DESCRIPTOR_LEN] {
+        let mut descriptor = custom_profile().encode().unwrap();
+        tamper(&mut descriptor);
+        descriptor[DESCRIPTOR_LEN - 1] = checksum(&descriptor[..DESCRIPTOR_LEN - 1]);
+        descriptor
+    }
+
+    #[test]
+    fn a_custom_curve_round_trips_through_its_descriptor() {
+        let profile = custom_profile();
+
+        let decoded = BoostProfile::decode(&profile.encode().unwrap()).unwrap();
+
+        assert_eq!(decoded, profile);
+    }
+
+    #[test]
+    fn the_loaded_mapping_reproduces_the_custom_curve() {
+        let mut tuned = MappingConfig::default();
+        custom_profile().apply(&mut tuned);
+        let mut loaded = MappingConfig::default();
+        let descriptor = BoostProfile::from_mapping(&tuned)
+            .unwrap()
+            .encode()
+            .unwrap();
+        BoostProfile::decode(&descriptor)
+            .unwrap()
+            .apply(&mut loaded);
+
+        for (speed, rpm) in [(0, 800), (25, 2200), (70, 3000), (130, 3500), (200, 4500)] {
+            let gear = Gear::Forward(3);
+            let map = |config: &MappingConfig| {
+                map_sensor_data_to_audio_behavior(
+                    speed,
+                    rpm,
+                    AudioPreset::Normal,
+                    gear,
+                    None,
+                    Some(40.0),
+                    config,
+                )
+            };
+            assert_eq!(map(&loaded), map(&tuned), "{} km/h, {} RPM", speed, rpm);
+        }
+    }
+
+    #[test]
+    fn an_erased_or_corrupt_descriptor_is_rejected() {
+        assert_eq!(BoostProfile::decode(&[0xFF; DESCRIPTOR_LEN]), None);
+
+        let mut corrupt = custom_profile().encode().unwrap();
+        corrupt[1] ^= 0x01;
+        assert_eq!(BoostProfile::decode(&corrupt), None);
+
+        let unknown_version = tampered(|descriptor| descriptor[0] = DESCRIPTOR_VERSION + 1);
+        assert_eq!(BoostProfile::decode(&unknown_version), None);
+    }
+
+    #[test]
+    fn a_descriptor_out_of_range_is_rejected() {
+        let loud = tampered(|descriptor| descriptor[1] = MAX_VOLUME + 1);
+        assert_eq!(BoostProfile::decode(&loud), None);
+
+        let nan_weight = tampered(|descriptor| {
+            descriptor[13..17].copy_from_slice(&f32::NAN.to_le_bytes());
+        });
+        assert_eq!(BoostProfile::decode(&nan_weight), None);
+
+        let negative_knee = tampered(|descriptor| {
+            descriptor[4..8].copy_from_slice(&(-1.0f32).to_le_bytes());
+        });
+        assert_eq!(BoostProfile::decode(&negative_knee), None);
+
+        // Swap the speeds of the first two table points
+        let unsorted = tampered(|descriptor| descriptor.swap(23, 23 + POINT_LEN));
+        assert_eq!(BoostProfile::decode(&unsorted), None);
+
+        let too_long = tampered(|descriptor| descriptor[22] = MAX_CURVE_POINTS as u8 + 1);
+        assert_eq!(BoostProfile::decode(&too_long), None);
+    }
+
+    #[test]
+    fn a_table_longer_than_a_descriptor_is_not_captured() {
+        static LONG_TABLE: [(u8, f32); MAX_CURVE_POINTS + 1] = [(0, 0.0); MAX_CURVE_POINTS + 1];
+        let config = MappingConfig {
+            loudness: LoudnessCurve::Table(&LONG_TABLE),
+            ..MappingConfig::default()
+        };
+
+        assert_eq!(BoostProfile::from_mapping(&config), None);
+    }
+}
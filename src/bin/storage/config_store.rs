@@ -0,0 +1,586 @@
+#![no_std]
+#![no_main]
+
+use crate::obd::vehicle_profile::VehicleProfile;
+use crate::storage::boost_profile::{BoostProfile, DESCRIPTOR_LEN};
+use alloc::string::{String, ToString};
+use core::cell::RefCell;
+use defmt::{error, info, warn};
+use embassy_stm32::flash::{Blocking, Error, Flash};
+
+/// The offset, from the start of the flash, of the sector holding the configuration.
+///
+/// This is the last 256 KiB sector of the STM32F767ZI in single-bank mode, far from the firmware.
+const CONFIG_OFFSET: u32 = 0x1C_0000;
+
+/// The size of the sector holding the configuration.
+const CONFIG_SECTOR_SIZE: u32 = 0x4_0000;
+
+/// The offset, from the start of the flash, of the sector a boost profile descriptor is staged
+/// in for import.
+///
+/// This is the sector just before the configuration, which `probe-rs download` can overwrite
+/// without erasing the stored configuration. The firmware must stay below this offset.
+const STAGING_OFFSET: u32 = 0x18_0000;
+
+/// The size of the configuration record, a multiple of the flash write size.
+///
+/// The sector is used as a log of records: each save appends one to the first erased slot, and
+/// the sector is only erased once all its slots are used.
+const RECORD_SIZE: usize = 256;
+
+/// The offset, within the record, of the checksum telling a complete record from one cut short
+/// by a power loss.
+const CHECKSUM_OFFSET: usize = RECORD_SIZE - 2;
+
+/// Marks a valid configuration record.
+const RECORD_MAGIC: [u8; 4] = *b"DMZ1";
+
+/// The maximum length of a stored Bluetooth address.
+const MAX_ADDRESS_LEN: usize = 32;
+
+/// The offset, within the record, of the stored vehicle profile.
+const PROFILE_OFFSET: usize = 5 + MAX_ADDRESS_LEN;
+
+/// Marks a stored vehicle profile.
+const PROFILE_MARKER: u8 = 0x01;
+
+/// The offset, within the record, of the stored boost profile.
+const BOOST_OFFSET: usize = PROFILE_OFFSET + 5;
+
+/// Marks a stored boost profile.
+const BOOST_MARKER: u8 = 0x01;
+
+/// The offset, within the record, of the stored volume trim.
+const TRIM_OFFSET: usize = BOOST_OFFSET + 1 + DESCRIPTOR_LEN;
+
+/// Marks a stored volume trim.
+const TRIM_MARKER: u8 = 0x01;
+
+/// Represents an error that can occur while persisting the configuration.
+#[derive(Debug, defmt::Format)]
+pub enum ConfigStoreError {
+    /// The flash reported an error.
+    FlashError(Error),
+    /// A value does not fit in the configuration record.
+    ValueTooLong,
+    /// A value is outside the range it can be stored with.
+    InvalidValue,
+}
+
+impl From<Error> for ConfigStoreError {
+    fn from(err: Error) -> ConfigStoreError {
+        ConfigStoreError::FlashError(err)
+    }
+}
+
+/// `ConfigFlash` is a trait that defines the flash region the configuration records live in.
+///
+/// Offsets are relative to the start of the region.
+pub trait ConfigFlash {
+    /// Returns the size of the region, in bytes.
+    fn region_size(&self) -> u32;
+
+    /// Reads bytes from the region.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - The offset of the first byte to read.
+    /// * `bytes` - The buffer where the bytes will be stored.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error>;
+
+    /// Writes bytes to an erased part of the region.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - The offset of the first byte to write.
+    /// * `bytes` - The bytes to write.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error>;
+
+    /// Erases the whole region.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    fn erase(&mut self) -> Result<(), Error>;
+
+    /// Reads the boost profile descriptor staged for import, which lives outside the region.
+    ///
+    /// # Arguments
+    ///
+    /// * `descriptor` - The buffer where the descriptor will be stored.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    fn read_staged_profile(&mut self, descriptor: &mut [u8; DESCRIPTOR_LEN]) -> Result<(), Error>;
+}
+
+impl<'d> ConfigFlash for Flash<'d, Blocking> {
+    fn region_size(&self) -> u32 {
+        CONFIG_SECTOR_SIZE
+    }
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+        self.blocking_read(CONFIG_OFFSET + offset, bytes)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+        self.blocking_write(CONFIG_OFFSET + offset, bytes)
+    }
+
+    fn erase(&mut self) -> Result<(), Error> {
+        self.blocking_erase(CONFIG_OFFSET, CONFIG_OFFSET + CONFIG_SECTOR_SIZE)
+    }
+
+    fn read_staged_profile(&mut self, descriptor: &mut [u8; DESCRIPTOR_LEN]) -> Result<(), Error> {
+        self.blocking_read(STAGING_OFFSET, descriptor)
+    }
+}
+
+/// Computes the Fletcher-16 checksum of the given bytes.
+fn checksum(bytes: &[u8]) -> [u8; 2] {
+    let (mut low, mut high) = (0u16, 0u16);
+    for &byte in bytes {
+        low = (low + byte as u16) % 255;
+        high = (high + low) % 255;
+    }
+    [low as u8, high as u8]
+}
+
+/// `StoredConfig` holds the settings persisted across power cycles.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StoredConfig {
+    /// The address of the last connected device.
+    pub last_address: Option<String>,
+    /// The RPM range learned for the vehicle, if it has been calibrated.
+    pub vehicle_profile: Option<VehicleProfile>,
+    /// The tuned speed and RPM to behavior curve, if one has been stored.
+    pub boost_profile: Option<BoostProfile>,
+    /// The offset added to every mapped volume.
+    pub volume_trim: i8,
+}
+
+impl StoredConfig {
+    /// Encodes the configuration into a flash record.
+    fn encode(&self) -> Result<[u8; RECORD_SIZE], ConfigStoreError> {
+        let mut record = [0xFFu8; RECORD_SIZE];
+        record[..4].copy_from_slice(&RECORD_MAGIC);
+
+        let address = self.last_address.as_deref().unwrap_or("").as_bytes();
+        if address.len() > MAX_ADDRESS_LEN {
+            return Err(ConfigStoreError::ValueTooLong);
+        }
+        record[4] = address.len() as u8;
+        record[5..5 + address.len()].copy_from_slice(address);
+
+        if let Some(profile) = self.vehicle_profile {
+            record[PROFILE_OFFSET] = PROFILE_MARKER;
+            record[PROFILE_OFFSET + 1..PROFILE_OFFSET + 3]
+                .copy_from_slice(&profile.idle_rpm.to_le_bytes());
+            record[PROFILE_OFFSET + 3..PROFILE_OFFSET + 5]
+                .copy_from_slice(&profile.max_rpm.to_le_bytes());
+        }
+
+        if let Some(profile) = &self.boost_profile {
+            let descriptor = profile.encode().ok_or(ConfigStoreError::ValueTooLong)?;
+            record[BOOST_OFFSET] = BOOST_MARKER;
+            record[BOOST_OFFSET + 1..BOOST_OFFSET + 1 + DESCRIPTOR_LEN]
+                .copy_from_slice(&descriptor);
+        }
+
+        if self.volume_trim != 0 {
+            record[TRIM_OFFSET] = TRIM_MARKER;
+            record[TRIM_OFFSET + 1] = self.volume_trim as u8;
+        }
+
+        let sum = checksum(&record[..CHECKSUM_OFFSET]);
+        record[CHECKSUM_OFFSET..].copy_from_slice(&sum);
+
+        Ok(record)
+    }
+
+    /// Decodes a flash record.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Self>` - The stored configuration, or `None` if the record is erased, corrupt or
+    ///   was cut short.
+    fn decode(record: &[u8; RECORD_SIZE]) -> Option<Self> {
+        if record[..4] != RECORD_MAGIC
+            || record[CHECKSUM_OFFSET..] != checksum(&record[..CHECKSUM_OFFSET])
+        {
+            return None;
+        }
+
+        let len = record[4] as usize;
+        if len > MAX_ADDRESS_LEN {
+            return None;
+        }
+        let last_address = match core::str::from_utf8(&record[5..5 + len]) {
+            Ok(address) if !address.is_empty() => Some(address.to_string()),
+            _ => None,
+        };
+
+        let vehicle_profile = match record[PROFILE_OFFSET] {
+            PROFILE_MARKER => Some(VehicleProfile {
+                idle_rpm: u16::from_le_bytes([
+                    record[PROFILE_OFFSET + 1],
+                    record[PROFILE_OFFSET + 2],
+                ]),
+                max_rpm: u16::from_le_bytes([
+                    record[PROFILE_OFFSET + 3],
+                    record[PROFILE_OFFSET + 4],
+                ]),
+            }),
+            _ => None,
+        };
+
+        // A corrupt descriptor falls back to the curve baked into the firmware
+        let boost_profile = match record[BOOST_OFFSET] {
+            BOOST_MARKER => {
+                let mut descriptor = [0u8; DESCRIPTOR_LEN];
+                descriptor
+                    .copy_from_slice(&record[BOOST_OFFSET + 1..BOOST_OFFSET + 1 + DESCRIPTOR_LEN]);
+                BoostProfile::decode(&descriptor)
+            }
+            _ => None,
+        };
+
+        let volume_trim = match record[TRIM_OFFSET] {
+            TRIM_MARKER => record[TRIM_OFFSET + 1] as i8,
+            _ => 0,
+        };
+
+        Some(Self {
+            last_address,
+            vehicle_profile,
+            boost_profile,
+            volume_trim,
+        })
+    }
+}
+
+/// Tells whether a slot of the record log has never been written since the last erase.
+fn is_erased(record: &[u8; RECORD_SIZE]) -> bool {
+    record.iter().all(|&byte| byte == 0xFF)
+}
+
+/// Represents a configuration store shared between the components persisting settings.
+pub type SharedConfigStore<'a> = RefCell<ConfigStore<'a>>;
+
+/// `ConfigStore` is a struct that persists the configuration in the internal flash.
+///
+/// Erasing the 256 KiB sector takes seconds and wears the flash, so saves append records to the
+/// sector instead of rewriting it. The latest valid record holds the configuration, and the
+/// sector is only erased when no slot is left, once every thousand saves.
+pub struct ConfigStore<'a> {
+    /// The flash region holding the record log.
+    flash: &'a mut dyn ConfigFlash,
+    /// The configuration as last read from or written to the flash.
+    config: StoredConfig,
+    /// The index of the slot the next record is written to.
+    next_slot: u32,
+}
+
+impl<'a> ConfigStore<'a> {
+    /// Creates a new instance of `ConfigStore`, loading the configuration from the flash.
+    ///
+    /// # Arguments
+    ///
+    /// * `flash` - The flash region holding the record log, usually the internal flash.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `ConfigStore` instance.
+    pub fn new(flash: &'a mut dyn ConfigFlash) -> Self {
+        let slots = Self::slots(flash);
+        let mut config = StoredConfig::default();
+        let mut next_slot = slots;
+
+        let mut record = [0u8; RECORD_SIZE];
+        for slot in 0..slots {
+            if let Err(e) = flash.read(slot * RECORD_SIZE as u32, &mut record) {
+                error!("Failed to read the stored configuration: {:?}", e);
+                break;
+            }
+            if is_erased(&record) {
+                next_slot = slot;
+                break;
+            }
+            // A record cut short by a power loss is skipped, keeping the previous one
+            if let Some(stored) = StoredConfig::decode(&record) {
+                config = stored;
+            }
+        }
+
+        Self {
+            flash,
+            config,
+            next_slot,
+        }
+    }
+
+    /// Returns the number of records the flash region holds.
+    fn slots(flash: &dyn ConfigFlash) -> u32 {
+        flash.region_size() / RECORD_SIZE as u32
+    }
+
+    /// Returns the address of the last connected device, if one was stored.
+    pub fn last_address(&self) -> Option<&str> {
+        self.config.last_address.as_deref()
+    }
+
+    /// Stores the address of the last connected device.
+    ///
+    /// The flash is only written if the address changed.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address of the device.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    pub fn set_last_address(&mut self, address: &str) -> Result<(), ConfigStoreError> {
+        if self.last_address() == Some(address) {
+            return Ok(());
+        }
+
+        let mut config = self.config.clone();
+        config.last_address = Some(address.to_string());
+        self.save(config)
+    }
+
+    /// Returns the RPM range learned for the vehicle, if it has been calibrated.
+    pub fn vehicle_profile(&self) -> Option<VehicleProfile> {
+        self.config.vehicle_profile
+    }
+
+    /// Stores the RPM range learned for the vehicle.
+    ///
+    /// The flash is only written if the profile changed.
+    ///
+    /// # Arguments
+    ///
+    /// * `profile` - The learned profile.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    pub fn set_vehicle_profile(&mut self, profile: VehicleProfile) -> Result<(), ConfigStoreError> {
+        if self.vehicle_profile() == Some(profile) {
+            return Ok(());
+        }
+
+        let mut config = self.config.clone();
+        config.vehicle_profile = Some(profile);
+        self.save(config)
+    }
+
+    /// Returns the tuned speed and RPM to behavior curve, if a valid one was stored.
+    pub fn boost_profile(&self) -> Option<BoostProfile> {
+        self.config.boost_profile
+    }
+
+    /// Stores a tuned speed and RPM to behavior curve, loaded into the mapping on next boot.
+    ///
+    /// The flash is only written if the profile changed.
+    ///
+    /// # Arguments
+    ///
+    /// * `profile` - The tuned profile.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    pub fn set_boost_profile(&mut self, profile: BoostProfile) -> Result<(), ConfigStoreError> {
+        if !profile.is_valid() {
+            return Err(ConfigStoreError::InvalidValue);
+        }
+        if self.boost_profile() == Some(profile) {
+            return Ok(());
+        }
+
+        let mut config = self.config.clone();
+        config.boost_profile = Some(profile);
+        self.save(config)
+    }
+
+    /// Stores the boost profile staged for import, if one was written to the staging sector.
+    ///
+    /// Power users tune the curve by writing a descriptor, laid out as documented on
+    /// `BoostProfile`, to the start of the staging sector with `probe-rs download`. The staged
+    /// profile is read on every boot, and the flash is only written when it differs from the
+    /// stored one. An erased staging sector keeps the stored profile, and an invalid descriptor
+    /// is ignored.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<BoostProfile>` - The imported profile, or `None` if no valid one was staged.
+    /// * `ConfigStoreError` - The staging sector could not be read, or the profile not stored.
+    pub fn import_boost_profile(&mut self) -> Result<Option<BoostProfile>, ConfigStoreError> {
+        let mut descriptor = [0u8; DESCRIPTOR_LEN];
+        self.flash.read_staged_profile(&mut descriptor)?;
+        if descriptor.iter().all(|&byte| byte == 0xFF) {
+            return Ok(None);
+        }
+
+        match BoostProfile::decode(&descriptor) {
+            Some(profile) => {
+                self.set_boost_profile(profile)?;
+                Ok(Some(profile))
+            }
+            None => {
+                warn!("Ignoring the invalid staged boost profile");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Returns the offset added to every mapped volume, zero if none was stored.
+    pub fn volume_trim(&self) -> i8 {
+        self.config.volume_trim
+    }
+
+    /// Stores the offset added to every mapped volume.
+    ///
+    /// The flash is only written if the trim changed.
+    ///
+    /// # Arguments
+    ///
+    /// * `trim` - The volume offset, in levels.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    pub fn set_volume_trim(&mut self, trim: i8) -> Result<(), ConfigStoreError> {
+        if self.volume_trim() == trim {
+            return Ok(());
+        }
+
+        let mut config = self.config.clone();
+        config.volume_trim = trim;
+        self.save(config)
+    }
+
+    /// Appends the given configuration to the record log, erasing the sector first if it is full.
+    fn save(&mut self, config: StoredConfig) -> Result<(), ConfigStoreError> {
+        let record = config.encode()?;
+
+        if self.next_slot >= Self::slots(self.flash) {
+            info!("Configuration sector full, erasing it");
+            self.flash.erase()?;
+            self.next_slot = 0;
+        }
+        let slot = self.next_slot;
+        // The slot is used up even if the write fails half way, so the next save skips it
+        self.next_slot += 1;
+        self.flash.write(slot * RECORD_SIZE as u32, &record)?;
+
+        info!("Configuration saved to flash slot {}", slot);
+        self.config = config;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "simulation"))]
+mod tests {
+    use super::*;
+    use crate::audio::loudness_curve::LoudnessCurve;
+    use crate::storage::ram_flash::RamFlash;
+
+    /// The size of the simulated configuration region, room for 16 records.
+    const REGION_SIZE: usize = 16 * RECORD_SIZE;
+
+    /// Returns a tuned profile, different from the baked-in defaults.
+    fn tuned_profile() -> BoostProfile {
+        BoostProfile {
+            max_volume: 12,
+            max_bass: 9,
+            soft_knee: Some(2.0),
+            loudness: LoudnessCurve::Logarithmic,
+            expander: false,
+            bass_slope: 0.8,
+            rpm_weight: 0.5,
+            throttle_weight: 0.5,
+            safety_speed_cap: None,
+        }
+    }
+
+    #[test]
+    fn a_stored_boost_profile_is_loaded_on_the_next_boot() {
+        let mut flash = RamFlash::new(REGION_SIZE);
+        ConfigStore::new(&mut flash)
+            .set_boost_profile(tuned_profile())
+            .unwrap();
+
+        let store = ConfigStore::new(&mut flash);
+
+        assert_eq!(store.boost_profile(), Some(tuned_profile()));
+    }
+
+    #[test]
+    fn an_empty_flash_has_no_boost_profile() {
+        let mut flash = RamFlash::new(REGION_SIZE);
+
+        let mut store = ConfigStore::new(&mut flash);
+
+        assert_eq!(store.boost_profile(), None);
+        assert_eq!(store.import_boost_profile().unwrap(), None);
+    }
+
+    #[test]
+    fn a_staged_boost_profile_is_imported_and_kept() {
+        let mut flash = RamFlash::new(REGION_SIZE);
+        flash.stage_profile(tuned_profile().encode().unwrap());
+
+        let imported = ConfigStore::new(&mut flash).import_boost_profile().unwrap();
+        assert_eq!(imported, Some(tuned_profile()));
+
+        // Erasing the staging sector keeps the imported profile
+        flash.stage_profile([0xFF; DESCRIPTOR_LEN]);
+        let mut store = ConfigStore::new(&mut flash);
+        assert_eq!(store.import_boost_profile().unwrap(), None);
+        assert_eq!(store.boost_profile(), Some(tuned_profile()));
+    }
+
+    #[test]
+    fn an_invalid_staged_boost_profile_keeps_the_stored_one() {
+        let mut flash = RamFlash::new(REGION_SIZE);
+        ConfigStore::new(&mut flash)
+            .set_boost_profile(tuned_profile())
+            .unwrap();
+        let mut corrupt = tuned_profile().encode().unwrap();
+        corrupt[1] ^= 0x01;
+        flash.stage_profile(corrupt);
+
+        let mut store = ConfigStore::new(&mut flash);
+
+        assert_eq!(store.import_boost_profile().unwrap(), None);
+        assert_eq!(store.boost_profile(), Some(tuned_profile()));
+    }
+
+    #[test]
+    fn an_out_of_range_boost_profile_is_not_stored() {
+        let mut flash = RamFlash::new(REGION_SIZE);
+        let mut store = ConfigStore::new(&mut flash);
+        let profile = BoostProfile {
+            rpm_weight: f32::NAN,
+            ..tuned_profile()
+        };
+
+        let result = store.set_boost_profile(profile);
+
+        assert!(matches!(result, Err(ConfigStoreError::InvalidValue)));
+        assert_eq!(store.boost_profile(), None);
+    }
+}
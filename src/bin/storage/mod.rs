@@ -0,0 +1,4 @@
+pub mod boost_profile;
+pub mod config_store;
+#[cfg(feature = "simulation")]
+pub mod ram_flash;
@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+
+use crate::storage::boost_profile::DESCRIPTOR_LEN;
+use crate::storage::config_store::ConfigFlash;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+use embassy_stm32::flash::Error;
+
+/// `RamFlash` is a `ConfigFlash` kept in RAM, standing in for the internal flash.
+///
+/// Like NOR flash, writes can only clear bits, so a slot written twice without an erase reads
+/// back garbage instead of the latest record.
+pub struct RamFlash {
+    /// The contents of the configuration region.
+    region: Vec<u8>,
+    /// The boost profile descriptor staged for import.
+    staged: [u8; DESCRIPTOR_LEN],
+}
+
+impl RamFlash {
+    /// Creates a new instance of `RamFlash`, erased.
+    ///
+    /// # Arguments
+    ///
+    /// * `region_size` - The size of the configuration region, in bytes.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `RamFlash` instance.
+    pub fn new(region_size: usize) -> Self {
+        Self {
+            region: vec![0xFF; region_size],
+            staged: [0xFF; DESCRIPTOR_LEN],
+        }
+    }
+
+    /// Stages a boost profile descriptor for import, as `probe-rs download` would.
+    ///
+    /// # Arguments
+    ///
+    /// * `descriptor` - The descriptor to stage.
+    pub fn stage_profile(&mut self, descriptor: [u8; DESCRIPTOR_LEN]) {
+        self.staged = descriptor;
+    }
+
+    /// Returns the indices of the bytes covered by an access, or `Size` if it runs past the end of
+    /// the region.
+    fn range(&self, offset: u32, len: usize) -> Result<Range<usize>, Error> {
+        let start = offset as usize;
+        match start.checked_add(len) {
+            Some(end) if end <= self.region.len() => Ok(start..end),
+            _ => Err(Error::Size),
+        }
+    }
+}
+
+impl ConfigFlash for RamFlash {
+    fn region_size(&self) -> u32 {
+        self.region.len() as u32
+    }
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+        let range = self.range(offset, bytes.len())?;
+        bytes.copy_from_slice(&self.region[range]);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+        let range = self.range(offset, bytes.len())?;
+        for (cell, &byte) in self.region[range].iter_mut().zip(bytes) {
+            *cell &= byte;
+        }
+        Ok(())
+    }
+
+    fn erase(&mut self) -> Result<(), Error> {
+        self.region.fill(0xFF);
+        Ok(())
+    }
+
+    fn read_staged_profile(&mut self, descriptor: &mut [u8; DESCRIPTOR_LEN]) -> Result<(), Error> {
+        *descriptor = self.staged;
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+#![no_std]
+#![no_main]
+
+use crate::audio::audio_behavior::AudioBehavior;
+use crate::csr8645::csr8645::ConnectionState;
+use defmt::info;
+use embassy_time::{Duration, Instant};
+
+/// `TelemetrySample` bundles the vehicle and audio state reported in one telemetry line.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct TelemetrySample {
+    /// The vehicle speed, in km/h.
+    pub speed: u8,
+    /// The engine speed, in revolutions per minute.
+    pub rpm: u16,
+    /// The audio behavior computed from the vehicle data.
+    pub behavior: AudioBehavior,
+    /// The state of the Bluetooth link.
+    pub connection_state: ConnectionState,
+    /// The RSSI of the Bluetooth link in dBm, if it could be read.
+    pub rssi: Option<i8>,
+}
+
+/// `Telemetry` is a struct that emits rate-limited telemetry over defmt.
+///
+/// However often it is fed, at most one line is logged per interval.
+pub struct Telemetry {
+    /// The minimum time between two telemetry lines.
+    interval: Duration,
+    /// The time the last telemetry line was emitted.
+    last_emit: Option<Instant>,
+}
+
+impl Telemetry {
+    /// Creates a new instance of `Telemetry`.
+    ///
+    /// # Arguments
+    ///
+    /// * `interval` - The minimum time between two telemetry lines.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `Telemetry` instance.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_emit: None,
+        }
+    }
+
+    /// Checks whether a telemetry line is due.
+    ///
+    /// Callers can use this to skip gathering expensive values, such as the RSSI, when the
+    /// sample would be dropped anyway.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current time.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the interval has elapsed since the last line, false otherwise.
+    pub fn is_due(&self, now: Instant) -> bool {
+        match self.last_emit {
+            Some(last) => now.saturating_duration_since(last) >= self.interval,
+            None => true,
+        }
+    }
+
+    /// Emits a telemetry line if one is due.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current time.
+    /// * `sample` - The state to report.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the line was emitted, false if it was coalesced.
+    pub fn record(&mut self, now: Instant, sample: &TelemetrySample) -> bool {
+        if !self.is_due(now) {
+            return false;
+        }
+
+        info!("Telemetry: {:?}", sample);
+        self.last_emit = Some(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The interval between telemetry lines used by the tests.
+    const INTERVAL: Duration = Duration::from_millis(100);
+
+    /// Returns a sample of a car cruising with the link up.
+    fn sample() -> TelemetrySample {
+        TelemetrySample {
+            speed: 50,
+            rpm: 2500,
+            behavior: AudioBehavior::default(),
+            connection_state: ConnectionState::Connected,
+            rssi: Some(-60),
+        }
+    }
+
+    /// Feeds the telemetry every `period` for one second and returns the times lines were emitted.
+    fn emitted_over_a_second(period: u64) -> Vec<u64> {
+        let mut telemetry = Telemetry::new(INTERVAL);
+        (0..1000)
+            .step_by(period as usize)
+            .filter(|&ms| telemetry.record(Instant::from_millis(ms), &sample()))
+            .collect()
+    }
+
+    #[test]
+    fn the_first_sample_is_emitted() {
+        let mut telemetry = Telemetry::new(INTERVAL);
+
+        assert!(telemetry.is_due(Instant::from_millis(0)));
+        assert!(telemetry.record(Instant::from_millis(0), &sample()));
+        assert!(!telemetry.is_due(Instant::from_millis(99)));
+        assert!(telemetry.is_due(Instant::from_millis(100)));
+    }
+
+    #[test]
+    fn at_most_one_line_is_emitted_per_interval_whatever_the_loop_rate() {
+        for period in [1, 7, 33, 100, 250] {
+            let emitted = emitted_over_a_second(period);
+
+            assert!(
+                emitted.windows(2).all(|pair| pair[1] - pair[0] >= 100),
+                "{:?}",
+                emitted
+            );
+            assert!(emitted.len() <= 10, "{:?}", emitted);
+        }
+        // A fast loop is coalesced down to one line per interval
+        assert_eq!(
+            emitted_over_a_second(1),
+            [0, 100, 200, 300, 400, 500, 600, 700, 800, 900]
+        );
+        // A loop slower than the interval logs every iteration
+        assert_eq!(emitted_over_a_second(250), [0, 250, 500, 750]);
+    }
+}
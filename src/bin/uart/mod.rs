@@ -1,2 +1,3 @@
+pub mod shared_uart;
 pub mod uart_controller;
 pub mod uart_service;
@@ -0,0 +1,208 @@
+#![no_std]
+#![no_main]
+
+use crate::csr8645::byte_channel::{ByteChannel, ReconfigureError};
+use defmt::warn;
+use embassy_stm32::usart::Error;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::{Mutex, MutexGuard};
+
+/// Represents a UART shared by several drivers, e.g. the CSR8645 module and the OBD-II adapter
+/// multiplexed on a single bus.
+pub type SharedUart<C> = Mutex<CriticalSectionRawMutex, C>;
+
+/// `SharedChannel` is the handle a driver talks through to a `SharedUart`.
+///
+/// Each driver gets its own handle and brackets every command/response exchange with
+/// `begin_exchange` and `end_exchange`. The bus stays locked in between, so a command and its
+/// response complete atomically and no user ever reads the bytes answering another user.
+///
+/// Holding the bus for a whole exchange means the other users wait for it, including while the
+/// exchange waits on a slow response or times out. Time-critical traffic such as audio streaming
+/// should not share a bus with a user whose exchanges take long. The bus configuration is shared
+/// too: a baud rate change applies to every user.
+///
+/// Operations issued outside an exchange lock the bus for their own duration only.
+pub struct SharedChannel<'a, C: ByteChannel> {
+    /// The shared UART.
+    uart: &'a SharedUart<C>,
+    /// The lock on the UART held for the exchange in progress, if any.
+    guard: Option<MutexGuard<'a, CriticalSectionRawMutex, C>>,
+}
+
+impl<'a, C: ByteChannel> SharedChannel<'a, C> {
+    /// Creates a new instance of `SharedChannel`.
+    ///
+    /// # Arguments
+    ///
+    /// * `uart` - The shared UART.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The new `SharedChannel` instance.
+    pub fn new(uart: &'a SharedUart<C>) -> Self {
+        Self { uart, guard: None }
+    }
+
+    /// Returns true while an exchange holds the bus.
+    pub fn in_exchange(&self) -> bool {
+        self.guard.is_some()
+    }
+}
+
+impl<'a, C: ByteChannel> ByteChannel for SharedChannel<'a, C> {
+    async fn write_some(&mut self, data: &[u8]) -> Result<usize, Error> {
+        match &mut self.guard {
+            Some(uart) => uart.write_some(data).await,
+            None => self.uart.lock().await.write_some(data).await,
+        }
+    }
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        match &mut self.guard {
+            Some(uart) => uart.read(buf).await,
+            None => self.uart.lock().await.read(buf).await,
+        }
+    }
+
+    async fn read_until_idle(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        match &mut self.guard {
+            Some(uart) => uart.read_until_idle(buf).await,
+            None => self.uart.lock().await.read_until_idle(buf).await,
+        }
+    }
+
+    /// Discards the received bytes, unless another user is in the middle of an exchange and the
+    /// bytes belong to it.
+    fn flush_rx(&mut self) {
+        match &mut self.guard {
+            Some(uart) => uart.flush_rx(),
+            None => match self.uart.try_lock() {
+                Ok(mut uart) => uart.flush_rx(),
+                Err(_) => warn!("Shared UART busy, RX flush skipped"),
+            },
+        }
+    }
+
+    async fn flush_tx(&mut self) -> Result<(), Error> {
+        match &mut self.guard {
+            Some(uart) => uart.flush_tx().await,
+            None => self.uart.lock().await.flush_tx().await,
+        }
+    }
+
+    /// Re-opens the bus at another baud rate, for every user.
+    ///
+    /// The bus cannot be reconfigured under another user's exchange, so the change is refused
+    /// with `ReconfigureError::Busy` while the bus is busy.
+    fn set_baudrate(&mut self, baudrate: u32) -> Result<(), ReconfigureError> {
+        match &mut self.guard {
+            Some(uart) => uart.set_baudrate(baudrate),
+            None => match self.uart.try_lock() {
+                Ok(mut uart) => uart.set_baudrate(baudrate),
+                Err(_) => {
+                    warn!("Shared UART busy, baud rate change refused");
+                    Err(ReconfigureError::Busy)
+                }
+            },
+        }
+    }
+
+    /// Returns the overruns of the bus, or zero while another user holds it.
+    fn overruns(&self) -> u32 {
+        match &self.guard {
+            Some(uart) => uart.overruns(),
+            None => self.uart.try_lock().map_or(0, |uart| uart.overruns()),
+        }
+    }
+
+    async fn begin_exchange(&mut self) {
+        if self.guard.is_none() {
+            self.guard = Some(self.uart.lock().await);
+        }
+    }
+
+    fn end_exchange(&mut self) {
+        self.guard = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csr8645::loopback::LoopbackChannel;
+    use crate::obd::obd_service::{ObdService, ObdServiceImpl};
+    use embassy_futures::join::join;
+    use embassy_futures::{block_on, yield_now};
+    use embassy_time::{with_timeout, Duration};
+
+    /// The number of exchanges each user runs.
+    const EXCHANGES: u8 = 4;
+
+    /// Returns a shared UART over a loopback channel echoing every write.
+    fn echo_uart() -> SharedUart<LoopbackChannel> {
+        let mut channel = LoopbackChannel::new();
+        channel.set_echo(true);
+        SharedUart::new(channel)
+    }
+
+    /// Runs a few exchanges, yielding between each command and its response, and checks every
+    /// response is the echo of the command just sent.
+    async fn exchange_messages(mut channel: SharedChannel<'_, LoopbackChannel>, user: u8) {
+        for index in 0..EXCHANGES {
+            let command = [user, b'0' + index, b'\r', b'\n'];
+            channel.begin_exchange().await;
+            channel.write_some(&command).await.unwrap();
+            // Let the other user run between the command and its response
+            yield_now().await;
+            let mut response = [0u8; 16];
+            let len = channel.read_until_idle(&mut response).await.unwrap();
+            assert_eq!(&response[..len], &command);
+            channel.end_exchange();
+            yield_now().await;
+        }
+    }
+
+    #[test]
+    fn interleaved_exchanges_each_read_their_own_response() {
+        let uart = echo_uart();
+
+        block_on(join(
+            exchange_messages(SharedChannel::new(&uart), b'A'),
+            exchange_messages(SharedChannel::new(&uart), b'B'),
+        ));
+
+        let written = uart.try_lock().unwrap().written().len();
+        assert_eq!(written, 2 * 4 * EXCHANGES as usize);
+    }
+
+    #[test]
+    fn set_baudrate_is_refused_during_another_exchange() {
+        let uart = echo_uart();
+        let mut bluetooth = SharedChannel::new(&uart);
+        let mut obd = SharedChannel::new(&uart);
+
+        block_on(obd.begin_exchange());
+        assert!(obd.in_exchange());
+        assert_eq!(bluetooth.set_baudrate(9600), Err(ReconfigureError::Busy));
+
+        obd.end_exchange();
+        assert_eq!(bluetooth.set_baudrate(9600), Ok(()));
+        assert_eq!(uart.try_lock().unwrap().baudrate(), Some(9600));
+    }
+
+    #[test]
+    fn a_timed_out_command_releases_the_bus() {
+        // The adapter never answers, so the command waits until it is cancelled
+        let uart = SharedUart::new(LoopbackChannel::new());
+        let mut obd = ObdServiceImpl::new(SharedChannel::new(&uart));
+
+        let result = block_on(with_timeout(
+            Duration::from_millis(20),
+            obd.send_command("010C"),
+        ));
+
+        assert!(result.is_err());
+        assert_eq!(uart.try_lock().unwrap().written(), b"010C\r");
+    }
+}
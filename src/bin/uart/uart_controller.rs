@@ -1,7 +1,8 @@
 #![no_std]
 #![no_main]
 
-use crate::uart::uart_service::UartService;
+use crate::uart::uart_service::{UartError, UartFraming, UartService};
+use embassy_stm32::usart;
 use embassy_stm32::Peripherals;
 
 /// `UartController` is a structure that handles high-level operations with the UART.
@@ -22,9 +23,48 @@ impl<'a> UartController<'a> {
     /// # Returns
     ///
     /// A `Result` indicating the success or failure of the operation.
-    pub fn new(p: Peripherals, baudrate: u32) -> Result<Self, dyn defmt::Format> {
+    pub fn new(p: Peripherals, baudrate: u32) -> Result<Self, UartError> {
         let uart_service = UartService::new(p, baudrate)?;
 
         Ok(Self { uart_service })
     }
+
+    /// Creates a new instance of `UartController` with the given framing.
+    ///
+    /// # Arguments
+    ///
+    /// * `p` - An instance of `Peripherals`.
+    /// * `baudrate` - The baud rate for the UART.
+    /// * `framing` - The data bits, parity and stop bits for the UART.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    pub fn new_with_framing(
+        p: Peripherals,
+        baudrate: u32,
+        framing: UartFraming,
+    ) -> Result<Self, UartError> {
+        let uart_service = UartService::new_with_framing(p, baudrate, framing)?;
+
+        Ok(Self { uart_service })
+    }
+
+    /// Sends a command and reads its reply.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command to send.
+    /// * `reply` - The buffer where the reply will be stored.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the number of reply bytes received or an error.
+    pub async fn send_command(
+        &mut self,
+        command: &[u8],
+        reply: &mut [u8],
+    ) -> Result<usize, usart::Error> {
+        self.uart_service.send_command(command, reply).await
+    }
 }
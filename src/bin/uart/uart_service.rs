@@ -2,20 +2,102 @@
 #![no_main]
 
 use defmt::{error, info};
-use embassy_stm32::dma::NoDma;
-use embassy_stm32::interrupt;
-use embassy_stm32::usart::{Config, Uart};
-use embassy_stm32::Peripherals;
+use embassy_stm32::usart::{self, Config, ConfigError, DataBits, Parity, StopBits, Uart};
+use embassy_stm32::{bind_interrupts, peripherals, Peripherals};
+
+bind_interrupts!(
+    /// Binds the USART1 interrupt to the embassy UART driver.
+    pub struct Irqs {
+        USART1 => usart::InterruptHandler<peripherals::USART1>;
+    }
+);
+
+/// Represents an error that can occur in the UART service.
+#[derive(Debug, defmt::Format)]
+pub enum UartError {
+    /// The UART peripheral rejected the configuration.
+    Config(ConfigError),
+    /// The requested combination of data bits, parity and stop bits is not supported.
+    UnsupportedFraming,
+}
+
+impl From<ConfigError> for UartError {
+    fn from(err: ConfigError) -> UartError {
+        UartError::Config(err)
+    }
+}
+
+/// `UartFraming` describes the character framing used on the UART line.
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct UartFraming {
+    /// The number of data bits per character.
+    pub data_bits: u8,
+    /// The parity mode.
+    pub parity: Parity,
+    /// The number of stop bits.
+    pub stop_bits: StopBits,
+}
+
+impl UartFraming {
+    /// The 8 data bits, no parity, 1 stop bit framing used by most adapters.
+    pub const EIGHT_N_ONE: UartFraming = UartFraming {
+        data_bits: 8,
+        parity: Parity::ParityNone,
+        stop_bits: StopBits::STOP1,
+    };
+
+    /// Builds the UART `Config` matching this framing.
+    ///
+    /// The peripheral always transmits 8 data bits, adding a ninth bit when parity is enabled,
+    /// so other data bit counts and the smartcard-only stop bit lengths are rejected.
+    ///
+    /// # Arguments
+    ///
+    /// * `baudrate` - The baud rate for the UART.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `Config` or an error if the framing is not supported.
+    pub fn to_config(&self, baudrate: u32) -> Result<Config, UartError> {
+        if self.data_bits != 8 {
+            return Err(UartError::UnsupportedFraming);
+        }
+
+        if !matches!(self.stop_bits, StopBits::STOP1 | StopBits::STOP2) {
+            return Err(UartError::UnsupportedFraming);
+        }
+
+        let mut config = Config::default();
+        config.baudrate = baudrate;
+        config.data_bits = DataBits::DataBits8;
+        config.parity = self.parity;
+        config.stop_bits = self.stop_bits;
+
+        Ok(config)
+    }
+}
 
 /// `UartService` is a structure that handles low-level operations with the UART.
 ///
 /// This structure provides methods for initializing the UART and configuring it.
+///
+/// The constructors consume `Peripherals` and the interrupt is bound statically through `Irqs`,
+/// so a second `UartService` cannot be created while the first one owns the UART.
+///
+/// ```compile_fail,E0382
+/// # use dmz_sound_booster::uart::uart_service::UartService;
+/// # fn open_twice(p: embassy_stm32::Peripherals) {
+/// let first = UartService::new(p, 115_200);
+/// // The peripherals were moved into the first service
+/// let second = UartService::new(p, 115_200);
+/// # }
+/// ```
 pub struct UartService<'a> {
-    uart: Uart<'a, interrupt::USART1, NoDma, NoDma>,
+    uart: Uart<'a, peripherals::USART1, peripherals::DMA2_CH7, peripherals::DMA2_CH2>,
 }
 
 impl<'a> UartService<'a> {
-    /// Creates a new instance of `UartService`.
+    /// Creates a new instance of `UartService` using 8N1 framing.
     ///
     /// # Arguments
     ///
@@ -25,16 +107,53 @@ impl<'a> UartService<'a> {
     /// # Returns
     ///
     /// A `Result` indicating the success or failure of the operation.
-    pub fn new(p: Peripherals, baudrate: u32) -> Result<Self, dyn defmt::Format> {
-        let mut config = Config::default();
-        config.baudrate = baudrate;
+    pub fn new(p: Peripherals, baudrate: u32) -> Result<Self, UartError> {
+        Self::new_with_framing(p, baudrate, UartFraming::EIGHT_N_ONE)
+    }
 
-        let irq = interrupt::USART1.steal();
-        let tx_dma = NoDma;
-        let rx_dma = NoDma;
+    /// Creates a new instance of `UartService` with the given framing.
+    ///
+    /// # Arguments
+    ///
+    /// * `p` - An instance of `Peripherals`.
+    /// * `baudrate` - The baud rate for the UART.
+    /// * `framing` - The data bits, parity and stop bits for the UART.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    pub fn new_with_framing(
+        p: Peripherals,
+        baudrate: u32,
+        framing: UartFraming,
+    ) -> Result<Self, UartError> {
+        let config = framing.to_config(baudrate).map_err(|e| {
+            error!("Unsupported UART framing: {:?}", framing);
+            e
+        })?;
+
+        Self::new_with_config(p, config)
+    }
+
+    /// Creates a new instance of `UartService` from a complete UART `Config`.
+    ///
+    /// # Arguments
+    ///
+    /// * `p` - An instance of `Peripherals`.
+    /// * `config` - The UART configuration.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    pub fn new_with_config(p: Peripherals, config: Config) -> Result<Self, UartError> {
+        let baudrate = config.baudrate;
+
+        let tx_dma = p.DMA2_CH7;
+        // Idle line detection only works with DMA reception
+        let rx_dma = p.DMA2_CH2;
 
         let uart =
-            Uart::new(p.USART1, p.PA9, p.PA10, irq, tx_dma, rx_dma, config).map_err(|e| {
+            Uart::new(p.USART1, p.PA10, p.PA9, Irqs, tx_dma, rx_dma, config).map_err(|e| {
                 error!(
                     "Failed to initialize UART with baudrate {}: {:?}",
                     baudrate, e
@@ -42,6 +161,113 @@ impl<'a> UartService<'a> {
                 e
             })?;
 
+        info!("UART initialized with baudrate {}", baudrate);
+
         Ok(Self { uart })
     }
+
+    /// Reads bytes until the line goes idle or the given buffer is full.
+    ///
+    /// The USART raises its IDLE flag once a character time passes without reception, which
+    /// marks the end of a variable-length response without knowing its length in advance.
+    ///
+    /// # Arguments
+    ///
+    /// * `buf` - The buffer where the received bytes will be stored.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the number of bytes received or an error.
+    pub async fn read_until_idle(&mut self, buf: &mut [u8]) -> Result<usize, usart::Error> {
+        self.uart.read_until_idle(buf).await
+    }
+
+    /// Sends a command and reads its reply.
+    ///
+    /// The transmitter is flushed between the two, so the reply is not awaited before the whole
+    /// command has left the UART.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command to send.
+    /// * `reply` - The buffer where the reply will be stored.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the number of reply bytes received or an error.
+    pub async fn send_command(
+        &mut self,
+        command: &[u8],
+        reply: &mut [u8],
+    ) -> Result<usize, usart::Error> {
+        self.uart.write(command).await?;
+        self.flush().await?;
+        self.read_until_idle(reply).await
+    }
+
+    /// Waits until all the written bytes have left the transmitter.
+    ///
+    /// Replies to a command must not be awaited before the command itself has been sent, which
+    /// matters once TX goes through a DMA channel or a buffer.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the success or failure of the operation.
+    pub async fn flush(&mut self) -> Result<(), usart::Error> {
+        embedded_io_async::Write::flush(&mut self.uart)
+            .await
+            .map_err(|e| {
+                error!("Failed to flush the UART: {:?}", e);
+                e
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_config_fields_are_set_as_requested() {
+        let framing = UartFraming {
+            data_bits: 8,
+            parity: Parity::ParityEven,
+            stop_bits: StopBits::STOP2,
+        };
+
+        let config = framing.to_config(9600).unwrap();
+
+        assert_eq!(config.baudrate, 9600);
+        assert_eq!(config.data_bits, DataBits::DataBits8);
+        assert_eq!(config.parity, Parity::ParityEven);
+        assert_eq!(config.stop_bits, StopBits::STOP2);
+    }
+
+    #[test]
+    fn eight_n_one_keeps_the_default_config() {
+        let config = UartFraming::EIGHT_N_ONE.to_config(115_200).unwrap();
+
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn unsupported_framings_are_rejected() {
+        let seven_bits = UartFraming {
+            data_bits: 7,
+            ..UartFraming::EIGHT_N_ONE
+        };
+        let half_stop_bit = UartFraming {
+            stop_bits: StopBits::STOP0P5,
+            ..UartFraming::EIGHT_N_ONE
+        };
+
+        assert!(matches!(
+            seven_bits.to_config(9600),
+            Err(UartError::UnsupportedFraming)
+        ));
+        assert!(matches!(
+            half_stop_bit.to_config(9600),
+            Err(UartError::UnsupportedFraming)
+        ));
+    }
 }
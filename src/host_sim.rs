@@ -0,0 +1,188 @@
+//! Runs the app on the host against the simulation doubles.
+
+use crate::app::{App, IGNITION_OFF};
+use crate::audio::audio_behavior::AudioBehavior;
+use crate::audio::audio_preset::PresetManager;
+use crate::audio::volume_schedule::{FixedClock, TimeOfDay};
+use crate::bluetooth::bluetooth_controller::BluetoothController;
+use crate::bluetooth::mock_csr8645::MockCsr8645Interface;
+use crate::config::app_config::AppConfig;
+use crate::obd::obd_controller::ObdController;
+use crate::obd::simulated_obd::{DriveSample, SimulatedObdTransport};
+use crate::storage::config_store::ConfigStore;
+use crate::storage::ram_flash::RamFlash;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use defmt::error;
+use embassy_time::{Duration, Timer};
+use futures::future::join;
+
+/// The size of the simulated flash region holding the configuration records.
+const FLASH_REGION_SIZE: usize = 4096;
+
+/// The time of day reported to the volume schedule, outside any quiet window.
+const SIMULATED_TIME: TimeOfDay = TimeOfDay::new(12, 0);
+
+/// The time the app keeps running on the last sample of a drive cycle, so the smoothed behavior
+/// settles before it is shut down.
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// Runs the app over a scripted drive cycle and returns the behaviors it applied.
+///
+/// The app talks to a `MockCsr8645Interface` and a `SimulatedObdTransport` and keeps its
+/// configuration in a `RamFlash`, so the run is the same on any host. Once the drive cycle is
+/// over and the behavior has settled, the app is shut down as on ignition-off.
+///
+/// # Arguments
+///
+/// * `script` - The drive cycle, ordered by time.
+/// * `config` - The settings of the app, whose poll schedule is used for the simulated adapter.
+///
+/// # Returns
+///
+/// * `Vec<AudioBehavior>` - The behaviors applied to the module, in order.
+pub async fn run_drive_cycle(
+    script: &'static [DriveSample],
+    config: &AppConfig,
+) -> Vec<AudioBehavior> {
+    let mut flash = RamFlash::new(FLASH_REGION_SIZE);
+    let config_store = RefCell::new(ConfigStore::new(&mut flash));
+    let mut bluetooth = BluetoothController::new(MockCsr8645Interface::new(), &config_store);
+    // Every behavior reaches the module, so the whole sequence is captured
+    bluetooth.set_behavior_interval(Duration::from_ticks(0));
+    let bluetooth = &bluetooth;
+
+    let transport = SimulatedObdTransport::new(script);
+    let duration = transport.duration();
+    let mut obd_module = ObdController::new(transport);
+    if let Err(e) = obd_module.init().await {
+        error!("Failed to initialize the simulated adapter: {:?}", e);
+    }
+    obd_module.set_poll_schedule(config.poll_schedule.clone());
+
+    let mut app = App::new(
+        bluetooth,
+        bluetooth,
+        obd_module,
+        PresetManager::new(config.default_preset),
+        FixedClock(SIMULATED_TIME),
+        &config_store,
+        config,
+    );
+    join(app.run(), async {
+        Timer::after(duration + SETTLE_TIME).await;
+        IGNITION_OFF.signal(());
+    })
+    .await;
+
+    bluetooth.service().applied_behaviors()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::tests::APP_LOCK;
+    use crate::audio::audio_mapping::{map_sensor_data_to_audio_behavior, MappingConfig};
+    use crate::audio::audio_preset::AudioPreset;
+    use crate::audio::volume_schedule::QuietWindow;
+    use crate::obd::gear_estimator::GearEstimator;
+    use embassy_futures::block_on;
+    use std::sync::PoisonError;
+
+    /// Idles, pulls away to a short cruise and stops again.
+    static DRIVE_CYCLE: [DriveSample; 3] = [
+        DriveSample {
+            at: Duration::from_millis(0),
+            speed: 0,
+            rpm: 800,
+        },
+        DriveSample {
+            at: Duration::from_millis(200),
+            speed: 50,
+            rpm: 2800,
+        },
+        DriveSample {
+            at: Duration::from_millis(700),
+            speed: 0,
+            rpm: 800,
+        },
+    ];
+
+    /// Returns the behavior the mapping computes for a sample of the drive cycle.
+    fn expected_behavior(config: &AppConfig, sample: DriveSample) -> AudioBehavior {
+        let gear = GearEstimator::default().estimate(sample.speed, sample.rpm);
+        map_sensor_data_to_audio_behavior(
+            sample.speed,
+            sample.rpm,
+            config.default_preset,
+            gear,
+            None,
+            None,
+            &config.mapping,
+        )
+    }
+
+    #[test]
+    fn a_short_drive_cycle_applies_the_mapped_behaviors() {
+        let _lock = APP_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let config = AppConfig::default();
+
+        let applied = block_on(run_drive_cycle(&DRIVE_CYCLE, &config));
+
+        let idle = expected_behavior(&config, DRIVE_CYCLE[0]);
+        let cruise = expected_behavior(&config, DRIVE_CYCLE[1]);
+        assert_eq!(applied.first(), Some(&idle));
+        assert_eq!(applied.last(), Some(&idle));
+        // The pull away raises the volume with the speed, and it comes back down at the stop
+        let loudest = applied.iter().map(|behavior| behavior.volume).max();
+        assert!(loudest > Some(idle.volume), "{:?}", applied);
+        assert!(loudest <= Some(cruise.volume), "{:?}", applied);
+    }
+
+    #[test]
+    fn a_custom_config_reaches_the_mapping_and_the_volume_schedule() {
+        let _lock = APP_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let config = AppConfig::builder()
+            .mapping(MappingConfig {
+                max_volume: 9,
+                max_bass: 6,
+                ..MappingConfig::default()
+            })
+            .default_preset(AudioPreset::Sport)
+            .build();
+
+        let applied = block_on(run_drive_cycle(&DRIVE_CYCLE, &config));
+
+        let idle = expected_behavior(&config, DRIVE_CYCLE[0]);
+        assert_ne!(
+            idle,
+            expected_behavior(&AppConfig::default(), DRIVE_CYCLE[0])
+        );
+        assert_eq!(applied.first(), Some(&idle));
+        assert!(
+            applied
+                .iter()
+                .all(|behavior| behavior.volume <= 9 && behavior.bass <= 6),
+            "{:?}",
+            applied
+        );
+
+        // A quiet window covering the simulated time caps every behavior
+        let config = AppConfig::builder()
+            .quiet_window(QuietWindow {
+                start: TimeOfDay::new(11, 0),
+                end: TimeOfDay::new(13, 0),
+                max_volume: 2,
+            })
+            .build();
+
+        let applied = block_on(run_drive_cycle(&DRIVE_CYCLE, &config));
+
+        assert!(!applied.is_empty());
+        assert!(
+            applied.iter().all(|behavior| behavior.volume <= 2),
+            "{:?}",
+            applied
+        );
+    }
+}
@@ -0,0 +1,35 @@
+//! The modules of the firmware, built as a library so their tests run on the host.
+//!
+//! The firmware itself is the `main` binary, which compiles the same modules for the board. On
+//! the host the simulation doubles stand in for the CSR8645 module, the OBD-II adapter and the
+//! flash, and `cargo test-host` runs the tests against them. With the `host-sim` feature the
+//! library also runs the whole app over a scripted drive cycle, see `host_sim`.
+#![cfg_attr(not(test), no_std)]
+// The module files repeat `#![no_std]` and `#![no_main]`, which only take effect at the crate
+// root; their warnings are allowed so that clippy -D warnings passes
+#![allow(unused_attributes)]
+
+extern crate alloc;
+
+#[path = "bin/app.rs"]
+pub mod app;
+#[path = "bin/audio/mod.rs"]
+pub mod audio;
+#[path = "bin/bluetooth/mod.rs"]
+pub mod bluetooth;
+#[path = "bin/config/mod.rs"]
+pub mod config;
+#[path = "bin/csr8645/mod.rs"]
+pub mod csr8645;
+#[path = "bin/diagnostics/mod.rs"]
+pub mod diagnostics;
+#[cfg(feature = "host-sim")]
+pub mod host_sim;
+#[path = "bin/obd/mod.rs"]
+pub mod obd;
+#[path = "bin/storage/mod.rs"]
+pub mod storage;
+#[path = "bin/telemetry/mod.rs"]
+pub mod telemetry;
+#[path = "bin/uart/mod.rs"]
+pub mod uart;